@@ -0,0 +1,191 @@
+// 简单的耗时测量：cargo bench
+use std::time::Instant;
+
+use rilox::lox::Lox;
+use rilox::parser::Parser;
+use rilox::scanner::Scanner;
+
+fn bench(name: &str, code: &str) {
+    let mut lox = Lox::new();
+    let start = Instant::now();
+    lox.test_code(code);
+    println!("{:<16} {:?}", name, start.elapsed());
+}
+
+// 只测扫描和解析
+fn bench_parse(name: &str, code: &str) {
+    let mut scanner = Scanner::new();
+    let start = Instant::now();
+    scanner.load_code(code);
+    scanner.scan_tokens().unwrap();
+    let statements = Parser::new(&scanner.tokens).parse();
+    println!("{:<16} {:?} ({} statements)", name, start.elapsed(), statements.len());
+}
+
+fn main() {
+    bench("fib", r#"
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+var result = fib(25);
+    "#);
+
+    bench("loop", r#"
+{
+    var sum = 0;
+    for (var i = 0; i < 1000000; i = i + 1) {
+        sum = sum + i;
+    }
+}
+    "#);
+
+    bench("variables", r#"
+var a = 1;
+var b = 2;
+var c = 3;
+var total = 0;
+for (var i = 0; i < 200000; i = i + 1) {
+    var x = a + b;
+    var y = b + c;
+    total = total + x * y - c;
+}
+    "#);
+
+    bench("nested blocks", r#"
+{
+    var a = 1;
+    {
+        var b = 2;
+        {
+            var sum = 0;
+            for (var i = 0; i < 200000; i = i + 1) {
+                var c = a + b;
+                sum = sum + c;
+            }
+        }
+    }
+}
+    "#);
+
+    bench("globals", r#"
+var counter = 0;
+var step = 1;
+fun tick() {
+    counter = counter + step;
+}
+for (var i = 0; i < 200000; i = i + 1) {
+    tick();
+}
+    "#);
+
+    bench("bound method", r#"
+class Counter {
+    init() { this.n = 0; }
+    inc() { this.n = this.n + 1; }
+}
+{
+    var counter = Counter();
+    for (var i = 0; i < 200000; i = i + 1) {
+        counter.inc();
+    }
+}
+    "#);
+
+    bench("inherited method", r#"
+class A {
+    value() { return 1; }
+}
+class B < A {}
+class C < B {}
+class D < C {}
+{
+    var d = D();
+    var sum = 0;
+    for (var i = 0; i < 200000; i = i + 1) {
+        sum = sum + d.value();
+    }
+}
+    "#);
+
+    bench("instantiate", r#"
+class Base {
+    a() {} b() {} c() {} d() {}
+}
+class Point < Base {
+    init(x, y) { this.x = x; this.y = y; }
+    e() {} f() {} g() {} h() {}
+}
+{
+    for (var i = 0; i < 100000; i = i + 1) {
+        Point(i, i);
+    }
+}
+    "#);
+
+    bench("string argument", r#"
+var s = "0123456789";
+for (var i = 0; i < 10; i = i + 1) {
+    s = s + s;
+}
+fun take(a, b, c) {
+    return nil;
+}
+for (var i = 0; i < 20000; i = i + 1) {
+    take(s, s, s);
+}
+    "#);
+
+    bench("closure", r#"
+fun makeCounter() {
+    var i = 0;
+    fun count() {
+        i = i + 1;
+        return i;
+    }
+    return count;
+}
+{
+    var counter = makeCounter();
+    for (var i = 0; i < 200000; i = i + 1) {
+        counter();
+    }
+}
+    "#);
+
+    bench("property access", r#"
+class Point {
+    init(x) {
+        this.x = x;
+    }
+}
+{
+    var p = Point(0);
+    for (var i = 0; i < 200000; i = i + 1) {
+        p.x = p.x + 1;
+    }
+}
+    "#);
+
+    bench_parse("parse", &r#"
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+    add(other) {
+        return Point(this.x + other.x, this.y + other.y);
+    }
+}
+fun sum(n) {
+    var total = 0;
+    for (var i = 0; i < n; i = i + 1) {
+        if (i > 10 and i < 100 or i == 1000) total = total + i * 2 - 1;
+        else total = -total / 3;
+    }
+    print "total: " + total;
+    return total;
+}
+var p = Point(1, 2).add(Point(3, 4));
+    "#.repeat(2000));
+}
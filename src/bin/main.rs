@@ -1,7 +1,17 @@
 
+use std::thread;
+
 use rilox::lox::Lox;
 
+// 树遍历解释器每层 Lox 调用要占好几层 Rust 栈帧，debug 构建下一层就要十几 KB，主线程默认的 8MB 撑不到调用深度的上限
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
 fn main() {
-    let mut lox = Lox::new();
-    lox.start();
-}
\ No newline at end of file
+    let interpreter_thread = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| Lox::new().start())
+        .expect("Failed to spawn the interpreter thread.");
+    // panic 信息已经打印过了，退出码和主线程 panic 时一样
+    let code = interpreter_thread.join().unwrap_or(101);
+    std::process::exit(code);
+}
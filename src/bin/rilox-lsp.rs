@@ -0,0 +1,8 @@
+use rilox::lsp;
+
+fn main() {
+    if let Err(err) = lsp::run_server() {
+        eprintln!("rilox-lsp: {}", err);
+        std::process::exit(1);
+    }
+}
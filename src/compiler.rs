@@ -0,0 +1,475 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::err::LoxErr;
+use crate::expr::*;
+use crate::object::Object;
+use crate::resolvable::Resolvable;
+use crate::stack;
+use crate::stmt::{FunctionDeclaration, Stmt};
+use crate::token::Token;
+use crate::token_type::TokenType;
+
+/*
+把语义分析后的 AST 编译成基于栈的字节码，交给 vm 执行。
+目前只支持表达式、控制流和函数，类和闭包（捕获外层函数的局部变量）会报编译错误。
+跳转指令的操作数是跳转目标的绝对下标。
+*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OpCode {
+    Constant(usize),    // 常量表下标
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(usize),    // 相对于当前调用帧栈底的下标
+    SetLocal(usize),
+    DefineGlobal(usize),    // 全局变量名表下标
+    GetGlobal(usize),
+    SetGlobal(usize),
+    Binary(TokenType),  // 运算语义和解释器共用 Interpreter::binary_operation
+    Unary(TokenType),
+    Print,
+    Jump(usize),
+    JumpIfFalse(usize), // 不弹出条件
+    Call(usize),    // 实参个数
+    Return,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<OpCode>,
+    pub lines: Vec<usize>,  // 每条指令对应的源码行号，报错用
+    pub constants: Vec<Object>,
+    pub names: Vec<Rc<str>>,    // 全局变量名
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BytecodeFunction {
+    pub name: Option<Rc<str>>,  // 顶层脚本没有名字
+    pub params: Vec<Rc<str>>,   // 参数名，只用来显示
+    pub chunk: Chunk,
+}
+
+impl BytecodeFunction {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl fmt::Display for BytecodeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}({})>", name, self.params.join(", ")),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+struct Local {
+    name: Rc<str>,
+    depth: usize,
+}
+
+struct LoopContext {
+    scope_depth: usize, // 循环外面的作用域深度，break 和 continue 时要弹出比它深的局部变量
+    break_jumps: Vec<usize>,
+    continue_jumps: Vec<usize>, // 跳到递增语句，循环体编译完才知道位置
+}
+
+pub struct Compiler {
+    function: BytecodeFunction,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<LoopContext>,
+    line: usize,    // 最近见过的 token 的行号，字面量之类没有 token 的节点用它
+}
+
+impl Compiler {
+    fn new(name: Option<Rc<str>>, params: Vec<Rc<str>>, scope_depth: usize) -> Compiler {
+        Compiler {
+            function: BytecodeFunction {
+                name: name,
+                params: params,
+                chunk: Chunk::default(),
+            },
+            // 调用帧的第 0 个槽位放被调用的函数自己
+            locals: vec![Local { name: Rc::from(""), depth: scope_depth }],
+            scope_depth: scope_depth,
+            loops: Vec::new(),
+            line: 1,
+        }
+    }
+
+    // 编译顶层脚本，结果是一个没有参数的函数
+    pub fn compile(statements: &[Stmt]) -> Result<BytecodeFunction, LoxErr> {
+        let mut compiler = Compiler::new(None, Vec::new(), 0);
+        for stmt in statements {
+            compiler.compile_stmt(stmt)?;
+        }
+        Ok(compiler.finish())
+    }
+
+    fn compile_function(function_declaration: &FunctionDeclaration) -> Result<BytecodeFunction, LoxErr> {
+        // 参数和函数体在同一个作用域
+        let params = function_declaration.params.iter().map(|param| Rc::clone(&param.lexeme)).collect();
+        let mut compiler = Compiler::new(Some(Rc::clone(&function_declaration.name.lexeme)), params, 1);
+        compiler.line = function_declaration.name.line;
+        for param in &function_declaration.params {
+            compiler.add_local(&param.lexeme);
+        }
+        for stmt in &function_declaration.body {
+            compiler.compile_stmt(stmt)?;
+        }
+        Ok(compiler.finish())
+    }
+
+    fn finish(mut self) -> BytecodeFunction {
+        self.emit(OpCode::Nil);
+        self.emit(OpCode::Return);
+        self.function
+    }
+
+    fn emit(&mut self, op: OpCode) -> usize {
+        self.function.chunk.code.push(op);
+        self.function.chunk.lines.push(self.line);
+        self.function.chunk.code.len() - 1
+    }
+
+    // 跳转目标是下一条要生成的指令
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.function.chunk.code.len();
+        match &mut self.function.chunk.code[at] {
+            OpCode::Jump(offset) | OpCode::JumpIfFalse(offset) => *offset = target,
+            other => unreachable!("Cannot patch {:?}.", other),
+        }
+    }
+
+    fn add_constant(&mut self, value: Object) -> usize {
+        self.function.chunk.constants.push(value);
+        self.function.chunk.constants.len() - 1
+    }
+
+    fn name_index(&mut self, name: &Rc<str>) -> usize {
+        match self.function.chunk.names.iter().position(|existing| existing == name) {
+            Some(index) => index,
+            None => {
+                self.function.chunk.names.push(Rc::clone(name));
+                self.function.chunk.names.len() - 1
+            }
+        }
+    }
+
+    fn add_local(&mut self, name: &Rc<str>) {
+        self.locals.push(Local { name: Rc::clone(name), depth: self.scope_depth });
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.locals.iter().rposition(|local| &*local.name == name)
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        while self.locals.last().is_some_and(|local| local.depth > self.scope_depth) {
+            self.locals.pop();
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    fn unsupported(&self, token: &Token, what: &str) -> LoxErr {
+        LoxErr::Compile { line: token.line, column: token.column, span: token.span, message: format!("{} are not supported by the bytecode backend yet.", what) }
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxErr> {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                for stmt in statements {
+                    self.compile_stmt(stmt)?;
+                }
+                self.end_scope();
+            }
+            Stmt::Break { keyword } => {
+                self.line = keyword.line;
+                let jump = self.emit_loop_exit_jump();
+                self.loops.last_mut().unwrap().break_jumps.push(jump);
+            }
+            Stmt::Continue { keyword } => {
+                self.line = keyword.line;
+                let jump = self.emit_loop_exit_jump();
+                self.loops.last_mut().unwrap().continue_jumps.push(jump);
+            }
+            Stmt::Assert { keyword, .. } => {
+                return Err(self.unsupported(keyword, "Assertions"));
+            }
+            Stmt::ClassDeclaration { class_declaration } => {
+                return Err(self.unsupported(&class_declaration.name, "Classes"));
+            }
+            Stmt::DoWhile { body, condition, .. } => {
+                let loop_start = self.function.chunk.code.len();
+                self.loops.push(LoopContext { scope_depth: self.scope_depth, break_jumps: Vec::new(), continue_jumps: Vec::new() });
+                let body_result = self.compile_stmt(body);
+                let loop_context = self.loops.pop().unwrap();
+                body_result?;
+                for continue_jump in loop_context.continue_jumps {
+                    self.patch_jump(continue_jump);
+                }
+                self.compile_expr(condition)?;
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.emit(OpCode::Jump(loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+                for break_jump in loop_context.break_jumps {
+                    self.patch_jump(break_jump);
+                }
+            }
+            Stmt::For { .. } => unreachable!("Resolver desugars for loops into while loops."),
+            Stmt::ForIn { name, .. } => {
+                return Err(self.unsupported(name, "For-in loops"));
+            }
+            Stmt::Import { keyword, .. } => {
+                return Err(self.unsupported(keyword, "Imports"));
+            }
+            Stmt::Throw { keyword, .. } => {
+                return Err(self.unsupported(keyword, "Exceptions"));
+            }
+            Stmt::Try { name, .. } => {
+                return Err(self.unsupported(name, "Exceptions"));
+            }
+            Stmt::Expression { expression, .. } => {
+                self.compile_expr(expression)?;
+                self.emit(OpCode::Pop);
+            }
+            Stmt::FunctionDeclaration { function_declaration } => {
+                self.line = function_declaration.name.line;
+                if function_declaration.is_variadic {
+                    return Err(self.unsupported(&function_declaration.name, "Rest parameters"));
+                }
+                let function = Compiler::compile_function(function_declaration)?;
+                let constant = self.add_constant(Object::BytecodeFunction(Rc::new(function)));
+                self.emit(OpCode::Constant(constant));
+                self.define_variable(&function_declaration.name);
+            }
+            Stmt::If { condition, then_branch, else_branch, .. } => {
+                self.compile_expr(condition)?;
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.compile_stmt(then_branch)?;
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.compile_stmt(else_branch)?;
+                }
+                self.patch_jump(else_jump);
+            }
+            // subject 只求值一次，放在一个隐藏的局部变量里，每个 case 取出来和值比较
+            Stmt::Switch { subject, cases, default, .. } => {
+                self.begin_scope();
+                self.compile_expr(subject)?;
+                self.add_local(&Rc::from(""));
+                let subject_slot = self.locals.len() - 1;
+                let mut end_jumps = Vec::new();
+                for case in cases {
+                    self.emit(OpCode::GetLocal(subject_slot));
+                    self.compile_expr(&case.value)?;
+                    self.emit(OpCode::Binary(TokenType::EqualEqual));
+                    let next_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.compile_stmt(&case.body)?;
+                    end_jumps.push(self.emit(OpCode::Jump(0)));
+                    self.patch_jump(next_jump);
+                    self.emit(OpCode::Pop);
+                }
+                if let Some(default) = default {
+                    self.compile_stmt(default)?;
+                }
+                for end_jump in end_jumps {
+                    self.patch_jump(end_jump);
+                }
+                self.end_scope();
+            }
+            Stmt::While { condition, body, increment, .. } => {
+                let loop_start = self.function.chunk.code.len();
+                self.compile_expr(condition)?;
+                let exit_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.loops.push(LoopContext { scope_depth: self.scope_depth, break_jumps: Vec::new(), continue_jumps: Vec::new() });
+                let body_result = self.compile_stmt(body);
+                let loop_context = self.loops.pop().unwrap();
+                body_result?;
+                for continue_jump in loop_context.continue_jumps {
+                    self.patch_jump(continue_jump);
+                }
+                for stmt in increment {
+                    self.compile_stmt(stmt)?;
+                }
+                self.emit(OpCode::Jump(loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(OpCode::Pop);
+                // break 时条件已经弹出了，直接跳到循环后面
+                for break_jump in loop_context.break_jumps {
+                    self.patch_jump(break_jump);
+                }
+            }
+            // 虚拟机还不支持闭包，不存在捕获循环变量的情况
+            Stmt::FreshLoopVariable { .. } => (),
+            Stmt::Print { expression, .. } => {
+                self.compile_expr(expression)?;
+                self.emit(OpCode::Print);
+            }
+            Stmt::Return { keyword, value } => {
+                self.line = keyword.line;
+                match value {
+                    Some(value) => self.compile_expr(value)?,
+                    None => {
+                        self.emit(OpCode::Nil);
+                    }
+                }
+                self.emit(OpCode::Return);
+            }
+            // 对常量赋值在语义分析时就报错了
+            Stmt::Var { name, initializer, .. } => {
+                self.line = name.line;
+                match initializer {
+                    Some(initializer) => self.compile_expr(initializer)?,
+                    None => {
+                        self.emit(OpCode::Nil);
+                    }
+                }
+                self.define_variable(name);
+            }
+        }
+        Ok(())
+    }
+
+    // break 和 continue 离开循环体：弹出循环体里的局部变量，再发出一条待回填的跳转
+    fn emit_loop_exit_jump(&mut self) -> usize {
+        let Some(loop_context) = self.loops.last() else {
+            unreachable!("Resolver rejects break and continue outside of a loop.");
+        };
+        let loop_depth = loop_context.scope_depth;
+        let locals_in_loop = self.locals.iter().rev().take_while(|local| local.depth > loop_depth).count();
+        for _ in 0..locals_in_loop {
+            self.emit(OpCode::Pop);
+        }
+        self.emit(OpCode::Jump(0))
+    }
+
+    // 值已经在栈顶。局部变量就留在栈上，全局变量弹出存进全局表
+    fn define_variable(&mut self, name: &Token) {
+        if self.scope_depth == 0 {
+            let index = self.name_index(&name.lexeme);
+            self.emit(OpCode::DefineGlobal(index));
+        } else {
+            self.add_local(&name.lexeme);
+        }
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), LoxErr> {
+        stack::guard(|| self.compile_expr_inner(expr))
+    }
+
+    fn compile_expr_inner(&mut self, expr: &Expr) -> Result<(), LoxErr> {
+        match expr {
+            Expr::Assign(assign_expr) => {
+                self.compile_expr(&assign_expr.value)?;
+                self.line = assign_expr.name.line;
+                let op = self.variable_op(&assign_expr.name, assign_expr.get_slot().is_some(), OpCode::SetLocal, OpCode::SetGlobal)?;
+                self.emit(op);
+            }
+            Expr::Binary(binary_expr) => {
+                self.compile_expr(&binary_expr.left)?;
+                self.compile_expr(&binary_expr.right)?;
+                self.line = binary_expr.operator.line;
+                self.emit(OpCode::Binary(binary_expr.operator.token_type));
+            }
+            Expr::Call(call_expr) => {
+                self.compile_expr(&call_expr.callee)?;
+                for argument in &call_expr.arguments {
+                    self.compile_expr(argument)?;
+                }
+                self.line = call_expr.paren.line;
+                self.emit(OpCode::Call(call_expr.arguments.len()));
+            }
+            Expr::Comma(comma_expr) => {
+                for (i, expr) in comma_expr.exprs.iter().enumerate() {
+                    if i > 0 {
+                        self.emit(OpCode::Pop);
+                    }
+                    self.compile_expr(expr)?;
+                }
+            }
+            Expr::Conditional(conditional_expr) => {
+                self.compile_expr(&conditional_expr.condition)?;
+                let then_jump = self.emit(OpCode::JumpIfFalse(0));
+                self.emit(OpCode::Pop);
+                self.compile_expr(&conditional_expr.then_branch)?;
+                let else_jump = self.emit(OpCode::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                self.compile_expr(&conditional_expr.else_branch)?;
+                self.patch_jump(else_jump);
+            }
+            Expr::Grouping(grouping_expr) => self.compile_expr(&grouping_expr.expression)?,
+            Expr::Literal(literal_expr) => {
+                match &literal_expr.literal {
+                    Object::None => self.emit(OpCode::Nil),
+                    Object::Bool(true) => self.emit(OpCode::True),
+                    Object::Bool(false) => self.emit(OpCode::False),
+                    literal => {
+                        let constant = self.add_constant(literal.clone());
+                        self.emit(OpCode::Constant(constant))
+                    }
+                };
+            }
+            Expr::Logical(logical_expr) => {
+                self.compile_expr(&logical_expr.left)?;
+                self.line = logical_expr.operator.line;
+                if logical_expr.operator.token_type == TokenType::Or {
+                    // 左边为真就跳过右边，留下左边的值
+                    let else_jump = self.emit(OpCode::JumpIfFalse(0));
+                    let end_jump = self.emit(OpCode::Jump(0));
+                    self.patch_jump(else_jump);
+                    self.emit(OpCode::Pop);
+                    self.compile_expr(&logical_expr.right)?;
+                    self.patch_jump(end_jump);
+                } else {
+                    let end_jump = self.emit(OpCode::JumpIfFalse(0));
+                    self.emit(OpCode::Pop);
+                    self.compile_expr(&logical_expr.right)?;
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Unary(unary_expr) => {
+                self.compile_expr(&unary_expr.right)?;
+                self.line = unary_expr.operator.line;
+                self.emit(OpCode::Unary(unary_expr.operator.token_type));
+            }
+            Expr::Variable(variable_expr) => {
+                self.line = variable_expr.name.line;
+                let op = self.variable_op(&variable_expr.name, variable_expr.get_slot().is_some(), OpCode::GetLocal, OpCode::GetGlobal)?;
+                self.emit(op);
+            }
+            Expr::Get(GetExpr { name, .. }) | Expr::Set(SetExpr { name, .. }) => return Err(self.unsupported(name, "Classes")),
+            Expr::Super(SuperExpr { keyword, .. }) | Expr::This(ThisExpr { keyword, .. }) => return Err(self.unsupported(keyword, "Classes")),
+        }
+        Ok(())
+    }
+
+    // Resolver 认为是局部变量的，必须是当前函数自己的局部变量，否则就是闭包捕获的外层变量
+    fn variable_op(&mut self, name: &Token, is_local: bool, local_op: fn(usize) -> OpCode, global_op: fn(usize) -> OpCode) -> Result<OpCode, LoxErr> {
+        if !is_local {
+            return Ok(global_op(self.name_index(&name.lexeme)));
+        }
+        match self.resolve_local(&name.lexeme) {
+            Some(index) => Ok(local_op(index)),
+            None => Err(self.unsupported(name, "Closures")),
+        }
+    }
+}
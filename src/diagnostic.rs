@@ -0,0 +1,279 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+use unicode_width::UnicodeWidthChar;
+
+use crate::err::{self, LoxErr};
+use crate::token::{Span, Token};
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum DiagnosticFormat {
+    #[default]
+    Human,
+    Json,   // 每条诊断一行 JSON，方便编辑器集成
+}
+
+// 诊断报告到哪里。默认打印到标准错误，嵌入的程序可以换成自己的实现，收集、过滤或者转发到别处
+pub trait ErrorReporter {
+    fn report(&mut self, diagnostic: &Diagnostic);
+}
+
+// 闭包也可以直接当 reporter 用
+impl<F: FnMut(&Diagnostic)> ErrorReporter for F {
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        self(diagnostic)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StderrReporter {
+    pub format: DiagnosticFormat,
+}
+
+impl StderrReporter {
+    pub fn new(format: DiagnosticFormat) -> StderrReporter {
+        StderrReporter { format: format }
+    }
+}
+
+impl ErrorReporter for StderrReporter {
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        eprintln!("{}", diagnostic.render(self.format));
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// 诊断是在哪个阶段产生的
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Usage,
+    Io,
+    Scan,
+    Parse,
+    Resolve,
+    Compile,
+    Runtime,
+}
+
+// JSON 里的字段按这里的顺序输出
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str, // 稳定的类别名，比如 parse-error、shadowed-variable，CI 可以按它过滤，不用去匹配信息文本
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,   // 出错的是导入的模块时，模块文件的路径。主脚本里的没有
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub span: Option<Span>, // 源代码里的字节范围。字节码虚拟机和少数不知道位置的运行时错误没有这个
+    pub message: String,
+    pub stage: Stage,
+    #[serde(skip)]
+    pub source_line: Option<String>,    // 出错的那一行源代码，人读的格式会显示出来并用 ^-- here 指出位置
+}
+
+impl Diagnostic {
+    // 警告都指向某个 token
+    pub fn warning(stage: Stage, code: &'static str, token: &Token, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: code,
+            file: None,
+            line: Some(token.line),
+            column: Some(token.column),
+            span: Some(token.span),
+            message: message,
+            stage: stage,
+            source_line: None,
+        }
+    }
+
+    // LoxErr::Many 会被展开成多条
+    pub fn from_err(lox_err: &LoxErr) -> Vec<Diagnostic> {
+        let (stage, code, line, column, span, message) = match lox_err {
+            LoxErr::Many(errs) => return errs.iter().flat_map(Diagnostic::from_err).collect(),
+            LoxErr::ScriptUsage => (Stage::Usage, "usage", None, 0, None, lox_err.to_string()),
+            LoxErr::Io(_) => (Stage::Io, "io-error", None, 0, None, lox_err.to_string()),
+            LoxErr::Scan { line, column, span, message } => (Stage::Scan, "scan-error", Some(*line), *column, Some(*span), message.clone()),
+            LoxErr::Parse { line, column, span, lexeme, message } => (Stage::Parse, "parse-error", Some(*line), *column, Some(*span), format!("at {}. {}", lexeme, message)),
+            LoxErr::Resolve { line, column, span, message } => (Stage::Resolve, "resolve-error", Some(*line), *column, Some(*span), message.clone()),
+            LoxErr::Compile { line, column, span, message } => (Stage::Compile, "unsupported-feature", Some(*line), *column, Some(*span), message.clone()),
+            LoxErr::Runtime { line, column, span, message } => (Stage::Runtime, "runtime-error", Some(*line), *column, *span, message.clone()),
+            LoxErr::RuntimeThrow { line, column, span, value } => (Stage::Runtime, "uncaught-exception", Some(*line), *column, Some(*span), format!("Uncaught exception: {}.", value)),
+            // 不知道位置时人读的格式只显示信息本身，所以带上 Runtime Error 前缀
+            LoxErr::Aborted { line: None, .. } => (Stage::Runtime, "aborted", None, 0, None, lox_err.to_string()),
+            LoxErr::Aborted { line, column, span, message } => (Stage::Runtime, "aborted", *line, *column, *span, message.clone()),
+            // 语义分析保证了 return、break 和 continue 不会逃出函数和循环
+            // exit 在最外层就被处理掉了，不会当成错误报告
+            LoxErr::RuntimeReturn { .. } | LoxErr::RuntimeBreak | LoxErr::RuntimeContinue | LoxErr::Exit { .. } => (Stage::Runtime, "runtime-error", None, 0, None, lox_err.to_string()),
+        };
+        vec![Diagnostic {
+            severity: Severity::Error,
+            code: code,
+            file: None,
+            line: line,
+            column: if column == 0 { None } else { Some(column) },
+            span: span,
+            message: message,
+            stage: stage,
+            source_line: None,
+        }]
+    }
+
+    pub fn in_file(mut self, path: &str) -> Diagnostic {
+        self.file = Some(path.to_string());
+        self
+    }
+
+    pub fn with_source(mut self, source: &str) -> Diagnostic {
+        self.source_line = self.line
+            .and_then(|line| line.checked_sub(1))
+            .and_then(|index| source.lines().nth(index))
+            .map(str::to_string);
+        self
+    }
+
+    pub fn render(&self, format: DiagnosticFormat) -> String {
+        match format {
+            DiagnosticFormat::Human => self.to_human(),
+            DiagnosticFormat::Json => self.to_json(),
+        }
+    }
+
+    // 和 LoxErr 的 Display 保持一致，比如 Parse Error: [line 1] at 'x'. Expect ';' after value.
+    fn to_human(&self) -> String {
+        let Some(line) = self.line else {
+            return self.message.clone();
+        };
+        let stage = match self.stage {
+            Stage::Usage | Stage::Io => "",
+            Stage::Scan => "Scan",
+            Stage::Parse => "Parse",
+            Stage::Resolve => "Resolve",
+            Stage::Compile => "Compile",
+            Stage::Runtime => "Runtime",
+        };
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        let mut location = err::location(line, self.column.unwrap_or(0));
+        if let Some(file) = &self.file {
+            location = format!("{}, {}", file, location);
+        }
+        let mut text = format!("{} {}: [{}] {}", stage, severity, location, self.message);
+        // 像现代的编译器一样，把出错的那一行也打印出来：
+        //   3 | print a +;
+        //     |          ^-- here
+        if let Some(source_line) = &self.source_line {
+            let number = line.to_string();
+            let _ = write!(text, "\n{} | {}", number, source_line);
+            if let Some(column) = self.column {
+                // 列号按字符数算。前面的制表符照样输出，中文这种全角字符占两格，^ 才能对齐
+                let padding: String = source_line.chars().take(column - 1).map(|c| match c {
+                    '\t' => "\t".to_string(),
+                    _ => " ".repeat(c.width().unwrap_or(0)),
+                }).collect();
+                let _ = write!(text, "\n{} | {}^-- here", " ".repeat(number.len()), padding);
+            }
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Diagnostics always serialize to JSON.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::Lox;
+    use crate::object::Object;
+
+    #[test]
+    fn test_parse_error_json() {
+        let err = LoxErr::Parse { line: 3, column: 5, span: Span { start: 20, end: 25 }, lexeme: "'print'".to_string(), message: "Expect ';' after value.".to_string() };
+        let json: Vec<String> = Diagnostic::from_err(&err).iter().map(Diagnostic::to_json).collect();
+        assert_eq!(json, vec![r#"{"severity":"error","code":"parse-error","line":3,"column":5,"span":{"start":20,"end":25},"message":"at 'print'. Expect ';' after value.","stage":"parse"}"#.to_string()]);
+    }
+
+    #[test]
+    fn test_runtime_error_json() {
+        let err = LoxErr::Runtime { line: 7, column: 0, span: None, message: "Undefined variable \"x\".".to_string() };
+        let json: Vec<String> = Diagnostic::from_err(&err).iter().map(Diagnostic::to_json).collect();
+        assert_eq!(json, vec![r#"{"severity":"error","code":"runtime-error","line":7,"column":null,"span":null,"message":"Undefined variable \"x\".","stage":"runtime"}"#.to_string()]);
+
+        // 运算符、调用出的错指向运算符和右括号
+        let mut lox = Lox::new();
+        lox.set_reporter(Box::new(|_: &Diagnostic| {}));
+        lox.test_code("print nil + 1;\nprint len(1);");
+        let json: Vec<String> = lox.take_diagnostics().iter().map(Diagnostic::to_json).collect();
+        assert_eq!(json, vec![
+            r#"{"severity":"error","code":"runtime-error","line":1,"column":11,"span":{"start":10,"end":11},"message":"Operands must be two numbers or include a string, got nil and number.","stage":"runtime"}"#.to_string(),
+            r#"{"severity":"error","code":"runtime-error","line":2,"column":12,"span":{"start":26,"end":27},"message":"Argument of len must be a string or list, got number.","stage":"runtime"}"#.to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_uncaught_exception_json() {
+        let err = LoxErr::RuntimeThrow { line: 2, column: 3, span: Span { start: 12, end: 17 }, value: Object::String("boom".to_string()) };
+        let json: Vec<String> = Diagnostic::from_err(&err).iter().map(Diagnostic::to_json).collect();
+        assert_eq!(json, vec![r#"{"severity":"error","code":"uncaught-exception","line":2,"column":3,"span":{"start":12,"end":17},"message":"Uncaught exception: boom.","stage":"runtime"}"#.to_string()]);
+        let err = LoxErr::Compile { line: 1, column: 1, span: Span { start: 0, end: 5 }, message: "Classes are not supported by the bytecode backend yet.".to_string() };
+        assert_eq!(Diagnostic::from_err(&err)[0].span, Some(Span { start: 0, end: 5 }));
+        let err = LoxErr::Aborted { line: Some(4), column: 5, span: Some(Span { start: 30, end: 35 }), message: "Interrupted.".to_string() };
+        assert_eq!(Diagnostic::from_err(&err)[0].span, Some(Span { start: 30, end: 35 }));
+    }
+
+    #[test]
+    fn test_human_matches_display() {
+        let err = LoxErr::Parse { line: 3, column: 0, span: Span::default(), lexeme: "end".to_string(), message: "Expect ';' after value.".to_string() };
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), err.to_string());
+        let err = LoxErr::Runtime { line: 1, column: 0, span: None, message: "Operand must be a number, got nil.".to_string() };
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), err.to_string());
+        let err = LoxErr::Runtime { line: 2, column: 7, span: None, message: "Operand must be a number, got nil.".to_string() };
+        assert_eq!(err.to_string(), "Runtime Error: [line 2:7] Operand must be a number, got nil.");
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), err.to_string());
+        let err = LoxErr::RuntimeThrow { line: 2, column: 3, span: Span { start: 12, end: 17 }, value: Object::String("boom".to_string()) };
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), "Runtime Error: [line 2:3] Uncaught exception: boom.");
+        let err = LoxErr::Compile { line: 1, column: 1, span: Span { start: 0, end: 5 }, message: "Classes are not supported by the bytecode backend yet.".to_string() };
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), err.to_string());
+        let err = LoxErr::Aborted { line: Some(4), column: 5, span: Some(Span { start: 30, end: 35 }), message: "Interrupted.".to_string() };
+        assert_eq!(err.to_string(), "Runtime Error: [line 4:5] Interrupted.");
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), err.to_string());
+        let err = LoxErr::Aborted { line: None, column: 0, span: None, message: "Interrupted.".to_string() };
+        assert_eq!(Diagnostic::from_err(&err)[0].render(DiagnosticFormat::Human), "Runtime Error: Interrupted.");
+    }
+
+    #[test]
+    fn test_source_snippet() {
+        let err = LoxErr::Parse { line: 2, column: 10, span: Span { start: 18, end: 19 }, lexeme: "';'".to_string(), message: "Expect expression.".to_string() };
+        let diagnostic = Diagnostic::from_err(&err).remove(0).with_source("var a = 1;\nprint a +;\n");
+        assert_eq!(diagnostic.render(DiagnosticFormat::Human), "Parse Error: [line 2:10] at ';'. Expect expression.\n2 | print a +;\n  |          ^-- here");
+        // JSON 里不带源代码
+        assert!(!diagnostic.to_json().contains("print a"));
+
+        let err = LoxErr::Runtime { line: 1, column: 0, span: None, message: "Oops.".to_string() };
+        let diagnostic = Diagnostic::from_err(&err).remove(0).with_source("\tprint f();");
+        assert_eq!(diagnostic.render(DiagnosticFormat::Human), "Runtime Error: [line 1] Oops.\n1 | \tprint f();");
+        let err = LoxErr::Runtime { line: 1, column: 8, span: None, message: "Oops.".to_string() };
+        let diagnostic = Diagnostic::from_err(&err).remove(0).with_source("\tprint f();");
+        assert_eq!(diagnostic.render(DiagnosticFormat::Human), "Runtime Error: [line 1:8] Oops.\n1 | \tprint f();\n  | \t      ^-- here");
+        let err = LoxErr::Runtime { line: 1, column: 12, span: None, message: "Oops.".to_string() };
+        let diagnostic = Diagnostic::from_err(&err).remove(0).with_source("print \"变量\" - café;");
+        assert_eq!(diagnostic.render(DiagnosticFormat::Human), "Runtime Error: [line 1:12] Oops.\n1 | print \"变量\" - café;\n  |              ^-- here");
+
+        // 导入的模块里的错误带上模块文件的路径
+        let err = LoxErr::Runtime { line: 2, column: 16, span: None, message: "Oops.".to_string() };
+        let diagnostic = Diagnostic::from_err(&err).remove(0).in_file("lib/util.lox").with_source("fun f() {\n    return nil + 1;\n}");
+        assert_eq!(diagnostic.render(DiagnosticFormat::Human), "Runtime Error: [lib/util.lox, line 2:16] Oops.\n2 |     return nil + 1;\n  |                ^-- here");
+        assert!(diagnostic.to_json().contains(r#""file":"lib/util.lox","line":2"#));
+    }
+}
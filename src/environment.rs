@@ -1,156 +1,267 @@
-use std::collections::HashMap;
-
-use std::rc::Rc;
-use std::cell::RefCell;
-
-use crate::err::LoxErr;
-
-use crate::object::Object;
-use crate::token::Token;
-
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct Environment {
-    pub enclosing: Option<Rc<RefCell<Environment>>>,
-    pub values: HashMap<String, Object>,
-}
-
-impl Environment {
-    pub fn new() -> Rc<RefCell<Environment>> {
-        Rc::new(RefCell::new( Environment{
-            enclosing: None,
-            values: HashMap::new(),
-        }))
-    }
-
-    pub fn set_enclosing(&mut self, enclosing: Rc<RefCell<Environment>>) {
-        self.enclosing = Some(enclosing);   // Rc::clone 在外面，不然所有权就转移进来了
-    }
-
-    pub fn define(&mut self, name: &str, value: Object) {
-        self.values.insert(name.to_string(), value);
-    }
-
-    pub fn get(&self, name: &Token) -> Result<Object, LoxErr> {
-        match self.values.get(&name.lexeme) {
-            Some(value) => Ok(value.clone()),
-            None => {
-                if let Some(enclosing) = &self.enclosing {
-                    enclosing.borrow().get(name)
-                } else {
-                    Err(LoxErr::Runtime { line: name.line, message: format!("Undefined variable '{}'.", name.lexeme)})
-                }
-            }
-        }
-    }
-
-    pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), LoxErr> {
-        match self.values.get_mut(&name.lexeme) {
-            Some(existing_value) => {
-                *existing_value = value;
-                Ok(())
-            },
-            None => {
-                if let Some(enclosing) = &self.enclosing {
-                    enclosing.borrow_mut().assign(name, value)
-                } else {
-                    Err(LoxErr::Runtime { line: name.line, message: format!("Undefined variable '{}'.", name.lexeme)})
-                }
-            }
-        }
-    }
-
-    pub fn get_at(&self, distance: usize, name: &str) -> Object {
-        if distance == 0 {
-            return self.values.get(name).unwrap().clone();
-        }
-
-        self.ancestor(distance).borrow().values.get(name).unwrap().clone()
-    }
-
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: Object) {
-        if distance == 0 {
-            *self.values.get_mut(&name.lexeme).unwrap() = value;
-        } else {
-            *self.ancestor(distance).borrow_mut().values.get_mut(&name.lexeme).unwrap() = value;
-
-        }
-    }
-
-    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
-        assert!(distance >= 1, "param distance should >= 1, now {}", distance);
-
-        let mut ancestor = self.enclosing.clone();
-        for _ in 1..distance {
-            ancestor = ancestor.unwrap().borrow_mut().enclosing.clone();
-        }
-        ancestor.unwrap()
-    }
-
-}
-
-
-
-#[cfg(test)]
-mod tests {
-
-    use super::*;
-    use crate::token_type::TokenType;
-    
-
-    #[test]
-    fn test_get_existing() {
-        let env = Environment::new();
-        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(42.0), 1);
-        env.borrow_mut().define("x", Object::Number(42.0));
-        match env.borrow().get(&token) {
-            Ok(value) => assert_eq!(value, Object::Number(42.0)),
-            Err(_) => panic!("Expected Ok(Object::Number(42.0))"),
-        };
-    }
-
-    #[test]
-    fn test_get_non_existing() {
-        let env = Environment::new();
-        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(0.0), 1);
-        match env.borrow().get(&token) {
-            Ok(_) => panic!("Expected an error for undefined variable"),
-            Err(err) => match err {
-                LoxErr::Runtime { line, message } => {
-                    assert_eq!(line, 1);
-                    assert_eq!(message, "Undefined variable 'x'.");
-                }
-                _ => panic!("Unexpected error type"),
-            },
-        };
-    }
-
-    #[test]
-    fn test_assign_existing() {
-        let env = Environment::new();
-        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(42.0), 1);
-        env.borrow_mut().define("x", Object::Number(42.0));
-        assert!(env.borrow_mut().assign(&token, Object::Number(100.0)).is_ok());
-        match env.borrow().get(&token) {
-            Ok(value) => assert_eq!(value, Object::Number(100.0)),
-            Err(_) => panic!("Expected Ok(Object::Number(100.0))"),
-        };
-    }
-
-    #[test]
-    fn test_assign_non_existing() {
-        let env = Environment::new();
-        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(0.0), 1);
-        match env.borrow_mut().assign(&token, Object::Number(100.0)) {
-            Ok(_) => panic!("Expected an error for undefined variable"),
-            Err(err) => match err {
-                LoxErr::Runtime { line, message } => {
-                    assert_eq!(line, 1);
-                    assert_eq!(message, "Undefined variable 'x'.");
-                }
-                _ => panic!("Unexpected error type"),
-            },
-        };
-    }
-
-}
\ No newline at end of file
+use std::collections::{HashMap, HashSet};
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use crate::err::LoxErr;
+use crate::gc;
+
+use crate::object::Object;
+use crate::resolvable::Slot;
+use crate::token::Token;
+
+
+// 全局环境按名字存变量；局部环境按语义分析算出来的槽位存，省掉字符串查找
+// 局部变量放在共享的 cell 里，闭包只需要拿走它捕获的那几个 cell，而不是整条环境链
+#[derive(Debug, PartialEq, Clone)]
+pub struct Environment {
+    pub enclosing: Option<Rc<RefCell<Environment>>>,
+    pub values: HashMap<Rc<str>, Object>,    // 只有全局环境用
+    pub constants: HashSet<Rc<str>>,    // 只有全局环境用：const 声明的全局变量。局部常量在语义分析时就检查完了
+    pub slots: Vec<Rc<RefCell<Object>>>,    // 局部变量，下标和 Resolver 里的声明顺序一致
+}
+
+impl Environment {
+    pub fn new() -> Rc<RefCell<Environment>> {
+        let env = Rc::new(RefCell::new( Environment{
+            enclosing: None,
+            values: HashMap::new(),
+            constants: HashSet::new(),
+            slots: Vec::new(),
+        }));
+        gc::track_env(&env);
+        env
+    }
+
+    // 局部变量的 cell 可能和闭包成环，要登记给 gc
+    fn new_cell(value: Object) -> Rc<RefCell<Object>> {
+        let cell = Rc::new(RefCell::new(value));
+        gc::track_cell(&cell);
+        cell
+    }
+
+    pub fn set_enclosing(&mut self, enclosing: Rc<RefCell<Environment>>) {
+        self.enclosing = Some(enclosing);   // Rc::clone 在外面，不然所有权就转移进来了
+    }
+
+    // 局部环境里 define 的顺序必须和 Resolver 里 declare 的顺序一致，这样槽位才对得上
+    pub fn define(&mut self, name: &Rc<str>, value: Object) {
+        if self.enclosing.is_none() {
+            self.values.insert(Rc::clone(name), value);
+        } else {
+            self.define_local(value);
+        }
+    }
+
+    // this、super 这种只会出现在局部环境里的隐式变量，不需要名字
+    pub fn define_local(&mut self, value: Object) {
+        self.slots.push(Environment::new_cell(value));
+    }
+
+    // 函数和类先 define 一个 nil 占位（方法或函数体可能捕获自己的名字），创建好之后用这个填上
+    pub fn assign_last_defined(&mut self, name: &Rc<str>, value: Object) {
+        if self.enclosing.is_none() {
+            self.values.insert(Rc::clone(name), value);
+        } else {
+            *self.slots.last().unwrap().borrow_mut() = value;
+        }
+    }
+
+    // 闭包的环境：只有 captures 指向的那几个 cell，没有 enclosing（全局变量直接去 globals 里找）
+    pub fn capture(&self, captures: &[Slot]) -> Result<Rc<RefCell<Environment>>, LoxErr> {
+        let env = Environment::new();
+        env.borrow_mut().slots = captures.iter().map(|slot| self.get_cell_at(*slot)).collect::<Result<_, _>>()?;
+        Ok(env)
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Object, LoxErr> {
+        match self.values.get(&name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => {
+                if let Some(enclosing) = &self.enclosing {
+                    enclosing.borrow().get(name)
+                } else {
+                    Err(LoxErr::Runtime { line: name.line, column: name.column, span: Some(name.span), message: format!("Undefined variable '{}'.", name.lexeme)})
+                }
+            }
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), LoxErr> {
+        match self.values.get_mut(&name.lexeme) {
+            Some(existing_value) => {
+                *existing_value = value;
+                Ok(())
+            },
+            None => {
+                if let Some(enclosing) = &self.enclosing {
+                    enclosing.borrow_mut().assign(name, value)
+                } else {
+                    Err(LoxErr::Runtime { line: name.line, column: name.column, span: Some(name.span), message: format!("Undefined variable '{}'.", name.lexeme)})
+                }
+            }
+        }
+    }
+
+    pub fn get_at(&self, slot: Slot) -> Result<Object, LoxErr> {
+        if slot.distance == 0 {
+            return Ok(self.slots[slot.index].borrow().clone());
+        }
+
+        Ok(self.ancestor(slot.distance)?.borrow().slots[slot.index].borrow().clone())
+    }
+
+    pub fn assign_at(&mut self, slot: Slot, value: Object) -> Result<(), LoxErr> {
+        *self.get_cell_at(slot)?.borrow_mut() = value;
+        Ok(())
+    }
+
+    // 换一个新 cell 放同样的值，已经捕获了旧 cell 的闭包看不到之后的赋值
+    pub fn refresh_at(&mut self, slot: Slot) -> Result<(), LoxErr> {
+        let cell = Environment::new_cell(self.get_at(slot)?);
+        if slot.distance == 0 {
+            self.slots[slot.index] = cell;
+        } else {
+            self.ancestor(slot.distance)?.borrow_mut().slots[slot.index] = cell;
+        }
+        Ok(())
+    }
+
+    fn get_cell_at(&self, slot: Slot) -> Result<Rc<RefCell<Object>>, LoxErr> {
+        if slot.distance == 0 {
+            return Ok(Rc::clone(&self.slots[slot.index]));
+        }
+
+        Ok(Rc::clone(&self.ancestor(slot.distance)?.borrow().slots[slot.index]))
+    }
+
+    // 沿 enclosing 链往外走 distance 层，每层只 clone 一次 Rc
+    // Resolver 算出来的距离不会超出链的长度，真超出了说明哪里有 bug，返回错误而不是 panic
+    fn ancestor(&self, distance: usize) -> Result<Rc<RefCell<Environment>>, LoxErr> {
+        debug_assert!(distance >= 1, "param distance should >= 1, now {}", distance);
+
+        let mut ancestor = self.enclosing.clone().ok_or_else(|| Environment::scope_chain_err(distance))?;
+        for _ in 1..distance {
+            let enclosing = ancestor.borrow().enclosing.clone();
+            ancestor = enclosing.ok_or_else(|| Environment::scope_chain_err(distance))?;
+        }
+        Ok(ancestor)
+    }
+
+    // 环境里不知道是哪个 token 在查变量，没有行号
+    fn scope_chain_err(distance: usize) -> LoxErr {
+        LoxErr::Runtime { line: 0, column: 0, span: None, message: format!("Scope chain is shorter than resolved distance {}.", distance) }
+    }
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::token_type::TokenType;
+    
+
+    #[test]
+    fn test_get_existing() {
+        let env = Environment::new();
+        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(42.0), 1);
+        env.borrow_mut().define(&Rc::from("x"), Object::Number(42.0));
+        match env.borrow().get(&token) {
+            Ok(value) => assert_eq!(value, Object::Number(42.0)),
+            Err(_) => panic!("Expected Ok(Object::Number(42.0))"),
+        };
+    }
+
+    #[test]
+    fn test_get_non_existing() {
+        let env = Environment::new();
+        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(0.0), 1);
+        match env.borrow().get(&token) {
+            Ok(_) => panic!("Expected an error for undefined variable"),
+            Err(err) => match err {
+                LoxErr::Runtime { line, message, .. } => {
+                    assert_eq!(line, 1);
+                    assert_eq!(message, "Undefined variable 'x'.");
+                }
+                _ => panic!("Unexpected error type"),
+            },
+        };
+    }
+
+    #[test]
+    fn test_assign_existing() {
+        let env = Environment::new();
+        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(42.0), 1);
+        env.borrow_mut().define(&Rc::from("x"), Object::Number(42.0));
+        assert!(env.borrow_mut().assign(&token, Object::Number(100.0)).is_ok());
+        match env.borrow().get(&token) {
+            Ok(value) => assert_eq!(value, Object::Number(100.0)),
+            Err(_) => panic!("Expected Ok(Object::Number(100.0))"),
+        };
+    }
+
+    #[test]
+    fn test_assign_non_existing() {
+        let env = Environment::new();
+        let token = Token::new(TokenType::Identifier, "x".to_string(), Object::Number(0.0), 1);
+        match env.borrow_mut().assign(&token, Object::Number(100.0)) {
+            Ok(_) => panic!("Expected an error for undefined variable"),
+            Err(err) => match err {
+                LoxErr::Runtime { line, message, .. } => {
+                    assert_eq!(line, 1);
+                    assert_eq!(message, "Undefined variable 'x'.");
+                }
+                _ => panic!("Unexpected error type"),
+            },
+        };
+    }
+
+    #[test]
+    fn test_local_slots() {
+        let globals = Environment::new();
+        let outer = Environment::new();
+        outer.borrow_mut().set_enclosing(Rc::clone(&globals));
+        outer.borrow_mut().define(&Rc::from("a"), Object::Number(1.0));
+        outer.borrow_mut().define(&Rc::from("b"), Object::Number(2.0));
+        let inner = Environment::new();
+        inner.borrow_mut().set_enclosing(Rc::clone(&outer));
+        inner.borrow_mut().define(&Rc::from("c"), Object::Number(3.0));
+
+        assert!(globals.borrow().values.is_empty());
+        assert_eq!(inner.borrow().get_at(Slot { distance: 0, index: 0 }).unwrap(), Object::Number(3.0));
+        assert_eq!(inner.borrow().get_at(Slot { distance: 1, index: 1 }).unwrap(), Object::Number(2.0));
+
+        inner.borrow_mut().assign_at(Slot { distance: 1, index: 0 }, Object::Number(10.0)).unwrap();
+        assert_eq!(outer.borrow().get_at(Slot { distance: 0, index: 0 }).unwrap(), Object::Number(10.0));
+    }
+
+    #[test]
+    fn test_deep_scope_chain() {
+        let globals = Environment::new();
+        let outermost = Environment::new();
+        outermost.borrow_mut().set_enclosing(Rc::clone(&globals));
+        outermost.borrow_mut().define(&Rc::from("a"), Object::Number(1.0));
+        let mut env = Rc::clone(&outermost);
+        for _ in 0..1000 {
+            let inner = Environment::new();
+            inner.borrow_mut().set_enclosing(env);
+            env = inner;
+        }
+
+        let slot = Slot { distance: 1000, index: 0 };
+        assert_eq!(env.borrow().get_at(slot).unwrap(), Object::Number(1.0));
+        env.borrow_mut().assign_at(slot, Object::Number(2.0)).unwrap();
+        assert_eq!(outermost.borrow().get_at(Slot { distance: 0, index: 0 }).unwrap(), Object::Number(2.0));
+
+        // 超出链的长度时返回错误，不会 panic
+        let result = env.borrow().get_at(Slot { distance: 1002, index: 0 });
+        match result {
+            Err(LoxErr::Runtime { message, .. }) => assert_eq!(message, "Scope chain is shorter than resolved distance 1002."),
+            other => panic!("Expected runtime error, got {:?}", other),
+        }
+    }
+
+}
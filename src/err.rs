@@ -1,50 +1,116 @@
-use std::io;
-use thiserror;
-
-use crate::object::Object;
-
-#[derive(thiserror::Error, Debug)]
-pub enum LoxErr {
-    #[error("Usage: rilox [script]")]
-    ScriptUsage,
-
-    #[error("Io Error from: {0}")]
-    Io(#[from] io::Error),
-
-    #[error("Scan Error: [line {line}] {message}")]
-    Scan{
-        line: usize,
-        message: String,
-    },
-
-    #[error("Parse Error: [line {line}] at {lexeme}. {message}")]
-    Parse{
-        line: usize,
-        lexeme: String,
-        message: String,
-    },
-
-    #[error("Runtime Error: [line {line}] {message}")]
-    Runtime{
-        line: usize,
-        message: String,
-    },
-
-    // Java 原版用异常实现 Return
-    #[error("RuntimeReturn: {ret_value}")]
-    RuntimeReturn {
-        ret_value: Object,
-    },
-
-    #[error("RuntimeBreak")]
-    RuntimeBreak,
-
-    #[error("Resolve Error: [line {line}] {message}")]
-    Resolve{
-        line: usize,
-        message: String,
-    },
-
-    #[error("Multiple errors occurred: {0:?}")]
-    Many(Vec<LoxErr>),
-}
+use std::io;
+use thiserror;
+
+use crate::object::Object;
+use crate::token::{Span, Token};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoxErr {
+    #[error("Usage: rilox [--diagnostics=human|json] [--warn-shadowing] [--deny-warnings] [--catch-runtime-errors] [--trace[=vars]] [--vm] [--check|--tokens|--ast|--emit=ast-json] [script [args...]] | rilox fmt [--write] script")]
+    ScriptUsage,
+
+    #[error("Io Error from: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Scan Error: [{}] {message}", location(*.line, *.column))]
+    Scan{
+        line: usize,
+        column: usize,
+        span: Span,
+        message: String,
+    },
+
+    #[error("Parse Error: [{}] at {lexeme}. {message}", location(*.line, *.column))]
+    Parse{
+        line: usize,
+        column: usize,
+        span: Span,
+        lexeme: String,
+        message: String,
+    },
+
+    // column 是 0 表示不知道列号，比如原生函数里报的错。span 一般是运算符、调用的右括号或者变量名，不知道时是 None
+    #[error("Runtime Error: [{}] {message}", location(*.line, *.column))]
+    Runtime{
+        line: usize,
+        column: usize,
+        span: Option<Span>,
+        message: String,
+    },
+
+    // Java 原版用异常实现 Return
+    #[error("RuntimeReturn: {ret_value}")]
+    RuntimeReturn {
+        ret_value: Object,
+    },
+
+    #[error("RuntimeBreak")]
+    RuntimeBreak,
+
+    #[error("RuntimeContinue")]
+    RuntimeContinue,
+
+    // throw 语句抛出的值，沿着调用栈往外传，直到被 catch 接住
+    #[error("Runtime Error: [{}] Uncaught exception: {value}.", location(*.line, *.column))]
+    RuntimeThrow {
+        line: usize,
+        column: usize,
+        span: Span,     // throw 关键字
+        value: Object,
+    },
+
+    // 原生函数 exit 请求结束程序。不是错误，catch 也接不住，一直传到最外层
+    #[error("Exit with code {code}")]
+    Exit {
+        code: i32,
+    },
+
+    // 宿主给的执行预算（步数或时间）用完了，或者用户在 REPL 里按了 Ctrl-C，程序被中止。catch 接不住，不然脚本能自己吞掉限制
+    // 位置是中止时正在执行的语句、表达式或调用。字节码虚拟机只知道行号，空的代码块连行号也没有
+    #[error("Runtime Error: {}{message}", line.map(|line| format!("[{}] ", location(line, *column))).unwrap_or_default())]
+    Aborted {
+        line: Option<usize>,
+        column: usize,
+        span: Option<Span>,
+        message: String,
+    },
+
+    #[error("Resolve Error: [{}] {message}", location(*.line, *.column))]
+    Resolve{
+        line: usize,
+        column: usize,
+        span: Span,
+        message: String,
+    },
+
+    // 字节码编译器还不支持的语法
+    #[error("Compile Error: [{}] {message}", location(*.line, *.column))]
+    Compile{
+        line: usize,
+        column: usize,
+        span: Span,
+        message: String,
+    },
+
+    #[error("Multiple errors occurred: {0:?}")]
+    Many(Vec<LoxErr>),
+}
+
+impl LoxErr {
+    // 只知道行号的运行时错误，补上出错的运算符、调用这些的列号和字节范围
+    pub fn with_position(self, token: &Token) -> LoxErr {
+        match self {
+            LoxErr::Runtime { line, column: 0, message, .. } => LoxErr::Runtime { line: line, column: token.column, span: Some(token.span), message: message },
+            other => other,
+        }
+    }
+}
+
+// 错误信息里的位置：知道列号时是 line 3:7，不知道时是 line 3
+pub fn location(line: usize, column: usize) -> String {
+    if column == 0 {
+        format!("line {}", line)
+    } else {
+        format!("line {}:{}", line, column)
+    }
+}
@@ -0,0 +1,293 @@
+use std::rc::Rc;
+
+use crate::err::LoxErr;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::token::Token;
+use crate::token_type::TokenType;
+
+/*
+rilox fmt 用的格式化。先完整地解析一遍，有错误就不格式化
+排版按 token 来，而不是从语法树重新生成代码：解析器把 ++、字符串插值都脱糖了，从语法树生成会把用户的写法改掉
+每个 token 原样输出，只重新安排空白：
+    - 一条语句一行，块里缩进 4 个空格，switch 的 case 再缩进一层
+    - 二元运算符两边有空格，一元运算符、调用的括号、逗号前面没有
+    - 保留注释和语句之间的空行，连续的空行只留一个
+*/
+pub fn format(code: &str) -> Result<String, LoxErr> {
+    let mut scanner = Scanner::new();
+    scanner.load_code(code);
+    scanner.scan_tokens()?;
+    let mut parser = Parser::new(&scanner.tokens);
+    parser.parse();
+    match parser.errors.len() {
+        0 => (),
+        1 => return Err(parser.errors.pop().unwrap()),
+        _ => return Err(LoxErr::Many(std::mem::take(&mut parser.errors))),
+    }
+
+    scanner.keep_comments = true;
+    scanner.load_code(code);
+    scanner.scan_tokens()?;
+    let mut formatter = Formatter::new();
+    for token in &scanner.tokens {
+        formatter.push(token);
+    }
+    Ok(formatter.finish())
+}
+
+const INDENT: &str = "    ";
+
+// 当前在哪种大括号里
+#[derive(PartialEq)]
+enum Brace {
+    Block,
+    Do, // do-while 的循环体，} 后面的 while 不换行
+    Switch { in_arm: bool },    // 进了某个 case 的分支体之后，语句比 case 多缩进一层
+}
+
+struct Formatter {
+    out: String,
+    braces: Vec<Brace>,
+    paren_depth: usize, // for (a; b; c) 括号里的分号不换行
+    pending_newline: bool,  // 下一个 token 要从新的一行开始
+    closed_do: bool,    // 刚输出的 } 是 do-while 循环体的
+    previous: Option<Rc<Token>>,
+    previous_is_prefix: bool,   // 上一个 token 是一元运算符，和后面的操作数之间不加空格
+    next_brace: Brace,  // 下一个 { 是什么，看到 do 或 switch 时记下
+    pending_label: Option<usize>,   // 在 case 或 default 后面，等着标签的冒号。记的是中间三元运算还没配对的 ? 的个数
+}
+
+impl Formatter {
+    fn new() -> Formatter {
+        Formatter {
+            out: String::new(),
+            braces: Vec::new(),
+            paren_depth: 0,
+            pending_newline: false,
+            closed_do: false,
+            previous: None,
+            previous_is_prefix: false,
+            next_brace: Brace::Block,
+            pending_label: None,
+        }
+    }
+
+    fn push(&mut self, token: &Rc<Token>) {
+        match token.token_type {
+            TokenType::Eof => return,
+            TokenType::Comment => return self.push_comment(token),
+            TokenType::RightBrace => {
+                self.closed_do = self.braces.pop() == Some(Brace::Do);
+                // 空的块写成 {}
+                if self.previous_type() == Some(TokenType::LeftBrace) {
+                    self.pending_newline = false;
+                }
+            }
+            TokenType::Case | TokenType::Default => {
+                if let Some(Brace::Switch { in_arm }) = self.braces.last_mut() {
+                    *in_arm = false;
+                }
+                self.pending_label = Some(0);
+                self.pending_newline = true;
+            }
+            // } else、} catch、do { } while 写在同一行
+            TokenType::Else | TokenType::Catch if self.previous_type() == Some(TokenType::RightBrace) => self.pending_newline = false,
+            TokenType::While if self.previous_type() == Some(TokenType::RightBrace) && self.closed_do => self.pending_newline = false,
+            _ => (),
+        }
+
+        if self.pending_newline {
+            self.new_line(token);
+        } else if self.needs_space(token) {
+            self.out.push(' ');
+        }
+        self.pending_newline = false;
+        self.out.push_str(&token.lexeme);
+
+        // do 后面不是 { 的话，循环体是单条语句
+        if self.next_brace == Brace::Do && token.token_type != TokenType::LeftBrace {
+            self.next_brace = Brace::Block;
+        }
+        let mut is_prefix = false;
+        match token.token_type {
+            TokenType::LeftParen => self.paren_depth += 1,
+            TokenType::RightParen => self.paren_depth = self.paren_depth.saturating_sub(1),
+            TokenType::LeftBrace => {
+                let brace = std::mem::replace(&mut self.next_brace, Brace::Block);
+                self.braces.push(brace);
+                self.pending_newline = true;
+            }
+            TokenType::RightBrace => self.pending_newline = true,
+            TokenType::Semicolon => self.pending_newline = self.paren_depth == 0,
+            TokenType::Do => self.next_brace = Brace::Do,
+            TokenType::Switch => self.next_brace = Brace::Switch { in_arm: false },
+            TokenType::Question => {
+                if let Some(questions) = &mut self.pending_label {
+                    *questions += 1;
+                }
+            }
+            TokenType::Colon => match self.pending_label {
+                Some(0) => {
+                    self.pending_label = None;
+                    if let Some(Brace::Switch { in_arm }) = self.braces.last_mut() {
+                        *in_arm = true;
+                    }
+                    self.pending_newline = true;
+                }
+                Some(questions) => self.pending_label = Some(questions - 1),
+                None => (),
+            },
+            TokenType::Bang | TokenType::DotDotDot => is_prefix = true,
+            TokenType::Minus | TokenType::MinusMinus | TokenType::PlusPlus => is_prefix = !self.previous_ends_operand(),
+            _ => (),
+        }
+        self.previous = Some(Rc::clone(token));
+        self.previous_is_prefix = is_prefix;
+    }
+
+    // 注释跟在代码同一行的话留在这一行，否则单独占一行。行注释和单独一行的注释之后都换行
+    fn push_comment(&mut self, comment: &Rc<Token>) {
+        let trailing = self.previous.as_ref().is_some_and(|previous| end_line(previous) == comment.line);
+        if trailing {
+            self.out.push(' ');
+        } else if self.pending_newline || self.previous.is_some() {
+            self.new_line(comment);
+        }
+        self.out.push_str(comment.lexeme.trim_end());
+        self.pending_newline = self.pending_newline || !trailing || comment.lexeme.starts_with("//");
+        self.previous = Some(Rc::clone(comment));
+        self.previous_is_prefix = false;
+    }
+
+    // 换行并缩进。源代码里语句之间有空行的话保留一个
+    fn new_line(&mut self, token: &Token) {
+        let Some(previous) = &self.previous else {
+            return;
+        };
+        if token.line > end_line(previous) + 1 && previous.token_type != TokenType::LeftBrace && token.token_type != TokenType::RightBrace {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+        // 右大括号已经出栈，case 标签所在的 switch 已经标成不在分支体里，都不用特殊处理
+        let arms = self.braces.iter().filter(|brace| **brace == Brace::Switch { in_arm: true }).count();
+        self.out.push_str(&INDENT.repeat(self.braces.len() + arms));
+    }
+
+    fn previous_type(&self) -> Option<TokenType> {
+        self.previous.as_ref().map(|previous| previous.token_type)
+    }
+
+    // 上一个 token 是不是一个操作数的结尾。是的话后面的 ( 是调用，- 是减号，++ 是后缀
+    fn previous_ends_operand(&self) -> bool {
+        let Some(previous) = &self.previous else {
+            return false;
+        };
+        match previous.token_type {
+            TokenType::Identifier | TokenType::Number | TokenType::String | TokenType::True | TokenType::False | TokenType::Nil
+                | TokenType::This | TokenType::RightParen => true,
+            TokenType::PlusPlus | TokenType::MinusMinus => !self.previous_is_prefix,
+            _ => false,
+        }
+    }
+
+    fn needs_space(&self, token: &Token) -> bool {
+        let Some(previous) = &self.previous else {
+            return false;
+        };
+        if self.previous_is_prefix || matches!(previous.token_type, TokenType::LeftParen | TokenType::Dot | TokenType::Interpolation) {
+            return false;
+        }
+        // 插值字符串的各段和中间的表达式紧挨着
+        if matches!(token.token_type, TokenType::String | TokenType::Interpolation) && token.lexeme.starts_with('}') {
+            return false;
+        }
+        match token.token_type {
+            TokenType::RightParen | TokenType::Comma | TokenType::Semicolon | TokenType::Dot => false,
+            TokenType::RightBrace => previous.token_type != TokenType::LeftBrace,
+            TokenType::LeftParen | TokenType::PlusPlus | TokenType::MinusMinus => !self.previous_ends_operand(),
+            TokenType::Colon => self.pending_label != Some(0),
+            _ => true,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
+
+// token 最后一个字符在哪一行，跨行的字符串和块注释要加上中间的换行
+fn end_line(token: &Token) -> usize {
+    token.line + token.lexeme.matches('\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stmt::Stmt;
+
+    fn ast(code: &str) -> Vec<String> {
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        scanner.scan_tokens().unwrap();
+        Parser::new(&scanner.tokens).parse().iter().map(Stmt::to_string).collect()
+    }
+
+    #[test]
+    fn test_format() {
+        let code = r#"// comment
+var a=1;var b = -a+2*(3-4);   // trailing
+class Foo < Bar{init(x,...rest){this.x=x;}
+  get(){return super.get()+this.x;}}
+
+
+for(var i=0;i<3;i=i+1){print i++;print "${i}!";}
+switch(a){case 1:print "one";default:print a>1?"big":"small";}
+do{a=a+1;}while(a<10);
+try{throw "e";}catch(e){}
+"#;
+        let expected = r#"// comment
+var a = 1;
+var b = -a + 2 * (3 - 4); // trailing
+class Foo < Bar {
+    init(x, ...rest) {
+        this.x = x;
+    }
+    get() {
+        return super.get() + this.x;
+    }
+}
+
+for (var i = 0; i < 3; i = i + 1) {
+    print i++;
+    print "${i}!";
+}
+switch (a) {
+    case 1:
+        print "one";
+    default:
+        print a > 1 ? "big" : "small";
+}
+do {
+    a = a + 1;
+} while (a < 10);
+try {
+    throw "e";
+} catch (e) {}
+"#;
+        let formatted = format(code).unwrap();
+        assert_eq!(formatted, expected);
+        // 再格式化一遍不变，语法树也和原来的一样
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert_eq!(ast(&formatted), ast(code));
+    }
+
+    #[test]
+    fn test_format_rejects_invalid_code() {
+        assert!(matches!(format("print 1 +;"), Err(LoxErr::Parse { .. })));
+        assert!(matches!(format("var a = 1;\nprint @;"), Err(LoxErr::Scan { line: 2, .. })));
+    }
+}
@@ -0,0 +1,355 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use crate::environment::Environment;
+use crate::lox_class::LoxClass;
+use crate::lox_instance::LoxInstance;
+use crate::object::Object;
+
+// 回收 Rc 回收不了的引用环：绑定到实例自己的方法存进了实例的字段、局部函数捕获了存着它自己的变量……
+// 做法和 CPython 一样，不需要知道根在哪：
+//   1. 可能成环的堆对象（环境、变量 cell、实例、类、列表）创建时登记一个 Weak
+//   2. 收集时数出每个对象被其他登记过的对象引用了几次，强引用比这个多，说明还有外部引用（解释器当前的环境、Rust 的局部变量等），它就是根
+//   3. 从根出发标记能到达的对象，剩下的只被环引用着，清空它们的内容，环断开后 Rc 自己会释放它们
+// 借不出来（正被 borrow_mut）的对象当成根，也不看它的内容，它引用的对象于是都有外部引用。所以任何时候收集都是安全的，顶多少回收一些
+
+const MIN_THRESHOLD: usize = 10_000;    // 新登记了这么多对象之后才考虑收集，太频繁的话数引用的开销比省下的内存还大
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::new());
+}
+
+struct Heap {
+    tracked: Vec<Tracked>,
+    allocated: usize,   // 上次收集之后新登记的对象数
+    threshold: usize,
+}
+
+impl Heap {
+    fn new() -> Heap {
+        Heap {
+            tracked: Vec::new(),
+            allocated: 0,
+            threshold: MIN_THRESHOLD,
+        }
+    }
+}
+
+enum Tracked {
+    Env(Weak<RefCell<Environment>>),
+    Cell(Weak<RefCell<Object>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    Class(Weak<LoxClass>),
+    List(Weak<Vec<Object>>),
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Node> {
+        match self {
+            Tracked::Env(env) => env.upgrade().map(Node::Env),
+            Tracked::Cell(cell) => cell.upgrade().map(Node::Cell),
+            Tracked::Instance(instance) => instance.upgrade().map(Node::Instance),
+            Tracked::Class(class) => class.upgrade().map(Node::Class),
+            Tracked::List(list) => list.upgrade().map(Node::List),
+        }
+    }
+}
+
+// 收集期间持有的强引用，保证清空内容时不会有对象在半路被释放
+enum Node {
+    Env(Rc<RefCell<Environment>>),
+    Cell(Rc<RefCell<Object>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Class(Rc<LoxClass>),
+    List(Rc<Vec<Object>>),
+}
+
+impl Node {
+    fn id(&self) -> usize {
+        match self {
+            Node::Env(env) => id(env),
+            Node::Cell(cell) => id(cell),
+            Node::Instance(instance) => id(instance),
+            Node::Class(class) => id(class),
+            Node::List(list) => id(list),
+        }
+    }
+
+    // 减掉 upgrade 出来的这一个
+    fn strong_count(&self) -> usize {
+        let count = match self {
+            Node::Env(env) => Rc::strong_count(env),
+            Node::Cell(cell) => Rc::strong_count(cell),
+            Node::Instance(instance) => Rc::strong_count(instance),
+            Node::Class(class) => Rc::strong_count(class),
+            Node::List(list) => Rc::strong_count(list),
+        };
+        count - 1
+    }
+
+    fn downgrade(&self) -> Tracked {
+        match self {
+            Node::Env(env) => Tracked::Env(Rc::downgrade(env)),
+            Node::Cell(cell) => Tracked::Cell(Rc::downgrade(cell)),
+            Node::Instance(instance) => Tracked::Instance(Rc::downgrade(instance)),
+            Node::Class(class) => Tracked::Class(Rc::downgrade(class)),
+            Node::List(list) => Tracked::List(Rc::downgrade(list)),
+        }
+    }
+
+    // 这个对象直接引用的其他对象，借不出来时返回 None
+    fn children(&self) -> Option<Vec<usize>> {
+        let mut children = Vec::new();
+        match self {
+            Node::Env(env) => {
+                let env = env.try_borrow().ok()?;
+                if let Some(enclosing) = &env.enclosing {
+                    children.push(id(enclosing));
+                }
+                env.values.values().for_each(|value| object_children(value, &mut children));
+                children.extend(env.slots.iter().map(id));
+            }
+            Node::Cell(cell) => object_children(&*cell.try_borrow().ok()?, &mut children),
+            Node::Instance(instance) => {
+                let instance = instance.try_borrow().ok()?;
+                children.push(id(instance.class()));
+                instance.fields().iter().flatten().for_each(|value| object_children(value, &mut children));
+            }
+            Node::Class(class) => {
+                if let Some(superclass) = &class.superclass {
+                    children.push(id(superclass));
+                }
+                children.extend(class.methods.values().map(|method| id(method.closure())));
+            }
+            Node::List(list) => list.iter().for_each(|value| object_children(value, &mut children)),
+        }
+        Some(children)
+    }
+
+    // 只清空可变的对象就够了：类和列表创建后不能修改，它们所在的环一定经过某个环境、cell 或实例
+    fn clear(&self) {
+        match self {
+            Node::Env(env) => {
+                if let Ok(mut env) = env.try_borrow_mut() {
+                    env.enclosing = None;
+                    env.values.clear();
+                    env.slots.clear();
+                }
+            }
+            Node::Cell(cell) => {
+                if let Ok(mut value) = cell.try_borrow_mut() {
+                    *value = Object::None;
+                }
+            }
+            Node::Instance(instance) => {
+                if let Ok(mut instance) = instance.try_borrow_mut() {
+                    instance.clear_fields();
+                }
+            }
+            Node::Class(_) | Node::List(_) => (),
+        }
+    }
+}
+
+fn id<T>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+fn object_children(object: &Object, children: &mut Vec<usize>) {
+    match object {
+        Object::Class(class) => children.push(id(class)),
+        Object::Instance(instance) => children.push(id(instance)),
+        Object::List(list) => children.push(id(list)),
+        Object::Function(function) => children.push(id(function.closure())),
+        _ => (),
+    }
+}
+
+fn track(tracked: Tracked) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked.push(tracked);
+        heap.allocated += 1;
+    });
+}
+
+pub fn track_env(env: &Rc<RefCell<Environment>>) {
+    track(Tracked::Env(Rc::downgrade(env)));
+}
+
+pub fn track_cell(cell: &Rc<RefCell<Object>>) {
+    track(Tracked::Cell(Rc::downgrade(cell)));
+}
+
+pub fn track_instance(instance: &Rc<RefCell<LoxInstance>>) {
+    track(Tracked::Instance(Rc::downgrade(instance)));
+}
+
+pub fn track_class(class: &Rc<LoxClass>) {
+    track(Tracked::Class(Rc::downgrade(class)));
+}
+
+pub fn track_list(list: &Rc<Vec<Object>>) {
+    track(Tracked::List(Rc::downgrade(list)));
+}
+
+// 解释器在进入代码块时调用，新登记的对象比上次收集后活下来的还多时才收集，均摊下来每次分配的开销是常数
+pub fn collect_if_needed() {
+    let needed = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.allocated >= heap.threshold
+    });
+    if needed {
+        collect();
+    }
+}
+
+// 立即收集一次，返回回收掉的对象个数
+pub fn collect() -> usize {
+    // 拿出来再处理，释放对象的时候不会碰到 HEAP 的借用
+    let tracked = HEAP.with(|heap| std::mem::take(&mut heap.borrow_mut().tracked));
+    let nodes: Vec<Node> = tracked.iter().filter_map(Tracked::upgrade).collect();
+    drop(tracked);
+
+    let index: HashMap<usize, usize> = nodes.iter().enumerate().map(|(i, node)| (node.id(), i)).collect();
+    let children: Vec<Option<Vec<usize>>> = nodes.iter()
+        .map(|node| node.children().map(|ids| ids.into_iter().filter_map(|id| index.get(&id).copied()).collect()))
+        .collect();
+
+    let mut internal = vec![0; nodes.len()];
+    for &child in children.iter().flatten().flatten() {
+        internal[child] += 1;
+    }
+
+    let mut reachable = vec![false; nodes.len()];
+    let mut worklist: Vec<usize> = (0..nodes.len())
+        .filter(|&i| children[i].is_none() || nodes[i].strong_count() > internal[i])
+        .collect();
+    while let Some(i) = worklist.pop() {
+        if reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        if let Some(children) = &children[i] {
+            worklist.extend(children.iter().filter(|&&child| !reachable[child]));
+        }
+    }
+
+    let mut freed = 0;
+    for (node, _) in nodes.iter().zip(&reachable).filter(|(_, reachable)| !**reachable) {
+        node.clear();
+        freed += 1;
+    }
+
+    let survivors: Vec<Tracked> = nodes.iter().zip(&reachable).filter(|(_, reachable)| **reachable).map(|(node, _)| node.downgrade()).collect();
+    drop(nodes);    // 垃圾在这里真正释放
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.threshold = MIN_THRESHOLD.max(survivors.len());
+        heap.allocated = 0;
+        // 释放过程中不会登记新对象，不过还是保留一下以防万一
+        let allocated_during_collect = std::mem::replace(&mut heap.tracked, survivors);
+        heap.tracked.extend(allocated_during_collect);
+    });
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+    use crate::module::resolved;
+
+    // 运行代码，脚本里 watch(x) 会记下 x 背后那个堆对象的 Weak，用来检查它最后有没有被释放
+    fn run_watched(code: &str) -> (Interpreter, Rc<RefCell<Vec<Tracked>>>) {
+        let statements = resolved(code);
+
+        let watched = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let watched_in_native = Rc::clone(&watched);
+        interpreter.register_native("watch", 1, move |_, arguments| {
+            let tracked = match &arguments[0] {
+                Object::Instance(instance) => Tracked::Instance(Rc::downgrade(instance)),
+                Object::Function(function) => Tracked::Env(Rc::downgrade(function.closure())),
+                Object::List(list) => Tracked::List(Rc::downgrade(list)),
+                _ => panic!("Can only watch heap objects."),
+            };
+            watched_in_native.borrow_mut().push(tracked);
+            Ok(Object::None)
+        });
+        interpreter.interpret(&statements);
+        assert!(!interpreter.had_runtime_error);
+        (interpreter, watched)
+    }
+
+    fn alive(watched: &RefCell<Vec<Tracked>>) -> usize {
+        watched.borrow().iter().filter(|tracked| tracked.upgrade().is_some()).count()
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let code = r#"
+class Node {
+    init() { this.self = this.method; }
+    method() {}
+}
+fun pair() {
+    var a = Node();
+    var b = Node();
+    a.other = b;
+    b.other = a;
+    watch(a);
+    watch(b);
+}
+fun counter() {
+    var count = 0;
+    fun next() { count = count + 1; return next; }
+    watch(next);
+}
+fun gather(...items) {
+    var holder = Node();
+    holder.items = items;
+    items = nil;
+    watch(holder);
+    watch(holder.items);
+}
+for (var i = 0; i < 10; i = i + 1) {
+    watch(Node());
+    pair();
+    counter();
+    gather(i, i);
+}
+        "#;
+        let (_interpreter, watched) = run_watched(code);
+        assert_eq!(watched.borrow().len(), 60);
+        assert!(alive(&watched) > 0);
+        assert!(collect() > 0);
+        assert_eq!(alive(&watched), 0);
+    }
+
+    #[test]
+    fn test_keep_reachable() {
+        let code = r#"
+class Node {
+    init() { this.self = this.method; }
+    method() { return "still here"; }
+}
+var kept = Node();
+watch(kept);
+fun make() {
+    var local = 1;
+    fun get() { return local; }
+    return get;
+}
+var getter = make();
+watch(getter);
+        "#;
+        let (mut interpreter, watched) = run_watched(code);
+        collect();
+        assert_eq!(alive(&watched), 2);
+
+        interpreter.interpret(&resolved("var result = kept.self() + str(getter());"));
+        assert_eq!(interpreter.globals.borrow().values.get("result"), Some(&Object::String("still here1".to_string())));
+    }
+}
@@ -1,492 +1,2368 @@
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::cell::{RefCell, Ref, RefMut};
-
-
-use crate::environment::Environment;
-use crate::lox_callable::LoxCallable;
-use crate::lox_class::LoxClass;
-use crate::lox_function::LoxFunction;
-use crate::resolvable::Resolvable;
-use crate::token::Token;
-use crate::expr::{AssignExpr, BinaryExpr, CallExpr, CommaExpr, ConditionalExpr, Expr, GetExpr, GroupingExpr, LiteralExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr, UnaryExpr, VariableExpr};
-use crate::err::LoxErr;
-use crate::stmt::{ClassDeclaration, FunctionDeclaration, Stmt};
-use crate::object::{NativeFunction, Object};
-use crate::token_type::TokenType;
-
-
-
-pub struct Interpreter{
-    pub had_runtime_error: bool,
-    environment: Rc<RefCell<Environment>>,
-    pub globals: Rc<RefCell<Environment>>,
-}
-
-
-impl Interpreter {
-    pub fn new() -> Interpreter {
-        let env = Environment::new();
-        env.borrow_mut().define("clock", Object::NativeFunction(NativeFunction{ name: "clock".to_string() }));
-        Interpreter {
-            had_runtime_error: false,
-            environment: Rc::clone(&env),
-            globals: env,
-        }
-    }
-
-    fn get_env(&self) -> Ref<Environment> {
-        self.environment.borrow()
-    }
-
-    fn get_env_mut(&self) -> RefMut<Environment> {
-        self.environment.borrow_mut()
-    }
-
-    fn get_globals(&self) -> Ref<Environment> {
-        self.globals.borrow()
-    }
-
-    fn get_globals_mut(&self) -> RefMut<Environment> {
-        self.globals.borrow_mut()
-    }
-
-    pub fn interpret(&mut self, statements: &Vec<Stmt>) {
-        for statement in statements {
-            if let Err(lox_err) = self.execute(statement) {
-                eprintln!("{}", lox_err);
-                self.had_runtime_error = true;
-            }
-        }
-    }
-
-    fn evaluate(&mut self, expr: &Expr) -> Result<Object, LoxErr> {
-        match expr {
-            Expr::Assign(assign_expr) => self.visit_assign_expr(assign_expr),
-            Expr::Binary(binary_expr) => self.visit_binary_expr(binary_expr),
-            Expr::Call(call_expr) => self.visit_call_expr(call_expr),
-            Expr::Comma(comma_expr) => self.visit_comma_expr(comma_expr),
-            Expr::Conditional(conditional_expr) => self.visit_conditional_expr(conditional_expr),
-            Expr::Get(get_expr) => self.visit_get_expr(get_expr),
-            Expr::Grouping(grouping_expr) => self.visit_grouping_expr(grouping_expr),
-            Expr::Literal(literal_expr) => self.visit_literal_expr(literal_expr),
-            Expr::Logical(logical_expr) => self.visit_logical_expr(logical_expr),
-            Expr::Set(set_expr) => self.visit_set_expr(set_expr),
-            Expr::Super(super_expr) => self.visit_super_expr(super_expr),
-            Expr::This(this_expr) => self.visit_this_expr(this_expr),
-            Expr::Unary(unary_expr) => self.visit_unary_expr(unary_expr),
-            Expr::Variable(variable_expr) => self.visit_variable_expr(variable_expr),
-        }
-    }
-
-    fn execute(&mut self, stmt: &Stmt) -> Result<(), LoxErr>{
-        match stmt {
-            Stmt::Block { statements: stmts } => self.visit_block_stmt(stmts)?,
-            Stmt::Break { .. } => self.visit_break_stmt()?,
-            Stmt::ClassDeclaration { class_declaration } => self.visit_class_declaration_stmt(class_declaration)?,
-            Stmt::Expression{ expression: expr} => self.visit_expression_stmt(expr)?,
-            Stmt::If { condition, then_branch, else_branch } => self.visit_if_stmt(condition, then_branch, else_branch)?,
-            Stmt::While { condition, body } => self.visit_while_stmt(condition, body)?,
-            Stmt::Print{ expression: expr} => self.visit_print_stmt(expr)?,
-            Stmt::Var { name, initializer } => self.visit_var_stmt(name, initializer)?,
-            Stmt::FunctionDeclaration { function_declaration } => self.visit_function_declaration_stmt(function_declaration)?,
-            Stmt::Return { keyword: _, value } => self.visit_return_stmt(value)?,
-        };
-        Ok(())
-    }
-
-    pub fn execute_block(&mut self, stmts: &Vec<Stmt>, environment: Rc<RefCell<Environment>>) -> Result<(), LoxErr> {
-        let previous = Rc::clone(&self.environment);
-        self.environment = environment;
-        // let mut f = || {
-        //     for stmt in stmts {
-        //         self.execute(stmt)?;
-        //     }
-        //     Result::<(), LoxErr>::Ok(())
-        // };
-        // let ret = f();
-        let ret = stmts.iter().try_for_each(|stmt| self.execute(stmt)); // 这一行代替上面那么多真是妙啊
-
-        self.environment = previous;
-        ret
-    }
-
-    fn visit_block_stmt(&mut self, stmts: &Vec<Stmt>) -> Result<(), LoxErr> {
-        let block_env = Environment::new();
-        block_env.borrow_mut().set_enclosing(Rc::clone(&self.environment));
-        self.execute_block(stmts, block_env)
-    }
-
-    fn visit_break_stmt(&mut self) -> Result<(), LoxErr> {
-        Err(LoxErr::RuntimeBreak)
-    }
-
-    fn visit_class_declaration_stmt(&mut self, class_declaration: &ClassDeclaration) -> Result<(), LoxErr> {
-
-        let mut superclass = None;
-        let mut superclass_obj = Object::default();
-        if let Some(exist_superclass) = &class_declaration.superclass {
-            superclass_obj = self.visit_variable_expr(exist_superclass)?;
-            let Object::Class(lox_class) = superclass_obj.clone() else {
-                return Err(LoxErr::Runtime { line: exist_superclass.name.line, message: "Superclass must be a class.".to_string() });
-            };
-            superclass = Some(Box::new(lox_class));
-        }
-
-        self.get_env_mut().define(&class_declaration.name.lexeme, Object::None);
-
-        if class_declaration.superclass.is_some() {
-            let env = Environment::new();
-            env.borrow_mut().set_enclosing(Rc::clone(&self.environment));
-            self.environment = env;
-            self.get_env_mut().define("super", superclass_obj);
-        }
-
-        let mut methods = HashMap::new();
-        for method_decl in &class_declaration.methods {
-            let function = LoxFunction::new(method_decl, Rc::clone(&self.environment), &method_decl.name.lexeme == "init");
-            methods.insert(method_decl.name.lexeme.clone(), function);
-        }
-        let class = LoxClass::new(class_declaration.name.lexeme.clone(), superclass, methods);
-        
-        if class_declaration.superclass.is_some() {
-            let o_env = &self.get_env_mut().enclosing.clone().unwrap();
-            self.environment = Rc::clone(&o_env);
-        }
-
-        self.get_env_mut().assign(&class_declaration.name, Object::Class(class))?;
-        Ok(())
-    }
-
-    fn visit_expression_stmt(&mut self, expr: &Expr) -> Result<(), LoxErr> {
-        self.evaluate(expr)?;
-        Ok(())
-    }
-
-    fn visit_function_declaration_stmt(&mut self, function_declaration: &FunctionDeclaration) -> Result<(), LoxErr> {
-        let function = LoxFunction::new(function_declaration, Rc::clone(&self.environment), false);
-        self.get_env_mut().define(&function_declaration.name.lexeme, Object::Function(function));
-        Ok(())
-    }
-
-    fn visit_if_stmt(&mut self, condition: &Expr, then_branch: &Box<Stmt>, else_branch: &Option<Box<Stmt>>) -> Result<(), LoxErr> {
-        if Interpreter::is_truthy(&self.evaluate(condition)?) {
-            self.execute(then_branch)?;
-        } else if let Some(exist_else_branch) = else_branch {
-            self.execute(exist_else_branch)?;
-        }
-        Ok(())
-    }
-
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Box<Stmt>) -> Result<(), LoxErr> {
-        while Interpreter::is_truthy(&self.evaluate(condition)?) {
-            match self.execute(body) {
-                Ok(_) => (),
-                Err(LoxErr::RuntimeBreak) => break,
-                Err(other_lox_err) => return Err(other_lox_err),
-            }
-        }
-        Ok(())
-    }
-
-    fn visit_print_stmt(&mut self, expr: &Expr) -> Result<(), LoxErr> {
-        let tl: Object = self.evaluate(expr)?;
-        println!("{}", tl);
-        Ok(())
-    }
-
-    fn visit_return_stmt(&mut self, value: &Option<Expr>) -> Result<(), LoxErr> {
-        let ret_value = if let Some(expr) = value {
-            self.evaluate(expr)?
-        } else {
-            Object::None
-        };
-        Err(LoxErr::RuntimeReturn { ret_value })
-    }
-
-    fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>) -> Result<(), LoxErr> {
-        let value = if initializer.is_some() {
-            self.evaluate(initializer.as_ref().unwrap())?
-        } else {
-            Object::None
-        };
-        self.get_env_mut().define(&name.lexeme, value);
-        Ok(())
-    }
-
-    fn visit_assign_expr(&mut self, assign_expr: &AssignExpr) -> Result<Object, LoxErr> {
-        let value = self.evaluate(&assign_expr.value)?;
-
-        if let Some(distance) = assign_expr.get_distance() {
-            self.get_env_mut().assign_at(distance, assign_expr.name(), value.clone());
-        } else {
-            self.get_globals_mut().assign(assign_expr.name(), value.clone())?;
-        }
-
-        Ok(value)   // 赋值表达式可以嵌套在其它表达式里，比如：print a = 2;
-    }
-
-    fn visit_literal_expr(&self, literal_expr: &LiteralExpr) -> Result<Object, LoxErr> {
-        Ok(literal_expr.literal.clone())
-    }
-
-    fn visit_grouping_expr(&mut self, grouping_expr: &GroupingExpr) -> Result<Object, LoxErr> {
-        self.evaluate(&grouping_expr.expression)
-    }
-
-    fn visit_unary_expr(&mut self, unary_expr: &UnaryExpr) -> Result<Object, LoxErr> {
-        let right = self.evaluate(&unary_expr.right)?;
-        match unary_expr.operator.token_type {
-            TokenType::Bang => {
-                return Ok(Object::Bool(!Interpreter::is_truthy(&right)));
-            },
-            TokenType::Minus => {
-                if let Object::Number(v) = right {
-                    Ok(Object::Number(-v))
-                } else {
-                    Interpreter::number_err(unary_expr.operator.line)
-                }
-            },
-            _ => unreachable!("Impossible operator for unary expr."),
-        }
-    }
-
-    fn visit_call_expr(&mut self, call_expr: &CallExpr) -> Result<Object, LoxErr> {
-        let callee = self.evaluate(&*(*call_expr).callee)?;
-        let mut arguments = Vec::new();
-        for arg in &call_expr.arguments {
-            arguments.push(self.evaluate(arg)?);
-        }
-        
-        match callee {
-            Object::Function(mut function) => {
-                if arguments.len() != function.arity() {
-                    return Err(LoxErr::Runtime { line: call_expr.paren.line, message: format!("Expected {} arguments but got {}.", function.arity(), arguments.len()) });
-                }
-                return function.call(self, arguments);
-            }
-            Object::NativeFunction(mut native_function) => {
-                return native_function.call(self, arguments);
-            }
-            Object::Class(mut class) => {
-                return class.call(self, arguments);
-            }
-            _ => {
-                return Err(LoxErr::Runtime { line: call_expr.paren.line, message: "Can only call functions and classes.".to_string() });
-            }
-        }
-    }
-
-    fn visit_comma_expr(&mut self, comma_expr: &CommaExpr) -> Result<Object, LoxErr> {
-        let mut res = Object::default();
-        for expr in &comma_expr.exprs {
-            res = self.evaluate(expr)?;
-        }
-        Ok(res)
-    }
-
-    fn visit_conditional_expr(&mut self, conditional_expr: &ConditionalExpr) -> Result<Object, LoxErr> {
-        if Interpreter::is_truthy(&self.evaluate(&*(*conditional_expr).condition)?) {
-            self.evaluate(&*(*conditional_expr).then_branch)
-        } else {
-            self.evaluate(&*(*conditional_expr).else_branch)
-        }
-    }
-
-    fn visit_get_expr(&mut self, get_expr: &GetExpr) -> Result<Object, LoxErr> {
-        let object = self.evaluate(&*(*get_expr).object)?;
-        if let Object::Instance(instance) = object {
-            return instance.borrow().get(&get_expr.name, &instance);
-        }
-        Err(LoxErr::Runtime { line: get_expr.name.line, message: "Only instances have properties.".to_string() })
-
-    }
-
-
-    // 逻辑运算符并不承诺会真正返回`true`或`false`，而只是保证它将返回一个具有适当真实性的值。
-    fn visit_logical_expr(&mut self, logical_expr: &LogicalExpr) -> Result<Object, LoxErr> {
-        let left = self.evaluate(&logical_expr.left)?;
-        if logical_expr.operator.token_type == TokenType::Or {
-            if Interpreter::is_truthy(&left) {
-                return Ok(left);
-            } // else right
-        } else {
-            // And
-            if !Interpreter::is_truthy(&left) {
-                return Ok(left);
-            } // else right
-        }
-        self.evaluate(&logical_expr.right)
-    }
-
-    fn visit_set_expr(&mut self, set_expr: &SetExpr) -> Result<Object, LoxErr> {
-        let object = self.evaluate(&set_expr.object)?;
-        match object {
-            Object::Instance(instance) => {
-                let value = self.evaluate(&set_expr.value)?;
-                instance.borrow_mut().set(&set_expr.name, value.clone());
-                Ok(value)
-            }
-            _ => Err(LoxErr::Runtime { line: set_expr.name.line, message: "Only instances have fields.".to_string() }),
-        }
-    }
-
-    fn visit_super_expr(&mut self, super_expr: &SuperExpr) -> Result<Object, LoxErr> {
-        let distance = super_expr.get_distance().unwrap();
-        let superclass = self.get_env().get_at(distance, "super");
-
-        let object = self.get_env_mut().get_at(distance - 1, "this");   // 从某 instance . get 到 method 时，会创建一个绑定 this 的 closure
-        if let Object::Class(lox_class) = superclass {
-            let method = lox_class.find_method(&super_expr.method.lexeme);
-            if method.is_none() {
-                return Err(LoxErr::Runtime { line: super_expr.method.line, message: format!("Undefined property '{}'.", super_expr.method.lexeme) });
-            }
-            if let Object::Instance(instance) = object {
-                return Ok(Object::Function(method.unwrap().bind(instance)));
-            } else {
-                unreachable!("this is not instance, WTF?")
-            }
-        } else {
-            unreachable!("superclass is not class, visit_class_declaration_stmt bug?");
-        }
-    }
-
-    fn visit_this_expr(&mut self, this_expr: &ThisExpr) -> Result<Object, LoxErr> {
-        self.look_up_variable(this_expr)    // 也就是说，这个 this 最终会变成 Instance 本身 
-    }
-
-
-    fn visit_binary_expr(&mut self, binary_expr: &BinaryExpr) -> Result<Object, LoxErr> {
-        let left = self.evaluate(&binary_expr.left)?;
-        let right = self.evaluate(&binary_expr.right)?;
-        match binary_expr.operator.token_type {
-            TokenType::EqualEqual => Ok(Object::Bool(left == right)),
-            TokenType::BangEqual => Ok(Object::Bool(left != right)),
-            TokenType::Greater => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    Ok(Object::Bool(left_number > right_number))
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::GreaterEqual => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    Ok(Object::Bool(left_number >= right_number))
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::Less => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    Ok(Object::Bool(left_number < right_number))
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::LessEqual => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    Ok(Object::Bool(left_number <= right_number))
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::Minus => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    Ok(Object::Number(left_number - right_number))
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::Slash => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    if right_number != 0.0 {
-                        Ok(Object::Number(left_number / right_number))
-                    } else {
-                        Err(LoxErr::Runtime { line: binary_expr.operator.line, message: format!("Attempt to divide `{}` by zero.", left_number) })
-                    }
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::Star => {
-                if let (Object::Number(left_number), Object::Number(right_number)) = (left, right) {
-                    Ok(Object::Number(left_number * right_number))
-                } else {
-                    Interpreter::number_err(binary_expr.operator.line)
-                }
-            }
-            TokenType::Plus => {
-                match (&left, &right) {
-                    (Object::Number(left_number), Object::Number(right_number)) => Ok(Object::Number(left_number + right_number)),
-                    (Object::String(left_string), Object::String(right_string)) => Ok(Object::String(format!("{}{}", left_string, right_string))),
-                    // 支持数字和字符串相加
-                    (Object::Number(left_number), Object::String(right_string)) => Ok(Object::String(format!("{}{}", left_number, right_string))),
-                    (Object::String(left_string), Object::Number(right_number)) => Ok(Object::String(format!("{}{}", left_string, right_number))),
-                    _ => Err(LoxErr::Runtime { line: binary_expr.operator.line, message: "Operands must be two numbers or two strings.".to_string() })
-                }
-            }
-            
-            _ => unreachable!("Impossible operator for binary expr."),
-        }
-        
-    }
-
-    fn visit_variable_expr(&self, variable_expr: &VariableExpr) -> Result<Object, LoxErr> {
-        self.look_up_variable(variable_expr)
-    }
-
-    fn look_up_variable(&self, val: &impl Resolvable) -> Result<Object, LoxErr> {
-        if let Some(distance) = val.get_distance() {
-            Ok(self.get_env().get_at(distance, &val.name().lexeme))
-        } else {
-            self.get_globals().get(val.name())
-        }
-    }
-
-    fn is_truthy(literal: &Object) -> bool {
-        match literal {
-            Object::None => false,
-            Object::Bool(v) => *v,
-            _ => true,
-        }
-    }
-
-    fn number_err(line: usize) -> Result<Object, LoxErr> {
-        Err(LoxErr::Runtime { line: line, message: "Operand must be a number.".to_string() })
-    }
-}
-
-
-
-#[cfg(test)]
-mod tests {
-    use crate::lox::Lox;
-
-    #[test]
-    fn test_block() {
-        let mut lox = Lox::new();
-        let code = r#"var a = "global a";
-var b = "global b";
-var c = "global c";
-{
-var a = "outer a";
-var b = "outer b";
-{
-    var a = "inner a";
-    print a;
-    print b;
-    print c;
-}
-print a;
-print b;
-print c;
-}
-print a;
-print b;
-print c;
-        "#;
-        // let code = r#"print "code";"#;
-
-        lox.test_code(code);
-    }
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::{RefCell, Ref, RefMut};
+
+
+use crate::diagnostic::{Diagnostic, ErrorReporter, StderrReporter};
+use crate::environment::Environment;
+use crate::gc;
+use crate::lox_callable::LoxCallable;
+use crate::lox_class::LoxClass;
+use crate::lox_function::LoxFunction;
+use crate::module::{self, Import, LoxModule, Modules};
+use crate::parser::MAX_EXPRESSION_DEPTH;
+use crate::resolvable::Resolvable;
+use crate::snapshot::Snapshot;
+use crate::token::Token;
+use crate::expr::{AssignExpr, BinaryExpr, CallExpr, CommaExpr, ConditionalExpr, Expr, GetExpr, GroupingExpr, LiteralExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr, UnaryExpr, VariableExpr};
+use crate::err::LoxErr;
+use crate::stack;
+use crate::stmt::{ClassDeclaration, FunctionDeclaration, Stmt, SwitchCase};
+use crate::native::{NativeFunction, NativeRegistry};
+use crate::object::{NumberPair, Object};
+use crate::token_type::TokenType;
+
+
+
+pub struct Interpreter{
+    pub had_runtime_error: bool,
+    pub exit_code: Option<i32>, // 脚本调用了 exit，后面的语句不再执行
+    reporter: Box<dyn ErrorReporter>,  // 错误和警告交给它，默认打印到标准错误
+    diagnostics: Vec<Diagnostic>,   // 报告过的错误和警告
+    captured_errors: Option<Vec<LoxErr>>,   // Lox::run、run_capture 用，错误收起来交给调用方
+    report_captured: bool,  // 收起来的错误是不是也照常报告
+    output: Box<dyn Write>,
+    source: Option<Rc<str>>,    // 正在运行的脚本的源代码，报错时显示出错的那一行
+    pub max_call_depth: usize,  // 超过这个调用深度报 Stack overflow.。求值时栈不够了会换一段新的栈，不会等 Rust 的栈溢出把整个进程带走
+    call_depth: usize,
+    pub max_expression_depth: usize,    // 解析导入的模块时用，和主脚本一样
+    pub catch_runtime_errors: bool, // catch 能不能接住运行时错误，默认只接 throw 抛出的值
+    fuel: Option<u64>,  // 还能执行多少步，每个语句和表达式算一步。None 表示不限
+    deadline: Option<Instant>,
+    steps_since_clock: u32,   // 每隔一段才看一次时间，Instant::now 比执行一步还贵
+    interrupted: Arc<AtomicBool>,   // 别的线程（比如 Ctrl-C 的信号处理）设置，循环和调用时检查
+    environment: Rc<RefCell<Environment>>,
+    pub globals: Rc<RefCell<Environment>>,   // 当前模块的全局环境
+    module: Rc<LoxModule>,  // 正在执行的代码所在的模块，一开始是主脚本
+    error_module: Option<Rc<LoxModule>>,    // 正在往外传的运行时错误出自哪个模块，出了模块边界才记下。没有的话就是当前模块
+    env_pool: Vec<Rc<RefCell<Environment>>>,    // 用完的块环境和函数调用环境，下次复用，少分配几次
+    modules: Modules,
+    trace: Trace,
+    trace_output: Box<dyn Write>,   // 跟踪信息默认打印到标准错误，不和 print 的输出混在一起
+}
+
+// --trace 打印执行的每条语句，--trace=vars 再加上变量的定义和赋值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trace {
+    Off,
+    Statements,
+    Variables,
+}
+
+const ENV_POOL_CAPACITY: usize = 64;
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+const CLOCK_CHECK_INTERVAL: u32 = 1024;
+const TRACE_WIDTH: usize = 60;  // 语句的 S 表达式超过这么多字符就截断
+const STACK_OVERFLOW: &str = "Stack overflow.";
+const ASSERTION_FAILED: &str = "Assertion failed";
+
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let env = Environment::new();
+        for native_function in NativeRegistry::standard().natives() {
+            env.borrow_mut().define(&native_function.name, Object::NativeFunction(native_function.clone()));
+        }
+        Interpreter {
+            had_runtime_error: false,
+            exit_code: None,
+            reporter: Box::new(StderrReporter::default()),
+            diagnostics: Vec::new(),
+            captured_errors: None,
+            report_captured: false,
+            output: Box::new(io::stdout()),
+            source: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            call_depth: 0,
+            max_expression_depth: MAX_EXPRESSION_DEPTH,
+            catch_runtime_errors: false,
+            fuel: None,
+            deadline: None,
+            steps_since_clock: 0,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            environment: Rc::clone(&env),
+            module: Rc::new(LoxModule::new("main", Rc::clone(&env))),
+            globals: env,
+            error_module: None,
+            env_pool: Vec::new(),
+            modules: Modules::new(),
+            trace: Trace::Off,
+            trace_output: Box::new(io::stderr()),
+        }
+    }
+
+    // 运行脚本文件前调用，import 的相对路径从脚本所在目录开始找
+    pub fn set_script_path(&mut self, path: &Path) {
+        self.modules.set_main_script(path, Rc::clone(&self.module));
+    }
+
+    // 给嵌入 rilox 的程序用：注入一个全局的原生函数，脚本里可以直接调用。同名的全局变量会被覆盖
+    // 函数返回的 LoxErr::Runtime 里的行号会被换成调用处的行号
+    pub fn register_native(&mut self, name: &str, arity: usize, function: impl Fn(&mut Interpreter, Vec<Object>) -> Result<Object, LoxErr> + 'static) {
+        let native_function = NativeFunction::new_host(name, arity, function);
+        self.globals.borrow_mut().define(&Rc::clone(&native_function.name), Object::NativeFunction(native_function));
+    }
+
+    // 给嵌入的程序用：运行脚本前放进配置之类的值，运行后读出结果。同名的全局变量会被覆盖，常量也一样
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.get_globals_mut().define(&Rc::from(name), value);
+    }
+
+    // 没有这个全局变量的话返回 None
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.get_globals().values.get(name).cloned()
+    }
+
+    // 全局变量里纯数据的部分，见 Snapshot
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(&self.get_globals())
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        snapshot.restore(&mut self.get_globals_mut());
+    }
+
+    fn get_env(&self) -> Ref<'_, Environment> {
+        self.environment.borrow()
+    }
+
+    fn get_env_mut(&self) -> RefMut<'_, Environment> {
+        self.environment.borrow_mut()
+    }
+
+    fn get_globals(&self) -> Ref<'_, Environment> {
+        self.globals.borrow()
+    }
+
+    fn get_globals_mut(&self) -> RefMut<'_, Environment> {
+        self.globals.borrow_mut()
+    }
+
+    pub fn interpret(&mut self, statements: &Vec<Stmt>) {
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(()) => (),
+                Err(LoxErr::Exit { code }) => {
+                    self.exit_code = Some(code);
+                    return;
+                }
+                Err(lox_err @ LoxErr::Aborted { .. }) => {
+                    self.report(lox_err);
+                    self.had_runtime_error = true;
+                    return;
+                }
+                Err(lox_err) => {
+                    self.report(lox_err);
+                    self.had_runtime_error = true;
+                }
+            }
+        }
+    }
+
+    // REPL 里对单个表达式求值，返回结果给调用方显示。出错时和 interpret 一样报告
+    pub fn interpret_expression(&mut self, expr: &Expr) -> Option<Object> {
+        match self.evaluate(expr) {
+            Ok(value) => Some(value),
+            Err(LoxErr::Exit { code }) => {
+                self.exit_code = Some(code);
+                None
+            }
+            Err(lox_err) => {
+                self.report(lox_err);
+                self.had_runtime_error = true;
+                None
+            }
+        }
+    }
+
+    pub fn set_reporter(&mut self, reporter: Box<dyn ErrorReporter>) {
+        self.reporter = reporter;
+    }
+
+    pub fn set_source(&mut self, source: &str) {
+        self.source = Some(Rc::from(source));
+    }
+
+    // 交给 reporter，同时收集起来，嵌入的程序可以用 take_diagnostics 取走
+    pub fn report_diagnostic(&mut self, diagnostic: Diagnostic) {
+        let module = Rc::clone(&self.module);
+        self.report_diagnostic_in(diagnostic, &module);
+    }
+
+    // 导入的模块里的错误带上模块的文件路径，显示模块自己的源代码
+    fn report_diagnostic_in(&mut self, mut diagnostic: Diagnostic, module: &LoxModule) {
+        match &module.file {
+            Some(file) => diagnostic = diagnostic.in_file(&file.path).with_source(&file.source),
+            None => if let Some(source) = &self.source {
+                diagnostic = diagnostic.with_source(source);
+            },
+        }
+        self.reporter.report(&diagnostic);
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn report(&mut self, lox_err: LoxErr) {
+        let module = self.error_module.take().unwrap_or_else(|| Rc::clone(&self.module));
+        self.report_in(lox_err, &module);
+    }
+
+    fn report_in(&mut self, lox_err: LoxErr, module: &LoxModule) {
+        if self.captured_errors.is_none() || self.report_captured {
+            for diagnostic in Diagnostic::from_err(&lox_err) {
+                self.report_diagnostic_in(diagnostic, module);
+            }
+        }
+        if let Some(errors) = &mut self.captured_errors {
+            errors.push(lox_err);
+        }
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    // 之后报告的错误攒起来等 take_captured_errors 取走。report 为 false 时不交给 reporter。警告不受影响
+    pub fn capture_errors(&mut self, report: bool) {
+        self.captured_errors = Some(Vec::new());
+        self.report_captured = report;
+    }
+
+    pub fn has_captured_errors(&self) -> bool {
+        self.captured_errors.as_ref().is_some_and(|errors| !errors.is_empty())
+    }
+
+    pub fn take_captured_errors(&mut self) -> Vec<LoxErr> {
+        self.captured_errors.take().unwrap_or_default()
+    }
+
+    // print 语句输出到哪里，默认是标准输出。返回原来的，方便用完后换回去
+    pub fn set_output(&mut self, output: Box<dyn Write>) -> Box<dyn Write> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    pub fn set_trace(&mut self, trace: Trace, output: Box<dyn Write>) {
+        self.trace = trace;
+        self.trace_output = output;
+    }
+
+    // 语句打印成一行：行号加上压成一行、截断过的 S 表达式
+    fn trace_stmt(&mut self, stmt: &Stmt) -> Result<(), LoxErr> {
+        let line = stmt.first_token().line;
+        let mut text = stmt.to_string().split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some((cut, _)) = text.char_indices().nth(TRACE_WIDTH) {
+            text.truncate(cut);
+            text.push_str("...");
+        }
+        writeln!(self.trace_output, "[trace] {:>4} | {}", line, text)?;
+        Ok(())
+    }
+
+    fn trace_variable(&mut self, action: &str, name: &str, value: &Object) -> Result<(), LoxErr> {
+        if self.trace == Trace::Variables {
+            writeln!(self.trace_output, "[trace]      |   {} {} = {}", action, name, value)?;
+        }
+        Ok(())
+    }
+
+    // 运行不可信的脚本时用：最多再执行 steps 步，用完后中止。None 取消限制
+    pub fn set_fuel(&mut self, steps: Option<u64>) {
+        self.fuel = steps;
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // 从现在开始最多再运行 limit 这么长时间。原生函数里花的时间（比如 sleep）中途打断不了，返回后才会中止
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.deadline = limit.map(|limit| Instant::now() + limit);
+        self.steps_since_clock = 0;
+    }
+
+    // 把返回的标志设成 true，正在运行的代码会在下一次循环或调用时中止
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+
+    // 中止的错误指向正在执行的代码。只有真的中止了才去找位置，平时不多花时间
+    fn aborted(token: Option<&Token>, message: &str) -> LoxErr {
+        LoxErr::Aborted {
+            line: token.map(|token| token.line),
+            column: token.map_or(0, |token| token.column),
+            span: token.map(|token| token.span),
+            message: message.to_string(),
+        }
+    }
+
+    fn check_interrupt(&self) -> Result<(), &'static str> {
+        if self.interrupted.load(Ordering::Relaxed) {
+            self.interrupted.store(false, Ordering::Relaxed);
+            return Err("Interrupted.");
+        }
+        Ok(())
+    }
+
+    fn consume_fuel(&mut self) -> Result<(), &'static str> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err("Execution step limit exceeded.");
+            }
+            *fuel -= 1;
+        }
+        if let Some(deadline) = self.deadline {
+            self.steps_since_clock += 1;
+            if self.steps_since_clock >= CLOCK_CHECK_INTERVAL {
+                self.steps_since_clock = 0;
+                if Instant::now() >= deadline {
+                    return Err("Execution time limit exceeded.");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Object, LoxErr> {
+        stack::guard(|| self.evaluate_expr(expr))
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> Result<Object, LoxErr> {
+        self.consume_fuel().map_err(|message| Interpreter::aborted(expr.first_token(), message))?;
+        match expr {
+            Expr::Assign(assign_expr) => self.visit_assign_expr(assign_expr),
+            Expr::Binary(binary_expr) => self.visit_binary_expr(binary_expr),
+            Expr::Call(call_expr) => self.visit_call_expr(call_expr),
+            Expr::Comma(comma_expr) => self.visit_comma_expr(comma_expr),
+            Expr::Conditional(conditional_expr) => self.visit_conditional_expr(conditional_expr),
+            Expr::Get(get_expr) => self.visit_get_expr(get_expr),
+            Expr::Grouping(grouping_expr) => self.visit_grouping_expr(grouping_expr),
+            Expr::Literal(literal_expr) => self.visit_literal_expr(literal_expr),
+            Expr::Logical(logical_expr) => self.visit_logical_expr(logical_expr),
+            Expr::Set(set_expr) => self.visit_set_expr(set_expr),
+            Expr::Super(super_expr) => self.visit_super_expr(super_expr),
+            Expr::This(this_expr) => self.visit_this_expr(this_expr),
+            Expr::Unary(unary_expr) => self.visit_unary_expr(unary_expr),
+            Expr::Variable(variable_expr) => self.visit_variable_expr(variable_expr),
+        }
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), LoxErr>{
+        self.consume_fuel().map_err(|message| Interpreter::aborted(Some(stmt.first_token()), message))?;
+        if self.trace != Trace::Off {
+            self.trace_stmt(stmt)?;
+        }
+        let result = match stmt {
+            Stmt::Assert { keyword, condition, message } => self.visit_assert_stmt(keyword, condition, message),
+            Stmt::Block { statements: stmts, .. } => self.visit_block_stmt(stmts),
+            Stmt::Break { .. } => self.visit_break_stmt(),
+            Stmt::Continue { .. } => self.visit_continue_stmt(),
+            Stmt::ClassDeclaration { class_declaration } => self.visit_class_declaration_stmt(class_declaration),
+            Stmt::DoWhile { keyword, body, condition } => self.visit_do_while_stmt(keyword, body, condition),
+            Stmt::Expression { expression: expr, .. } => self.visit_expression_stmt(expr),
+            Stmt::If { condition, then_branch, else_branch, .. } => self.visit_if_stmt(condition, then_branch, else_branch),
+            Stmt::While { keyword, condition, body, increment } => self.visit_while_stmt(keyword, condition, body, increment),
+            Stmt::FreshLoopVariable { variable } => self.visit_fresh_loop_variable_stmt(variable),
+            Stmt::Import { keyword, name, path } => self.visit_import_stmt(keyword, name, path),
+            Stmt::Print { expression: expr, .. } => self.visit_print_stmt(expr),
+            Stmt::Throw { keyword, value } => self.visit_throw_stmt(keyword, value),
+            Stmt::Try { try_block, name: _, catch_block, .. } => self.visit_try_stmt(try_block, catch_block),
+            Stmt::Var { name, initializer, is_const } => self.visit_var_stmt(name, initializer, *is_const),
+            Stmt::ForIn { name, iterable, body } => self.visit_for_in_stmt(name, iterable, body),
+            Stmt::For { .. } => unreachable!("Resolver desugars for loops into while loops."),
+            Stmt::FunctionDeclaration { function_declaration } => self.visit_function_declaration_stmt(function_declaration),
+            Stmt::Return { keyword: _, value } => self.visit_return_stmt(value),
+            Stmt::Switch { subject, cases, default, .. } => self.visit_switch_stmt(subject, cases, default),
+        };
+        // 中止时正在求值的表达式可能找不到 token（比如字面量），用外面这条语句的位置
+        result.map_err(|lox_err| match lox_err {
+            LoxErr::Aborted { line: None, message, .. } => Interpreter::aborted(Some(stmt.first_token()), &message),
+            other => other,
+        })
+    }
+
+    pub fn execute_block(&mut self, stmts: &[Stmt], environment: Rc<RefCell<Environment>>) -> Result<(), LoxErr> {
+        gc::collect_if_needed();
+        let previous = Rc::clone(&self.environment);
+        self.environment = environment;
+        // let mut f = || {
+        //     for stmt in stmts {
+        //         self.execute(stmt)?;
+        //     }
+        //     Result::<(), LoxErr>::Ok(())
+        // };
+        // let ret = f();
+        let ret = stmts.iter().try_for_each(|stmt| self.execute(stmt)); // 这一行代替上面那么多真是妙啊
+
+        let environment = std::mem::replace(&mut self.environment, previous);
+        self.recycle_env(environment);
+        ret
+    }
+
+    pub fn new_env(&mut self, enclosing: Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let env = self.env_pool.pop().unwrap_or_else(Environment::new);
+        env.borrow_mut().set_enclosing(enclosing);
+        env
+    }
+
+    // 只有没人再引用的环境才能回收，比如被 bind 出来的方法或者还没结束的类声明引用着的就不行。gc 登记的那个 Weak 不算
+    fn recycle_env(&mut self, env: Rc<RefCell<Environment>>) {
+        if Rc::strong_count(&env) != 1 || Rc::weak_count(&env) > 1 || self.env_pool.len() >= ENV_POOL_CAPACITY {
+            return;
+        }
+        {
+            let mut env_mut = env.borrow_mut();
+            env_mut.enclosing = None;
+            env_mut.slots.clear();  // 被闭包捕获的 cell 由闭包自己持有，这里只是清掉引用
+        }
+        self.env_pool.push(env);
+    }
+
+    fn visit_block_stmt(&mut self, stmts: &[Stmt]) -> Result<(), LoxErr> {
+        let block_env = self.new_env(Rc::clone(&self.environment));
+        self.execute_block(stmts, block_env)
+    }
+
+    fn visit_break_stmt(&mut self) -> Result<(), LoxErr> {
+        Err(LoxErr::RuntimeBreak)
+    }
+
+    fn visit_continue_stmt(&mut self) -> Result<(), LoxErr> {
+        Err(LoxErr::RuntimeContinue)
+    }
+
+    fn visit_class_declaration_stmt(&mut self, class_declaration: &ClassDeclaration) -> Result<(), LoxErr> {
+
+        let mut superclass = None;
+        let mut superclass_obj = Object::default();
+        if let Some(exist_superclass) = &class_declaration.superclass {
+            superclass_obj = self.visit_variable_expr(exist_superclass)?;
+            let Object::Class(lox_class) = superclass_obj.clone() else {
+                return Err(LoxErr::Runtime { line: exist_superclass.name.line, column: exist_superclass.name.column, span: Some(exist_superclass.name.span), message: "Superclass must be a class.".to_string() });
+            };
+            // 超类在声明时就确定了，LoxClass 创建后不可变，之后再重新定义同名类也不会改到这里，所以继承链不可能成环
+            superclass = Some(lox_class);
+        }
+
+        // 方法可能捕获类名，所以先占位
+        self.check_not_constant(&class_declaration.name)?;
+        self.get_env_mut().define(&class_declaration.name.lexeme, Object::None);
+
+        if class_declaration.superclass.is_some() {
+            let env = Environment::new();
+            env.borrow_mut().set_enclosing(Rc::clone(&self.environment));
+            self.environment = env;
+            self.get_env_mut().define_local(superclass_obj);
+        }
+
+        let mut methods = HashMap::new();
+        for method_decl in &class_declaration.methods {
+            let closure = self.get_env().capture(&method_decl.captures)?;
+            let function = LoxFunction::new(Rc::clone(method_decl), closure, Rc::clone(&self.module), &*method_decl.name.lexeme == "init");
+            methods.insert(method_decl.name.lexeme.clone(), function);
+        }
+        let class = Rc::new(LoxClass::new(class_declaration.name.lexeme.clone(), superclass, methods));
+        gc::track_class(&class);
+        
+        if class_declaration.superclass.is_some() {
+            let o_env = &self.get_env_mut().enclosing.clone().unwrap();
+            self.environment = Rc::clone(o_env);
+        }
+
+        self.trace_variable("define", &class_declaration.name.lexeme, &Object::Class(Rc::clone(&class)))?;
+        self.get_env_mut().assign_last_defined(&class_declaration.name.lexeme, Object::Class(class));
+        Ok(())
+    }
+
+    fn visit_do_while_stmt(&mut self, keyword: &Token, body: &Stmt, condition: &Expr) -> Result<(), LoxErr> {
+        loop {
+            self.check_interrupt().map_err(|message| Interpreter::aborted(Some(keyword), message))?;
+            match self.execute(body) {
+                Ok(_) | Err(LoxErr::RuntimeContinue) => (),
+                Err(LoxErr::RuntimeBreak) => break,
+                Err(other_lox_err) => return Err(other_lox_err),
+            }
+            if !Interpreter::is_truthy(&self.evaluate(condition)?) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&mut self, expr: &Expr) -> Result<(), LoxErr> {
+        self.evaluate(expr)?;
+        Ok(())
+    }
+
+    // 每次迭代新建一个只有循环变量的环境，循环体里创建的闭包各自捕获这一次的值
+    fn visit_for_in_stmt(&mut self, name: &Token, iterable: &Expr, body: &Stmt) -> Result<(), LoxErr> {
+        let iterable = self.evaluate(iterable)?;
+        for value in Interpreter::iterate(name.line, &iterable).map_err(|err| err.with_position(name))? {
+            self.check_interrupt().map_err(|message| Interpreter::aborted(Some(name), message))?;
+            let env = self.new_env(Rc::clone(&self.environment));
+            env.borrow_mut().define_local(value);
+            match self.execute_block(std::slice::from_ref(body), env) {
+                Ok(_) | Err(LoxErr::RuntimeContinue) => (),
+                Err(LoxErr::RuntimeBreak) => break,
+                Err(other_lox_err) => return Err(other_lox_err),
+            }
+        }
+        Ok(())
+    }
+
+    // for-in 的迭代协议：先把要遍历的值全部取出来，循环体里改动被遍历的对象不影响这次遍历
+    // 列表按元素遍历，字符串按字符遍历
+    pub fn iterate(line: usize, iterable: &Object) -> Result<Vec<Object>, LoxErr> {
+        match iterable {
+            Object::List(list) => Ok(list.to_vec()),
+            Object::String(s) => Ok(s.chars().map(|c| Object::String(c.to_string())).collect()),
+            other => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Can only iterate over lists and strings, got {}.", other.type_name()) }),
+        }
+    }
+
+    fn visit_function_declaration_stmt(&mut self, function_declaration: &Rc<FunctionDeclaration>) -> Result<(), LoxErr> {
+        // 函数体可能捕获函数自己（递归），所以先占位
+        self.check_not_constant(&function_declaration.name)?;
+        self.get_env_mut().define(&function_declaration.name.lexeme, Object::None);
+        let closure = self.get_env().capture(&function_declaration.captures)?;
+        let function = Object::Function(LoxFunction::new(Rc::clone(function_declaration), closure, Rc::clone(&self.module), false));
+        self.trace_variable("define", &function_declaration.name.lexeme, &function)?;
+        self.get_env_mut().assign_last_defined(&function_declaration.name.lexeme, function);
+        Ok(())
+    }
+
+    fn visit_if_stmt(&mut self, condition: &Expr, then_branch: &Stmt, else_branch: &Option<Box<Stmt>>) -> Result<(), LoxErr> {
+        if Interpreter::is_truthy(&self.evaluate(condition)?) {
+            self.execute(then_branch)?;
+        } else if let Some(exist_else_branch) = else_branch {
+            self.execute(exist_else_branch)?;
+        }
+        Ok(())
+    }
+
+    // case 的值按顺序求值，遇到第一个相等的就停下，后面的 case 值不会被求值
+    fn visit_switch_stmt(&mut self, subject: &Expr, cases: &[SwitchCase], default: &Option<Box<Stmt>>) -> Result<(), LoxErr> {
+        let subject = self.evaluate(subject)?;
+        for case in cases {
+            if Interpreter::is_equal(&subject, &self.evaluate(&case.value)?) {
+                return self.execute(&case.body);
+            }
+        }
+        if let Some(exist_default) = default {
+            self.execute(exist_default)?;
+        }
+        Ok(())
+    }
+
+    fn visit_assert_stmt(&mut self, keyword: &Token, condition: &Expr, message: &Option<Expr>) -> Result<(), LoxErr> {
+        if Interpreter::is_truthy(&self.evaluate(condition)?) {
+            return Ok(());
+        }
+        let message = match message {
+            Some(exist_message) => format!("{}: {}.", ASSERTION_FAILED, self.evaluate(exist_message)?),
+            None => format!("{}.", ASSERTION_FAILED),
+        };
+        Err(LoxErr::Runtime { line: keyword.line, column: keyword.column, span: Some(keyword.span), message: message })
+    }
+
+    fn visit_throw_stmt(&mut self, keyword: &Token, value: &Expr) -> Result<(), LoxErr> {
+        let value = self.evaluate(value)?;
+        Err(LoxErr::RuntimeThrow { line: keyword.line, column: keyword.column, span: keyword.span, value: value })
+    }
+
+    // return、break、continue 也是用错误实现的，它们不是异常，照样往外传
+    // 运行时错误要打开 catch_runtime_errors 才接得住，接住时 e 是错误信息。栈溢出和断言失败说明程序本身有毛病，总是往外传
+    fn visit_try_stmt(&mut self, try_block: &[Stmt], catch_block: &[Stmt]) -> Result<(), LoxErr> {
+        let try_env = self.new_env(Rc::clone(&self.environment));
+        let exception = match self.execute_block(try_block, try_env) {
+            Err(LoxErr::RuntimeThrow { value, .. }) => value,
+            Err(LoxErr::Runtime { message, .. }) if self.catch_runtime_errors && !Interpreter::is_fatal(&message) => Object::String(message),
+            other => return other,
+        };
+        self.error_module = None;
+        let catch_env = self.new_env(Rc::clone(&self.environment));
+        catch_env.borrow_mut().define_local(exception);
+        self.execute_block(catch_block, catch_env)
+    }
+
+    fn is_fatal(message: &str) -> bool {
+        message == STACK_OVERFLOW || message.starts_with(ASSERTION_FAILED)
+    }
+
+    fn visit_while_stmt(&mut self, keyword: &Token, condition: &Expr, body: &Stmt, increment: &[Stmt]) -> Result<(), LoxErr> {
+        while Interpreter::is_truthy(&self.evaluate(condition)?) {
+            self.check_interrupt().map_err(|message| Interpreter::aborted(Some(keyword), message))?;
+            match self.execute(body) {
+                Ok(_) | Err(LoxErr::RuntimeContinue) => (),
+                Err(LoxErr::RuntimeBreak) => break,
+                Err(other_lox_err) => return Err(other_lox_err),
+            }
+            for stmt in increment {
+                self.execute(stmt)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 没有槽位说明循环变量没被闭包捕获，不用换
+    fn visit_fresh_loop_variable_stmt(&mut self, variable: &VariableExpr) -> Result<(), LoxErr> {
+        if let Some(slot) = variable.get_slot() {
+            self.get_env_mut().refresh_at(slot)?;
+        }
+        Ok(())
+    }
+
+    // 语义分析保证了 import 只在顶层，这时当前环境就是全局环境
+    // 同一个模块只执行一次，循环导入也不会死循环
+    fn visit_import_stmt(&mut self, keyword: &Token, name: &Option<Rc<Token>>, path: &str) -> Result<(), LoxErr> {
+        let import_err = |reason: String| LoxErr::Runtime { line: keyword.line, column: keyword.column, span: Some(keyword.span), message: format!("Could not import '{}': {}.", path, reason) };
+        let module = match self.modules.begin_import(path) {
+            Ok(Import::Loaded(module)) => module,
+            Ok(Import::New(module_path)) => {
+                let code = fs::read_to_string(&module_path).map_err(|io_err| import_err(io_err.to_string()))?;
+                let module = self.new_module(&module_path, &code);
+                self.modules.begin_module(&module_path, Rc::clone(&module));
+                let result = self.run_module(&code, &module);
+                self.modules.end_import(&module_path, matches!(result, Ok(true)));
+                match result {
+                    Ok(true) => module,
+                    Ok(false) => return Err(import_err("the module has errors".to_string())),
+                    Err(lox_err) => return Err(lox_err),
+                }
+            }
+            Err(io_err) => return Err(import_err(io_err.to_string())),
+        };
+        if let Some(name) = name {
+            self.check_not_constant(name)?;
+            let value = Object::Module(module);
+            self.trace_variable("define", &name.lexeme, &value)?;
+            self.get_env_mut().define(&name.lexeme, value);
+        }
+        Ok(())
+    }
+
+    // 新模块的全局环境里有标准库，和导入它的模块里的原生函数（宿主注册的、args()）
+    // 报错时显示的路径尽量相对于工作目录，短一些
+    fn new_module(&self, module_path: &Path, code: &str) -> Rc<LoxModule> {
+        let globals = Environment::new();
+        {
+            let mut globals_mut = globals.borrow_mut();
+            for native_function in NativeRegistry::standard().natives() {
+                globals_mut.define(&native_function.name, Object::NativeFunction(native_function.clone()));
+            }
+            for (name, value) in &self.get_globals().values {
+                if let Object::NativeFunction(_) = value {
+                    globals_mut.define(name, value.clone());
+                }
+            }
+        }
+        let name = module_path.file_stem().map_or_else(|| module_path.to_string_lossy(), |stem| stem.to_string_lossy());
+        let shown_path = std::env::current_dir().ok()
+            .and_then(|dir| module_path.strip_prefix(dir).ok().map(Path::to_path_buf))
+            .unwrap_or_else(|| module_path.to_path_buf());
+        Rc::new(LoxModule::new(&name, globals).with_file(&shown_path.to_string_lossy(), code))
+    }
+
+    // 模块的扫描、解析、语义分析错误在这里报告，返回 false
+    fn run_module(&mut self, code: &str, module: &Rc<LoxModule>) -> Result<bool, LoxErr> {
+        let statements = match module::compile(code, self.max_expression_depth) {
+            Ok((statements, warnings)) => {
+                for warning in warnings {
+                    self.report_diagnostic_in(warning, module);
+                }
+                statements
+            }
+            Err(errors) => {
+                for lox_err in errors {
+                    self.report_in(lox_err, module);
+                }
+                return Ok(false);
+            }
+        };
+        let importer_environment = std::mem::replace(&mut self.environment, Rc::clone(&module.globals));
+        let importer_module = self.enter_module(module);
+        let result = statements.iter().try_for_each(|statement| self.execute(statement));
+        self.leave_module(importer_module, &result);
+        self.environment = importer_environment;
+        result.map(|_| true)
+    }
+
+    // 换到 module 的全局环境，返回原来的模块，用完交给 leave_module 换回去。已经在这个模块里的话什么都不做
+    pub fn enter_module(&mut self, module: &Rc<LoxModule>) -> Option<Rc<LoxModule>> {
+        if Rc::ptr_eq(&self.module, module) {
+            return None;
+        }
+        self.globals = Rc::clone(&module.globals);
+        Some(std::mem::replace(&mut self.module, Rc::clone(module)))
+    }
+
+    // 运行时错误第一次出模块边界时，记下它出自哪个模块，报告的时候显示那个模块的文件和源代码
+    pub fn leave_module<T>(&mut self, previous: Option<Rc<LoxModule>>, result: &Result<T, LoxErr>) {
+        if let Some(previous) = previous {
+            if let Err(LoxErr::Runtime { .. } | LoxErr::RuntimeThrow { .. } | LoxErr::Aborted { .. }) = result {
+                self.error_module.get_or_insert_with(|| Rc::clone(&self.module));
+            }
+            self.globals = Rc::clone(&previous.globals);
+            self.module = previous;
+        }
+    }
+
+    fn visit_print_stmt(&mut self, expr: &Expr) -> Result<(), LoxErr> {
+        let tl: Object = self.evaluate(expr)?;
+        writeln!(self.output, "{}", tl)?;
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, value: &Option<Expr>) -> Result<(), LoxErr> {
+        let ret_value = if let Some(expr) = value {
+            self.evaluate(expr)?
+        } else {
+            Object::None
+        };
+        Err(LoxErr::RuntimeReturn { ret_value })
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Option<Expr>, is_const: bool) -> Result<(), LoxErr> {
+        let value = if initializer.is_some() {
+            self.evaluate(initializer.as_ref().unwrap())?
+        } else {
+            Object::None
+        };
+        self.check_not_constant(name)?;
+        self.trace_variable("define", &name.lexeme, &value)?;
+        let mut env = self.get_env_mut();
+        env.define(&name.lexeme, value);
+        if is_const && env.enclosing.is_none() {
+            env.constants.insert(Rc::clone(&name.lexeme));
+        }
+        Ok(())
+    }
+
+    // 全局常量在运行时再检查一遍，不单靠语义分析
+    fn check_not_constant(&self, name: &Token) -> Result<(), LoxErr> {
+        let env = self.get_env();
+        if env.enclosing.is_none() && env.constants.contains(&name.lexeme) {
+            return Err(LoxErr::Runtime { line: name.line, column: name.column, span: Some(name.span), message: format!("Can't redeclare constant '{}'.", name.lexeme) });
+        }
+        Ok(())
+    }
+
+    fn visit_assign_expr(&mut self, assign_expr: &AssignExpr) -> Result<Object, LoxErr> {
+        let value = self.evaluate(&assign_expr.value)?;
+
+        if let Some(slot) = assign_expr.get_slot() {
+            self.get_env_mut().assign_at(slot, value.clone())?;
+        } else if self.get_globals().constants.contains(&assign_expr.name.lexeme) {
+            return Err(LoxErr::Runtime { line: assign_expr.name.line, column: assign_expr.name.column, span: Some(assign_expr.name.span), message: format!("Can't assign to constant '{}'.", assign_expr.name.lexeme) });
+        } else if let Some(existing_value) = self.get_globals_mut().values.get_mut(&assign_expr.name.lexeme) {
+            *existing_value = value.clone();
+        } else {
+            return Err(Interpreter::undefined_variable_err(&assign_expr.name));
+        }
+        self.trace_variable("assign", &assign_expr.name.lexeme, &value)?;
+
+        Ok(value)   // 赋值表达式可以嵌套在其它表达式里，比如：print a = 2;
+    }
+
+    fn visit_literal_expr(&self, literal_expr: &LiteralExpr) -> Result<Object, LoxErr> {
+        Ok(literal_expr.literal.clone())
+    }
+
+    fn visit_grouping_expr(&mut self, grouping_expr: &GroupingExpr) -> Result<Object, LoxErr> {
+        self.evaluate(&grouping_expr.expression)
+    }
+
+    fn visit_unary_expr(&mut self, unary_expr: &UnaryExpr) -> Result<Object, LoxErr> {
+        let right = self.evaluate(&unary_expr.right)?;
+        Interpreter::unary_operation(unary_expr.operator.token_type, unary_expr.operator.line, &right)
+            .map_err(|err| err.with_position(&unary_expr.operator))
+    }
+
+    pub fn unary_operation(operator: TokenType, line: usize, right: &Object) -> Result<Object, LoxErr> {
+        match operator {
+            TokenType::Bang => {
+                return Ok(Object::Bool(!Interpreter::is_truthy(right)));
+            },
+            TokenType::Minus => {
+                match right {
+                    // 整数没有 -0，这时换成浮点数，和 jlox 一样打印出 -0
+                    Object::Int(0) => Ok(Object::Number(-0.0)),
+                    Object::Int(int) => Interpreter::checked_int(line, int.checked_neg()),
+                    Object::Number(number) => Ok(Object::Number(-number)),
+                    _ => Interpreter::number_err(line, right),
+                }
+            },
+            _ => unreachable!("Impossible operator for unary expr."),
+        }
+    }
+
+    fn visit_call_expr(&mut self, call_expr: &CallExpr) -> Result<Object, LoxErr> {
+        let callee = self.evaluate(&*(*call_expr).callee)?;
+        let mut arguments = Vec::new();
+        for arg in &call_expr.arguments {
+            arguments.push(self.evaluate(arg)?);
+        }
+
+        self.check_interrupt().map_err(|message| Interpreter::aborted(Some(&call_expr.paren), message))?;
+        if self.call_depth >= self.max_call_depth {
+            return Err(LoxErr::Runtime { line: call_expr.paren.line, column: call_expr.paren.column, span: Some(call_expr.paren.span), message: STACK_OVERFLOW.to_string() });
+        }
+        self.call_depth += 1;
+        let result = self.call_value(callee, arguments, call_expr);
+        self.call_depth -= 1;
+        result
+    }
+
+    fn call_value(&mut self, callee: Object, arguments: Vec<Object>, call_expr: &CallExpr) -> Result<Object, LoxErr> {
+        match callee {
+            Object::Function(mut function) => {
+                Interpreter::check_arity(function.arity(), function.is_variadic(), arguments.len(), &call_expr.paren)?;
+                return function.call(self, arguments);
+            }
+            Object::NativeFunction(native_function) => {
+                Interpreter::check_arity(native_function.arity(), false, arguments.len(), &call_expr.paren)?;
+                return native_function.call_with_interpreter(self, call_expr.paren.line, arguments)
+                    .map_err(|err| err.with_position(&call_expr.paren));
+            }
+            Object::Class(mut class) => {
+                Interpreter::check_arity(class.arity(), class.is_variadic(), arguments.len(), &call_expr.paren)?;
+                return class.call(self, arguments);
+            }
+            _ => {
+                return Err(LoxErr::Runtime { line: call_expr.paren.line, column: call_expr.paren.column, span: Some(call_expr.paren.span), message: "Can only call functions and classes.".to_string() });
+            }
+        }
+    }
+
+    fn check_arity(arity: usize, is_variadic: bool, argument_count: usize, paren: &Token) -> Result<(), LoxErr> {
+        if is_variadic && argument_count < arity {
+            return Err(LoxErr::Runtime { line: paren.line, column: paren.column, span: Some(paren.span), message: format!("Expected at least {} arguments but got {}.", arity, argument_count) });
+        }
+        if !is_variadic && argument_count != arity {
+            return Err(LoxErr::Runtime { line: paren.line, column: paren.column, span: Some(paren.span), message: format!("Expected {} arguments but got {}.", arity, argument_count) });
+        }
+        Ok(())
+    }
+
+    fn visit_comma_expr(&mut self, comma_expr: &CommaExpr) -> Result<Object, LoxErr> {
+        let mut res = Object::default();
+        for expr in &comma_expr.exprs {
+            res = self.evaluate(expr)?;
+        }
+        Ok(res)
+    }
+
+    fn visit_conditional_expr(&mut self, conditional_expr: &ConditionalExpr) -> Result<Object, LoxErr> {
+        if Interpreter::is_truthy(&self.evaluate(&*(*conditional_expr).condition)?) {
+            self.evaluate(&*(*conditional_expr).then_branch)
+        } else {
+            self.evaluate(&*(*conditional_expr).else_branch)
+        }
+    }
+
+    fn visit_get_expr(&mut self, get_expr: &GetExpr) -> Result<Object, LoxErr> {
+        let object = self.evaluate(&*(*get_expr).object)?;
+        if let Object::Instance(instance) = object {
+            let instance_ref = instance.borrow();
+            if let Some(value) = get_expr.cache.lookup(instance_ref.class()).and_then(|index| instance_ref.get_field_at(index)) {
+                return Ok(value);
+            }
+            if let Some(index) = instance_ref.field_index(&get_expr.name.lexeme) {
+                get_expr.cache.update(instance_ref.class(), index);
+            }
+            return instance_ref.get(&get_expr.name, &instance);
+        }
+        if let Object::Module(module) = object {
+            return module.get(&get_expr.name);
+        }
+        Err(LoxErr::Runtime { line: get_expr.name.line, column: get_expr.name.column, span: Some(get_expr.name.span), message: "Only instances have properties.".to_string() })
+
+    }
+
+
+    // 逻辑运算符并不承诺会真正返回`true`或`false`，而只是保证它将返回一个具有适当真实性的值。
+    fn visit_logical_expr(&mut self, logical_expr: &LogicalExpr) -> Result<Object, LoxErr> {
+        let left = self.evaluate(&logical_expr.left)?;
+        if logical_expr.operator.token_type == TokenType::Or {
+            if Interpreter::is_truthy(&left) {
+                return Ok(left);
+            } // else right
+        } else {
+            // And
+            if !Interpreter::is_truthy(&left) {
+                return Ok(left);
+            } // else right
+        }
+        self.evaluate(&logical_expr.right)
+    }
+
+    fn visit_set_expr(&mut self, set_expr: &SetExpr) -> Result<Object, LoxErr> {
+        let object = self.evaluate(&set_expr.object)?;
+        match object {
+            Object::Instance(instance) => {
+                let value = self.evaluate(&set_expr.value)?;
+                let mut instance_mut = instance.borrow_mut();
+                if let Some(index) = set_expr.cache.lookup(instance_mut.class()) {
+                    instance_mut.set_field_at(index, value.clone());
+                } else {
+                    let index = instance_mut.set(&set_expr.name, value.clone());
+                    set_expr.cache.update(instance_mut.class(), index);
+                }
+                Ok(value)
+            }
+            _ => Err(LoxErr::Runtime { line: set_expr.name.line, column: set_expr.name.column, span: Some(set_expr.name.span), message: "Only instances have fields.".to_string() }),
+        }
+    }
+
+    fn visit_super_expr(&mut self, super_expr: &SuperExpr) -> Result<Object, LoxErr> {
+        let slot = super_expr.get_slot().unwrap();
+        let superclass = self.get_env().get_at(slot)?;
+
+        let object = self.get_env().get_at(super_expr.this_slot.unwrap())?;   // 从某 instance . get 到 method 时，会创建一个绑定 this 的 closure
+        if let Object::Class(lox_class) = superclass {
+            let method = lox_class.find_method(&super_expr.method.lexeme);
+            if method.is_none() {
+                return Err(LoxErr::Runtime { line: super_expr.method.line, column: super_expr.method.column, span: Some(super_expr.method.span), message: format!("Undefined property '{}'.", super_expr.method.lexeme) });
+            }
+            if let Object::Instance(instance) = object {
+                return Ok(Object::Function(method.unwrap().bind(instance)));
+            } else {
+                unreachable!("this is not instance, WTF?")
+            }
+        } else {
+            unreachable!("superclass is not class, visit_class_declaration_stmt bug?");
+        }
+    }
+
+    fn visit_this_expr(&mut self, this_expr: &ThisExpr) -> Result<Object, LoxErr> {
+        self.look_up_variable(this_expr)    // 也就是说，这个 this 最终会变成 Instance 本身 
+    }
+
+
+    fn visit_binary_expr(&mut self, binary_expr: &BinaryExpr) -> Result<Object, LoxErr> {
+        let left = self.evaluate(&binary_expr.left)?;
+        let right = self.evaluate(&binary_expr.right)?;
+        Interpreter::binary_operation(binary_expr.operator.token_type, binary_expr.operator.line, &left, &right)
+            .map_err(|err| err.with_position(&binary_expr.operator))
+    }
+
+    // 不同类型的值一律不相等，而不是报错。先比较类型，以后给某种值换比较方式也不会影响跨类型比较
+    // 实例按身份比较：两个字段一样的不同实例不相等，有环的实例也不会一直递归下去。要比较结构用 deep_equal
+    pub fn is_equal(left: &Object, right: &Object) -> bool {
+        match (left, right) {
+            // 整数和浮点数按数值比较，1 == 1.0
+            (Object::Int(int), Object::Number(number)) | (Object::Number(number), Object::Int(int)) => return *int as f64 == *number,
+            _ if std::mem::discriminant(left) != std::mem::discriminant(right) => return false,
+            _ => (),
+        }
+        match (left, right) {
+            (Object::Instance(left_instance), Object::Instance(right_instance)) => Rc::ptr_eq(left_instance, right_instance),
+            (Object::List(left_list), Object::List(right_list)) => Rc::ptr_eq(left_list, right_list),
+            _ => left == right,
+        }
+    }
+
+    // 二元运算的语义，字节码虚拟机也用这个，保证两种执行方式结果一致
+    pub fn binary_operation(operator: TokenType, line: usize, left: &Object, right: &Object) -> Result<Object, LoxErr> {
+        match operator {
+            TokenType::EqualEqual => Ok(Object::Bool(Interpreter::is_equal(left, right))),
+            TokenType::BangEqual => Ok(Object::Bool(!Interpreter::is_equal(left, right))),
+            TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
+                let ordering = match NumberPair::new(left, right) {
+                    Some(NumberPair::Ints(left_int, right_int)) => left_int.partial_cmp(&right_int),
+                    Some(NumberPair::Floats(left_number, right_number)) => left_number.partial_cmp(&right_number),
+                    None => return Interpreter::number_operands_err(line, left, right),
+                };
+                // 有 NaN 时 ordering 是 None，四种比较都是 false
+                let result = match operator {
+                    TokenType::Greater => ordering.is_some_and(|ordering| ordering.is_gt()),
+                    TokenType::GreaterEqual => ordering.is_some_and(|ordering| ordering.is_ge()),
+                    TokenType::Less => ordering.is_some_and(|ordering| ordering.is_lt()),
+                    _ => ordering.is_some_and(|ordering| ordering.is_le()),
+                };
+                Ok(Object::Bool(result))
+            }
+            TokenType::Minus => match NumberPair::new(left, right) {
+                Some(NumberPair::Ints(left_int, right_int)) => Interpreter::checked_int(line, left_int.checked_sub(right_int)),
+                Some(NumberPair::Floats(left_number, right_number)) => Ok(Object::Number(left_number - right_number)),
+                None => Interpreter::number_operands_err(line, left, right),
+            },
+            // 除法的结果总是浮点数，7 / 2 是 3.5
+            TokenType::Slash => match NumberPair::new(left, right) {
+                Some(NumberPair::Ints(_, 0) | NumberPair::Floats(_, 0.0)) => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Attempt to divide `{}` by zero.", left) }),
+                Some(NumberPair::Ints(left_int, right_int)) => Ok(Object::Number(left_int as f64 / right_int as f64)),
+                Some(NumberPair::Floats(left_number, right_number)) => Ok(Object::Number(left_number / right_number)),
+                None => Interpreter::number_operands_err(line, left, right),
+            },
+            // 截断取余，符号跟被除数一样。要数学上的模用原生函数 mod
+            TokenType::Percent => match NumberPair::new(left, right) {
+                Some(NumberPair::Ints(_, 0) | NumberPair::Floats(_, 0.0)) => Err(Interpreter::modulo_by_zero_err(line, left)),
+                Some(NumberPair::Ints(left_int, right_int)) => Interpreter::checked_int(line, left_int.checked_rem(right_int)),
+                Some(NumberPair::Floats(left_number, right_number)) => Ok(Object::Number(left_number % right_number)),
+                None => Interpreter::number_operands_err(line, left, right),
+            },
+            TokenType::Star => match NumberPair::new(left, right) {
+                Some(NumberPair::Ints(left_int, right_int)) => Interpreter::checked_int(line, left_int.checked_mul(right_int)),
+                Some(NumberPair::Floats(left_number, right_number)) => Ok(Object::Number(left_number * right_number)),
+                None => Interpreter::number_operands_err(line, left, right),
+            },
+            TokenType::Plus => {
+                match (left, right) {
+                    // 只要有一边是字符串，另一边就按 print 的格式转成字符串再拼接："count: " + 3 是 "count: 3"
+                    (Object::String(_), _) | (_, Object::String(_)) => Ok(Object::String(format!("{}{}", left, right))),
+                    _ => match NumberPair::new(left, right) {
+                        Some(NumberPair::Ints(left_int, right_int)) => Interpreter::checked_int(line, left_int.checked_add(right_int)),
+                        Some(NumberPair::Floats(left_number, right_number)) => Ok(Object::Number(left_number + right_number)),
+                        None => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Operands must be two numbers or include a string, got {} and {}.", left.type_name(), right.type_name()) }),
+                    },
+                }
+            }
+            
+            _ => unreachable!("Impossible operator for binary expr."),
+        }
+        
+    }
+
+    // 整数运算溢出时报错，而不是悄悄回绕或者变成浮点数
+    pub fn checked_int(line: usize, result: Option<i64>) -> Result<Object, LoxErr> {
+        match result {
+            Some(int) => Ok(Object::Int(int)),
+            None => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: "Integer overflow.".to_string() }),
+        }
+    }
+
+    fn visit_variable_expr(&self, variable_expr: &VariableExpr) -> Result<Object, LoxErr> {
+        self.look_up_variable(variable_expr)
+    }
+
+    fn look_up_variable(&self, val: &impl Resolvable) -> Result<Object, LoxErr> {
+        if let Some(slot) = val.get_slot() {
+            self.get_env().get_at(slot)
+        } else if let Some(value) = self.get_globals().values.get(&val.name().lexeme) {
+            // 全局环境只有一层，直接查表，不用走 enclosing 链
+            Ok(value.clone())
+        } else {
+            Err(Interpreter::undefined_variable_err(val.name()))
+        }
+    }
+
+    fn undefined_variable_err(name: &Token) -> LoxErr {
+        LoxErr::Runtime { line: name.line, column: name.column, span: Some(name.span), message: format!("Undefined variable '{}'.", name.lexeme) }
+    }
+
+    pub fn is_truthy(literal: &Object) -> bool {
+        match literal {
+            Object::None => false,
+            Object::Bool(v) => *v,
+            _ => true,
+        }
+    }
+
+    fn number_err(line: usize, operand: &Object) -> Result<Object, LoxErr> {
+        Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Operand must be a number, got {}.", operand.type_name()) })
+    }
+
+    // % 和原生函数 mod 共用
+    pub fn modulo_by_zero_err(line: usize, left: &Object) -> LoxErr {
+        LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Attempt to take `{}` modulo zero.", left) }
+    }
+
+    // 二元运算时报告第一个不是数字的操作数
+    fn number_operands_err(line: usize, left: &Object, right: &Object) -> Result<Object, LoxErr> {
+        if let Object::Int(_) | Object::Number(_) = left {
+            Interpreter::number_err(line, right)
+        } else {
+            Interpreter::number_err(line, left)
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::diagnostic::Diagnostic;
+    use crate::err::LoxErr;
+    use crate::expr::{ConditionalExpr, Expr, LiteralExpr};
+    use crate::lox::Lox;
+    use crate::module::{self, resolved};
+    use crate::native::NativeRegistry;
+    use crate::object::Object;
+    use crate::parser::MAX_EXPRESSION_DEPTH;
+
+    use super::Interpreter;
+
+    // 执行代码，返回第一个运行时错误
+    fn run_err(code: &str) -> Option<LoxErr> {
+        let statements = resolved(code);
+
+        let mut interpreter = Interpreter::new();
+        statements.iter().find_map(|stmt| interpreter.execute(stmt).err())
+    }
+
+    // 执行代码，返回全局变量 name 的值
+    fn global_value(code: &str, name: &str) -> Object {
+        let statements = resolved(code);
+
+        let mut interpreter = Interpreter::new();
+        for stmt in &statements {
+            interpreter.execute(stmt).unwrap();
+        }
+        interpreter.get_global(name).unwrap()
+    }
+
+    fn runtime_message(code: &str) -> String {
+        match run_err(code) {
+            Some(LoxErr::Runtime { message, .. }) => message,
+            other => panic!("Expected runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_closure_slots() {
+        let code = r#"
+fun makeCounter() {
+    var skipped = "slot 0";
+    var i = 0;
+    fun count() {
+        i = i + 1;
+        return i;
+    }
+    return count;
+}
+var counter = makeCounter();
+counter();
+counter();
+var result = counter();
+        "#;
+        assert_eq!(global_value(code, "result"), Object::Int(3));
+    }
+
+    #[test]
+    fn test_block_slots() {
+        let code = r#"
+var result;
+{
+    var a = 1;
+    var b = 2;
+    {
+        var c = 3;
+        a = a + c;
+        {
+            var d = 4;
+            result = a + b + c + d;
+        }
+    }
+}
+        "#;
+        assert_eq!(global_value(code, "result"), Object::Int(13));
+    }
+
+    #[test]
+    fn test_local_class_and_super_slots() {
+        let code = r#"
+var result;
+{
+    var prefix = "B";
+    class A {
+        name() { return "A"; }
+    }
+    class B < A {
+        name() { return prefix + super.name(); }
+    }
+    result = B().name();
+}
+        "#;
+        assert_eq!(global_value(code, "result"), Object::String("BA".to_string()));
+    }
+
+    #[test]
+    fn test_many_bound_methods() {
+        let code = r#"
+class Counter {
+    init() { this.n = 0; }
+    inc() { this.n = this.n + 1; return this; }
+}
+var counter = Counter();
+for (var i = 0; i < 1000; i = i + 1) {
+    var inc = counter.inc;
+    inc();
+}
+var result = counter.inc().n;
+        "#;
+        assert_eq!(global_value(code, "result"), Object::Int(1001));
+    }
+
+    #[test]
+    fn test_inheritance_chain() {
+        let code = r#"
+class A {
+    init(x) { this.x = x; }
+    describe() { return "A" + this.x; }
+    base() { return "base"; }
+}
+class B < A {
+    describe() { return "B" + super.describe(); }
+}
+class C < B {
+    init(x) { super.init(x * 10); }
+    describe() { return "C" + super.describe(); }
+}
+var c1 = C(1);
+var c2 = C(2);
+var result = c1.describe() + c2.describe() + c2.base();
+        "#;
+        assert_eq!(global_value(code, "result"), Object::String("CBA10CBA20base".to_string()));
+    }
+
+    #[test]
+    fn test_overridden_method_lookup() {
+        let code = r#"
+class A {
+    name() { return "A"; }
+    shared() { return "shared"; }
+}
+class B < A {
+    name() { return "B"; }
+}
+class C < B {}
+var result = "";
+var c = C();
+for (var i = 0; i < 3; i = i + 1) {
+    result = result + c.name() + c.shared();
+}
+result = result + A().name() + B().name();
+        "#;
+        assert_eq!(global_value(code, "result"), Object::String("BsharedBsharedBsharedAB".to_string()));
+    }
+
+    #[test]
+    fn test_large_string_argument() {
+        let code = r#"
+var s = "0123456789";
+for (var i = 0; i < 10; i = i + 1) {
+    s = s + s;
+}
+fun identity(a, b) {
+    return b + a;
+}
+var result = identity(s, "!") == "!" + s;
+        "#;
+        assert_eq!(global_value(code, "result"), Object::Bool(true));
+    }
+
+    #[test]
+    fn test_undefined_global() {
+        assert_eq!(runtime_message("print missing;"), "Undefined variable 'missing'.");
+        assert_eq!(runtime_message("missing = 1;"), "Undefined variable 'missing'.");
+        assert_eq!(runtime_message("fun f() { return missing; } f();"), "Undefined variable 'missing'.");
+    }
+
+    #[test]
+    fn test_global_read_and_assign() {
+        let code = r#"
+var a = 1;
+fun bump() { a = a + 1; }
+bump();
+bump();
+var result = a;
+        "#;
+        assert_eq!(global_value(code, "result"), Object::Int(3));
+    }
+
+    #[test]
+    fn test_captured_variables() {
+        let code = r#"
+var result = "";
+{
+    fun fact(n) { if (n < 2) return 1; return n * fact(n - 1); }
+    result = result + fact(5);
+    var x = 1;
+    fun get() { return x; }
+    x = 2;
+    result = result + get();
+}
+class A { greet() { return "A"; } }
+class B < A {
+    greet() {
+        fun later() { return super.greet() + this.name; }
+        return later;
+    }
+}
+var b = B();
+b.name = "!";
+result = result + b.greet()();
+        "#;
+        assert_eq!(global_value(code, "result"), Object::String("1202A!".to_string()));
+    }
+
+    #[test]
+    fn test_env_pool_reuse() {
+        let code = r#"
+var getters = nil;
+var sum = 0;
+for (var i = 0; i < 10; i = i + 1) {
+    var j = i * 2;
+    sum = sum + j;
+    if (i == 3) {
+        fun get() { return j; }
+        getters = get;
+    }
+}
+var result = sum + getters();
+        "#;
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(&resolved(code));
+        assert!(!interpreter.had_runtime_error);
+        // for 脱糖后外层的块、循环体、if 里的块，同时最多用到 3 个环境，之后都回收了
+        assert_eq!(interpreter.env_pool.len(), 3);
+        // 被捕获的 j 不受环境回收影响
+        assert_eq!(interpreter.get_globals().values.get("result"), Some(&Object::Int(96)));
+    }
+
+    #[test]
+    fn test_unary_operand_type_in_message() {
+        assert_eq!(runtime_message(r#"-"a";"#), "Operand must be a number, got string.");
+    }
+
+    #[test]
+    fn test_binary_operand_type_in_message() {
+        assert_eq!(runtime_message(r#"1 < "b";"#), "Operand must be a number, got string.");
+        assert_eq!(runtime_message(r#"nil * 2;"#), "Operand must be a number, got nil.");
+    }
+
+    #[test]
+    fn test_mutual_inheritance() {
+        let code = r#"
+class A < B {}
+class B < A {}
+        "#;
+        assert_eq!(runtime_message(code), "Undefined variable 'B'.");
+    }
+
+    #[test]
+    fn test_redefined_superclass_is_not_cyclic() {
+        let code = r#"
+class A {}
+class B < A {}
+class A < B {}
+A().missing();
+        "#;
+        assert_eq!(runtime_message(code), "Undefined property missing.");
+    }
+
+    #[test]
+    fn test_property_cache_across_classes() {
+        // 同一个 o.x / o.y 调用点先后遇到不同类的实例，字段下标各不相同
+        let code = r#"
+class A {
+    init() {
+        this.x = 1;
+        this.y = 2;
+    }
+}
+class B {
+    init() {
+        this.y = 20;
+        this.x = 10;
+    }
+}
+fun sum(o) {
+    o.x = o.x + 100;
+    return o.x + o.y;
+}
+var a = A();
+var b = B();
+var total = sum(a) + sum(b) + sum(a) + sum(b);
+        "#;
+        assert_eq!(global_value(code, "total"), Object::Int(103 + 130 + 203 + 230));
+    }
+
+    #[test]
+    fn test_property_cache_missing_field() {
+        // 缓存命中的类但这个实例还没有这个字段，要回退到方法查找和报错
+        let code = r#"
+class A {
+    set() { this.x = 1; }
+}
+fun get(o) { return o.x; }
+var a = A();
+a.set();
+get(a);
+get(A());
+        "#;
+        assert_eq!(runtime_message(code), "Undefined property x.");
+    }
+
+    #[test]
+    fn test_print_instance_inside_own_method() {
+        let code = r#"
+class Node {
+    show() {
+        print this;
+        this.shown = "" + this.describe();
+        return this;
+    }
+    describe() {
+        print this;
+        return 1;
+    }
+}
+var node = Node().show();
+        "#;
+        let Object::Instance(instance) = global_value(code, "node") else {
+            panic!("Expected instance");
+        };
+        // 打印只读实例，外面还借用着的时候也不能 panic
+        let _borrowed = instance.borrow();
+        assert_eq!(Object::Instance(Rc::clone(&instance)).to_string(), "<Node instance>");
+    }
+
+    #[test]
+    fn test_long_operator_chain() {
+        // 一长串左结合的运算符是很深的树，语义分析、求值和释放都在测试线程默认的栈里跑完
+        let code = format!("var sum = 0{};", " + 1".repeat(10_000));
+        assert_eq!(global_value(&code, "sum"), Object::Int(10_000));
+        let code = format!("var all = true{};", " and true".repeat(10_000));
+        assert_eq!(global_value(&code, "all"), Object::Bool(true));
+    }
+
+    #[test]
+    fn test_conditional_expr() {
+        let literal = |value: Object| Expr::Literal(LiteralExpr::new(value));
+        let conditional = |condition: Object| Expr::Conditional(ConditionalExpr::new(
+            literal(condition),
+            literal(Object::String("then".to_string())),
+            literal(Object::String("else".to_string())),
+        ));
+
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.evaluate(&conditional(Object::Bool(true))).unwrap(), Object::String("then".to_string()));
+        assert_eq!(interpreter.evaluate(&conditional(Object::Number(0.0))).unwrap(), Object::String("then".to_string()));
+        assert_eq!(interpreter.evaluate(&conditional(Object::Bool(false))).unwrap(), Object::String("else".to_string()));
+        assert_eq!(interpreter.evaluate(&conditional(Object::None)).unwrap(), Object::String("else".to_string()));
+    }
+
+    #[test]
+    fn test_for_loop_closures_capture_each_iteration() {
+        let code = r#"
+var f0;
+var f1;
+var f2;
+for (var i = 0; i < 3; i = i + 1) {
+    fun get() { return i; }
+    if (i == 0) f0 = get;
+    if (i == 1) f1 = get;
+    if (i == 2) f2 = get;
+}
+var result = f0() + "," + f1() + "," + f2();
+        "#;
+        assert_eq!(global_value(code, "result"), Object::String("0,1,2".to_string()));
+    }
+
+    #[test]
+    fn test_for_loop_variable_assigned_in_body() {
+        // 同一次迭代里对循环变量的修改（包括闭包里的）要带到递增语句
+        let code = r#"
+var count = 0;
+for (var i = 0; i < 10; i = i + 1) {
+    fun skip() { i = i + 2; }
+    skip();
+    count = count + 1;
+}
+var result = count;
+        "#;
+        assert_eq!(global_value(code, "result"), Object::Int(4));
+    }
+
+    #[test]
+    fn test_cross_type_equality() {
+        let code = r#"
+class A {}
+var a = A();
+var numberString = 1 == "1";
+var nilFalse = nil == false;
+var instanceNumber = a == 1;
+var numberInstance = 1 != a;
+var classInstance = A == a;
+var zeroFalse = 0 == false;
+var emptyNil = "" != nil;
+        "#;
+        assert_eq!(global_value(code, "numberString"), Object::Bool(false));
+        assert_eq!(global_value(code, "nilFalse"), Object::Bool(false));
+        assert_eq!(global_value(code, "instanceNumber"), Object::Bool(false));
+        assert_eq!(global_value(code, "numberInstance"), Object::Bool(true));
+        assert_eq!(global_value(code, "classInstance"), Object::Bool(false));
+        assert_eq!(global_value(code, "zeroFalse"), Object::Bool(false));
+        assert_eq!(global_value(code, "emptyNil"), Object::Bool(true));
+    }
+
+    #[test]
+    fn test_modulo_sign() {
+        let code = r#"
+var truncated = -7 % 3;
+var floored = mod(-7, 3);
+var positive = 7 % 3;
+var negativeDivisor = mod(7, -3);
+var fraction = 5.5 % 2;
+        "#;
+        assert_eq!(global_value(code, "truncated"), Object::Int(-1));
+        assert_eq!(global_value(code, "floored"), Object::Int(2));
+        assert_eq!(global_value(code, "positive"), Object::Int(1));
+        assert_eq!(global_value(code, "negativeDivisor"), Object::Int(-2));
+        assert_eq!(global_value(code, "fraction"), Object::Number(1.5));
+
+        assert_eq!(runtime_message("1 % 0;"), "Attempt to take `1` modulo zero.");
+        assert_eq!(runtime_message("mod(1, 0);"), "Attempt to take `1` modulo zero.");
+        assert_eq!(runtime_message("mod(1, \"2\");"), "Arguments of mod must be numbers, got number and string.");
+    }
+
+    #[test]
+    fn test_integers() {
+        let code = r#"
+var sum = 1 + 2;
+var mixed = 1 + 0.5;
+var quotient = 7 / 2;
+var exact = 6 / 3;
+var same = 1 == 1.0;
+var less = 1 < 1.5;
+var big = 9007199254740993;
+var text = "n=" + 3 + "," + 2.5;
+var negative_zero = "" + -0 + " " + (-0 + 1) + " " + 8 / 2;
+        "#;
+        assert_eq!(global_value(code, "sum"), Object::Int(3));
+        assert_eq!(global_value(code, "mixed"), Object::Number(1.5));
+        assert_eq!(global_value(code, "quotient"), Object::Number(3.5));
+        assert_eq!(global_value(code, "exact"), Object::Number(2.0));
+        assert_eq!(global_value(code, "same"), Object::Bool(true));
+        assert_eq!(global_value(code, "less"), Object::Bool(true));
+        // 超过 f64 精度的整数不会被舍入
+        assert_eq!(global_value(code, "big"), Object::Int(9007199254740993));
+        assert_eq!(global_value(code, "text"), Object::String("n=3,2.5".to_string()));
+        assert_eq!(global_value(code, "negative_zero"), Object::String("-0 1 4".to_string()));
+
+        assert_eq!(runtime_message("9223372036854775807 + 1;"), "Integer overflow.");
+        // 运算符所在的列
+        assert!(matches!(run_err("var s = \"a\";\nvar t = 1 -  s;"), Some(LoxErr::Runtime { line: 2, column: 11, .. })));
+        assert_eq!(runtime_message("var x = -9223372036854775807 - 1; -x;"), "Integer overflow.");
+    }
+
+    #[test]
+    fn test_typeof() {
+        let code = r#"
+class Point { m() {} }
+fun f(...rest) { return rest; }
+var types = typeof(1) + " " + typeof(1.5) + " " + typeof("s") + " " + typeof(true) + " " + typeof(nil);
+var callables = typeof(f) + " " + typeof(clock) + " " + typeof(Point) + " " + typeof(Point().m);
+var instance = typeof(Point());
+var list = typeof(f());
+        "#;
+        assert_eq!(global_value(code, "types"), Object::String("number number string bool nil".to_string()));
+        assert_eq!(global_value(code, "callables"), Object::String("function function class function".to_string()));
+        assert_eq!(global_value(code, "instance"), Object::String("Point instance".to_string()));
+        assert_eq!(global_value(code, "list"), Object::String("list".to_string()));
+    }
+
+    #[test]
+    fn test_string_natives() {
+        let code = r#"
+var lengths = "${len("héllo")} ${len("")} ${len(split("a,b", ","))}";
+var sub = substr("héllo", 1, 3) + "|" + substr("abc", 1, 10) + "|" + substr("abc", 5, 1);
+var cases = upper("abc") + lower("ÀB");
+var trimmed = trim("  padded 
+");
+var indices = "${indexOf("héllo", "l")} ${indexOf("abc", "x")} ${indexOf("abc", "")}";
+var parts = split("a,b,,c", ",");
+var chars = split("hé", "");
+var whole = split("abc", ";");
+        "#;
+        let string = |s: &str| Object::String(s.to_string());
+        assert_eq!(global_value(code, "lengths"), string("5 0 2"));
+        assert_eq!(global_value(code, "sub"), string("éll|bc|"));
+        assert_eq!(global_value(code, "cases"), string("ABCàb"));
+        assert_eq!(global_value(code, "trimmed"), string("padded"));
+        assert_eq!(global_value(code, "indices"), string("2 -1 0"));
+        assert_eq!(global_value(code, "parts").to_string(), r#"["a", "b", "", "c"]"#);
+        assert_eq!(global_value(code, "chars").to_string(), r#"["h", "é"]"#);
+        assert_eq!(global_value(code, "whole").to_string(), r#"["abc"]"#);
+
+        assert_eq!(runtime_message("len(1);"), "Argument of len must be a string or list, got number.");
+        assert_eq!(runtime_message("upper(nil);"), "Argument of upper must be a string, got nil.");
+        assert_eq!(runtime_message("substr(\"abc\", -1, 1);"), "Argument of substr must be a non-negative integer, got -1.");
+        assert_eq!(runtime_message("substr(\"abc\", 0, \"1\");"), "Argument of substr must be a non-negative integer, got \"1\".");
+        assert_eq!(runtime_message("split(\"abc\");"), "Expected 2 arguments but got 1.");
+    }
+
+    #[test]
+    fn test_math_natives() {
+        let code = r#"
+var absolute = "${abs(-3)} ${abs(-2.5)} ${abs(4)}";
+var rounded = "${floor(2.7)} ${ceil(2.2)} ${floor(-2.5)} ${ceil(7)}";
+var root = sqrt(16);
+var powers = "${pow(2, 10)} ${pow(2, -1)} ${pow(4, 0.5)}";
+var smaller = min(3, 2.5);
+var larger = max(3, 2.5);
+var tie = min(1, 1.0);
+var inRange = true;
+for (var i = 0; i < 100; i = i + 1) {
+    var r = random();
+    if (r < 0 or r >= 1) inRange = false;
+}
+        "#;
+        let string = |s: &str| Object::String(s.to_string());
+        assert_eq!(global_value(code, "absolute"), string("3 2.5 4"));
+        assert_eq!(global_value(code, "rounded"), string("2 3 -3 7"));
+        assert_eq!(global_value(code, "root"), Object::Number(4.0));
+        assert_eq!(global_value(code, "powers"), string("1024 0.5 2"));
+        assert_eq!(global_value(code, "smaller"), Object::Number(2.5));
+        assert_eq!(global_value(code, "larger"), Object::Int(3));
+        assert_eq!(global_value(code, "tie"), Object::Int(1));
+        assert_eq!(global_value(code, "inRange"), Object::Bool(true));
+
+        assert_eq!(runtime_message("sqrt(\"4\");"), "Argument of sqrt must be a number, got string.");
+        assert_eq!(runtime_message("max(1, nil);"), "Arguments of max must be numbers, got number and nil.");
+        assert_eq!(runtime_message("pow(10, 30);"), "Integer overflow.");
+        assert_eq!(runtime_message("abs(-9223372036854775807 - 1);"), "Integer overflow.");
+        assert_eq!(runtime_message("random(1);"), "Expected 0 arguments but got 1.");
+    }
+
+    #[test]
+    fn test_conversion_natives() {
+        let code = r#"
+class Point {}
+var strings = str(1) + "|" + str(2.5) + "|" + str(nil) + "|" + str(Point()) + "|" + str("s");
+var int = num(" 42 ");
+var float = num("-2.5e3");
+var same = num(7);
+var invalid = "${num("abc")} ${num("")} ${num("1 2")} ${num("inf")} ${num("NaN")}";
+var roundTrip = num(str(0.1 + 0.2)) == 0.1 + 0.2;
+var exponent = num("1e5");
+var bigRoundTrip = num(str(12345678.5)) == 12345678.5;
+var radix = "${num("0x10")} ${num("0b1")} ${num("0o7")}";
+        "#;
+        assert_eq!(global_value(code, "strings"), Object::String("1|2.5|nil|<Point instance>|s".to_string()));
+        assert_eq!(global_value(code, "int"), Object::Int(42));
+        assert_eq!(global_value(code, "float"), Object::Number(-2500.0));
+        assert_eq!(global_value(code, "same"), Object::Int(7));
+        assert_eq!(global_value(code, "invalid"), Object::String("nil nil nil nil nil".to_string()));
+        assert_eq!(global_value(code, "roundTrip"), Object::Bool(true));
+        assert_eq!(global_value(code, "exponent"), Object::Number(100000.0));
+        assert_eq!(global_value(code, "bigRoundTrip"), Object::Bool(true));
+        assert_eq!(global_value(code, "radix"), Object::String("nil nil nil".to_string()));
+        assert_eq!(runtime_message("num(true);"), "Argument of num must be a string or number, got bool.");
+    }
+
+    #[test]
+    fn test_time_natives() {
+        let code = r#"
+var start = clockMonotonic();
+sleep(0.01);
+sleep(0);
+var elapsed = clockMonotonic() - start;
+var slept = elapsed >= 0.01;
+        "#;
+        assert_eq!(global_value(code, "slept"), Object::Bool(true));
+        assert_eq!(runtime_message("sleep(-1);"), "Argument of sleep must be a non-negative number of seconds, got -1.");
+        assert_eq!(runtime_message("sleep(\"1\");"), "Argument of sleep must be a number, got string.");
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        // 测试线程的栈只有 2MB，栈不够时会换新的栈接着跑，最后报的是调用深度超限而不是让进程崩溃
+        assert_eq!(runtime_message("fun f() { f(); }\nf();"), "Stack overflow.");
+        let mut lox = Lox::new();
+        lox.set_reporter(Box::new(|_: &Diagnostic| {}));
+        match &lox.eval("fun depth(n) { return depth(n + 1) + 1; }\ndepth(0);").unwrap_err()[..] {
+            [LoxErr::Runtime { line: 1, message, .. }] => assert_eq!(message, "Stack overflow."),
+            other => panic!("Expected a stack overflow, got {:?}", other),
+        }
+        // 和 main 一样在大栈的线程里跑，也正好用满默认的调用深度
+        let calls = std::thread::Builder::new()
+            .stack_size(256 * 1024 * 1024)
+            .spawn(|| {
+                let statements = resolved("var n = 0;\nfun f() { n = n + 1; f(); }\nf();");
+                let mut interpreter = Interpreter::new();
+                let error = statements.iter().find_map(|stmt| interpreter.execute(stmt).err());
+                assert!(matches!(error, Some(LoxErr::Runtime { message, .. }) if message == "Stack overflow."));
+                match interpreter.get_global("n") {
+                    Some(Object::Int(n)) => n,
+                    other => panic!("Expected the call count, got {:?}", other),
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(calls, super::DEFAULT_MAX_CALL_DEPTH as i64);
+
+        let code = "fun depth(n) { if (n == 0) return 0; return depth(n - 1) + 1; }";
+        let mut interpreter = Interpreter::new();
+        interpreter.max_call_depth = 10;
+        interpreter.execute(&resolved(code)[0]).unwrap();
+        let mut call = |n: usize| interpreter.execute(&resolved(&format!("depth({});", n))[0]);
+        assert!(call(9).is_ok());
+        assert!(matches!(call(10), Err(LoxErr::Runtime { line: 1, .. })));
+        // 出错后深度计数复原了
+        assert!(call(9).is_ok());
+    }
+
+    #[test]
+    fn test_execution_budget() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_fuel(Some(1000));
+        interpreter.interpret(&resolved("var n = 0; while (true) n = n + 1;"));
+        assert!(interpreter.had_runtime_error);
+        assert_eq!(interpreter.remaining_fuel(), Some(0));
+        let n = interpreter.get_globals().values.get("n").cloned();
+        assert!(matches!(n, Some(Object::Int(n)) if n > 100));
+
+        // catch 接不住，脚本没法自己吞掉限制
+        let mut interpreter = Interpreter::new();
+        interpreter.set_fuel(Some(1000));
+        let statements = resolved("while (true) { try { while (true) {} } catch (e) {} }");
+        assert!(matches!(interpreter.execute(&statements[0]), Err(LoxErr::Aborted { .. })));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_time_limit(Some(std::time::Duration::from_millis(20)));
+        match interpreter.execute(&resolved("while (true) {}")[0]) {
+            Err(lox_err @ LoxErr::Aborted { .. }) => assert_eq!(lox_err.to_string(), "Runtime Error: [line 1:1] Execution time limit exceeded."),
+            other => panic!("Expected the time limit to abort, got {:?}", other),
+        }
+
+        // 预算之内正常执行
+        let mut interpreter = Interpreter::new();
+        interpreter.set_fuel(Some(1000));
+        interpreter.interpret(&resolved("var x = 1 + 2;"));
+        assert!(!interpreter.had_runtime_error);
+        assert!(interpreter.remaining_fuel().unwrap() > 990);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let statements = resolved("var n = 0;\nwhile (true) { try { n = n + 1; } catch (e) {} }\nn = -1;");
+
+        let mut interpreter = Interpreter::new();
+        let handle = interpreter.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+        interpreter.execute(&statements[0]).unwrap();
+        match interpreter.execute(&statements[1]) {
+            Err(LoxErr::Aborted { line, column, message, .. }) => {
+                assert_eq!(message, "Interrupted.");
+                assert_eq!((line, column), (Some(2), 1));
+            }
+            other => panic!("Expected an interrupt, got {:?}", other),
+        }
+        interrupter.join().unwrap();
+        // 中止的只是正在运行的语句，之后还能接着执行
+        interpreter.execute(&statements[2]).unwrap();
+        assert_eq!(interpreter.get_globals().values.get("n"), Some(&Object::Int(-1)));
+    }
+
+    #[test]
+    fn test_register_native() {
+        let logged = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&logged);
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native("log", 1, move |_interpreter, arguments| {
+            log.borrow_mut().push(arguments[0].to_string());
+            Ok(Object::None)
+        });
+        // 能读写解释器的状态
+        interpreter.register_native("countGlobals", 0, |interpreter, _arguments| {
+            Ok(Object::Int(interpreter.globals.borrow().values.len() as i64))
+        });
+        interpreter.register_native("fail", 0, |_interpreter, _arguments| {
+            Err(LoxErr::Runtime { line: 0, column: 0, span: None, message: "Host failure.".to_string() })
+        });
+
+        let code = "log(\"hi \" + 1);\nvar n = countGlobals();\nvar same = log == log;\n\nfail();";
+        let statements = resolved(code);
+        for stmt in &statements[..3] {
+            interpreter.execute(stmt).unwrap();
+        }
+        match interpreter.execute(&statements[3]) {
+            Err(LoxErr::Runtime { line: 5, column: 6, span: Some(_), message }) => assert_eq!(message, "Host failure."),
+            other => panic!("Expected runtime error on line 5, got {:?}", other),
+        }
+
+        assert_eq!(*logged.borrow(), vec!["hi 1".to_string()]);
+        let globals = interpreter.globals.borrow();
+        let native_count = NativeRegistry::standard().natives().len() as i64;
+        assert_eq!(globals.values.get("n"), Some(&Object::Int(native_count + 3)));
+        assert_eq!(globals.values.get("same"), Some(&Object::Bool(true)));
+        assert_eq!(globals.values.get("log").unwrap().to_string(), "<native fn log>");
+    }
+
+    #[test]
+    fn test_host_globals() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("limit", Object::Int(3));
+        interpreter.set_global("clock", Object::String("overridden".to_string()));
+        let code = "var total = 0;\nfor (var i = 0; i < limit; i = i + 1) total = total + i;\nlimit = clock;";
+        let statements = resolved(code);
+        interpreter.interpret(&statements);
+
+        assert_eq!(interpreter.get_global("total"), Some(Object::Int(3)));
+        assert_eq!(interpreter.get_global("limit"), Some(Object::String("overridden".to_string())));
+        assert_eq!(interpreter.get_global("missing"), None);
+    }
+
+    #[test]
+    fn test_deep_equal() {
+        let code = r#"
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+}
+class Other {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+}
+var a = Point(1, Point(2, "s"));
+var b = Point(1, Point(2, "s"));
+var identity = a == b;
+var selfIdentity = a == a;
+var same = deep_equal(a, b);
+var differentValue = deep_equal(a, Point(1, Point(3, "s")));
+var differentClass = deep_equal(Point(1, 2), Other(1, 2));
+var extraField = Point(1, 2);
+extraField.z = 3;
+var missingField = deep_equal(Point(1, 2), extraField);
+var plain = deep_equal(1, 1) and !deep_equal(1, "1");
+
+var c = Point(1, nil);
+c.y = c;
+var d = Point(1, nil);
+d.y = d;
+var cyclic = deep_equal(c, d);
+        "#;
+        assert_eq!(global_value(code, "identity"), Object::Bool(false));
+        assert_eq!(global_value(code, "selfIdentity"), Object::Bool(true));
+        assert_eq!(global_value(code, "same"), Object::Bool(true));
+        assert_eq!(global_value(code, "differentValue"), Object::Bool(false));
+        assert_eq!(global_value(code, "differentClass"), Object::Bool(false));
+        assert_eq!(global_value(code, "missingField"), Object::Bool(false));
+        assert_eq!(global_value(code, "plain"), Object::Bool(true));
+        assert_eq!(global_value(code, "cyclic"), Object::Bool(true));
+    }
+
+    #[test]
+    fn test_continue() {
+        let code = r#"
+var forSum = 0;
+for (var i = 0; i < 10; i = i + 1) {
+    if (i % 2 == 0) continue;
+    forSum = forSum + i;
+}
+var whileSum = 0;
+var n = 0;
+while (n < 10) {
+    n = n + 1;
+    {
+        var inner = n;
+        if (inner > 3) continue;
+    }
+    whileSum = whileSum + n;
+}
+var nested = "";
+for (var a = 0; a < 3; a = a + 1) {
+    for (var b = 0; b < 3; b = b + 1) {
+        if (b == a) continue;
+        nested = nested + a + b + ",";
+    }
+}
+var closures = "";
+var fns = nil;
+for (var k = 0; k < 3; k = k + 1) {
+    fun show() { return k; }
+    if (k == 1) { fns = show; continue; }
+}
+closures = closures + fns();
+        "#;
+        assert_eq!(global_value(code, "forSum"), Object::Int(25));
+        assert_eq!(global_value(code, "whileSum"), Object::Int(6));
+        assert_eq!(global_value(code, "nested"), Object::String("01,02,10,12,20,21,".to_string()));
+        assert_eq!(global_value(code, "closures"), Object::String("1".to_string()));
+    }
+
+    #[test]
+    fn test_increment_and_decrement() {
+        let code = r#"
+var x = 5;
+var prefix = ++x;
+var postfix = x++;
+var afterPostfix = x;
+var down = --x + x--;
+class Counter {
+    init() { this.count = 0; }
+    tick() {
+        this.count++;
+        return ++this.count;
+    }
+}
+var counter = Counter();
+var ticked = counter.tick();
+var count = counter.count;
+var local = "";
+{
+    var i = 0;
+    while (i < 3) local = local + i++;
+}
+var negated = -x++;
+        "#;
+        assert_eq!(global_value(code, "prefix"), Object::Int(6));
+        assert_eq!(global_value(code, "postfix"), Object::Int(6));
+        assert_eq!(global_value(code, "afterPostfix"), Object::Int(7));
+        assert_eq!(global_value(code, "down"), Object::Int(12));
+        assert_eq!(global_value(code, "ticked"), Object::Int(2));
+        assert_eq!(global_value(code, "count"), Object::Int(2));
+        assert_eq!(global_value(code, "local"), Object::String("012".to_string()));
+        assert_eq!(global_value(code, "negated"), Object::Int(-5));
+        assert_eq!(global_value(code, "x"), Object::Int(6));
+
+        assert_eq!(runtime_message("var s = \"a\"; ++s;"), "Operand must be a number, got string.");
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let code = r#"
+var name = "Lox";
+var age = 30;
+var greeting = "hello ${name}, you are ${age + 1}";
+var numbers = "${1}${2}";
+var nested = "outer ${"inner ${name}"}!";
+var call = "${clock() > 0 ? "yes" : "no"}";
+var plain = "cost: $5 {x}";
+        "#;
+        assert_eq!(global_value(code, "greeting"), Object::String("hello Lox, you are 31".to_string()));
+        assert_eq!(global_value(code, "numbers"), Object::String("12".to_string()));
+        assert_eq!(global_value(code, "nested"), Object::String("outer inner Lox!".to_string()));
+        assert_eq!(global_value(code, "call"), Object::String("yes".to_string()));
+        assert_eq!(global_value(code, "plain"), Object::String("cost: $5 {x}".to_string()));
+    }
+
+    #[test]
+    fn test_implicit_stringification() {
+        let code = r#"
+class Point {}
+fun f() {}
+var count = "count: " + 3;
+var ratio = 0.5 + "x";
+var flags = "" + true + nil;
+var instance = Point() + "!";
+var callables = "${f} ${clock} ${Point}";
+var sum = 1 + 2 + "3";
+        "#;
+        let string = |s: &str| Object::String(s.to_string());
+        assert_eq!(global_value(code, "count"), string("count: 3"));
+        assert_eq!(global_value(code, "ratio"), string("0.5x"));
+        assert_eq!(global_value(code, "flags"), string("truenil"));
+        assert_eq!(global_value(code, "instance"), string("<Point instance>!"));
+        assert_eq!(global_value(code, "callables"), string("<fn f()> <native fn clock> <class Point>"));
+        // 从左往右算，先做数字加法
+        assert_eq!(global_value(code, "sum"), string("33"));
+
+        assert_eq!(runtime_message("true + nil;"), "Operands must be two numbers or include a string, got bool and nil.");
+    }
+
+    #[test]
+    fn test_do_while() {
+        let code = r#"
+var once = 0;
+do once = once + 1; while (false);
+var count = 0;
+do {
+    var next = count + 1;
+    count = next;
+} while (count < 5);
+var odd = 0;
+var i = 0;
+do {
+    i = i + 1;
+    if (i % 2 == 0) continue;
+    if (i > 7) break;
+    odd = odd + i;
+} while (i < 10);
+        "#;
+        assert_eq!(global_value(code, "once"), Object::Int(1));
+        assert_eq!(global_value(code, "count"), Object::Int(5));
+        assert_eq!(global_value(code, "odd"), Object::Int(16));
+        assert_eq!(global_value(code, "i"), Object::Int(9));
+    }
+
+    #[test]
+    fn test_for_in() {
+        let code = r#"
+var reversed = "";
+for (var c in "héllo") reversed = c + reversed;
+var skipped = "";
+for (var c in "a-b-c!d") {
+    if (c == "-") continue;
+    if (c == "!") break;
+    skipped = skipped + c;
+}
+var fns = "";
+var first = nil;
+for (var c in "xyz") {
+    fun show() { return c; }
+    if (first == nil) first = show;
+}
+fns = first();
+var empty = 0;
+for (var c in "") empty = empty + 1;
+        "#;
+        assert_eq!(global_value(code, "reversed"), Object::String("olléh".to_string()));
+        assert_eq!(global_value(code, "skipped"), Object::String("abc".to_string()));
+        assert_eq!(global_value(code, "fns"), Object::String("x".to_string()));
+        assert_eq!(global_value(code, "empty"), Object::Int(0));
+        assert_eq!(runtime_message("for (var x in 42) print x;"), "Can only iterate over lists and strings, got number.");
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let code = r#"
+class Oops {
+    init(code) { this.code = code; }
+}
+fun fail(code) { throw Oops(code); }
+var caught = nil;
+try {
+    fail(42);
+    caught = "not reached";
+} catch (e) {
+    caught = e.code;
+}
+fun findFirst(s, target) {
+    for (var c in s) {
+        try {
+            if (c == target) return c;
+        } catch (e) {}
+    }
+    return nil;
+}
+var found = findFirst("abc", "b");
+var rethrown = nil;
+try {
+    try { throw "inner"; } catch (e) { throw e + "!"; }
+} catch (e) {
+    rethrown = e;
+}
+var count = 0;
+while (true) {
+    try { count = count + 1; if (count == 3) break; } catch (e) {}
+}
+        "#;
+        assert_eq!(global_value(code, "caught"), Object::Int(42));
+        assert_eq!(global_value(code, "found"), Object::String("b".to_string()));
+        assert_eq!(global_value(code, "rethrown"), Object::String("inner!".to_string()));
+        assert_eq!(global_value(code, "count"), Object::Int(3));
+        match run_err("fun f() {\n  throw \"boom\";\n}\nf();") {
+            Some(lox_err @ LoxErr::RuntimeThrow { line: 2, .. }) => {
+                assert_eq!(lox_err.to_string(), "Runtime Error: [line 2:3] Uncaught exception: boom.");
+            }
+            other => panic!("Expected uncaught exception, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_catch_runtime_errors() {
+        let code = "var x = nil;\ntry {\n    x.field;\n} catch (e) {\n    print e;\n}";
+        // 默认接不住
+        match run_err(code) {
+            Some(LoxErr::Runtime { line: 3, message, .. }) => assert_eq!(message, "Only instances have properties."),
+            other => panic!("Expected runtime error, got {:?}", other),
+        }
+
+        let mut lox = Lox::new();
+        lox.set_catch_runtime_errors(true);
+        let (output, errors) = lox.run_capture(code);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(output, "Only instances have properties.\n");
+
+        // 打开了也接不住栈溢出和断言失败
+        let (output, errors) = lox.run_capture("fun f() { f(); }\ntry { f(); } catch (e) { print e; }");
+        assert_eq!(output, "");
+        assert!(matches!(&errors[..], [LoxErr::Runtime { message, .. }] if message == "Stack overflow."), "{:?}", errors);
+        let (output, errors) = lox.run_capture("try {\n    assert 1 > 2, \"math\";\n} catch (e) { print e; }");
+        assert_eq!(output, "");
+        assert!(matches!(&errors[..], [LoxErr::Runtime { line: 2, message, .. }] if message == "Assertion failed: math."), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_assert() {
+        let code = r#"
+var evaluated = 0;
+fun message() { evaluated = evaluated + 1; return "unused"; }
+assert 1 + 1 == 2;
+assert "non-empty", message();
+        "#;
+        assert_eq!(global_value(code, "evaluated"), Object::Int(0));
+        assert_eq!(runtime_message("assert nil, \"value was \" + nil;"), "Assertion failed: value was nil.");
+        assert_eq!(runtime_message("assert false;"), "Assertion failed.");
+        match run_err("var x = 1;\n\nassert x == 2, x;") {
+            Some(lox_err @ LoxErr::Runtime { line: 3, .. }) => {
+                assert_eq!(lox_err.to_string(), "Runtime Error: [line 3:1] Assertion failed: 1.");
+            }
+            other => panic!("Expected assertion failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join(format!("rilox_import_{}", std::process::id()));
+        let write = |name: &str, code: &str| {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, code).unwrap();
+        };
+        write("lib/a.lox", "import b from \"b.lox\"; var loads = 1; fun square(x) { return b.mul(x, x); }");
+        // b 反过来导入 a，a 正在执行，拿到的是还没执行完的 a
+        write("lib/b.lox", "import a from \"a.lox\"; var name = \"b\"; fun mul(x, y) { return x * y; } fun loads() { return a.loads; }");
+        write("lib/c.lox", "var name = \"c\"; fun getName() { return name; }");
+        write("lib/bad.lox", "var = 1;");
+        write("main.lox", "");
+
+        let run = |code: &str| {
+            let (statements, _) = module::compile(code, MAX_EXPRESSION_DEPTH).unwrap();
+            let mut interpreter = Interpreter::new();
+            interpreter.set_script_path(&dir.join("main.lox"));
+            let result = statements.iter().try_for_each(|stmt| interpreter.execute(stmt));
+            (interpreter, result)
+        };
+
+        let (interpreter, result) = run(r#"
+import a from "lib/a.lox";
+import again from "lib/a.lox";
+import b from "lib/b.lox";
+var result = a.square(4);
+var same = a == again;
+var loads = b.loads();
+        "#);
+        result.unwrap();
+        assert_eq!(interpreter.get_global("result"), Some(Object::Int(16)));
+        assert_eq!(interpreter.get_global("same"), Some(Object::Bool(true)));
+        assert_eq!(interpreter.get_global("loads"), Some(Object::Int(1)));
+        assert_eq!(interpreter.get_global("a").unwrap().to_string(), "<module a>");
+        // 模块的顶层声明不会混进导入它的脚本
+        assert_eq!(interpreter.get_global("square"), None);
+        assert_eq!(interpreter.get_global("mul"), None);
+
+        // 两个模块里的同名声明互不影响，函数里的全局变量查的是定义它的模块
+        let (interpreter, result) = run(r#"
+var name = "main";
+import b from "lib/b.lox";
+import c from "lib/c.lox";
+var names = b.name + c.name + c.getName() + name;
+        "#);
+        result.unwrap();
+        assert_eq!(interpreter.get_global("names"), Some(Object::String("bccmain".to_string())));
+        assert!(matches!(run("import c from \"lib/c.lox\"; c.missing;").1, Err(LoxErr::Runtime { message, .. }) if message == "Undefined name 'missing' in module 'c'."));
+
+        match run("import \"lib/bad.lox\";").1 {
+            Err(LoxErr::Runtime { message, .. }) => assert_eq!(message, "Could not import 'lib/bad.lox': the module has errors."),
+            other => panic!("Expected import error, got {:?}", other),
+        }
+        match run("import \"missing.lox\";").1 {
+            Err(LoxErr::Runtime { message, .. }) => assert!(message.starts_with("Could not import 'missing.lox': "), "{}", message),
+            other => panic!("Expected import error, got {:?}", other),
+        }
+
+        // 出错的模块不算导入过，改好之后再导入会重新执行
+        write("lib/flaky.lox", "var ready = nil + 1;");
+        let (statements, _) = module::compile("import flaky from \"lib/flaky.lox\";", MAX_EXPRESSION_DEPTH).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_script_path(&dir.join("main.lox"));
+        assert!(interpreter.execute(&statements[0]).is_err());
+        write("lib/flaky.lox", "var ready = true;");
+        interpreter.execute(&statements[0]).unwrap();
+        interpreter.execute(&resolved("var ready = flaky.ready;")[0]).unwrap();
+        assert_eq!(interpreter.get_global("ready"), Some(Object::Bool(true)));
+
+        // 模块里的运行时错误显示模块的路径和源代码，出了模块之后主脚本里的错误还是显示主脚本的
+        write("lib/boom.lox", "fun explode() {\n    return nil + 1;\n}");
+        write("lib/broken.lox", "var ok = 1;\nvar bad = -\"x\";");
+        let code = "import boom from \"lib/boom.lox\";\nboom.explode();\nvar y = nil - 1;\nimport \"lib/broken.lox\";";
+        let mut interpreter = Interpreter::new();
+        interpreter.set_reporter(Box::new(|_: &Diagnostic| {}));
+        interpreter.set_script_path(&dir.join("main.lox"));
+        interpreter.set_source(code);
+        interpreter.interpret(&module::compile(code, MAX_EXPRESSION_DEPTH).unwrap().0);
+        let located: Vec<(Option<String>, Option<usize>, Option<String>)> = interpreter.take_diagnostics().into_iter()
+            .map(|diagnostic| (diagnostic.file.map(|file| file.replace('\\', "/")), diagnostic.line, diagnostic.source_line))
+            .collect();
+        match &located[..] {
+            [(Some(boom), Some(2), Some(boom_line)), (None, Some(3), Some(main_line)), (Some(broken), Some(2), Some(broken_line))] => {
+                assert!(boom.ends_with("lib/boom.lox"), "{}", boom);
+                assert_eq!(boom_line, "    return nil + 1;");
+                assert_eq!(main_line, "var y = nil - 1;");
+                assert!(broken.ends_with("lib/broken.lox"), "{}", broken);
+                assert_eq!(broken_line, "var bad = -\"x\";");
+            }
+            other => panic!("Expected errors located in the modules and the main script, got {:?}", other),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rest_parameters() {
+        let code = r#"
+fun sum(...numbers) {
+    var total = 0;
+    for (var n in numbers) total = total + n;
+    return total;
+}
+fun log(level, ...args) { return args; }
+class Point {
+    init(x, ...rest) { this.x = x; this.rest = rest; }
+}
+var total = sum(1, 2, 3, 4);
+var none = sum();
+var logged = log("info", "a", 1, nil);
+var onlyLevel = log("warn");
+var extra = Point(1, 2, 3).rest;
+var signature = log;
+        "#;
+        assert_eq!(global_value(code, "total"), Object::Int(10));
+        assert_eq!(global_value(code, "none"), Object::Int(0));
+        assert_eq!(global_value(code, "logged").to_string(), r#"["a", 1, nil]"#);
+        assert_eq!(global_value(code, "onlyLevel").to_string(), "[]");
+        assert_eq!(global_value(code, "extra").to_string(), "[2, 3]");
+        assert_eq!(global_value(code, "signature").to_string(), "<fn log(level, ...args)>");
+        assert_eq!(runtime_message("fun f(a, b, ...c) {} f(1);"), "Expected at least 2 arguments but got 1.");
+        assert_eq!(runtime_message("class A { init(a, ...b) {} } A();"), "Expected at least 1 arguments but got 0.");
+
+        // 列表按身份比较，deep_equal 逐个元素比较
+        let code = r#"
+fun list(...elements) { return elements; }
+var a = list(1, list("x"));
+var identity = a == list(1, list("x"));
+var selfIdentity = a == a;
+var same = deep_equal(a, list(1, list("x")));
+var different = deep_equal(a, list(1, list("y"))) or deep_equal(a, list(1));
+        "#;
+        assert_eq!(global_value(code, "identity"), Object::Bool(false));
+        assert_eq!(global_value(code, "selfIdentity"), Object::Bool(true));
+        assert_eq!(global_value(code, "same"), Object::Bool(true));
+        assert_eq!(global_value(code, "different"), Object::Bool(false));
+    }
+
+    #[test]
+    fn test_switch() {
+        let code = r#"
+fun describe(x) {
+    switch (x) {
+        case 1:
+            return "one";
+        case "two":
+            var word = "tw";
+            return word + "o";
+        case nil:
+            return "nothing";
+        default:
+            return "other";
+    }
+}
+var results = describe(1) + describe("two") + describe(nil) + describe(3) + describe("1");
+var noMatch = "unchanged";
+switch (42) {
+    case 1: noMatch = "changed";
+}
+var noFallthrough = "";
+switch (1) {
+    case 1: noFallthrough = noFallthrough + "a";
+    case 1: noFallthrough = noFallthrough + "b";
+    default: noFallthrough = noFallthrough + "c";
+}
+var evaluated = 0;
+fun next() { evaluated = evaluated + 1; return evaluated; }
+switch (1) {
+    case next(): print "first";
+    case next(): print "second";
+}
+var odd = 0;
+for (var i = 0; i < 6; i = i + 1) {
+    switch (i % 2) {
+        case 0: continue;
+        default:
+            if (i == 5) break;
+    }
+    odd = odd + i;
+}
+        "#;
+        assert_eq!(global_value(code, "results"), Object::String("onetwonothingotherother".to_string()));
+        assert_eq!(global_value(code, "noMatch"), Object::String("unchanged".to_string()));
+        assert_eq!(global_value(code, "noFallthrough"), Object::String("a".to_string()));
+        assert_eq!(global_value(code, "evaluated"), Object::Int(1));
+        assert_eq!(global_value(code, "odd"), Object::Int(4));
+    }
+
+    #[test]
+    fn test_native_function_arity() {
+        assert_eq!(runtime_message("clock(1, 2, 3);"), "Expected 0 arguments but got 3.");
+    }
+
+    #[test]
+    fn test_class_initializer_arity() {
+        let code = r#"
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+}
+Point(1);
+        "#;
+        assert_eq!(runtime_message(code), "Expected 2 arguments but got 1.");
+    }
+
+    #[test]
+    fn test_block() {
+        let mut lox = Lox::new();
+        let code = r#"var a = "global a";
+var b = "global b";
+var c = "global c";
+{
+var a = "outer a";
+var b = "outer b";
+{
+    var a = "inner a";
+    print a;
+    print b;
+    print c;
+}
+print a;
+print b;
+print c;
+}
+print a;
+print b;
+print c;
+        "#;
+        // let code = r#"print "code";"#;
+
+        lox.test_code(code);
+    }
 }
\ No newline at end of file
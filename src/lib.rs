@@ -1,17 +1,31 @@
-pub mod lox;
-pub mod err;
-pub mod object;
-pub mod token_type;
-pub mod token;
-pub mod scanner;
-pub mod expr;
-pub mod parser;
-pub mod interpreter;
-pub mod stmt;
-pub mod environment;
-pub mod lox_callable;
-pub mod lox_function;
-pub mod resolver;
-pub mod resolvable;
-pub mod lox_class;
-pub mod lox_instance;
+pub mod lox;
+pub mod err;
+pub mod diagnostic;
+pub mod object;
+pub mod native;
+pub mod token_type;
+pub mod token;
+pub mod scanner;
+pub mod expr;
+pub mod parser;
+pub mod formatter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lsp;
+pub mod interpreter;
+pub mod stmt;
+pub mod environment;
+pub mod gc;
+pub mod lox_callable;
+pub mod lox_function;
+pub mod resolver;
+pub mod optimizer;
+pub mod module;
+pub mod compiler;
+pub mod vm;
+pub mod resolvable;
+mod stack;
+pub mod snapshot;
+pub mod lox_class;
+pub mod lox_instance;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
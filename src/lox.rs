@@ -1,177 +1,1149 @@
-/*
-后缀`*`允许前一个符号或组重复零次或多次
-后缀`+`与此类似，但要求前面的生成式至少出现一次
-后缀`?`表示可选生成式，它之前的生成式可以出现零次或一次，但不能出现多次
-
-expression     -> assignment ( "," assignment )* ;    // 支持了逗号表达式
-assignment     → ( call "." )? IDENTIFIER "=" assignment
-               | conditional ;
-conditional    → logic_or ( "?" expression ":" conditional )? ; // 三元表达式是右结合，因此 : 后面还是 conditional
-logic_or       → logic_and ( "or" logic_and )* ;
-logic_and      → equality ( "and" equality )* ;
-equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
-term           → factor ( ( "-" | "+" ) factor )* ;     // term 项，项之间通常通过加法或减法连接
-factor         → unary ( ( "/" | "*" ) unary )* ;       // factor 因子，因子之间通常通过乘法或除法连接
-unary          → ( "!" | "-" ) unary | call ;
-call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;     // . 也是一种 call
-arguments      → assignment ( "," assignment )* ;   // 这里之前是 expression，但是现在 expression 里可能有逗号，就改成没有逗号的 assignment
-primary        → "true" | "false" | "nil" | "this"
-               | NUMBER | STRING | IDENTIFIER | "(" expression ")"
-               | "super" "." IDENTIFIER ;
-*/
-
-/*
-program        → declaration* EOF ;
-
-declaration    → classDecl      // 类的方法没有前导的`fun`关键字
-               | funDecl
-               | varDecl
-               | statement ;
-
-classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
-                 "{" function* "}" ;
-funDecl        → "fun" function ;
-function       → IDENTIFIER "(" parameters? ")" block ;
-parameters     → IDENTIFIER ( "," IDENTIFIER )* ;
-
-varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
-
-statement      → exprStmt
-               | forStmt
-               | ifStmt
-               | printStmt
-               | returnStmt
-               | whileStmt
-               | block
-               | breakStmt ;
-
-breakStmt      → "break" ";" ;
-
-returnStmt     → "return" expression? ";" ;
-forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
-                 expression? ";"
-                 expression? ")" statement ;
-whileStmt      → "while" "(" expression ")" statement ;
-ifStmt         → "if" "(" expression ")" statement
-               ( "else" statement )? ;
-block          → "{" declaration* "}" ;
-exprStmt       → expression ";" ;
-printStmt      → "print" expression ";" ;
-*/
-
-use std::fs;
-use std::io::Write;
-
-use crate::err::LoxErr;
-use crate::interpreter::Interpreter;
-use crate::parser::Parser;
-use crate::resolver::Resolver;
-use crate::scanner::Scanner;
-
-pub struct Lox {
-    interpreter: Interpreter,
-    scanner: Scanner,
-}
-
-impl Lox {
-    pub fn new() -> Lox {
-        Lox {
-            interpreter: Interpreter::new(),
-            scanner: Scanner::new(),
-        }
-    }
-    pub fn start(&mut self) {
-        if let Err(lox_err) = self.run_with_args() {
-            self.report_error(lox_err);
-        }
-    }
-
-    pub fn test_code(&mut self, code: &str) {
-        if let Err(lox_err) = self.run(code) {
-            self.report_error(lox_err);
-        }
-    }
-
-    fn run_with_args(&mut self) -> Result<(), LoxErr>{
-        let args: Vec<String> = std::env::args().collect();
-
-        if args.len() > 2 {
-            return Err(LoxErr::ScriptUsage);
-        } else if args.len() == 2 {
-            self.run_file(&args[1])?;
-        } else {
-            self.run_prompt()?;
-        }
-        Ok(())
-    }
-
-    fn run_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), LoxErr>{
-        let code = fs::read_to_string(path)?;
-        self.run(&code)?;
-        Ok(())
-    }
-
-    fn run_prompt(&mut self) -> Result<(), LoxErr> {
-        let mut input_line = String::new();
-
-        loop {
-            print!("> ");
-            std::io::stdout().flush()?;
-    
-            match std::io::stdin().read_line(&mut input_line) {
-                Ok(n) => {
-                    if n == 0 {
-                        // Windows 系统 Ctrl + Z
-                        break;
-                    }
-
-                    self.run(&input_line.trim())?;
-                    input_line.clear();
-
-
-                }
-                Err(error) => {
-                    return Err(LoxErr::Io(error));
-                }
-            }
-        }
-        Ok(())
-    
-    }
-
-    fn run(&mut self, code: &str) -> Result<(), LoxErr> {
-        
-        // 扫描遇到错误的话，在这里打印出来，并继续处理 token
-        self.scanner.load_code(code);
-        if let Err(scan_err) = self.scanner.scan_tokens() {
-            self.report_error(scan_err);
-        }
-
-        // 解析（语法分析）遇到错误的话，内部会处理
-        let mut parser = Parser::new(&self.scanner.tokens);
-        let mut statements = parser.parse();
-        
-        // 语义分析遇到错误的话，内部会处理，并停止
-        let mut resolver = Resolver::new();
-        resolver.resolve(&mut statements);
-        if resolver.had_resolve_error {
-            return Ok(())
-        }
-
-        // 可以看下 statements 长啥样
-        // dbg!(&statements);
-
-        // 解释执行遇到错误的话，内部会处理
-        self.interpreter.interpret(&statements);
-
-        Ok(())
-
-    }
-
-    fn report_error(&self, lox_err: LoxErr) {
-        eprintln!("{}", lox_err)
-    }
-
-    
-}
\ No newline at end of file
+/*
+后缀`*`允许前一个符号或组重复零次或多次
+后缀`+`与此类似，但要求前面的生成式至少出现一次
+后缀`?`表示可选生成式，它之前的生成式可以出现零次或一次，但不能出现多次
+
+expression     -> assignment ( "," assignment )* ;    // 支持了逗号表达式
+assignment     → ( call "." )? IDENTIFIER "=" assignment
+               | conditional ;
+conditional    → logic_or ( "?" expression ":" conditional )? ; // 三元表达式是右结合，因此 : 后面还是 conditional
+logic_or       → logic_and ( "or" logic_and )* ;
+logic_and      → equality ( "and" equality )* ;
+equality       → comparison ( ( "!=" | "==" ) comparison )* ;
+comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+term           → factor ( ( "-" | "+" ) factor )* ;     // term 项，项之间通常通过加法或减法连接
+factor         → unary ( ( "/" | "*" | "%" ) unary )* ;   // factor 因子，因子之间通常通过乘法、除法或取余连接
+unary          → ( "!" | "-" ) unary | ( "++" | "--" ) call | postfix ;    // 自增自减是赋值的语法糖
+postfix        → call ( "++" | "--" )? ;
+call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;     // . 也是一种 call
+arguments      → assignment ( "," assignment )* ;   // 这里之前是 expression，但是现在 expression 里可能有逗号，就改成没有逗号的 assignment
+primary        → "true" | "false" | "nil" | "this"
+               | NUMBER | STRING | IDENTIFIER | "(" expression ")"
+               | "super" "." IDENTIFIER
+               | interpolation ;
+interpolation  → ( INTERPOLATION expression )+ STRING ;  // "a ${b} c" 扫描成 INTERPOLATION("a ") b STRING(" c")
+*/
+
+/*
+program        → declaration* EOF ;
+
+declaration    → classDecl      // 类的方法没有前导的`fun`关键字
+               | funDecl
+               | varDecl
+               | constDecl
+               | statement ;
+
+classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
+                 "{" function* "}" ;
+funDecl        → "fun" function ;
+function       → IDENTIFIER "(" parameters? ")" block ;
+parameters     → IDENTIFIER ( "," IDENTIFIER )* ( "," "..." IDENTIFIER )?
+               | "..." IDENTIFIER ;     // 剩余参数把多出来的实参收进一个列表
+
+varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
+constDecl      → "const" IDENTIFIER "=" expression ";" ;
+
+statement      → exprStmt
+               | doWhileStmt
+               | forStmt
+               | forInStmt
+               | assertStmt
+               | ifStmt
+               | importStmt
+               | printStmt
+               | returnStmt
+               | switchStmt
+               | throwStmt
+               | tryStmt
+               | whileStmt
+               | block
+               | breakStmt
+               | continueStmt ;
+
+breakStmt      → "break" ";" ;
+continueStmt   → "continue" ";" ;
+
+returnStmt     → "return" expression? ";" ;
+forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
+                 expression? ";"
+                 expression? ")" statement ;
+forInStmt      → "for" "(" "var" IDENTIFIER "in" expression ")" statement ;
+whileStmt      → "while" "(" expression ")" statement ;
+doWhileStmt    → "do" statement "while" "(" expression ")" ";" ;
+ifStmt         → "if" "(" expression ")" statement
+               ( "else" statement )? ;
+switchStmt     → "switch" "(" expression ")" "{"
+                 ( "case" expression ":" declaration* )*
+                 ( "default" ":" declaration* )? "}" ;
+importStmt     → "import" STRING ";" ;     // 只能写在顶层
+assertStmt     → "assert" assignment ( "," assignment )? ";" ;
+throwStmt      → "throw" expression ";" ;
+tryStmt        → "try" block "catch" "(" IDENTIFIER ")" block ;
+block          → "{" declaration* "}" ;
+exprStmt       → expression ";" ;
+printStmt      → "print" expression ";" ;
+*/
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{BufRead, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::IsTerminal;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+
+#[cfg(not(target_arch = "wasm32"))]
+use rustyline::history::{FileHistory, History};
+#[cfg(not(target_arch = "wasm32"))]
+use rustyline::{Config, Editor};
+#[cfg(not(target_arch = "wasm32"))]
+use rustyline::error::ReadlineError;
+
+use crate::compiler::Compiler;
+use crate::diagnostic::{Diagnostic, DiagnosticFormat, ErrorReporter, Severity, StderrReporter};
+use crate::err::LoxErr;
+use crate::expr::Expr;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::formatter;
+use crate::interpreter::{Interpreter, Trace};
+use crate::native::{self, NativeFunction};
+use crate::object::Object;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::snapshot::Snapshot;
+use crate::stmt::Stmt;
+use crate::vm::Vm;
+
+pub struct Lox {
+    interpreter: Interpreter,
+    scanner: Scanner,
+    resolver: Resolver, // 整个会话共用，REPL 里前面几行声明的全局常量后面还认得
+    vm: Option<Vm>, // 用 --vm 时编译成字节码交给虚拟机执行，而不是树遍历
+    had_error: bool,    // 扫描、解析、语义分析或编译出错
+    had_runtime_error: bool,    // 虚拟机的运行时错误，树遍历解释器的记在 interpreter 里
+    exit_code: Option<i32>, // 虚拟机里调用了 exit
+    deny_warnings: bool,    // --deny-warnings：警告当成错误，不再执行
+}
+
+// 和原书一样用 sysexits.h 里的退出码
+#[cfg(not(target_arch = "wasm32"))]
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATA_ERROR: i32 = 65;
+const EXIT_SOFTWARE: i32 = 70;
+#[cfg(not(target_arch = "wasm32"))]
+const EXIT_IO_ERROR: i32 = 74;
+
+impl Lox {
+    pub fn new() -> Lox {
+        Lox {
+            interpreter: Interpreter::new(),
+            scanner: Scanner::new(),
+            resolver: Resolver::new(),
+            vm: None,
+            had_error: false,
+            had_runtime_error: false,
+            exit_code: None,
+            deny_warnings: false,
+        }
+    }
+
+    // 换掉默认的打印到标准错误，错误和警告都交给 reporter
+    pub fn set_reporter(&mut self, reporter: Box<dyn ErrorReporter>) {
+        self.interpreter.set_reporter(reporter);
+    }
+
+    pub fn set_diagnostic_format(&mut self, format: DiagnosticFormat) {
+        self.set_reporter(Box::new(StderrReporter::new(format)));
+    }
+
+    pub fn set_warn_on_shadowing(&mut self, warn: bool) {
+        self.resolver.warn_on_shadowing = warn;
+    }
+
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    // 让 catch 也能接住运行时错误，见 Interpreter::catch_runtime_errors
+    pub fn set_catch_runtime_errors(&mut self, catch: bool) {
+        self.interpreter.catch_runtime_errors = catch;
+    }
+
+    // 原生函数 args() 返回的列表，默认是空的
+    pub fn set_script_args(&mut self, args: Vec<String>) {
+        let native_function = NativeFunction::new("args", 0, native::script_args(args));
+        if let Some(vm) = &mut self.vm {
+            vm.globals.insert(Rc::clone(&native_function.name), Object::NativeFunction(native_function.clone()));
+        }
+        self.interpreter.set_global("args", Object::NativeFunction(native_function));
+    }
+
+    // 表达式最多嵌套多少层，括号、一元运算符、调用的参数这些才算一层，a + b + c 这样的一串不算。导入的模块也用这个上限
+    pub fn set_max_expression_depth(&mut self, depth: usize) {
+        self.interpreter.max_expression_depth = depth;
+    }
+
+    // 跟踪信息打印到标准错误。只有树遍历解释器支持
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.interpreter.set_trace(trace, Box::new(std::io::stderr()));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    // 返回进程的退出码
+    pub fn start(&mut self) -> i32 {
+        match self.run_with_args() {
+            Ok(code) => code,
+            Err(lox_err) => {
+                let code = match lox_err {
+                    LoxErr::ScriptUsage => EXIT_USAGE,
+                    _ => EXIT_IO_ERROR,
+                };
+                self.report_error(lox_err);
+                code
+            }
+        }
+    }
+
+    // 脚本调用了 exit 的话，是它的参数
+    fn requested_exit(&self) -> Option<i32> {
+        self.interpreter.exit_code.or(self.exit_code)
+    }
+
+    // 运行脚本文件后的退出码：exit 指定的优先，其次是静态错误 65，运行时错误 70
+    pub fn exit_status(&self) -> i32 {
+        if let Some(code) = self.requested_exit() {
+            code
+        } else if self.had_error {
+            EXIT_DATA_ERROR
+        } else if self.had_runtime_error || self.interpreter.had_runtime_error {
+            EXIT_SOFTWARE
+        } else {
+            0
+        }
+    }
+
+    pub fn test_code(&mut self, code: &str) {
+        let _ = self.run(code);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run_with_args(&mut self) -> Result<i32, LoxErr>{
+        let mut args: Vec<String> = std::env::args().collect();
+        // 脚本文件后面的参数都留给脚本，rilox 自己的参数要写在脚本文件前面
+        let script_args = match args.iter().skip(1).position(|arg| !arg.starts_with("--")) {
+            Some(pos) if args[pos + 1] != "fmt" => args.split_off(pos + 2),
+            _ => Vec::new(),
+        };
+
+        // --json 是 --diagnostics=json 的简写
+        if let Some(pos) = args.iter().position(|arg| arg == "--json" || arg.starts_with("--diagnostics=")) {
+            let format = match args.remove(pos).as_str() {
+                "--json" | "--diagnostics=json" => DiagnosticFormat::Json,
+                "--diagnostics=human" => DiagnosticFormat::Human,
+                _ => return Err(LoxErr::ScriptUsage),
+            };
+            self.set_diagnostic_format(format);
+        }
+
+        if take_flag(&mut args, "--warn-shadowing") {
+            self.set_warn_on_shadowing(true);
+        }
+        if take_flag(&mut args, "--deny-warnings") {
+            self.set_deny_warnings(true);
+        }
+        if take_flag(&mut args, "--catch-runtime-errors") {
+            self.set_catch_runtime_errors(true);
+        }
+        if let Some(pos) = args.iter().position(|arg| arg == "--trace" || arg.starts_with("--trace=")) {
+            let trace = match args.remove(pos).as_str() {
+                "--trace" => Trace::Statements,
+                "--trace=vars" => Trace::Variables,
+                _ => return Err(LoxErr::ScriptUsage),
+            };
+            self.set_trace(trace);
+        }
+        if take_flag(&mut args, "--vm") {
+            self.vm = Some(Vm::new());
+        }
+        let check = take_flag(&mut args, "--check");
+        let dump = if take_flag(&mut args, "--tokens") {
+            Some(Dump::Tokens)
+        } else if take_flag(&mut args, "--ast") {
+            Some(Dump::Ast)
+        } else if let Some(pos) = args.iter().position(|arg| arg.starts_with("--emit=")) {
+            match args.remove(pos).as_str() {
+                "--emit=ast-json" => Some(Dump::AstJson),
+                _ => return Err(LoxErr::ScriptUsage),
+            }
+        } else {
+            None
+        };
+
+        if args.get(1).is_some_and(|arg| arg == "fmt") {
+            let write = take_flag(&mut args, "--write");
+            let [_, _, path] = &args[..] else {
+                return Err(LoxErr::ScriptUsage);
+            };
+            self.format_file(path, write)?;
+            return Ok(self.exit_status());
+        }
+
+        if args.len() > 2 {
+            Err(LoxErr::ScriptUsage)
+        } else if check {
+            let Some(path) = args.get(1) else {
+                return Err(LoxErr::ScriptUsage);
+            };
+            self.check_file(path)?;
+            Ok(self.exit_status())
+        } else if let Some(dump) = dump {
+            // 只对文件有用，REPL 里没有意义
+            let Some(path) = args.get(1) else {
+                return Err(LoxErr::ScriptUsage);
+            };
+            self.dump(path, dump)?;
+            Ok(self.exit_status())
+        } else if args.len() == 2 {
+            self.set_script_args(script_args);
+            // 错误都已经报告过了，读写文件出错的话退出码是 74，其他的看 exit_status
+            match self.run_file(&args[1]) {
+                Err(errors) if errors.iter().any(|lox_err| matches!(lox_err, LoxErr::Io(_))) => Ok(EXIT_IO_ERROR),
+                _ => Ok(self.exit_status()),
+            }
+        } else {
+            // REPL 里的错误不影响退出码
+            self.install_interrupt_handler();
+            if std::io::stdin().is_terminal() {
+                self.run_editor()?;
+            } else {
+                self.run_prompt(std::io::stdin().lock())?;
+            }
+            Ok(self.requested_exit().unwrap_or(0))
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    // REPL 里按 Ctrl-C 只中止正在运行的那一行，回到提示符，而不是结束整个进程
+    fn install_interrupt_handler(&self) {
+        let mut flags = vec![self.interpreter.interrupt_handle()];
+        flags.extend(self.vm.as_ref().map(Vm::interrupt_handle));
+        // 装不上的话 Ctrl-C 还是直接结束进程，不影响 REPL 使用
+        let _ = ctrlc::set_handler(move || {
+            for flag in &flags {
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+
+    fn clear_interrupt(&self) {
+        self.interpreter.interrupt_handle().store(false, Ordering::Relaxed);
+        if let Some(vm) = &self.vm {
+            vm.interrupt_handle().store(false, Ordering::Relaxed);
+        }
+    }
+
+    // 运行脚本文件，同 run。读不了文件也当成错误报告并返回
+    pub fn run_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), Vec<LoxErr>> {
+        let code = match fs::read_to_string(&path) {
+            Ok(code) => code,
+            Err(io_err) => {
+                self.interpreter.capture_errors(true);
+                self.report_error(io_err.into());
+                return Err(self.interpreter.take_captured_errors());
+            }
+        };
+        self.interpreter.set_script_path(path.as_ref());
+        self.interpreter.set_source(&code);
+        self.run(&code)
+    }
+
+    // 交互模式，从 input 一行一行地读。不是终端（比如管道）的时候用这个
+    pub fn run_prompt<R: BufRead>(&mut self, mut input: R) -> Result<(), LoxErr> {
+        self.prompt_loop(|prompt| {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+
+            let mut input_line = String::new();
+            if input.read_line(&mut input_line)? == 0 {
+                // Windows 系统 Ctrl + Z
+                return Ok(PromptInput::End);
+            }
+            Ok(PromptInput::Line(input_line))
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    // 在终端里用 rustyline 读输入：方向键翻历史，Ctrl-A、Ctrl-E 这些编辑键都能用，历史记录存在 ~/.rilox_history
+    // rustyline 的 Editor 活着的时候会换掉 SIGINT 的处理函数，Ctrl-C 就中止不了正在运行的代码
+    // 所以每读一行新建一个 Editor，读完就丢掉，历史记录在它们之间传递
+    fn run_editor(&mut self) -> Result<(), LoxErr> {
+        let history_path = std::env::home_dir().map(|home| home.join(HISTORY_FILE));
+        let mut history = FileHistory::new();
+        if let Some(path) = &history_path {
+            let _ = history.load(path);  // 第一次用的时候还没有这个文件
+        }
+
+        let result = self.prompt_loop(|prompt| {
+            let mut editor = Editor::<(), FileHistory>::with_history(Config::default(), std::mem::replace(&mut history, FileHistory::new())).map_err(readline_err)?;
+            let line = editor.readline(prompt);
+            history = std::mem::replace(editor.history_mut(), FileHistory::new());
+            drop(editor);
+            match line {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        let _ = history.add(&line);
+                    }
+                    Ok(PromptInput::Line(line + "\n"))
+                }
+                Err(ReadlineError::Interrupted) => Ok(PromptInput::Cancel),
+                Err(ReadlineError::Eof) => Ok(PromptInput::End),
+                Err(err) => Err(readline_err(err)),
+            }
+        });
+
+        if let Some(path) = &history_path {
+            let _ = history.save(path);
+        }
+        result
+    }
+
+    // 交互模式的主循环，read 显示提示符并读一行
+    // 输入没写完（比如块或括号没闭合、函数跨了好几行）时显示续行提示符，接着读下一行，攒够了再一起执行
+    // 续行时输入空行表示不再等待，直接执行并报告错误
+    fn prompt_loop<F: FnMut(&str) -> Result<PromptInput, LoxErr>>(&mut self, mut read: F) -> Result<(), LoxErr> {
+        let mut code = String::new();
+
+        loop {
+            let input_line = match read(if code.is_empty() { "> " } else { ".. " })? {
+                PromptInput::Line(input_line) => input_line,
+                PromptInput::Cancel => {
+                    code.clear();
+                    continue;
+                }
+                PromptInput::End => break,
+            };
+
+            // Lox 的语句不会以冒号开头，:save 这些是 REPL 自己的命令
+            if let Some(command) = input_line.trim().strip_prefix(':').filter(|_| code.is_empty()) {
+                self.run_command(command);
+                continue;
+            }
+
+            let gave_up = !code.is_empty() && input_line.trim().is_empty();
+            code.push_str(&input_line);
+            if !gave_up && self.is_incomplete(&code) {
+                continue;
+            }
+
+            if let Some(value) = self.run_prompt_line(code.trim()) {
+                println!("{}", value);
+            }
+            code.clear();
+            self.take_diagnostics();    // 已经打印过了，REPL 里不用一直攒着
+            if self.requested_exit().is_some() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // :save 文件 把全局变量存下来，:load 文件 恢复。只有树遍历解释器支持
+    fn run_command(&mut self, command: &str) {
+        if self.vm.is_some() {
+            println!("Snapshots are not supported with --vm.");
+            return;
+        }
+        let result = match command.split_once(' ').map(|(name, path)| (name, path.trim())) {
+            Some(("save", path)) => self.save_snapshot(path).map(|skipped| {
+                if !skipped.is_empty() {
+                    println!("Not saved: {}.", skipped.join(", "));
+                }
+            }),
+            Some(("load", path)) => self.load_snapshot(path),
+            _ => {
+                println!("Unknown command. Use :save <file> or :load <file>.");
+                return;
+            }
+        };
+        if let Err(lox_err) = result {
+            self.report_error(lox_err);
+        }
+    }
+
+    // 代码是不是只写了一半：字符串或块注释没闭合，或者解析到末尾才出错
+    fn is_incomplete(&mut self, code: &str) -> bool {
+        self.scanner.load_code(code);
+        if self.scanner.scan_tokens().is_err() {
+            return self.scanner.unterminated;
+        }
+        if Parser::new(&self.scanner.tokens).parse_bare_expression().is_some() {
+            return false;
+        }
+        let mut parser = Parser::new(&self.scanner.tokens);
+        parser.max_expression_depth = self.interpreter.max_expression_depth;
+        parser.parse();
+        parser.unexpected_eof
+    }
+
+    // 执行 REPL 里的一行。输入是不带分号的单个表达式时，像 Python 的 REPL 一样返回它的值，由 run_prompt 打印
+    // 其他输入和运行文件一样执行，返回 None
+    pub fn run_prompt_line(&mut self, line: &str) -> Option<String> {
+        // 每次输入单独算，上一行的运行时错误不影响这一行，等待输入时按的 Ctrl-C 也不算
+        self.interpreter.had_runtime_error = false;
+        self.clear_interrupt();
+        // 运行时错误显示出错的代码用这一行
+        self.interpreter.set_source(line);
+        self.scanner.load_code(line);
+        if self.scanner.scan_tokens().is_ok() {
+            if let Some(expression) = Parser::new(&self.scanner.tokens).parse_bare_expression() {
+                return self.run_bare_expression(expression);
+            }
+        }
+        // 扫描出错的话，run 会重新扫描一遍并报告错误。错误都已经打印了，返回值不用管
+        let _ = self.run(line);
+        None
+    }
+
+    fn run_bare_expression(&mut self, expression: Expr) -> Option<String> {
+        let start = Rc::clone(&self.scanner.tokens[0]);
+        let mut statements = vec![Stmt::Expression { start: Rc::clone(&start), expression: expression }];
+        if !self.resolve(&mut statements) {
+            return None;
+        }
+        let Some(Stmt::Expression { expression, .. }) = statements.pop() else {
+            unreachable!("Resolver keeps the bare expression statement.");
+        };
+
+        // 虚拟机没有返回值的接口，直接编译成 print 语句
+        if let Some(vm) = &mut self.vm {
+            let result = Compiler::compile(&[Stmt::Print { keyword: start, expression: expression }]).and_then(|function| vm.interpret(function));
+            if let Err(lox_err) = result {
+                self.report_error(lox_err);
+            }
+            return None;
+        }
+
+        self.interpreter.interpret_expression(&expression).map(|value| value.to_string())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    // 只跑前端，把 token 或者语法树打印出来，不执行
+    fn dump<P: AsRef<std::path::Path>>(&mut self, path: P, dump: Dump) -> Result<(), LoxErr> {
+        let code = fs::read_to_string(&path)?;
+        self.interpreter.set_source(&code);
+        match dump {
+            Dump::Tokens => {
+                self.scanner.load_code(&code);
+                if let Err(scan_err) = self.scanner.scan_tokens() {
+                    self.report_error(scan_err);
+                }
+                for token in &self.scanner.tokens {
+                    println!("{}", token);
+                }
+            }
+            Dump::Ast => {
+                for statement in self.parse(&code).0 {
+                    println!("{}", statement);
+                }
+            }
+            Dump::AstJson => {
+                let (statements, _) = self.parse(&code);
+                println!("{}", serde_json::to_string_pretty(&statements).expect("Syntax trees always serialize to JSON."));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    // rilox fmt：打印格式化后的代码，--write 的话写回文件。代码有错误时只报告错误
+    fn format_file(&mut self, path: &str, write: bool) -> Result<(), LoxErr> {
+        let code = fs::read_to_string(path)?;
+        self.interpreter.set_source(&code);
+        match formatter::format(&code) {
+            Ok(formatted) if write => fs::write(path, formatted)?,
+            Ok(formatted) => print!("{}", formatted),
+            Err(lox_err) => self.report_error(lox_err),
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    // --check：只扫描、解析、做语义分析，报告错误和警告，什么都不执行。CI 里检查脚本用
+    fn check_file(&mut self, path: &str) -> Result<(), LoxErr> {
+        let code = fs::read_to_string(path)?;
+        self.interpreter.set_source(&code);
+        self.check(&code);
+        Ok(())
+    }
+
+    // 代码没有错误的话返回 true。运行时错误只有执行了才知道，不算
+    pub fn check(&mut self, code: &str) -> bool {
+        let had_error = std::mem::replace(&mut self.had_error, false);
+        let (mut statements, _) = self.parse(code);
+        let resolved = self.resolve(&mut statements);
+        let passed = resolved && !self.had_error;
+        self.had_error |= had_error;
+        passed
+    }
+
+    // 返回解析出的语句，以及扫描和解析有没有出错
+    fn parse(&mut self, code: &str) -> (Vec<Stmt>, bool) {
+        // 扫描遇到错误的话，在这里打印出来，并继续处理 token
+        self.scanner.load_code(code);
+        let scan_result = self.scanner.scan_tokens();
+        let mut had_error = scan_result.is_err();
+        if let Err(scan_err) = scan_result {
+            self.report_error(scan_err);
+        }
+
+        // 解析（语法分析）遇到错误的话，在这里打印出来，丢掉出错的语句后继续
+        let mut parser = Parser::new(&self.scanner.tokens);
+        parser.max_expression_depth = self.interpreter.max_expression_depth;
+        let statements = parser.parse();
+        let parse_errors = std::mem::take(&mut parser.errors);
+        had_error |= !parse_errors.is_empty();
+        for parse_err in parse_errors {
+            self.report_error(parse_err);
+        }
+        (statements, had_error)
+    }
+
+    // 执行一段代码。错误照常报告，同时按出现的顺序全部返回，调用方可以检查具体出了什么错
+    // 和原书一样，扫描、解析或语义分析出错时什么都不执行；顶层语句的运行时错误不影响后面的语句。调用 exit 不算错误
+    pub fn run(&mut self, code: &str) -> Result<(), Vec<LoxErr>> {
+        self.interpreter.capture_errors(true);
+        self.execute(code);
+        let errors = self.interpreter.take_captured_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // 遇到错误就报告
+    fn execute(&mut self, code: &str) {
+        // 扫描或解析出错的话，错误已经报告过了，整段代码都不执行
+        let (mut statements, had_error) = self.parse(code);
+        if had_error {
+            return;
+        }
+
+        // 语义分析遇到错误的话，在这里打印出来，并停止
+        if !self.resolve(&mut statements) {
+            return;
+        }
+
+        // 去掉条件是字面量的死分支
+        optimizer::eliminate_dead_code(&mut statements);
+
+        // 可以看下 statements 长啥样
+        // dbg!(&statements);
+
+        if let Some(vm) = &mut self.vm {
+            let result = Compiler::compile(&statements).and_then(|function| vm.interpret(function));
+            if let Err(lox_err) = result {
+                self.report_error(lox_err);
+            }
+            return;
+        }
+
+        // 解释执行遇到错误的话，内部会处理
+        self.interpreter.interpret(&statements);
+    }
+
+    // 语义分析，打印警告和错误。有错误时返回 false
+    // 警告不影响执行和退出码，除非用了 --deny-warnings，这时警告按错误报告
+    fn resolve(&mut self, statements: &mut Vec<Stmt>) -> bool {
+        self.resolver.resolve_input(statements);
+        let warnings = std::mem::take(&mut self.resolver.warnings);
+        let denied = self.deny_warnings && !warnings.is_empty();
+        for mut warning in warnings {
+            if self.deny_warnings {
+                warning.severity = Severity::Error;
+            }
+            self.interpreter.report_diagnostic(warning);
+        }
+        for resolve_err in std::mem::take(&mut self.resolver.errors) {
+            self.report_error(resolve_err);
+        }
+        if denied {
+            self.had_error = true;
+        }
+        !self.resolver.had_resolve_error && !denied
+    }
+
+    // 报告错误，同时记下错误的种类，决定退出码
+    fn report_error(&mut self, lox_err: LoxErr) {
+        match lox_err {
+            LoxErr::Exit { code } => {
+                self.exit_code = Some(code);
+                return;
+            }
+            LoxErr::Runtime { .. } | LoxErr::RuntimeThrow { .. } | LoxErr::Aborted { .. } => self.had_runtime_error = true,
+            LoxErr::ScriptUsage | LoxErr::Io(_) => (),
+            _ => self.had_error = true,
+        }
+        self.interpreter.report(lox_err);
+    }
+
+    // print 语句输出到哪里，默认是标准输出。只影响树遍历解释器
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.interpreter.set_output(output);
+    }
+
+    // 运行一段代码，返回它打印的内容和出的所有错误，都不输出到终端。警告还是照常交给 reporter
+    pub fn run_capture(&mut self, code: &str) -> (String, Vec<LoxErr>) {
+        let buffer = CaptureBuffer::default();
+        let interpreter_output = self.interpreter.set_output(Box::new(buffer.clone()));
+        let vm_output = self.vm.as_mut().map(|vm| vm.set_output(Box::new(buffer.clone())));
+        self.interpreter.capture_errors(false);
+        self.execute(code);
+
+        let errors = self.interpreter.take_captured_errors();
+        self.interpreter.set_output(interpreter_output);
+        if let (Some(vm), Some(output)) = (&mut self.vm, vm_output) {
+            vm.set_output(output);
+        }
+        (buffer.into_string(), errors)
+    }
+
+    // 见 Interpreter::set_global 和 get_global。只有树遍历解释器的全局变量
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.interpreter.set_global(name, value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.interpreter.get_global(name)
+    }
+
+    // 把全局变量存到文件里，返回没存下来的函数、类这些的名字
+    pub fn save_snapshot<P: AsRef<std::path::Path>>(&self, path: P) -> Result<Vec<String>, LoxErr> {
+        let snapshot = self.interpreter.snapshot();
+        fs::write(path, snapshot.to_json())?;
+        Ok(snapshot.skipped)
+    }
+
+    // 恢复 save_snapshot 存下的全局变量，同名的会被覆盖
+    pub fn load_snapshot<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<(), LoxErr> {
+        let snapshot = Snapshot::from_json(&fs::read_to_string(path)?)?;
+        for global in &snapshot.globals {
+            self.resolver.set_global_constant(&global.name, global.constant);
+        }
+        self.interpreter.restore(&snapshot);
+        Ok(())
+    }
+
+    // 嵌入到别的程序里用：执行一段代码，返回最后一条表达式语句的值，最后一条不是表达式语句的话返回 nil
+    // 错误不打印，全部返回。扫描、解析或语义分析出错时什么都不执行；调用了 exit 的话返回 LoxErr::Exit，由调用方决定怎么退出
+    // 总是用树遍历解释器，虚拟机没有返回值的接口。警告还是照常交给 reporter
+    pub fn eval(&mut self, code: &str) -> Result<Object, Vec<LoxErr>> {
+        self.interpreter.capture_errors(false);
+        self.interpreter.exit_code = None;
+        let value = self.eval_captured(code);
+        let mut errors = self.interpreter.take_captured_errors();
+        if let Some(code) = self.interpreter.exit_code {
+            errors.push(LoxErr::Exit { code: code });
+        }
+        match value {
+            Some(value) if errors.is_empty() => Ok(value),
+            _ => Err(errors),
+        }
+    }
+
+    fn eval_captured(&mut self, code: &str) -> Option<Object> {
+        let (mut statements, had_error) = self.parse(code);
+        if had_error || !self.resolve(&mut statements) || self.interpreter.has_captured_errors() {
+            return None;
+        }
+        // 最后的表达式在优化之前拿出来，免得 if (true) 1; 这种被优化成表达式语句
+        let last = match statements.last() {
+            Some(Stmt::Expression { .. }) => statements.pop(),
+            _ => None,
+        };
+        optimizer::eliminate_dead_code(&mut statements);
+        self.interpreter.interpret(&statements);
+        if self.interpreter.has_captured_errors() || self.interpreter.exit_code.is_some() {
+            return None;
+        }
+        match last {
+            Some(Stmt::Expression { expression, .. }) => self.interpreter.interpret_expression(&expression),
+            _ => Some(Object::None),
+        }
+    }
+
+    // 到目前为止报告过的所有错误和警告，包括运行时的，取走后清空
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.interpreter.take_diagnostics()
+    }
+}
+
+// 交互模式读到的一次输入
+enum PromptInput {
+    Line(String),
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]   // 只有 rustyline 读得出 Ctrl-C
+    Cancel, // 在提示符下按了 Ctrl-C，丢掉还没写完的输入
+    End,    // Ctrl-D，Windows 上是 Ctrl-Z
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const HISTORY_FILE: &str = ".rilox_history";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn readline_err(err: ReadlineError) -> LoxErr {
+    match err {
+        ReadlineError::Io(io_err) => LoxErr::Io(io_err),
+        other => LoxErr::Io(std::io::Error::other(other)),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+// --tokens 打印扫描出的 token，--ast 打印解析出的语法树，--emit=ast-json 把语法树输出成 JSON 给别的工具用
+enum Dump {
+    Tokens,
+    Ast,
+    AstJson,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+// 命令行参数里有 flag 的话去掉它，返回 true
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+// run_capture 用的输出，解释器和虚拟机各拿一个，写进同一块缓冲区
+#[derive(Clone, Default)]
+struct CaptureBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl CaptureBuffer {
+    fn into_string(self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for CaptureBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Object;
+
+    #[test]
+    fn test_prompt_prints_bare_expression() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.run_prompt_line("1 + 2"), Some("3".to_string()));
+        assert_eq!(lox.run_prompt_line("var x = 1;"), None);
+        assert_eq!(lox.run_prompt_line("x"), Some("1".to_string()));
+        assert_eq!(lox.run_prompt_line("x = x + 1"), Some("2".to_string()));
+        // 带分号的表达式语句照常执行，不显示结果
+        assert_eq!(lox.run_prompt_line("x = 10;"), None);
+        assert_eq!(lox.run_prompt_line("\"x is \" + x"), Some("x is 10".to_string()));
+        assert_eq!(lox.run_prompt_line("fun f() { return x; }"), None);
+        assert_eq!(lox.run_prompt_line("f()"), Some("10".to_string()));
+    }
+
+    #[test]
+    fn test_prompt_incomplete_input() {
+        let mut lox = Lox::new();
+        assert!(lox.is_incomplete("fun f() {\n"));
+        assert!(lox.is_incomplete("print (1 +\n"));
+        assert!(lox.is_incomplete("var x =\n"));
+        assert!(lox.is_incomplete("print \"abc\n"));
+        assert!(lox.is_incomplete("/* comment\n"));
+        assert!(!lox.is_incomplete("fun f() {}\n"));
+        assert!(!lox.is_incomplete("1 + 2\n"));
+        // 错误不在末尾的话，再读几行也没用，直接报错
+        assert!(!lox.is_incomplete("print );\n{\n"));
+        assert!(!lox.is_incomplete("print + 3\n"));
+    }
+
+    #[test]
+    fn test_collect_diagnostics() {
+        let mut lox = Lox::new();
+        lox.test_code("var a = 1 +;\n\nprint nope;");
+        lox.test_code("print 1 - nil;");
+        let diagnostics: Vec<(Severity, &str, Option<usize>, Option<usize>)> = lox.take_diagnostics().iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.code, diagnostic.line, diagnostic.column))
+            .collect();
+        assert_eq!(diagnostics, vec![
+            (Severity::Error, "parse-error", Some(1), Some(12)),
+            (Severity::Error, "runtime-error", Some(1), Some(9)),
+        ]);
+        assert!(lox.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_custom_reporter() {
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&reported);
+        let mut lox = Lox::new();
+        lox.set_reporter(Box::new(move |diagnostic: &Diagnostic| sink.borrow_mut().push(diagnostic.code)));
+        lox.test_code("print 1 +;");
+        lox.test_code("print -nil;");
+        assert_eq!(*reported.borrow(), vec!["parse-error", "runtime-error"]);
+    }
+
+    #[test]
+    fn test_run_capture() {
+        let mut lox = Lox::new();
+        let (output, errors) = lox.run_capture("print 1 + 2;\nprint \"a\" + \"b\";\nprint nil - 1;\nprint 4;");
+        assert_eq!(output, "3\nab\n4\n");
+        let messages: Vec<String> = errors.iter().map(LoxErr::to_string).collect();
+        assert_eq!(messages, vec!["Runtime Error: [line 3:11] Operand must be a number, got nil.".to_string()]);
+        assert!(lox.take_diagnostics().is_empty());
+
+        let (output, errors) = lox.run_capture("print 1 +;");
+        assert_eq!(output, "");
+        assert!(matches!(errors[..], [LoxErr::Parse { line: 1, .. }]));
+    }
+
+    #[test]
+    fn test_run_returns_errors() {
+        let mut lox = Lox::new();
+        lox.set_reporter(Box::new(|_: &Diagnostic| {}));
+        let output = CaptureBuffer::default();
+        lox.set_output(Box::new(output.clone()));
+        assert!(lox.run("print 1;").is_ok());
+        let errors = lox.run("print nil - 1;\nprint 2;\nvar a = 1 +;").unwrap_err();
+        assert!(matches!(errors[..], [LoxErr::Parse { line: 3, .. }]));
+        let errors = lox.run("print nil - 1;\nprint 2;").unwrap_err();
+        assert!(matches!(errors[..], [LoxErr::Runtime { line: 1, .. }]));
+        assert_eq!(output.into_string(), "1\n2\n");
+        // 照常报告
+        assert_eq!(lox.take_diagnostics().len(), 2);
+        assert!(matches!(lox.run("return 1;").unwrap_err()[..], [LoxErr::Resolve { .. }]));
+        assert!(lox.run("exit(0);").is_ok());
+        assert!(matches!(lox.run_file("no/such/script.lox").unwrap_err()[..], [LoxErr::Io(_)]));
+    }
+
+    #[test]
+    fn test_script_args() {
+        for use_vm in [false, true] {
+            let mut lox = Lox::new();
+            if use_vm {
+                lox.vm = Some(Vm::new());
+            }
+            let (output, _) = lox.run_capture("print args();");
+            assert_eq!(output, "[]\n");
+            lox.set_script_args(vec!["a".to_string(), "--b".to_string()]);
+            let (output, errors) = lox.run_capture("print args(); print len(args());");
+            assert!(errors.is_empty());
+            assert_eq!(output, "[\"a\", \"--b\"]\n2\n");
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut lox = Lox::new();
+        let output = CaptureBuffer::default();
+        lox.set_output(Box::new(output.clone()));
+        assert_eq!(lox.eval("var a = 1;\nfun f(x) { return x * 2; }\nf(a) + 1;").unwrap(), Object::Int(3));
+        // 全局变量留在会话里
+        assert_eq!(lox.eval("a = \"s\"; a;").unwrap(), Object::String("s".to_string()));
+        assert_eq!(lox.eval("print a;").unwrap(), Object::None);
+        assert_eq!(output.into_string(), "s\n");
+
+        assert!(matches!(lox.eval("1 +;").unwrap_err()[..], [LoxErr::Parse { line: 1, .. }]));
+        assert!(matches!(lox.eval("var b = nil - 1;\n2;").unwrap_err()[..], [LoxErr::Runtime { line: 1, .. }]));
+        assert!(matches!(lox.eval("nil - 1;").unwrap_err()[..], [LoxErr::Runtime { line: 1, .. }]));
+        assert!(matches!(lox.eval("exit(3); 1;").unwrap_err()[..], [LoxErr::Exit { code: 3 }]));
+        lox.set_global("n", Object::Int(20));
+        assert_eq!(lox.eval("var m = n + 1; m * 2;").unwrap(), Object::Int(42));
+        assert_eq!(lox.get_global("m"), Some(Object::Int(21)));
+        assert!(lox.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        let mut lox = Lox::new();
+        let output = CaptureBuffer::default();
+        lox.set_output(Box::new(output.clone()));
+        // 不执行，运行时错误也就不会发生
+        assert!(lox.check("print 1;\nprint nil - 1;"));
+        assert_eq!(lox.exit_status(), 0);
+        assert!(!lox.check("var a = 1 +;"));
+        assert!(!lox.check("return 1;"));
+        assert!(lox.check("print 2;"));
+        assert_eq!(lox.exit_status(), EXIT_DATA_ERROR);
+        let codes: Vec<&str> = lox.take_diagnostics().iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(codes, vec!["parse-error", "resolve-error"]);
+        assert_eq!(output.into_string(), "");
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut lox = Lox::new();
+        let trace = CaptureBuffer::default();
+        lox.interpreter.set_trace(Trace::Variables, Box::new(trace.clone()));
+        lox.test_code("var a = 1;\nif (a > 0) {\n    a = a + 1;\n}");
+        assert_eq!(trace.into_string(), "\
+[trace]    1 | (var a 1)
+[trace]      |   define a = 1
+[trace]    2 | (if (> a 0) (block (; (= a (+ a 1)))))
+[trace]    2 | (block (; (= a (+ a 1))))
+[trace]    3 | (; (= a (+ a 1)))
+[trace]      |   assign a = 2
+");
+
+        let trace = CaptureBuffer::default();
+        lox.interpreter.set_trace(Trace::Statements, Box::new(trace.clone()));
+        lox.test_code("var long = \"abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvwxyz\";");
+        assert_eq!(trace.into_string(), "[trace]    1 | (var long \"abcdefghijklmnopqrstuvwxyzabcdefghijklmnopqrstuvw...\n");
+    }
+
+    #[test]
+    fn test_deny_warnings() {
+        let code = "{ var x = 1; { var x = 2; print x; } }";
+        let mut lox = Lox::new();
+        lox.set_warn_on_shadowing(true);
+        lox.test_code(code);
+        let severities: Vec<Severity> = lox.take_diagnostics().iter().map(|diagnostic| diagnostic.severity).collect();
+        assert_eq!(severities, vec![Severity::Warning]);
+        assert_eq!(lox.exit_status(), 0);
+
+        let mut lox = Lox::new();
+        lox.set_warn_on_shadowing(true);
+        lox.set_deny_warnings(true);
+        lox.test_code(code);
+        let diagnostics = lox.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].severity, diagnostics[0].code), (Severity::Error, "shadowed-variable"));
+        assert_eq!(lox.exit_status(), EXIT_DATA_ERROR);
+    }
+
+    #[test]
+    fn test_prompt_constants() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.run_prompt_line("const limit = 10;"), None);
+        assert_eq!(lox.run_prompt_line("limit * 2"), Some("20".to_string()));
+        // 语义分析记得前面几行声明的常量，不用等到运行时
+        lox.take_diagnostics();
+        assert_eq!(lox.run_prompt_line("limit = 1;"), None);
+        assert_eq!(lox.run_prompt_line("fun limit() {}"), None);
+        let diagnostics: Vec<(&str, String)> = lox.take_diagnostics().into_iter().map(|diagnostic| (diagnostic.code, diagnostic.message)).collect();
+        assert_eq!(diagnostics, vec![
+            ("resolve-error", "Can't assign to constant 'limit'.".to_string()),
+            ("resolve-error", "Can't redeclare constant 'limit'.".to_string()),
+        ]);
+        assert!(!lox.interpreter.had_runtime_error);
+        assert_eq!(lox.run_prompt_line("limit"), Some("10".to_string()));
+        // 分析出错的行不执行，里面声明的常量也不算
+        assert_eq!(lox.run_prompt_line("const other = 1; other = 2;"), None);
+        assert_eq!(lox.run_prompt_line("var other = 3;"), None);
+        assert_eq!(lox.run_prompt_line("other"), Some("3".to_string()));
+        assert_eq!(lox.run_prompt_line("{ var limit = 1; limit = 2; }"), None);
+        assert!(!lox.interpreter.had_runtime_error);
+    }
+
+    #[test]
+    fn test_expression_depth() {
+        let mut lox = Lox::new();
+        let code = format!("var s = \"\"{};\nprint s;", " + \"x\"".repeat(10_000));
+        let (output, errors) = lox.run_capture(&code);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(output, format!("{}\n", "x".repeat(10_000)));
+
+        lox.set_max_expression_depth(3);
+        let (output, errors) = lox.run_capture("print (1) + (2) + (3);\nprint (((1)));");
+        assert_eq!(output, "");
+        assert!(matches!(&errors[..], [LoxErr::Parse { line: 2, message, .. }] if message == "Expression nesting is too deep."), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_prompt_recovers_after_errors() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.run_prompt_line("nil + 1"), None);
+        assert!(lox.interpreter.had_runtime_error);
+        assert_eq!(lox.run_prompt_line("1 + 1"), Some("2".to_string()));
+        assert!(!lox.interpreter.had_runtime_error);
+
+        // 块里出错后，环境要回到全局
+        assert_eq!(lox.run_prompt_line("var a = 1; { var b = 2; b = nil + b; }"), None);
+        assert_eq!(lox.run_prompt_line("a"), Some("1".to_string()));
+        assert!(!lox.interpreter.had_runtime_error);
+
+        // 扫描出错的行不会让 REPL 崩掉
+        assert_eq!(lox.run_prompt_line("print 1 @;"), None);
+        assert_eq!(lox.run_prompt_line("a + 1"), Some("2".to_string()));
+
+        // 运行时错误显示的是刚输入的那一行
+        lox.take_diagnostics();
+        assert_eq!(lox.run_prompt_line("print a - nil;"), None);
+        assert_eq!(lox.run_prompt_line("-\"x\""), None);
+        let source_lines: Vec<Option<String>> = lox.take_diagnostics().into_iter().map(|diagnostic| diagnostic.source_line).collect();
+        assert_eq!(source_lines, vec![Some("print a - nil;".to_string()), Some("-\"x\"".to_string())]);
+    }
+
+    #[test]
+    fn test_prompt_multiline_input() {
+        let mut lox = Lox::new();
+        let input = "fun add(a, b) {\n    var sum = a + b;\n    return sum;\n}\nvar s = (1 +\n2);\nvar text = \"two\nlines\";\n";
+        lox.run_prompt(input.as_bytes()).unwrap();
+        assert_eq!(lox.run_prompt_line("add(s, 4)"), Some("7".to_string()));
+        assert_eq!(lox.run_prompt_line("text"), Some("two\nlines".to_string()));
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let mut lox = Lox::new();
+        assert!(lox.is_incomplete("fun f() {"));
+        assert!(lox.is_incomplete("print (1 +"));
+        assert!(lox.is_incomplete("var s = \"open"));
+        assert!(lox.is_incomplete("/* comment"));
+        assert!(!lox.is_incomplete("1 + 2"));
+        assert!(!lox.is_incomplete("print 1;"));
+        // 真正的语法错误不用再等下一行
+        assert!(!lox.is_incomplete("print );"));
+        assert!(!lox.is_incomplete("}"));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let status = |code: &str, use_vm: bool| {
+            let mut lox = Lox::new();
+            if use_vm {
+                lox.vm = Some(Vm::new());
+            }
+            lox.test_code(code);
+            lox.exit_status()
+        };
+        for use_vm in [false, true] {
+            assert_eq!(status("print 1;", use_vm), 0);
+            assert_eq!(status("print 1 +;", use_vm), 65);
+            assert_eq!(status("{ var a = a; }", use_vm), 65);
+            assert_eq!(status("nil();", use_vm), 70);
+            assert_eq!(status("fun f() { exit(3); } f(); print \"not reached\";", use_vm), 3);
+            assert_eq!(status("exit(0); nil();", use_vm), 0);
+            assert_eq!(status("exit(1.5);", use_vm), 70);
+        }
+        assert_eq!(status("class A {}", true), 65);
+        assert_eq!(status("throw 1;", false), 70);
+        // catch 接不住 exit
+        assert_eq!(status("try { exit(4); } catch (e) { print e; }", false), 4);
+    }
+
+    #[test]
+    fn test_prompt_exit() {
+        let mut lox = Lox::new();
+        lox.run_prompt("var x = 1;\nexit(2)\nx = 2;\n".as_bytes()).unwrap();
+        assert_eq!(lox.requested_exit(), Some(2));
+        assert_eq!(lox.interpreter.globals.borrow().values.get("x"), Some(&Object::Int(1)));
+    }
+}
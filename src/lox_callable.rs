@@ -1,6 +1,12 @@
-use crate::{err::LoxErr, interpreter::Interpreter, object::Object};
-
-pub trait LoxCallable {
-    fn arity(&self) -> usize;
-    fn call(&mut self, interpreter: &mut Interpreter, arguments: Vec<Object>) -> Result<Object, LoxErr>;
+use crate::{err::LoxErr, interpreter::Interpreter, object::Object};
+
+pub trait LoxCallable {
+    // 至少要传几个实参。不是可变参数的话必须正好这么多
+    fn arity(&self) -> usize;
+
+    fn is_variadic(&self) -> bool {
+        false
+    }
+
+    fn call(&mut self, interpreter: &mut Interpreter, arguments: Vec<Object>) -> Result<Object, LoxErr>;
 }
\ No newline at end of file
@@ -1,67 +1,137 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
-use std::fmt;
-
-use crate::lox_callable::LoxCallable;
-use crate::lox_function::LoxFunction;
-use crate::lox_instance::LoxInstance;
-use crate::object::Object;
-use crate::interpreter::Interpreter;
-use crate::err::LoxErr;
-
-
-
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct LoxClass {
-    pub name: String,
-    pub superclass: Option<Box<LoxClass>>,
-    pub methods: HashMap<String, LoxFunction>,
-}
-
-impl LoxClass {
-    pub fn new(name: String, superclass: Option<Box<LoxClass>>, methods: HashMap<String, LoxFunction>) -> LoxClass {
-        LoxClass{
-            name: name,
-            superclass: superclass,
-            methods: methods,
-        }
-    }
-
-    pub fn find_method(&self, name: &str) -> Option<LoxFunction> {
-        if let Some(method) = self.methods.get(name) {
-            return Some(method.clone());
-        }
-        if let Some(exist_super) = &self.superclass {
-            return exist_super.find_method(name);
-        }
-        None
-    }
-}
-
-impl fmt::Display for LoxClass {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<class {}>", self.name)
-    }
-}
-
-impl LoxCallable for LoxClass {
-    fn arity(&self) -> usize {
-        let initializer = self.find_method("init");
-        if let Some(exist_init) = initializer {
-            return exist_init.arity();
-        }
-        0
-    }
-
-    fn call(&mut self, interpreter: &mut Interpreter, arguments: Vec<Object>) -> Result<Object, LoxErr> {
-        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
-        let initializer = self.find_method("init");
-        if let Some(exist_init) = initializer {
-            exist_init.bind(Rc::clone(&instance)).call(interpreter, arguments)?;    // 在返回 instance 前，调用它的 init 方法，在调用它的 init 方法前，让它 bind 一下找到 this
-        }
-
-        Ok(Object::Instance(instance))
-    }
-}
\ No newline at end of file
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::fmt;
+
+use crate::lox_callable::LoxCallable;
+use crate::lox_function::LoxFunction;
+use crate::lox_instance::LoxInstance;
+use crate::object::Object;
+use crate::interpreter::Interpreter;
+use crate::err::LoxErr;
+use crate::gc;
+
+
+
+
+#[derive(Debug, Clone)]
+pub struct LoxClass {
+    pub name: Rc<str>,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: HashMap<Rc<str>, LoxFunction>,    // 包括从超类链上继承来的方法，子类的覆盖同名的。类定义后不可变，所以创建时一次合并好
+    field_layout: RefCell<HashMap<Rc<str>, usize>>,  // 这个类的实例的字段名 -> 下标，所有实例共用，第一次给某个字段赋值时分配
+}
+
+impl LoxClass {
+    pub fn new(name: Rc<str>, superclass: Option<Rc<LoxClass>>, methods: HashMap<Rc<str>, LoxFunction>) -> LoxClass {
+        // 超类的 methods 已经合并过它自己的超类链了，这里只需要再合并一层
+        let mut all_methods = match &superclass {
+            Some(exist_super) => exist_super.methods.clone(),
+            None => HashMap::new(),
+        };
+        all_methods.extend(methods);
+        LoxClass{
+            name: name,
+            superclass: superclass,
+            methods: all_methods,
+            field_layout: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.field_layout.borrow().get(name).copied()
+    }
+
+    pub fn field_index_or_insert(&self, name: &Rc<str>) -> usize {
+        let mut layout = self.field_layout.borrow_mut();
+        let next_index = layout.len();
+        *layout.entry(Rc::clone(name)).or_insert(next_index)
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<LoxFunction> {
+        self.methods.get(name).cloned()
+    }
+}
+
+// 字段布局不参与比较
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.superclass == other.superclass && self.methods == other.methods
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+// 实例要持有类的 Rc，所以给 Rc<LoxClass> 实现
+impl LoxCallable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        let initializer = self.find_method("init");
+        if let Some(exist_init) = initializer {
+            return exist_init.arity();
+        }
+        0
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.find_method("init").is_some_and(|init| init.is_variadic())
+    }
+
+    fn call(&mut self, interpreter: &mut Interpreter, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(Rc::clone(self))));
+        gc::track_instance(&instance);
+        let initializer = self.find_method("init");
+        if let Some(exist_init) = initializer {
+            exist_init.bind(Rc::clone(&instance)).call(interpreter, arguments)?;    // 在返回 instance 前，调用它的 init 方法，在调用它的 init 方法前，让它 bind 一下找到 this
+        }
+
+        Ok(Object::Instance(instance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::resolved;
+
+    // 代码里定义的几个类，在同一个解释器里执行，方法才能互相比较
+    fn global_classes<const N: usize>(code: &str, names: [&str; N]) -> [Rc<LoxClass>; N] {
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(&resolved(code));
+
+        names.map(|name| match interpreter.get_global(name) {
+            Some(Object::Class(class)) => class,
+            other => panic!("Expected class, got {:?}", other),
+        })
+    }
+
+    #[test]
+    fn test_methods_flattened_with_overrides() {
+        let code = r#"
+class A {
+    a() {}
+    shared() {}
+}
+class B < A {
+    b() {}
+    shared() {}
+}
+class C < B {
+    c() {}
+}
+        "#;
+        let [a, b, c] = global_classes(code, ["A", "B", "C"]);
+
+        let mut names: Vec<&str> = c.methods.keys().map(|name| &**name).collect();
+        names.sort();
+        assert_eq!(names, vec!["a", "b", "c", "shared"]);
+        // 覆盖的方法来自离得最近的类
+        assert_eq!(c.find_method("shared"), b.methods.get("shared").cloned());
+        assert_ne!(c.find_method("shared"), a.methods.get("shared").cloned());
+        assert_eq!(c.find_method("a"), a.methods.get("a").cloned());
+        assert_eq!(c.find_method("missing"), None);
+    }
+}
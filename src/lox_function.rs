@@ -1,79 +1,192 @@
-use std::cell::RefCell;
-use std::fmt;
-use std::rc::Rc;
-
-use crate::environment::Environment;
-use crate::err::LoxErr;
-use crate::interpreter::Interpreter;
-use crate::lox_instance::LoxInstance;
-use crate::stmt::FunctionDeclaration;
-use crate::lox_callable::LoxCallable;
-use crate::object::Object;
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct LoxFunction {
-    declaration: Box<FunctionDeclaration>,
-    closure: Rc<RefCell<Environment>>,  // 闭包，它 "封闭 "并保留着函数声明的外围变量
-    is_initializer: bool,   // 如果该函数是一个初始化方法，我们会覆盖实际的返回值并强行返回`this`
-}
-
-impl LoxFunction {
-    pub fn new(fun_decl: &FunctionDeclaration, closure: Rc<RefCell<Environment>>, is_initializer: bool) -> LoxFunction {
-
-        LoxFunction{
-            declaration: Box::new(fun_decl.clone()),
-            closure: closure,
-            is_initializer: is_initializer,
-        }
-    }
-
-    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> Self {
-        // bind 会返回一个能找到 this (即 instance 自身 ) 的方法
-        // instance 的 .xx 是方法时，需要一个新的能找到 this 的 LoxFunction，这个新 LoxFunction 的 closure 里添加了 this，新 LoxFunction 的 enclosing 是原 method 的 closure
-        let env = Environment::new();
-        env.borrow_mut().set_enclosing(Rc::clone(&self.closure));
-        env.borrow_mut().define("this", Object::Instance(Rc::clone(&instance)));
-        LoxFunction::new(&self.declaration, env, self.is_initializer)
-    }
-
-}
-
-impl fmt::Display for LoxFunction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<fn {}>", self.declaration.name.lexeme)
-    }
-}
-
-
-impl LoxCallable for LoxFunction {
-    fn arity(&self) -> usize {
-        self.declaration.params.len()
-    }
-
-    fn call(&mut self, interpreter: &mut Interpreter, arguments: Vec<Object>) -> Result<Object, LoxErr> {
-        let env = Environment::new();
-        env.borrow_mut().set_enclosing(Rc::clone(&self.closure));
-
-        for i in 0..self.declaration.params.len() {
-            env.borrow_mut().define(&self.declaration.params[i].lexeme, arguments[i].clone());
-        }
-
-        match interpreter.execute_block(&self.declaration.body, env) {
-            Err(LoxErr::RuntimeReturn { ret_value }) => {
-                if self.is_initializer {
-                    // 仅当 init 里有空 return; 时会跑这里，返回 this
-                    return Ok(self.closure.borrow().get_at(0, "this"));
-                }
-                return Ok(ret_value)
-            }
-            Err(other_lox_err) => return Err(other_lox_err),
-            Ok(_) => (),
-        }
-        if self.is_initializer {
-            return Ok(self.closure.borrow().get_at(0, "this"));
-        }
-        Ok(Object::None)
-
-    }
-}
-
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::err::LoxErr;
+use crate::gc;
+use crate::interpreter::Interpreter;
+use crate::lox_instance::LoxInstance;
+use crate::module::LoxModule;
+use crate::stmt::FunctionDeclaration;
+use crate::lox_callable::LoxCallable;
+use crate::object::Object;
+use crate::resolvable::Slot;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LoxFunction {
+    declaration: Rc<FunctionDeclaration>,
+    closure: Rc<RefCell<Environment>>,  // 闭包，它 "封闭 "并保留着函数声明的外围变量
+    module: Rc<LoxModule>,  // 定义函数的模块，函数体里的全局变量在这个模块里找
+    is_initializer: bool,   // 如果该函数是一个初始化方法，我们会覆盖实际的返回值并强行返回`this`
+}
+
+impl LoxFunction {
+    pub fn new(declaration: Rc<FunctionDeclaration>, closure: Rc<RefCell<Environment>>, module: Rc<LoxModule>, is_initializer: bool) -> LoxFunction {
+
+        LoxFunction{
+            declaration: declaration,
+            closure: closure,
+            module: module,
+            is_initializer: is_initializer,
+        }
+    }
+
+    pub fn closure(&self) -> &Rc<RefCell<Environment>> {
+        &self.closure
+    }
+
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> Self {
+        // bind 会返回一个能找到 this (即 instance 自身 ) 的方法
+        // instance 的 .xx 是方法时，需要一个新的能找到 this 的 LoxFunction，这个新 LoxFunction 的 closure 里添加了 this，新 LoxFunction 的 enclosing 是原 method 的 closure
+        let env = Environment::new();
+        env.borrow_mut().set_enclosing(Rc::clone(&self.closure));
+        env.borrow_mut().define_local(Object::Instance(Rc::clone(&instance)));
+        LoxFunction::new(Rc::clone(&self.declaration), env, Rc::clone(&self.module), self.is_initializer)
+    }
+
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 带上参数列表，比如 <fn add(a, b)>
+        let mut params: Vec<String> = self.declaration.params.iter().map(|param| param.lexeme.to_string()).collect();
+        if let Some(rest) = params.last_mut().filter(|_| self.declaration.is_variadic) {
+            rest.insert_str(0, "...");
+        }
+        write!(f, "<fn {}({})>", self.declaration.name.lexeme, params.join(", "))
+    }
+}
+
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        if self.declaration.is_variadic {
+            return self.declaration.params.len() - 1;
+        }
+        self.declaration.params.len()
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.declaration.is_variadic
+    }
+
+    fn call(&mut self, interpreter: &mut Interpreter, mut arguments: Vec<Object>) -> Result<Object, LoxErr> {
+        let env = interpreter.new_env(Rc::clone(&self.closure));
+
+        if self.declaration.is_variadic {
+            let rest = arguments.split_off(self.arity());
+            let rest = Rc::new(rest);
+            gc::track_list(&rest);
+            arguments.push(Object::List(rest));
+        }
+
+        // 实参按值移进新环境，不用逐个 clone
+        let mut call_env = env.borrow_mut();
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            call_env.define(&param.lexeme, argument);
+        }
+        drop(call_env);
+
+        let caller_module = interpreter.enter_module(&self.module);
+        let result = interpreter.execute_block(&self.declaration.body, env);
+        interpreter.leave_module(caller_module, &result);
+        match result {
+            Err(LoxErr::RuntimeReturn { ret_value }) => {
+                if self.is_initializer {
+                    // 仅当 init 里有空 return; 时会跑这里，返回 this
+                    return self.closure.borrow().get_at(Slot { distance: 0, index: 0 });
+                }
+                return Ok(ret_value)
+            }
+            Err(other_lox_err) => return Err(other_lox_err),
+            Ok(_) => (),
+        }
+        if self.is_initializer {
+            return self.closure.borrow().get_at(Slot { distance: 0, index: 0 });
+        }
+        Ok(Object::None)
+
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::NativeRegistry;
+    use crate::module::resolved;
+
+    fn global_function(code: &str, name: &str) -> LoxFunction {
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(&resolved(code));
+
+        let value = interpreter.globals.borrow().values.get(name).cloned();
+        match value {
+            Some(Object::Function(function)) => function,
+            other => panic!("Expected function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_closure_only_keeps_captured_variables() {
+        let code = r#"
+fun makeCounter() {
+    var big = "unrelated sibling";
+    var n = 0;
+    var other = "another sibling";
+    fun count() {
+        n = n + 1;
+        return n;
+    }
+    return count;
+}
+var counter = makeCounter();
+        "#;
+        let counter = global_function(code, "counter");
+        let closure = counter.closure.borrow();
+        assert!(closure.enclosing.is_none());
+        assert_eq!(closure.slots.len(), 1);
+        assert_eq!(*closure.slots[0].borrow(), Object::Int(0));
+    }
+
+    #[test]
+    fn test_nested_closure_captures_through_middle_function() {
+        let code = r#"
+fun outer() {
+    var a = "a";
+    var unused = "unused";
+    fun middle() {
+        fun inner() { return a; }
+        return inner;
+    }
+    return middle;
+}
+var middle = outer();
+var inner = middle();
+        "#;
+        let middle = global_function(code, "middle");
+        assert_eq!(middle.closure.borrow().slots.len(), 1);
+        let inner = global_function(code, "inner");
+        let closure = inner.closure.borrow();
+        assert_eq!(closure.slots.len(), 1);
+        assert_eq!(*closure.slots[0].borrow(), Object::String("a".to_string()));
+    }
+
+    #[test]
+    fn test_display_signature() {
+        let code = r#"
+fun add(a, b) { return a + b; }
+fun noParams() {}
+class Point {
+    move(dx, dy) {}
+}
+var method = Point().move;
+        "#;
+        assert_eq!(global_function(code, "add").to_string(), "<fn add(a, b)>");
+        assert_eq!(global_function(code, "noParams").to_string(), "<fn noParams()>");
+        assert_eq!(global_function(code, "method").to_string(), "<fn move(dx, dy)>");
+        let clock = NativeRegistry::standard().get("clock").unwrap().clone();
+        assert_eq!(Object::NativeFunction(clock).to_string(), "<native fn clock>");
+    }
+}
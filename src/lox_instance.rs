@@ -1,51 +1,80 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::fmt;
-use std::collections::HashMap;
-
-use crate::err::LoxErr;
-use crate::lox_class::LoxClass;
-use crate::object::Object;
-use crate::token::Token;
-
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct LoxInstance {
-    class: LoxClass,
-    fields: HashMap<String, Object>,
-}
-
-impl LoxInstance {
-    pub fn new(class: LoxClass) -> LoxInstance {
-        LoxInstance {
-            class: class,
-            fields: HashMap::new(), // 字段
-        }
-    }
-
-    pub fn get(&self, name: &Token, instance: &Rc<RefCell<Self>>) -> Result<Object, LoxErr> {
-        // field（字段）是直接保存在实例中的命名状态。propetry（属性）是 get 表达式可能返回的已命名的东西。每个 field 都是一个 propetry，并非每个 propetry 都是一个 field。
-        match self.fields.get(&name.lexeme) {
-            Some(existing_property) => Ok(existing_property.clone()),
-            None => {
-                // 这意味着字段会遮蔽方法
-                if let Some(method) = self.class.find_method(&name.lexeme) {
-                    Ok(Object::Function(method.bind(Rc::clone(instance))))  // method 复制出一个新的，不同之处在于新 LoxFunction 的 closure 里添加了 this，新 LoxFunction 的 enclosing 是原 method 的 closure
-                } else {
-                    Err(LoxErr::Runtime { line: name.line, message: format!("Undefined property {}.", name.lexeme) })
-                }
-            }
-        }
-    }
-
-    pub fn set(&mut self, name: &Token, value: Object) {
-        self.fields.insert(name.lexeme.clone(), value);
-    }
-}
-
-impl fmt::Display for LoxInstance {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<{} instance>", self.class.name)
-    }
-}
-
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::fmt;
+
+use crate::err::LoxErr;
+use crate::lox_class::LoxClass;
+use crate::object::Object;
+use crate::token::Token;
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: Vec<Option<Object>>,    // 下标由类的 field_layout 分配，还没赋值过的字段是 None
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> LoxInstance {
+        LoxInstance {
+            class: class,
+            fields: Vec::new(), // 字段
+        }
+    }
+
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+
+    pub fn get(&self, name: &Token, instance: &Rc<RefCell<Self>>) -> Result<Object, LoxErr> {
+        // field（字段）是直接保存在实例中的命名状态。propetry（属性）是 get 表达式可能返回的已命名的东西。每个 field 都是一个 propetry，并非每个 propetry 都是一个 field。
+        match self.field_index(&name.lexeme).and_then(|index| self.get_field_at(index)) {
+            Some(existing_property) => Ok(existing_property),
+            None => {
+                // 这意味着字段会遮蔽方法
+                if let Some(method) = self.class.find_method(&name.lexeme) {
+                    Ok(Object::Function(method.bind(Rc::clone(instance))))  // method 复制出一个新的，不同之处在于新 LoxFunction 的 closure 里添加了 this，新 LoxFunction 的 enclosing 是原 method 的 closure
+                } else {
+                    Err(LoxErr::Runtime { line: name.line, column: name.column, span: Some(name.span), message: format!("Undefined property {}.", name.lexeme) })
+                }
+            }
+        }
+    }
+
+    // 返回字段的下标，给内联缓存用
+    pub fn set(&mut self, name: &Token, value: Object) -> usize {
+        let index = self.class.field_index_or_insert(&name.lexeme);
+        self.set_field_at(index, value);
+        index
+    }
+
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.class.field_index(name)
+    }
+
+    pub fn fields(&self) -> &[Option<Object>] {
+        &self.fields
+    }
+
+    // gc 回收实例时用来断开环
+    pub fn clear_fields(&mut self) {
+        self.fields.clear();
+    }
+
+    pub fn get_field_at(&self, index: usize) -> Option<Object> {
+        self.fields.get(index).cloned().flatten()
+    }
+
+    pub fn set_field_at(&mut self, index: usize, value: Object) {
+        if index >= self.fields.len() {
+            self.fields.resize(index + 1, None);
+        }
+        self.fields[index] = Some(value);
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
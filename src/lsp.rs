@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkupContent, MarkupKind, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::parser::Parser;
+use crate::resolver::{Reference, Resolver};
+use crate::scanner::Scanner;
+use crate::stmt::{FunctionDeclaration, Stmt};
+use crate::token::{Span, Token};
+
+/*
+rilox-lsp 语言服务器，通过标准输入输出和编辑器通信
+    - 打开或修改文件时重新扫描、解析、做语义分析，把错误和警告发给编辑器
+    - 跳转到定义：用语义分析记下的每处变量引用指向的声明
+    - 悬停：显示变量、函数、类的声明
+文档每次改动都整个发过来（TextDocumentSyncKind::FULL），Lox 脚本不大，每次请求都重新分析一遍
+*/
+pub fn run_server() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents: HashMap<String, String> = HashMap::new();    // 按 URI 的字符串存，Uri 里有缓存，不适合当键
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some((uri, code)) = handle_notification(&mut documents, notification) {
+                    let diagnostics = Analysis::new(&code).diagnostics.iter().map(|diagnostic| to_lsp_diagnostic(&code, diagnostic)).collect();
+                    let params = PublishDiagnosticsParams { uri: uri, diagnostics: diagnostics, version: None };
+                    connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_string(), params)))?;
+                }
+            }
+            Message::Response(_) => (),
+        }
+    }
+    // 写线程要等 sender 都释放了才会结束
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+// 返回内容有变化的文档，关掉的文档返回空字符串，清掉它的诊断
+fn handle_notification(documents: &mut HashMap<String, String>, notification: Notification) -> Option<(Uri, String)> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            documents.insert(params.text_document.uri.to_string(), params.text_document.text.clone());
+            Some((params.text_document.uri, params.text_document.text))
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            let code = params.content_changes.into_iter().last()?.text;
+            documents.insert(params.text_document.uri.to_string(), code.clone());
+            Some((params.text_document.uri, code))
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params).ok()?;
+            documents.remove(params.text_document.uri.as_str());
+            Some((params.text_document.uri, String::new()))
+        }
+        _ => None,
+    }
+}
+
+fn handle_request(documents: &HashMap<String, String>, request: Request) -> Response {
+    match request.method.as_str() {
+        GotoDefinition::METHOD => match serde_json::from_value::<GotoDefinitionParams>(request.params) {
+            Ok(params) => {
+                let position = params.text_document_position_params;
+                let result = documents.get(position.text_document.uri.as_str()).and_then(|code| {
+                    let analysis = Analysis::new(code);
+                    let declaration = analysis.definition(offset_at(code, position.position))?;
+                    let location = Location { uri: position.text_document.uri.clone(), range: range_of(code, declaration.span) };
+                    Some(GotoDefinitionResponse::Scalar(location))
+                });
+                Response::new_ok(request.id, result)
+            }
+            Err(err) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string()),
+        },
+        HoverRequest::METHOD => match serde_json::from_value::<HoverParams>(request.params) {
+            Ok(params) => {
+                let position = params.text_document_position_params;
+                let result = documents.get(position.text_document.uri.as_str()).and_then(|code| {
+                    let analysis = Analysis::new(code);
+                    let detail = analysis.hover(offset_at(code, position.position))?;
+                    let contents = MarkupContent { kind: MarkupKind::Markdown, value: format!("```lox\n{}\n```", detail) };
+                    Some(Hover { contents: HoverContents::Markup(contents), range: None })
+                });
+                Response::new_ok(request.id, result)
+            }
+            Err(err) => Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string()),
+        },
+        _ => Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("Unsupported request '{}'.", request.method)),
+    }
+}
+
+// 一份源代码的分析结果。位置都用源代码里的字节偏移
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    references: Vec<Reference>,
+    symbols: Vec<Symbol>,
+}
+
+// 一个声明和悬停时显示的样子
+struct Symbol {
+    token: Token,
+    detail: String,
+}
+
+impl Analysis {
+    pub fn new(code: &str) -> Analysis {
+        let mut diagnostics = Vec::new();
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        // 扫描出错时照样有 token，接着解析，把能报的错误都报出来
+        if let Err(scan_err) = scanner.scan_tokens() {
+            diagnostics.extend(Diagnostic::from_err(&scan_err));
+        }
+        let mut parser = Parser::new(&scanner.tokens);
+        let mut statements = parser.parse();
+        for parse_err in &parser.errors {
+            diagnostics.extend(Diagnostic::from_err(parse_err));
+        }
+
+        // 有语法错误时语法树不完整，语义分析的错误多半是连带的，只拿它找引用
+        let mut resolver = Resolver::new();
+        resolver.track_references = true;
+        resolver.resolve_input(&mut statements);
+        if diagnostics.is_empty() {
+            diagnostics.extend(resolver.errors.iter().flat_map(Diagnostic::from_err));
+            diagnostics.append(&mut resolver.warnings);
+        }
+
+        let mut symbols = Vec::new();
+        collect_symbols(&statements, &mut symbols);
+        Analysis { diagnostics: diagnostics, references: resolver.references, symbols: symbols }
+    }
+
+    // offset 处的变量引用指向的声明
+    pub fn definition(&self, offset: usize) -> Option<&Token> {
+        self.references.iter().find(|reference| contains(reference.usage.span, offset)).map(|reference| &reference.declaration)
+    }
+
+    // offset 处是一个声明，或者是对某个声明的引用的话，返回那个声明的样子
+    pub fn hover(&self, offset: usize) -> Option<&str> {
+        let span = match self.definition(offset) {
+            Some(declaration) => declaration.span,
+            None => self.symbols.iter().find(|symbol| contains(symbol.token.span, offset))?.token.span,
+        };
+        self.symbols.iter().find(|symbol| symbol.token.span == span).map(|symbol| symbol.detail.as_str())
+    }
+}
+
+// 光标在标识符末尾时也算在标识符上。脱糖时合成的 token 没有位置，不算
+fn contains(span: Span, offset: usize) -> bool {
+    span.start < span.end && span.start <= offset && offset <= span.end
+}
+
+fn collect_symbols(statements: &[Stmt], symbols: &mut Vec<Symbol>) {
+    for statement in statements {
+        match statement {
+            Stmt::Block { statements, .. } => collect_symbols(statements, symbols),
+            Stmt::ClassDeclaration { class_declaration } => {
+                let name = &class_declaration.name;
+                let detail = match &class_declaration.superclass {
+                    Some(superclass) => format!("class {} < {}", name.lexeme, superclass.name.lexeme),
+                    None => format!("class {}", name.lexeme),
+                };
+                symbols.push(Symbol { token: (**name).clone(), detail: detail });
+                for method in &class_declaration.methods {
+                    collect_function(method, &format!("{}.", name.lexeme), symbols);
+                }
+            }
+            Stmt::DoWhile { body, .. } => collect_symbols(std::slice::from_ref(&**body), symbols),
+            Stmt::ForIn { name, body, .. } => {
+                symbols.push(Symbol { token: (**name).clone(), detail: format!("var {}", name.lexeme) });
+                collect_symbols(std::slice::from_ref(&**body), symbols);
+            }
+            Stmt::FunctionDeclaration { function_declaration } => collect_function(function_declaration, "fun ", symbols),
+            Stmt::If { then_branch, else_branch, .. } => {
+                collect_symbols(std::slice::from_ref(&**then_branch), symbols);
+                if let Some(else_branch) = else_branch {
+                    collect_symbols(std::slice::from_ref(&**else_branch), symbols);
+                }
+            }
+            Stmt::While { body, increment, .. } => {
+                collect_symbols(std::slice::from_ref(&**body), symbols);
+                collect_symbols(increment, symbols);
+            }
+            Stmt::Switch { cases, default, .. } => {
+                for case in cases {
+                    collect_symbols(std::slice::from_ref(&case.body), symbols);
+                }
+                if let Some(default) = default {
+                    collect_symbols(std::slice::from_ref(&**default), symbols);
+                }
+            }
+            Stmt::Try { try_block, name, catch_block, .. } => {
+                collect_symbols(try_block, symbols);
+                symbols.push(Symbol { token: (**name).clone(), detail: format!("catch ({})", name.lexeme) });
+                collect_symbols(catch_block, symbols);
+            }
+            Stmt::Var { name, is_const, .. } => {
+                let keyword = if *is_const { "const" } else { "var" };
+                symbols.push(Symbol { token: (**name).clone(), detail: format!("{} {}", keyword, name.lexeme) });
+            }
+            _ => (),
+        }
+    }
+}
+
+// 函数和方法的签名，prefix 是 "fun " 或者 "类名."
+fn collect_function(function_declaration: &FunctionDeclaration, prefix: &str, symbols: &mut Vec<Symbol>) {
+    let last = function_declaration.params.len().saturating_sub(1);
+    let params: Vec<String> = function_declaration.params.iter().enumerate()
+        .map(|(index, param)| if function_declaration.is_variadic && index == last { format!("...{}", param.lexeme) } else { param.lexeme.to_string() })
+        .collect();
+    let signature = format!("{}{}({})", prefix, function_declaration.name.lexeme, params.join(", "));
+    symbols.push(Symbol { token: (*function_declaration.name).clone(), detail: signature.clone() });
+    for param in &function_declaration.params {
+        symbols.push(Symbol { token: (**param).clone(), detail: format!("(parameter) {} of {}", param.lexeme, signature) });
+    }
+    collect_symbols(&function_declaration.body, symbols);
+}
+
+fn to_lsp_diagnostic(code: &str, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    // 没有字节范围的诊断标在那一行的开头
+    let range = match (diagnostic.span, diagnostic.line) {
+        (Some(span), _) => range_of(code, span),
+        (None, Some(line)) => {
+            let start = Position::new(line.saturating_sub(1) as u32, 0);
+            Range::new(start, start)
+        }
+        (None, None) => Range::default(),
+    };
+    let severity = match diagnostic.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+    };
+    lsp_types::Diagnostic {
+        range: range,
+        severity: Some(severity),
+        code: Some(NumberOrString::String(diagnostic.code.to_string())),
+        source: Some("rilox".to_string()),
+        message: diagnostic.message.clone(),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+// LSP 的位置是从 0 开始的行号，列按 UTF-16 编码单元算
+fn position_at(code: &str, offset: usize) -> Position {
+    let before = &code[..offset.min(code.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    let line = before.matches('\n').count();
+    let character = before[line_start..].encode_utf16().count();
+    Position::new(line as u32, character as u32)
+}
+
+fn range_of(code: &str, span: Span) -> Range {
+    Range::new(position_at(code, span.start), position_at(code, span.end))
+}
+
+// 位置超出了这一行的话算在行尾
+fn offset_at(code: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for (index, line) in code.split_inclusive('\n').enumerate() {
+        if index == position.line as usize {
+            let mut units = 0;
+            for (offset, c) in line.char_indices() {
+                if units >= position.character as usize || c == '\n' {
+                    return line_start + offset;
+                }
+                units += c.len_utf16();
+            }
+            return line_start + line.len();
+        }
+        line_start += line.len();
+    }
+    code.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 第 n 个 needle 在代码里的字节偏移
+    fn nth_offset(code: &str, needle: &str, n: usize) -> usize {
+        code.match_indices(needle).nth(n).unwrap().0
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let code = r#"var total = 0;
+fun add(x, ...rest) {
+    var sum = x + total;
+    fun inner() { return sum; }
+    return inner;
+}
+class Counter < Base {
+    bump(by) { total = total + by; }
+}
+print add(1);
+"#;
+        let analysis = Analysis::new(code);
+        let definition = |needle: &str, n: usize| analysis.definition(nth_offset(code, needle, n)).map(|token| (token.line, token.column));
+        assert_eq!(definition("total", 1), Some((1, 5)));   // 函数里引用全局变量
+        assert_eq!(definition("total", 2), Some((1, 5)));   // 赋值
+        assert_eq!(definition("x + ", 0), Some((2, 9)));    // 参数
+        assert_eq!(definition("sum;", 0), Some((3, 9)));    // 闭包捕获的局部变量
+        assert_eq!(definition("add(1)", 0), Some((2, 5)));  // 先声明后使用的全局函数
+        assert_eq!(definition("Base", 0), None);    // 没有声明
+        assert_eq!(definition("var", 0), None);
+
+        let hover = |needle: &str, n: usize| analysis.hover(nth_offset(code, needle, n));
+        assert_eq!(hover("add(1)", 0), Some("fun add(x, ...rest)"));
+        assert_eq!(hover("x + ", 0), Some("(parameter) x of fun add(x, ...rest)"));
+        assert_eq!(hover("total", 0), Some("var total"));
+        assert_eq!(hover("Counter", 0), Some("class Counter < Base"));
+        assert_eq!(hover("bump", 0), Some("Counter.bump(by)"));
+        assert_eq!(hover("print", 0), None);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let analysis = Analysis::new("print 1 +;\nprint @;");
+        let codes: Vec<&str> = analysis.diagnostics.iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(codes, vec!["scan-error", "parse-error", "parse-error"]);
+
+        let code = "{ var a = a; }\nreturn 1;";
+        let diagnostics: Vec<lsp_types::Diagnostic> = Analysis::new(code).diagnostics.iter().map(|diagnostic| to_lsp_diagnostic(code, diagnostic)).collect();
+        let ranges: Vec<Range> = diagnostics.iter().map(|diagnostic| diagnostic.range).collect();
+        assert_eq!(ranges, vec![
+            Range::new(Position::new(0, 10), Position::new(0, 11)),
+            Range::new(Position::new(1, 0), Position::new(1, 6)),
+        ]);
+        assert_eq!(diagnostics[0].message, "Can't read local variable in its own initializer.");
+    }
+
+    #[test]
+    fn test_positions() {
+        // "é" 是两个字节、一个 UTF-16 单元，"😀" 是四个字节、两个 UTF-16 单元
+        let code = "var é = 1;\nvar s = \"😀\"; print s;\n";
+        let offset = nth_offset(code, "s;", 0);
+        assert_eq!(position_at(code, offset), Position::new(1, 20));
+        assert_eq!(offset_at(code, Position::new(1, 20)), offset);
+        assert_eq!(offset_at(code, Position::new(0, 5)), nth_offset(code, " =", 0));
+        assert_eq!(offset_at(code, Position::new(0, 100)), nth_offset(code, "\n", 0));
+        assert_eq!(offset_at(code, Position::new(5, 0)), code.len());
+    }
+}
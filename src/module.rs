@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::diagnostic::Diagnostic;
+use crate::environment::Environment;
+use crate::err::LoxErr;
+use crate::object::Object;
+use crate::token::Token;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+
+/*
+模块对象：每个模块在自己的全局环境里执行，顶层声明都留在这个环境里，不会混进导入它的脚本
+    import util from "lib/util.lox";    util 绑定到模块对象，用 util.name 访问模块里的顶层声明
+    import "lib/setup.lox";             只执行模块，不引入任何名字
+主脚本自己也是一个模块。函数记着定义它的模块，在别的模块里被调用时，全局变量照样查定义它的那个模块
+*/
+pub struct LoxModule {
+    pub name: Rc<str>,
+    pub globals: Rc<RefCell<Environment>>,
+    pub file: Option<ModuleFile>,   // 导入的模块来自哪个文件，主脚本没有
+}
+
+// 报告模块里的错误时，显示文件路径和模块自己的源代码
+pub struct ModuleFile {
+    pub path: String,
+    pub source: Rc<str>,
+}
+
+impl LoxModule {
+    pub fn new(name: &str, globals: Rc<RefCell<Environment>>) -> LoxModule {
+        LoxModule { name: Rc::from(name), globals: globals, file: None }
+    }
+
+    pub fn with_file(mut self, path: &str, source: &str) -> LoxModule {
+        self.file = Some(ModuleFile { path: path.to_string(), source: Rc::from(source) });
+        self
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Object, LoxErr> {
+        match self.globals.borrow().values.get(&name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => Err(LoxErr::Runtime { line: name.line, column: name.column, span: Some(name.span), message: format!("Undefined name '{}' in module '{}'.", name.lexeme, self.name) }),
+        }
+    }
+}
+
+// 全局环境里可能有引用这个模块的函数，不能展开比较或打印
+impl PartialEq for LoxModule {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.globals, &other.globals)
+    }
+}
+
+impl fmt::Debug for LoxModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoxModule({})", self.name)
+    }
+}
+
+impl fmt::Display for LoxModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+pub enum Import {
+    Loaded(Rc<LoxModule>),  // 导入过了，或者正在执行（循环导入），直接用
+    New(PathBuf),   // 第一次导入，要执行这个文件
+}
+
+// import 语句用到的状态：导入过的模块，相对路径从哪个目录开始找
+pub struct Modules {
+    loaded: HashMap<PathBuf, Rc<LoxModule>>,
+    dirs: Vec<PathBuf>, // 正在执行的模块所在的目录，栈顶是当前模块。空栈时相对于工作目录
+}
+
+impl Modules {
+    pub fn new() -> Modules {
+        Modules {
+            loaded: HashMap::new(),
+            dirs: Vec::new(),
+        }
+    }
+
+    // 主脚本所在目录作为最外层的起点，主脚本自己也算导入过，被模块反过来导入时拿到的是主脚本的模块对象
+    pub fn set_main_script(&mut self, path: &Path, main: Rc<LoxModule>) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(dir) = path.parent() {
+            self.dirs = vec![dir.to_path_buf()];
+        }
+        self.loaded.insert(path, main);
+    }
+
+    // 返回 New 时要用 begin_module 登记模块对象，执行完之后调用 end_import
+    pub fn begin_import(&mut self, path: &str) -> io::Result<Import> {
+        let path = match self.dirs.last() {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        let path = path.canonicalize()?;
+        if let Some(module) = self.loaded.get(&path) {
+            return Ok(Import::Loaded(Rc::clone(module)));
+        }
+        Ok(Import::New(path))
+    }
+
+    // 执行前就登记，模块执行中被循环导入时拿到的是这个还没执行完的模块
+    pub fn begin_module(&mut self, path: &Path, module: Rc<LoxModule>) {
+        self.loaded.insert(path.to_path_buf(), module);
+        self.dirs.push(path.parent().map_or_else(PathBuf::new, Path::to_path_buf));
+    }
+
+    // 模块出错的话不算导入过，下次 import 重新执行
+    pub fn end_import(&mut self, path: &Path, succeeded: bool) {
+        self.dirs.pop();
+        if !succeeded {
+            self.loaded.remove(path);
+        }
+    }
+}
+
+// 模块的前端：扫描、解析、语义分析、去掉死分支。任何一步有错误，整个模块都不执行
+pub fn compile(code: &str, max_expression_depth: usize) -> Result<(Vec<Stmt>, Vec<Diagnostic>), Vec<LoxErr>> {
+    let mut errors = Vec::new();
+
+    let mut scanner = Scanner::new();
+    scanner.load_code(code);
+    if let Err(scan_err) = scanner.scan_tokens() {
+        errors.push(scan_err);
+    }
+
+    let mut parser = Parser::new(&scanner.tokens);
+    parser.max_expression_depth = max_expression_depth;
+    let mut statements = parser.parse();
+    errors.append(&mut parser.errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut resolver = Resolver::new();
+    resolver.resolve(&mut statements);
+    if resolver.had_resolve_error {
+        return Err(resolver.errors);
+    }
+
+    optimizer::eliminate_dead_code(&mut statements);
+    Ok((statements, resolver.warnings))
+}
+
+// 测试用：扫描、解析，代码里不能有扫描或解析错误
+#[cfg(test)]
+pub(crate) fn parse(code: &str) -> Vec<Stmt> {
+    let mut scanner = Scanner::new();
+    scanner.load_code(code);
+    scanner.scan_tokens().unwrap();
+    let mut parser = Parser::new(&scanner.tokens);
+    let statements = parser.parse();
+    assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+    statements
+}
+
+// 测试用：扫描、解析、语义分析，代码里不能有错误。不去掉死分支
+#[cfg(test)]
+pub(crate) fn resolved(code: &str) -> Vec<Stmt> {
+    let mut statements = parse(code);
+    let mut resolver = Resolver::new();
+    resolver.resolve(&mut statements);
+    assert!(!resolver.had_resolve_error, "{:?}", resolver.errors);
+    statements
+}
@@ -0,0 +1,493 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant, UNIX_EPOCH, SystemTime};
+
+use crate::err::LoxErr;
+use crate::gc;
+use crate::interpreter::Interpreter;
+use crate::lox_instance::LoxInstance;
+use crate::object::{floored_modulo, floored_modulo_int, NumberPair, Object};
+
+// 原生函数的实现，第一个参数是调用处的行号，报错时用。实参个数已经由调用方按 arity 检查过了
+pub type NativeFn = dyn Fn(usize, Vec<Object>) -> Result<Object, LoxErr>;
+
+// 嵌入 rilox 的程序通过 Interpreter::register_native 注入的函数，能拿到解释器本身
+pub type HostFn = dyn Fn(&mut Interpreter, Vec<Object>) -> Result<Object, LoxErr>;
+
+#[derive(Clone)]
+enum NativeImpl {
+    Builtin(Rc<NativeFn>),
+    Host(Rc<HostFn>),
+}
+
+// 名字、参数个数和实现。闭包可以捕获状态，所以不能只靠名字区分，两个值是同一个闭包才相等
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: Rc<str>,
+    arity: usize,
+    function: NativeImpl,
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.function, &other.function) {
+            (NativeImpl::Builtin(left), NativeImpl::Builtin(right)) => Rc::ptr_eq(left, right),
+            (NativeImpl::Host(left), NativeImpl::Host(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+// 原生函数报错时要用调用处的行号，所以不走 LoxCallable::call，解释器和虚拟机直接调 call_native
+impl NativeFunction {
+    pub fn new(name: &str, arity: usize, function: impl Fn(usize, Vec<Object>) -> Result<Object, LoxErr> + 'static) -> NativeFunction {
+        NativeFunction {
+            name: Rc::from(name),
+            arity: arity,
+            function: NativeImpl::Builtin(Rc::new(function)),
+        }
+    }
+
+    pub fn new_host(name: &str, arity: usize, function: impl Fn(&mut Interpreter, Vec<Object>) -> Result<Object, LoxErr> + 'static) -> NativeFunction {
+        NativeFunction {
+            name: Rc::from(name),
+            arity: arity,
+            function: NativeImpl::Host(Rc::new(function)),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    // 虚拟机没有树遍历解释器，只能调内置的原生函数
+    pub fn call_native(&self, line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+        match &self.function {
+            NativeImpl::Builtin(function) => function(line, arguments),
+            NativeImpl::Host(_) => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Native function '{}' needs the tree-walking interpreter.", self.name) }),
+        }
+    }
+
+    // 宿主函数不知道调用处的行号，它返回的运行时错误统一改成调用处的行号
+    pub fn call_with_interpreter(&self, interpreter: &mut Interpreter, line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+        match &self.function {
+            NativeImpl::Builtin(function) => function(line, arguments),
+            NativeImpl::Host(function) => function(interpreter, arguments).map_err(|lox_err| match lox_err {
+                LoxErr::Runtime { message, .. } => LoxErr::Runtime { line: line, column: 0, span: None, message: message },
+                other => other,
+            }),
+        }
+    }
+}
+
+// 解释器和虚拟机启动时把登记在这里的原生函数都定义成全局变量。同名的后登记的覆盖先登记的
+pub struct NativeRegistry {
+    natives: Vec<NativeFunction>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> NativeRegistry {
+        NativeRegistry {
+            natives: Vec::new(),
+        }
+    }
+
+    // 内置的标准库，加原生函数只要在这里加一行
+    pub fn standard() -> NativeRegistry {
+        let mut registry = NativeRegistry::new();
+        registry.define("abs", 1, abs);
+        registry.define("args", 0, script_args(Vec::new()));
+        registry.define("ceil", 1, ceil);
+        registry.define("clock", 0, clock);
+        registry.define("clockMonotonic", 0, clock_monotonic);
+        registry.define("deep_equal", 2, deep_equal_native);
+        registry.define("exit", 1, exit);
+        registry.define("floor", 1, floor);
+        registry.define("indexOf", 2, index_of);
+        registry.define("len", 1, len);
+        registry.define("lower", 1, lower);
+        registry.define("max", 2, max);
+        registry.define("min", 2, min);
+        registry.define("mod", 2, modulo);
+        registry.define("num", 1, num);
+        registry.define("pow", 2, pow);
+        registry.define("random", 0, random);
+        registry.define("sleep", 1, sleep);
+        registry.define("split", 2, split);
+        registry.define("sqrt", 1, sqrt);
+        registry.define("str", 1, str);
+        registry.define("substr", 3, substr);
+        registry.define("trim", 1, trim);
+        registry.define("typeof", 1, type_of);
+        registry.define("upper", 1, upper);
+        registry
+    }
+
+    pub fn define(&mut self, name: &str, arity: usize, function: impl Fn(usize, Vec<Object>) -> Result<Object, LoxErr> + 'static) {
+        self.natives.retain(|native_function| &*native_function.name != name);
+        self.natives.push(NativeFunction::new(name, arity, function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeFunction> {
+        self.natives.iter().find(|native_function| &*native_function.name == name)
+    }
+
+    pub fn natives(&self) -> &[NativeFunction] {
+        &self.natives
+    }
+}
+
+// 命令行里脚本文件后面的参数，rilox script.lox a b 里 args() 返回 ["a", "b"]。每次调用返回同一个列表
+pub fn script_args(args: Vec<String>) -> impl Fn(usize, Vec<Object>) -> Result<Object, LoxErr> {
+    let list = Rc::new(args.into_iter().map(Object::String).collect::<Vec<Object>>());
+    move |_line, _arguments| Ok(Object::List(Rc::clone(&list)))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn clock(_line: usize, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::Number(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64()))
+}
+
+// clock 是墙上时间，系统调时间时会跳。测耗时用 clockMonotonic，它从第一次调用开始计时，只增不减
+#[cfg(not(target_arch = "wasm32"))]
+fn clock_monotonic(_line: usize, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    static START: OnceLock<Instant> = OnceLock::new();
+    Ok(Object::Number(START.get_or_init(Instant::now).elapsed().as_secs_f64()))
+}
+
+// WebAssembly 里没有系统时钟，用 JavaScript 的 Date.now()，单位是毫秒
+#[cfg(target_arch = "wasm32")]
+fn clock(_line: usize, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::Number(js_sys::Date::now() / 1000.0))
+}
+
+// Date.now() 也会跟着系统调时间跳，往回跳的时候按 0 算
+#[cfg(target_arch = "wasm32")]
+fn clock_monotonic(_line: usize, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    static START: OnceLock<f64> = OnceLock::new();
+    let now = js_sys::Date::now();
+    Ok(Object::Number((now - *START.get_or_init(|| now)).max(0.0) / 1000.0))
+}
+
+// 浏览器的主线程不能阻塞
+#[cfg(target_arch = "wasm32")]
+fn sleep(line: usize, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Err(LoxErr::Runtime { line: line, column: 0, span: None, message: "sleep is not supported in WebAssembly.".to_string() })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn sleep(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match Duration::try_from_secs_f64(number_argument(line, "sleep", &arguments[0])?) {
+        Ok(duration) => {
+            thread::sleep(duration);
+            Ok(Object::None)
+        }
+        Err(_) => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of sleep must be a non-negative number of seconds, got {}.", arguments[0]) }),
+    }
+}
+
+// 不直接调 process::exit，而是像 throw 一样往外传，让驱动程序正常收尾后用这个退出码退出
+fn exit(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match &arguments[0] {
+        Object::Int(code) if i32::try_from(*code).is_ok() => Err(LoxErr::Exit { code: *code as i32 }),
+        other => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of exit must be an integer exit code, got {}.", other.nested_string()) }),
+    }
+}
+
+fn deep_equal_native(_line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::Bool(deep_equal(&arguments[0], &arguments[1], &mut HashSet::new())))
+}
+
+fn modulo(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let (left, right) = (&arguments[0], &arguments[1]);
+    match NumberPair::new(left, right) {
+        Some(NumberPair::Ints(_, 0) | NumberPair::Floats(_, 0.0)) => Err(Interpreter::modulo_by_zero_err(line, left)),
+        // 只有 i64::MIN 对 -1 取模会溢出，数学上结果是 0
+        Some(NumberPair::Ints(left_int, right_int)) => Ok(Object::Int(floored_modulo_int(left_int, right_int))),
+        Some(NumberPair::Floats(left_number, right_number)) => Ok(Object::Number(floored_modulo(left_number, right_number))),
+        None => Err(numbers_err(line, "mod", left, right)),
+    }
+}
+
+// 和 type_name 基本一样，但原生函数也算 function，实例带上类名，比如 "Point instance"
+fn type_of(_line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::String(match &arguments[0] {
+        Object::Instance(instance) => format!("{} instance", instance.borrow().class().name),
+        Object::NativeFunction(_) => "function".to_string(),
+        other => other.type_name().to_string(),
+    }))
+}
+
+// 和 print、字符串拼接用的是同一种格式
+fn str(_line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::String(arguments[0].to_string()))
+}
+
+// 字符串转成十进制的整数或浮点数，忽略两端的空白，转不了的返回 nil。没有小数点和指数的是整数
+// 和数字字面量不一样：可以带正负号和指数（str 打印很大或很小的数时用 1.0E7 这种写法，要能转回来），不认 0x、0b、0o 前缀
+fn num(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let s = match &arguments[0] {
+        Object::Int(_) | Object::Number(_) => return Ok(arguments[0].clone()),
+        Object::String(s) => s.trim(),
+        other => return Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of num must be a string or number, got {}.", other.type_name()) }),
+    };
+    if let Ok(int) = s.parse::<i64>() {
+        return Ok(Object::Int(int));
+    }
+    // f64 的 parse 还接受 "inf"、"NaN" 这种，Lox 里写不出这样的字面量，不算数字
+    match s.parse::<f64>() {
+        Ok(number) if number.is_finite() && s.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) => Ok(Object::Number(number)),
+        _ => Ok(Object::None),
+    }
+}
+
+/*
+数学函数尽量保持整数：abs、floor、ceil 的参数是整数时结果也是整数，浮点数的结果还是浮点数
+    pow(a, b) 两个都是整数且 b >= 0 时按整数算，溢出报错；否则按浮点数算
+    min、max 返回原来的那个值，不做类型转换
+    sqrt 和 random 的结果总是浮点数，random() 在 [0, 1) 之间
+*/
+fn abs(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match &arguments[0] {
+        Object::Int(int) => Interpreter::checked_int(line, int.checked_abs()),
+        _ => Ok(Object::Number(number_argument(line, "abs", &arguments[0])?.abs())),
+    }
+}
+
+fn floor(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match &arguments[0] {
+        Object::Int(int) => Ok(Object::Int(*int)),
+        _ => Ok(Object::Number(number_argument(line, "floor", &arguments[0])?.floor())),
+    }
+}
+
+fn ceil(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match &arguments[0] {
+        Object::Int(int) => Ok(Object::Int(*int)),
+        _ => Ok(Object::Number(number_argument(line, "ceil", &arguments[0])?.ceil())),
+    }
+}
+
+fn sqrt(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::Number(number_argument(line, "sqrt", &arguments[0])?.sqrt()))
+}
+
+fn pow(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match NumberPair::new(&arguments[0], &arguments[1]) {
+        Some(NumberPair::Ints(base, exponent)) if exponent >= 0 => {
+            let result = u32::try_from(exponent).ok().and_then(|exponent| base.checked_pow(exponent));
+            Interpreter::checked_int(line, result)
+        }
+        Some(NumberPair::Ints(base, exponent)) => Ok(Object::Number((base as f64).powf(exponent as f64))),
+        Some(NumberPair::Floats(base, exponent)) => Ok(Object::Number(base.powf(exponent))),
+        None => Err(numbers_err(line, "pow", &arguments[0], &arguments[1])),
+    }
+}
+
+fn min(line: usize, mut arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let is_less = match NumberPair::new(&arguments[0], &arguments[1]) {
+        Some(NumberPair::Ints(left, right)) => left <= right,
+        Some(NumberPair::Floats(left, right)) => left <= right,
+        None => return Err(numbers_err(line, "min", &arguments[0], &arguments[1])),
+    };
+    Ok(arguments.swap_remove(if is_less { 0 } else { 1 }))
+}
+
+fn max(line: usize, mut arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let is_greater = match NumberPair::new(&arguments[0], &arguments[1]) {
+        Some(NumberPair::Ints(left, right)) => left >= right,
+        Some(NumberPair::Floats(left, right)) => left >= right,
+        None => return Err(numbers_err(line, "max", &arguments[0], &arguments[1])),
+    };
+    Ok(arguments.swap_remove(if is_greater { 0 } else { 1 }))
+}
+
+thread_local! {
+    // xorshift64* 的状态，用系统提供的随机哈希种子初始化，不能是 0
+    static RANDOM_STATE: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+fn random(_line: usize, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let bits = RANDOM_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    });
+    // 取高 53 位，正好是 f64 尾数的精度
+    Ok(Object::Number((bits >> 11) as f64 / (1u64 << 53) as f64))
+}
+
+/*
+字符串函数都按字符（Unicode 标量值）计数，和 for-in 遍历字符串一致，不是按字节
+    len("héllo") == 5
+    substr(s, start, count) 越界的部分会被截掉，substr("abc", 1, 10) == "bc"
+    indexOf(s, needle) 找不到时返回 -1
+    split(s, sep) 返回字符串列表，sep 是空串时拆成单个字符
+*/
+fn len(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    match &arguments[0] {
+        Object::String(s) => Ok(Object::Int(s.chars().count() as i64)),
+        Object::List(list) => Ok(Object::Int(list.len() as i64)),
+        other => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of len must be a string or list, got {}.", other.type_name()) }),
+    }
+}
+
+fn substr(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let s = string_argument(line, "substr", &arguments[0])?;
+    let start = index_argument(line, "substr", &arguments[1])?;
+    let count = index_argument(line, "substr", &arguments[2])?;
+    Ok(Object::String(s.chars().skip(start).take(count).collect()))
+}
+
+fn upper(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::String(string_argument(line, "upper", &arguments[0])?.to_uppercase()))
+}
+
+fn lower(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::String(string_argument(line, "lower", &arguments[0])?.to_lowercase()))
+}
+
+fn trim(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    Ok(Object::String(string_argument(line, "trim", &arguments[0])?.trim().to_string()))
+}
+
+fn index_of(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let s = string_argument(line, "indexOf", &arguments[0])?;
+    let needle = string_argument(line, "indexOf", &arguments[1])?;
+    Ok(Object::Int(match s.find(needle) {
+        Some(byte_index) => s[..byte_index].chars().count() as i64,
+        None => -1,
+    }))
+}
+
+fn split(line: usize, arguments: Vec<Object>) -> Result<Object, LoxErr> {
+    let s = string_argument(line, "split", &arguments[0])?;
+    let separator = string_argument(line, "split", &arguments[1])?;
+    let parts: Vec<Object> = if separator.is_empty() {
+        s.chars().map(|c| Object::String(c.to_string())).collect()
+    } else {
+        s.split(separator).map(|part| Object::String(part.to_string())).collect()
+    };
+    let list = Rc::new(parts);
+    gc::track_list(&list);
+    Ok(Object::List(list))
+}
+
+fn number_argument(line: usize, name: &str, argument: &Object) -> Result<f64, LoxErr> {
+    match argument {
+        Object::Int(int) => Ok(*int as f64),
+        Object::Number(number) => Ok(*number),
+        other => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of {} must be a number, got {}.", name, other.type_name()) }),
+    }
+}
+
+fn numbers_err(line: usize, name: &str, left: &Object, right: &Object) -> LoxErr {
+    LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Arguments of {} must be numbers, got {} and {}.", name, left.type_name(), right.type_name()) }
+}
+
+fn string_argument<'a>(line: usize, name: &str, argument: &'a Object) -> Result<&'a str, LoxErr> {
+    match argument {
+        Object::String(s) => Ok(s),
+        other => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of {} must be a string, got {}.", name, other.type_name()) }),
+    }
+}
+
+// 下标和长度：非负整数。值是整数的浮点数也可以，比如 4 / 2
+fn index_argument(line: usize, name: &str, argument: &Object) -> Result<usize, LoxErr> {
+    let index = match argument {
+        Object::Int(int) => Some(*int),
+        Object::Number(number) if number.fract() == 0.0 => Some(*number as i64),
+        _ => None,
+    };
+    match index {
+        Some(index) if index >= 0 => Ok(index as usize),
+        _ => Err(LoxErr::Runtime { line: line, column: 0, span: None, message: format!("Argument of {} must be a non-negative integer, got {}.", name, argument.nested_string()) }),
+    }
+}
+
+// 结构相等：同一个类的两个实例，字段逐个递归比较；两个列表元素逐个递归比较；其他值和 == 一样
+// visited 记下正在比较的实例对，再次遇到时当作相等，这样有环的结构也能比较完
+fn deep_equal(left: &Object, right: &Object, visited: &mut HashSet<(*const RefCell<LoxInstance>, *const RefCell<LoxInstance>)>) -> bool {
+    match (left, right) {
+        (Object::Instance(left_instance), Object::Instance(right_instance)) => {
+            if Rc::ptr_eq(left_instance, right_instance) || !visited.insert((Rc::as_ptr(left_instance), Rc::as_ptr(right_instance))) {
+                return true;
+            }
+            let left_instance = left_instance.borrow();
+            let right_instance = right_instance.borrow();
+            if !Rc::ptr_eq(left_instance.class(), right_instance.class()) {
+                return false;
+            }
+            let field_count = left_instance.fields().len().max(right_instance.fields().len());
+            (0..field_count).all(|index| match (left_instance.get_field_at(index), right_instance.get_field_at(index)) {
+                (Some(left_field), Some(right_field)) => deep_equal(&left_field, &right_field, visited),
+                (None, None) => true,
+                _ => false,
+            })
+        }
+        (Object::List(left_list), Object::List(right_list)) => {
+            left_list.len() == right_list.len()
+                && left_list.iter().zip(right_list.iter()).all(|(left_element, right_element)| deep_equal(left_element, right_element, visited))
+        }
+        _ => Interpreter::is_equal(left, right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut registry = NativeRegistry::new();
+        let counter = Rc::new(Cell::new(0));
+        let captured = Rc::clone(&counter);
+        registry.define("tick", 0, move |_line, _arguments| {
+            captured.set(captured.get() + 1);
+            Ok(Object::Int(captured.get()))
+        });
+        registry.define("answer", 0, |_line, _arguments| Ok(Object::Int(41)));
+        registry.define("answer", 1, |_line, _arguments| Ok(Object::Int(42)));
+        assert_eq!(registry.natives().len(), 2);
+
+        let tick = registry.get("tick").unwrap();
+        assert_eq!(tick.call_native(1, Vec::new()).unwrap(), Object::Int(1));
+        assert_eq!(tick.call_native(1, Vec::new()).unwrap(), Object::Int(2));
+        assert_eq!(counter.get(), 2);
+
+        // 后登记的覆盖先登记的
+        let answer = registry.get("answer").unwrap();
+        assert_eq!(answer.arity(), 1);
+        assert_eq!(answer.call_native(1, vec![Object::None]).unwrap(), Object::Int(42));
+
+        // 同一个闭包的拷贝相等，同名的不同闭包不相等
+        assert_eq!(tick.clone(), *tick);
+        let standard = NativeRegistry::standard();
+        assert_eq!(standard.get("clock"), standard.get("clock"));
+        assert_ne!(standard.get("clock"), NativeRegistry::standard().get("clock"));
+        assert!(standard.get("tick").is_none());
+    }
+}
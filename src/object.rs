@@ -1,74 +1,227 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::fmt::{self, Debug};
-use std::time::{UNIX_EPOCH, SystemTime};
-
-use crate::err::LoxErr;
-use crate::lox_callable::LoxCallable;
-use crate::interpreter::Interpreter;
-use crate::lox_class::LoxClass;
-use crate::lox_function::LoxFunction;
-use crate::lox_instance::LoxInstance;
-
-#[derive(Debug, PartialEq, Clone)]
-pub enum Object {
-    None,
-    Bool(bool),
-    Class(LoxClass),
-    Instance(Rc<RefCell<LoxInstance>>),
-    String(String),
-    Number(f64),
-    Function(LoxFunction), // 函数对象
-    NativeFunction(NativeFunction),
-
-}
-
-impl Default for Object {
-    fn default() -> Self {
-        Object::None
-    }
-}
-
-impl fmt::Display for Object {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-
-        match self {
-            Object::None => write!(f, "nil"),
-            Object::Bool(b) => fmt::Display::fmt(b, f),
-            Object::Class(class) => fmt::Display::fmt(class, f),
-            Object::String(s) => fmt::Display::fmt(s, f),
-            Object::Number(n) => fmt::Display::fmt(n, f),
-            Object::Function(func) => fmt::Display::fmt(func, f),
-            Object::NativeFunction(native_func) => fmt::Display::fmt(native_func, f),
-            Object::Instance(instance) => fmt::Display::fmt(&instance.borrow_mut(), f),
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct NativeFunction {
-    pub name: String,
-}
-
-impl fmt::Display for NativeFunction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<native fn {}>", self.name)
-    }
-}
-
-impl LoxCallable for NativeFunction {
-    fn arity(&self) -> usize {
-        match self.name.as_str() {
-            "clock" => 0,
-            _ => unreachable!("Invalid native fn arity."),
-        }
-    }
-
-    fn call(&mut self, _interpreter: &mut Interpreter, _arguments: Vec<Object>) -> Result<Object, LoxErr> {
-        match self.name.as_str() {
-            "clock" => Ok(Object::Number(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64())),
-            
-            _ => unreachable!("Invalid native fn call."),
-        }
-    }
-}
\ No newline at end of file
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::fmt::{self, Debug};
+
+use crate::compiler::BytecodeFunction;
+use crate::lox_class::LoxClass;
+use crate::lox_function::LoxFunction;
+use crate::lox_instance::LoxInstance;
+use crate::module::LoxModule;
+use crate::native::NativeFunction;
+
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub enum Object {
+    #[default]
+    None,
+    Bool(bool),
+    Class(Rc<LoxClass>),  // 实例和子类共享同一个类，不用拷贝方法表
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<Vec<Object>>),  // 目前只有剩余参数和 args() 会创建列表，创建后不能修改
+    String(String),
+    Int(i64),   // 不带小数点的数字字面量，整数之间运算结果还是整数
+    Number(f64),
+    Function(LoxFunction), // 函数对象
+    NativeFunction(NativeFunction),
+    BytecodeFunction(Rc<BytecodeFunction>), // 字节码虚拟机里的函数，树遍历解释器用不到
+    Module(Rc<LoxModule>),  // import m from "..." 绑定的模块对象
+}
+
+impl Object {
+    // 类型名，用于报错信息
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::None => "nil",
+            Object::Bool(_) => "bool",
+            Object::Class(_) => "class",
+            Object::Instance(_) => "instance",
+            Object::List(_) => "list",
+            Object::String(_) => "string",
+            Object::Int(_) | Object::Number(_) => "number",
+            Object::Function(_) => "function",
+            Object::NativeFunction(_) => "native function",
+            Object::BytecodeFunction(_) => "function",
+            Object::Module(_) => "module",
+        }
+    }
+
+    // 作为集合元素显示时的文本形式。字符串加引号并转义，其余和 Display 一样
+    // 顶层 print 直接用 Display，打出原始字符串
+    pub fn nested_string(&self) -> String {
+        match self {
+            Object::String(s) => escape_string(s),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        match self {
+            Object::None => write!(f, "nil"),
+            Object::Bool(b) => fmt::Display::fmt(b, f),
+            Object::Class(class) => fmt::Display::fmt(class, f),
+            Object::String(s) => fmt::Display::fmt(s, f),
+            Object::Int(int) => fmt::Display::fmt(int, f),
+            Object::Number(n) => write!(f, "{}", format_number(*n)),
+            Object::Function(func) => fmt::Display::fmt(func, f),
+            Object::NativeFunction(native_func) => fmt::Display::fmt(native_func, f),
+            Object::Instance(instance) => fmt::Display::fmt(&instance.borrow(), f),
+            Object::List(list) => {
+                let elements: Vec<String> = list.iter().map(Object::nested_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Object::BytecodeFunction(function) => fmt::Display::fmt(function, f),
+            Object::Module(module) => fmt::Display::fmt(module, f),
+        }
+    }
+}
+
+// 语法树里的对象都是字面量，按 JSON 里对应的类型输出。函数、类、实例没有对应的类型，输出打印出来的样子
+impl Serialize for Object {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Object::None => serializer.serialize_unit(),
+            Object::Bool(b) => serializer.serialize_bool(*b),
+            Object::String(s) => serializer.serialize_str(s),
+            Object::Int(int) => serializer.serialize_i64(*int),
+            Object::Number(n) => serializer.serialize_f64(*n),
+            Object::List(list) => serializer.collect_seq(list.iter()),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+// 算术运算的两个操作数。都是整数时按整数算，有一个是浮点数就都转成浮点数
+pub enum NumberPair {
+    Ints(i64, i64),
+    Floats(f64, f64),
+}
+
+impl NumberPair {
+    // 有一个不是数字就返回 None
+    pub fn new(left: &Object, right: &Object) -> Option<NumberPair> {
+        match (left, right) {
+            (Object::Int(left_int), Object::Int(right_int)) => Some(NumberPair::Ints(*left_int, *right_int)),
+            (Object::Int(left_int), Object::Number(right_number)) => Some(NumberPair::Floats(*left_int as f64, *right_number)),
+            (Object::Number(left_number), Object::Int(right_int)) => Some(NumberPair::Floats(*left_number, *right_int as f64)),
+            (Object::Number(left_number), Object::Number(right_number)) => Some(NumberPair::Floats(*left_number, *right_number)),
+            _ => None,
+        }
+    }
+}
+
+// 给字符串加上双引号，并把引号、反斜杠和控制字符转义，输出没有歧义，照着写回源码也能得到同一个字符串
+pub fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // ${ 在字符串里是插值
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\0' => escaped.push_str("\\0"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// 数字的文本形式，和 jlox 一样：Java 的 Double.toString，再去掉结尾的 .0
+// 用能还原出同一个 f64 的最短写法。绝对值在 [0.001, 10000000) 之外用科学计数法，比如 1.0E7、1.5E-7
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return "NaN".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "Infinity".to_string() } else { "-Infinity".to_string() };
+    }
+    let abs = n.abs();
+    if abs != 0.0 && !(1e-3..1e7).contains(&abs) {
+        let scientific = format!("{:e}", n);
+        let (mantissa, exponent) = scientific.split_once('e').unwrap();
+        // Java 的尾数至少带一位小数，这时结尾不是 .0，jlox 也就不去掉
+        return if mantissa.contains('.') {
+            format!("{}E{}", mantissa, exponent)
+        } else {
+            format!("{}.0E{}", mantissa, exponent)
+        };
+    }
+    // f64 的 Display 本身就不会给整数值加 .0，-0.0 会显示成 -0
+    n.to_string()
+}
+
+/*
+两种取模：
+    a % b       截断取余，结果的符号跟被除数 a 一样，和 Rust、C 的 % 一致：-7 % 3 == -1
+    mod(a, b)   向下取整的取模，结果的符号跟除数 b 一样，就是数学上的模：mod(-7, 3) == 2
+除数是 0 时两者都报运行时错误，和除法一样
+*/
+pub fn floored_modulo_int(left: i64, right: i64) -> i64 {
+    let remainder = left.checked_rem(right).unwrap_or(0);
+    if remainder != 0 && (remainder < 0) != (right < 0) {
+        remainder + right
+    } else {
+        remainder
+    }
+}
+
+pub fn floored_modulo(left: f64, right: f64) -> f64 {
+    let remainder = left % right;
+    if remainder != 0.0 && (remainder < 0.0) != (right < 0.0) {
+        remainder + right
+    } else {
+        remainder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(Object::Number(1.0).to_string(), "1");
+        assert_eq!(Object::Number(1.5).to_string(), "1.5");
+        assert_eq!(Object::Number(-0.0).to_string(), "-0");
+        assert_eq!(Object::Number(0.1 + 0.2).to_string(), "0.30000000000000004");
+        assert_eq!(Object::Number(-0.001).to_string(), "-0.001");
+        assert_eq!(Object::Number(9999999.5).to_string(), "9999999.5");
+        assert_eq!(Object::Number(1e7).to_string(), "1.0E7");
+        assert_eq!(Object::Number(123456789012345680000.0).to_string(), "1.2345678901234568E20");
+        assert_eq!(Object::Number(-2.5e30).to_string(), "-2.5E30");
+        assert_eq!(Object::Number(0.0001).to_string(), "1.0E-4");
+        assert_eq!(Object::Number(1.5e-7).to_string(), "1.5E-7");
+        assert_eq!(Object::Number(f64::INFINITY).to_string(), "Infinity");
+        assert_eq!(Object::Number(f64::NEG_INFINITY).to_string(), "-Infinity");
+        assert_eq!(Object::Number(f64::NAN).to_string(), "NaN");
+    }
+
+    #[test]
+    fn test_nested_string_escaped() {
+        let value = Object::String("say \"hi\"\nbye".to_string());
+        assert_eq!(value.to_string(), "say \"hi\"\nbye");
+        assert_eq!(value.nested_string(), r#""say \"hi\"\nbye""#);
+        assert_eq!(escape_string("tab\there\\\u{1}"), r#""tab\there\\\u{1}""#);
+        assert_eq!(Object::Number(1.0).nested_string(), "1");
+        assert_eq!(Object::None.nested_string(), "nil");
+    }
+
+    #[test]
+    fn test_floored_modulo() {
+        assert_eq!(floored_modulo(-7.0, 3.0), 2.0);
+        assert_eq!(floored_modulo(7.0, -3.0), -2.0);
+        assert_eq!(floored_modulo(7.0, 3.0), 1.0);
+        assert_eq!(floored_modulo(-6.0, 3.0), 0.0);
+        assert_eq!(floored_modulo(5.5, 2.0), 1.5);
+    }
+}
@@ -0,0 +1,190 @@
+use std::rc::Rc;
+
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::stmt::{FunctionDeclaration, Stmt, SwitchCase};
+
+/*
+死代码消除：条件是字面量的 if / while 在编译期就能确定走哪个分支
+    if (false) A else B  =>  B
+    if (true) A else B   =>  A
+    while (false) A      =>  删掉
+在语义分析之后做，这样死分支里的语义错误照样会报出来。
+Lox 里 if / while 的分支只能是语句而不是声明，分支里声明的变量一定在分支自己的块作用域里，
+所以删掉分支不会影响外面的变量，也不会改变其他变量的槽位。
+*/
+pub fn eliminate_dead_code(statements: &mut Vec<Stmt>) {
+    let old_statements = std::mem::take(statements);
+    for stmt in old_statements {
+        if let Some(stmt) = eliminate_in_stmt(stmt) {
+            statements.push(stmt);
+        }
+    }
+}
+
+// 返回 None 表示整条语句都被删掉了
+fn eliminate_in_stmt(stmt: Stmt) -> Option<Stmt> {
+    match stmt {
+        Stmt::Block { brace, mut statements } => {
+            eliminate_dead_code(&mut statements);
+            Some(Stmt::Block { brace: brace, statements: statements })
+        }
+        Stmt::ClassDeclaration { mut class_declaration } => {
+            for method in class_declaration.methods.iter_mut() {
+                eliminate_in_function(method);
+            }
+            Some(Stmt::ClassDeclaration { class_declaration: class_declaration })
+        }
+        Stmt::FunctionDeclaration { mut function_declaration } => {
+            eliminate_in_function(&mut function_declaration);
+            Some(Stmt::FunctionDeclaration { function_declaration: function_declaration })
+        }
+        // 循环体至少执行一次，条件是字面量也不能删
+        Stmt::DoWhile { keyword, body, condition } => Some(Stmt::DoWhile {
+            keyword: keyword,
+            body: Box::new(eliminate_in_branch(*body)),
+            condition: condition,
+        }),
+        Stmt::ForIn { name, iterable, body } => Some(Stmt::ForIn {
+            name: name,
+            iterable: iterable,
+            body: Box::new(eliminate_in_branch(*body)),
+        }),
+        Stmt::Try { keyword, mut try_block, name, mut catch_block } => {
+            eliminate_dead_code(&mut try_block);
+            eliminate_dead_code(&mut catch_block);
+            Some(Stmt::Try { keyword: keyword, try_block: try_block, name: name, catch_block: catch_block })
+        }
+        Stmt::If { keyword, condition, then_branch, else_branch } => match literal_truthiness(&condition) {
+            Some(true) => eliminate_in_stmt(*then_branch),
+            Some(false) => else_branch.and_then(|else_branch| eliminate_in_stmt(*else_branch)),
+            None => Some(Stmt::If {
+                keyword: keyword,
+                condition: condition,
+                then_branch: Box::new(eliminate_in_branch(*then_branch)),
+                else_branch: else_branch.and_then(|else_branch| eliminate_in_stmt(*else_branch)).map(Box::new),
+            }),
+        },
+        Stmt::While { keyword, condition, body, increment } => match literal_truthiness(&condition) {
+            Some(false) => None,
+            _ => Some(Stmt::While {
+                keyword: keyword,
+                condition: condition,
+                body: Box::new(eliminate_in_branch(*body)),
+                increment: increment,
+            }),
+        },
+        Stmt::Switch { keyword, subject, cases, default } => Some(Stmt::Switch {
+            keyword: keyword,
+            subject: subject,
+            cases: cases.into_iter().map(|case| SwitchCase { value: case.value, body: eliminate_in_branch(case.body) }).collect(),
+            default: default.map(|default| Box::new(eliminate_in_branch(*default))),
+        }),
+        other => Some(other),
+    }
+}
+
+// 分支位置上必须有一条语句，整条删掉的话用空块代替，位置还是原来那条语句的
+fn eliminate_in_branch(stmt: Stmt) -> Stmt {
+    let brace = Rc::clone(stmt.first_token());
+    eliminate_in_stmt(stmt).unwrap_or(Stmt::Block { brace: brace, statements: Vec::new() })
+}
+
+fn eliminate_in_function(function_declaration: &mut Rc<FunctionDeclaration>) {
+    eliminate_dead_code(&mut Rc::make_mut(function_declaration).body);
+}
+
+// 条件是（可能带括号的）字面量时，返回它的真假
+fn literal_truthiness(condition: &Expr) -> Option<bool> {
+    match condition {
+        Expr::Literal(literal_expr) => Some(Interpreter::is_truthy(&literal_expr.literal)),
+        Expr::Grouping(grouping_expr) => literal_truthiness(&grouping_expr.expression),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::resolved;
+
+    fn eliminate(code: &str) -> Vec<Stmt> {
+        let mut statements = resolved(code);
+        eliminate_dead_code(&mut statements);
+        statements
+    }
+
+    fn printed(stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Print { expression, .. } => expression.to_string(),
+            other => panic!("Expected print statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_if_with_literal_condition() {
+        let statements = eliminate(r#"
+if (false) print "dead"; else print "live";
+if ((true)) print "then"; else print "else";
+if (nil) print "dead";
+        "#);
+        assert_eq!(statements.len(), 2);
+        assert_eq!(printed(&statements[0]), "\"live\"");
+        assert_eq!(printed(&statements[1]), "\"then\"");
+    }
+
+    #[test]
+    fn test_while_false_removed() {
+        let statements = eliminate(r#"
+var i = 0;
+while (false) { i = i + 1; }
+for (var j = 0; false; j = j + 1) print j;
+print i;
+        "#);
+        assert_eq!(statements.len(), 3);
+        assert!(matches!(&statements[0], Stmt::Var { .. }));
+        // for 循环脱糖后的初始化语句要保留
+        match &statements[1] {
+            Stmt::Block { statements, .. } => {
+                assert_eq!(statements.len(), 1);
+                assert!(matches!(&statements[0], Stmt::Var { .. }));
+            }
+            other => panic!("Expected block, got {:?}", other),
+        }
+        assert_eq!(printed(&statements[2]), "i");
+    }
+
+    #[test]
+    fn test_live_code_preserved() {
+        let statements = eliminate(r#"
+fun f(x) {
+    var a = "kept";
+    if (x) {
+        if (false) return 1;
+        print a;
+    }
+    while (x) if (false) print "dead";
+    return a;
+}
+        "#);
+        let Some(Stmt::FunctionDeclaration { function_declaration }) = statements.first() else {
+            panic!("Expected function declaration, got {:?}", statements);
+        };
+        let body = &function_declaration.body;
+        assert_eq!(body.len(), 4);
+        match &body[1] {
+            Stmt::If { then_branch, else_branch: None, .. } => match &**then_branch {
+                Stmt::Block { statements, .. } => {
+                    assert_eq!(statements.len(), 1);
+                    assert_eq!(printed(&statements[0]), "a");
+                }
+                other => panic!("Expected block, got {:?}", other),
+            },
+            other => panic!("Expected if statement, got {:?}", other),
+        }
+        match &body[2] {
+            Stmt::While { body, .. } => assert!(matches!(&**body, Stmt::Block { statements, .. } if statements.is_empty())),
+            other => panic!("Expected while statement, got {:?}", other),
+        }
+    }
+}
@@ -1,512 +1,1328 @@
-use crate::err::LoxErr;
-use crate::stmt::{ClassDeclaration, FunctionDeclaration, Stmt};
-use crate::token::Token;
-use crate::object::Object;
-
-use crate::expr::{AssignExpr, CallExpr, CommaExpr, ConditionalExpr, Expr, GetExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr};
-use crate::expr::{BinaryExpr, GroupingExpr, LiteralExpr, UnaryExpr, VariableExpr};
-use crate::token_type::TokenType;
-
-
-pub struct Parser<'a> {
-    tokens: &'a Vec<Token>,
-    current: usize,
-}
-
-impl Parser<'_> {
-
-    pub fn new(tokens: &Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
-    }
-
-    pub fn parse(&mut self) -> Vec<Stmt> {
-        let mut statements: Vec<Stmt> = Vec::new();
-        while !self.is_at_end() {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-
-                // 原版是在 declaration 处理错误
-                Err(lox_err) => {
-                    eprintln!("{}", lox_err);
-                    self.synchronize();
-                }
-            }            
-        }
-        statements
-    }
-
-
-    fn declaration(&mut self) -> Result<Stmt, LoxErr> {
-        match self.get_match_type(&[TokenType::Var, TokenType::Fun, TokenType::Class]) {
-            Some(TokenType::Var) => self.var_declaration(),
-            Some(TokenType::Fun) => self.function_declaration("function"),
-            Some(TokenType::Class) => self.class_declaration(),
-            _ => self.statement(),
-        }
-    }
-
-    fn expression(&mut self) -> Result<Expr, LoxErr> {
-        self.comma_expression()
-    }
-
-    fn comma_expression(&mut self) -> Result<Expr, LoxErr> {
-        let mut exprs = Vec::new();
-        exprs.push(self.assignment()?);
-        while self.matches(&[TokenType::Comma]) {
-            exprs.push(self.assignment()?);
-        }
-        if exprs.len() == 1 {
-            return Ok(exprs.pop().unwrap());
-        } else {
-            return Ok(Expr::Comma(CommaExpr::new(exprs)));
-        }
-    }
-
-    fn assignment(&mut self) -> Result<Expr, LoxErr> {
-        let expr = self.conditional()?;    // （在可能存在的等号前面的）表达式
-        if self.matches(&[TokenType::Equal]) {
-            let equals = self.previous().clone();
-            let value = self.assignment()?; // 等号后面的表达式
-
-            match expr {
-                Expr::Variable(variable_expr) => return Ok(Expr::Assign(AssignExpr::new(variable_expr.name, value))),
-                Expr::Get(get_expr) => return Ok(Expr::Set(SetExpr::new(*get_expr.object, get_expr.name, value))),
-
-                _ => return Err(LoxErr::Parse { line: equals.line, lexeme: equals.lexeme, message: "Invalid assignment target.".to_string() }),
-            }            
-        }
-        Ok(expr)
-    }
-
-    fn conditional(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.or()?;
-        if self.matches(&[TokenType::Question]) {
-            let then_branch = self.expression()?;
-            self.consume(&TokenType::Colon, "Expect ':' after then branch of conditional expression.")?;
-            let else_branch = self.conditional()?;
-            expr = Expr::Conditional(ConditionalExpr::new(expr, then_branch, else_branch));
-        }
-        Ok(expr)
-    }
-
-    fn or(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.and()?;
-        while self.matches(&[TokenType::Or]) {
-            let operator = self.previous().clone();
-            let right = self.and()?;
-            expr = Expr::Logical(LogicalExpr::new(expr, operator, right));
-        }
-        Ok(expr)
-    }
-
-    fn and(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.equality()?;
-        while self.matches(&[TokenType::And]) {
-            let operator = self.previous().clone();
-            let right = self.equality()?;
-            expr = Expr::Logical(LogicalExpr::new(expr, operator, right));
-        }
-        Ok(expr)
-    }
-
-    fn statement(&mut self) -> Result<Stmt, LoxErr> {
-        match self.get_match_type(&[TokenType::Break, TokenType::If, TokenType::Print, TokenType::Return, TokenType::While, TokenType::For, TokenType::LeftBrace,]) {
-            Some(TokenType::Break) => self.break_statement(),
-            Some(TokenType::If) => self.if_statement(),
-            Some(TokenType::Print) => self.print_statement(),
-            Some(TokenType::Return) => self.return_statement(),
-            Some(TokenType::While) => self.while_statement(),
-            Some(TokenType::For) => self.for_statement(),
-            Some(TokenType::LeftBrace) => Ok(Stmt::Block { statements: self.block()? }),
-            _ => self.expression_statement(),   // None
-        }
-    }
-
-    fn break_statement(&mut self) -> Result<Stmt, LoxErr> {
-        let keyword = self.previous().clone();
-
-        self.consume(&TokenType::Semicolon, "Expect ';' after break.")?;
-        Ok(Stmt::Break { keyword: keyword })
-    }
-
-    fn if_statement(&mut self) -> Result<Stmt, LoxErr> {
-        self.consume(&TokenType::LeftParen, "Expect '(' after 'if'.")?;
-        let condition = self.expression()?;
-        self.consume(&TokenType::RightParen, "Expect ')' after if condition.")?;
-
-        let then_branch = Box::new(self.statement()?);
-        let else_branch = if self.matches(&[TokenType::Else]) {
-            Some(Box::new(self.statement()?))
-        } else {
-            None
-        };
-        Ok(Stmt::If{
-            condition: condition,
-            then_branch: then_branch,
-            else_branch: else_branch,
-        })
-    }
-
-    fn while_statement(&mut self) -> Result<Stmt, LoxErr> {
-        self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
-        let condition = self.expression()?;
-        self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
-        let body = Box::new(self.statement()?);
-        Ok(Stmt::While { condition: condition, body: body })
-    }
-
-    // 语法糖，变成 while
-    fn for_statement(&mut self) -> Result<Stmt, LoxErr> {
-        
-        self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.")?;
-        
-        let initializer = match self.get_match_type(&[TokenType::Semicolon, TokenType::Var,]) {
-            Some(TokenType::Semicolon) => None,
-            Some(TokenType::Var) => Some(self.var_declaration()?),
-            _ => Some(self.expression_statement()?),
-        };
-
-        let condition = if self.check(&TokenType::Semicolon) {
-            Expr::Literal(LiteralExpr::new(Object::Bool(true)))     // 没写条件时，视为 true
-        } else {
-            self.expression()?
-        };
-        self.consume(&TokenType::Semicolon, "Expect ';' after loop condition.")?;
-        let increment = if self.check(&TokenType::RightParen) {
-            None
-        } else {
-            Some(self.expression()?)
-        };
-        self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
-
-        let mut for_body = self.statement()?;
-        if increment.is_some() {
-            for_body = Stmt::Block { 
-                statements: vec![for_body, Stmt::Expression { expression: increment.unwrap() },]
-            };
-        }
-
-        let mut desugar_res = Stmt::While { 
-            condition: condition, 
-            body: Box::new(for_body),
-        };
-
-        if initializer.is_some() {
-            desugar_res = Stmt::Block { 
-                statements: vec![initializer.unwrap(), desugar_res,] 
-            };
-        }
-
-        Ok(desugar_res)
-    }
-
-    fn print_statement(&mut self) -> Result<Stmt, LoxErr> {
-        let value = self.expression()?;
-        self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print{expression: value})
-    }
-
-    fn return_statement(&mut self) -> Result<Stmt, LoxErr> {
-        let keyword = self.previous().clone();
-        let value = if self.check(&TokenType::Semicolon) {
-            None
-        } else {
-            Some(self.expression()?)
-        };
-        self.consume(&TokenType::Semicolon, "Expect ';' after return value.")?;
-        Ok(Stmt::Return { keyword: keyword, value: value })
-    }
-
-    fn expression_statement(&mut self) -> Result<Stmt, LoxErr> {
-        let expr = self.expression()?;
-        self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Expression{expression: expr})
-    }
-
-    fn var_declaration(&mut self) -> Result<Stmt, LoxErr> {
-        let name = self.consume(&TokenType::Identifier, "Expect variable name.")?.clone();
-        
-        let initializer: Option<Expr> = if self.matches(&[TokenType::Equal]) {
-            Some(self.expression()?)
-        } else {
-            None
-        };
-        self.consume(&TokenType::Semicolon, "Expect ';' after variable declaration.")?;
-        Ok(Stmt::Var { name: name, initializer: initializer })
-    }
-
-    fn function_declaration(&mut self, kind: &str) -> Result<Stmt, LoxErr> {
-        let name = self.consume(&TokenType::Identifier, &format!("Expect {} name.", kind))?.clone();
-        self.consume(&TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
-        let mut parameters = Vec::new();
-        if !self.check(&TokenType::RightParen) {
-            loop {
-                if parameters.len() >= 255 {
-                    eprintln!("{}", LoxErr::Parse { line: self.peek().line, lexeme: self.peek().lexeme.clone(), message: "Can't have more than 255 parameters.".to_string() });
-                }
-                parameters.push(self.consume(&TokenType::Identifier, "Expect parameter name.")?.clone());
-                if !self.matches(&[TokenType::Comma]) {
-                    break;
-                }
-            }
-        }
-        self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
-
-        self.consume(&TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;    // format 里的大括号需要使用两个连续的大括号 {{ 或 }}
-        let body = self.block()?;
-        Ok(Stmt::FunctionDeclaration { function_declaration: FunctionDeclaration {
-            name: name,
-            params: parameters,
-            body: body,
-        } })
-    }
-
-    fn class_declaration(&mut self) -> Result<Stmt, LoxErr> {
-        let name = self.consume(&TokenType::Identifier, "Expect class name.")?.clone();
-        let superclass = if self.matches(&[TokenType::Less]) {
-            self.consume(&TokenType::Identifier, "Expect superclass name.")?;
-            Some(VariableExpr::new(self.previous().clone()))
-        } else {
-            None
-        };
-
-
-        self.consume(&TokenType::LeftBrace, "Expect '{' before class body.")?;
-        let mut methods = Vec::new();
-        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.function_declaration("method")?.into_function_declaration().unwrap());
-        }
-        self.consume(&TokenType::RightBrace, "Expect '}' after class body.")?;
-        Ok(Stmt::ClassDeclaration { class_declaration: ClassDeclaration {
-            name: name,
-            superclass: superclass,
-            methods: methods,
-        } })
-    }
-
-    // 调用 block 前要先消费掉开头的 `{` 
-    fn block(&mut self) -> Result<Vec<Stmt>, LoxErr>{
-        let mut statements = Vec::new();
-        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
-        }
-        self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
-        Ok(statements)
-    }
-
-    fn equality(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.comparison()?;
-
-        while self.matches(&[TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous().clone();
-            let right: Expr = self.comparison()?;
-            expr = Expr::Binary(BinaryExpr::new(expr, operator, right));
-        }
-        Ok(expr)
-    }
-
-    fn comparison(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.term()?;
-
-        while self.matches(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
-            let operator = self.previous().clone();
-            let right: Expr = self.term()?;
-            expr = Expr::Binary(BinaryExpr::new(expr, operator, right));
-        }
-        Ok(expr)
-    }
-
-    fn term(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.factor()?;
-
-        while self.matches(&[TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous().clone();
-            let right: Expr = self.factor()?;
-            expr = Expr::Binary(BinaryExpr::new(expr, operator, right));
-        }
-        Ok(expr)
-    }
-
-    fn factor(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.unary()?;
-
-        while self.matches(&[TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous().clone();
-            let right: Expr = self.unary()?;
-            expr = Expr::Binary(BinaryExpr::new(expr, operator, right));
-        }
-        Ok(expr)
-    }
-
-    fn unary(&mut self) -> Result<Expr, LoxErr> {
-        if self.matches(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            return Ok(Expr::Unary(UnaryExpr::new(operator, right)));
-        }
-        self.call()
-    }
-
-
-
-    // todo 把 call 和 get 分开
-    fn call(&mut self) -> Result<Expr, LoxErr> {
-        let mut expr = self.primary()?;
-
-        // 这里有个 loop，是因为一个 call 的结果可能也是 callee，比如f1(a1, a2) 的结果是 f2，可以 f1(a1, a2)(b1, b2) 这样调用。加了 . 后可能是 a.b.c(d)e(f,g).h
-        loop {
-            // if self.matches(&[TokenType::LeftParen]) {
-            //     expr = self.finish_call(expr)?;
-            // } else {
-            //     break;
-            // }
-
-            match self.get_match_type(&[TokenType::LeftParen, TokenType::Dot]) {
-                Some(TokenType::LeftParen) => expr = self.finish_call(expr)?,
-                Some(TokenType::Dot) => {
-                    let name = self.consume(&TokenType::Identifier, "Expect property name after '.'.")?;
-                    expr = Expr::Get(GetExpr::new(expr, name.clone()));
-                }
-                _ => break,
-            }
-        }
-        Ok(expr)
-    }
-
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, LoxErr> {
-        let mut arguments = Vec::new();
-        if !self.check(&TokenType::RightParen) {
-            loop {
-                if arguments.len() >= 255 {
-                    // 它会报告这个错误，并继续执行解析
-                    eprintln!("{}", LoxErr::Parse { line: self.peek().line, lexeme: self.peek().lexeme.clone(), message: "Can't have more than 255 arguments.".to_string() });
-                    
-                }
-                arguments.push(self.assignment()?);     // 函数调用的参数列表里的 expression 不支持逗号，只能是 assignment
-                if !self.matches(&[TokenType::Comma]) {
-                    break;
-                }
-            }
-        }
-        let paren = self.consume(&TokenType::RightParen, "Expect ')' after arguments.")?.clone();
-        Ok(Expr::Call(CallExpr::new(callee, paren, arguments)))
-    }
-
-    fn primary(&mut self) -> Result<Expr, LoxErr> {
-        // 原版用的是多个 if else 配合 self.matches，会自动 advance，所以这里记得要手动 advance。这里还是不要用 get_match_type 了，没必要多写一遍
-        match self.peek().token_type {
-
-            TokenType::False | TokenType::True | TokenType::Nil | TokenType::Number | TokenType::String => {
-                self.advance();
-                Ok(Expr::Literal(LiteralExpr::new(self.previous().literal.clone())))
-            }
-
-            TokenType::This => {
-                self.advance();
-                Ok(Expr::This(ThisExpr::new(self.previous().clone())))
-            }
-
-            TokenType::Super => {
-                self.advance();
-                let keyword = self.previous().clone();
-                self.consume(&TokenType::Dot, "Expect '.' after 'super'.")?;
-                let method = self.consume(&TokenType::Identifier, "Expect superclass method name.")?.clone();
-
-                Ok(Expr::Super(SuperExpr::new(keyword, method)))
-            }
-
-            TokenType::Identifier => {
-                self.advance();
-                Ok(Expr::Variable(VariableExpr::new(self.previous().clone())))
-            }
-
-            TokenType::LeftParen => {
-                self.advance();
-                let expr = self.expression()?;
-                self.consume(&TokenType::RightParen, "Expect ')' after expression.")?;
-                Ok(Expr::Grouping(GroupingExpr::new(expr)))
-            }
-            _ => {
-                Err(LoxErr::Parse { line: self.peek().line, lexeme: String::new(), message: "Expect expression.".to_string() })
-            }
-        }
-
-
-    }
-
-    fn consume(&mut self, tt: &TokenType, message: &str) -> Result<&Token, LoxErr> {
-        if self.check(tt) {
-            Ok(self.advance())
-        } else {
-            let peek = self.peek();
-            match peek.token_type {
-                TokenType::Eof => Err(LoxErr::Parse { line: peek.line, lexeme: "end".to_string(), message: message.to_string() }),
-                _ => Err(LoxErr::Parse { line: peek.line, lexeme: format!("'{}'", peek.lexeme.clone()), message: message.to_string() }),
-            }
-        }
-    }
-
-    fn matches(&mut self, types: &[TokenType]) -> bool{
-        for t in types {
-            if self.check(t) {
-                self.advance();
-                return true;
-            }
-        }
-        false
-    }
-
-    fn get_match_type(&mut self, types: &[TokenType]) -> Option<TokenType> {
-        for t in types {
-            if self.check(t) {
-                self.advance();
-                return Some(*t);
-            }
-        }
-        None
-    }
-
-    fn check(&self, tt: &TokenType) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        self.peek().token_type == *tt
-    }
-
-    fn advance(&mut self) -> &Token {
-        if !self.is_at_end() {
-            self.current += 1;
-        }
-        self.previous()
-    }
-
-    fn is_at_end(&self) -> bool {
-        self.peek().token_type == TokenType::Eof
-    }
-
-    fn peek(&self) -> &Token {
-        &self.tokens[self.current]
-    }
-
-    fn previous(&self) -> &Token {
-        &self.tokens[self.current - 1]
-    }
-
-    // 校准到下一条语句
-    fn synchronize(&mut self) {
-        self.advance();
-
-        while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
-                return;
-            }
-
-            match self.peek().token_type {
-                TokenType::Class | TokenType::Fun | TokenType::Var | TokenType::For | TokenType::If | TokenType::While | TokenType::Print | TokenType::Return => return,
-                _ => (),
-            }
-            self.advance();
-        }
-    }
-
-}
+use std::rc::Rc;
+
+use crate::err::LoxErr;
+use crate::stack;
+use crate::stmt::{ClassDeclaration, FunctionDeclaration, Stmt, SwitchCase};
+use crate::token::Token;
+use crate::object::Object;
+
+use crate::expr::{AssignExpr, CallExpr, CommaExpr, ConditionalExpr, Expr, GetExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr};
+use crate::expr::{BinaryExpr, GroupingExpr, LiteralExpr, UnaryExpr, VariableExpr};
+use crate::token_type::TokenType;
+
+
+// 表达式嵌套层数的默认上限。递归时栈不够会换新的栈，这个上限是防着病态的输入把内存耗光，WebAssembly 里没法换栈也靠它
+pub const MAX_EXPRESSION_DEPTH: usize = 1000;
+
+// 中缀运算符的优先级，从低到高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Assignment, // =，右结合
+    Conditional,    // ?:，右结合
+    Or,
+    And,
+    Equality,   // == !=
+    Comparison, // < <= > >=
+    Term,   // + -
+    Factor, // * / %
+    Unary,  // ! -，前缀运算符的操作数只能是更高优先级的
+}
+
+impl Precedence {
+    // 左结合的运算符，右操作数里的运算符优先级至少要高一级
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::Assignment => Precedence::Conditional,
+            Precedence::Conditional => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor | Precedence::Unary => Precedence::Unary,
+        }
+    }
+}
+
+// 中缀运算符解析出来是哪种表达式
+#[derive(Debug, Clone, Copy)]
+enum Infix {
+    Binary,
+    Logical,    // and、or 短路求值
+    Conditional,
+    Assign,
+}
+
+// 加中缀运算符只要在这里加一行，再在 infix 里处理新的表达式种类
+const INFIX_RULES: &[(TokenType, Precedence, Infix)] = &[
+    (TokenType::Equal, Precedence::Assignment, Infix::Assign),
+    (TokenType::Question, Precedence::Conditional, Infix::Conditional),
+    (TokenType::Or, Precedence::Or, Infix::Logical),
+    (TokenType::And, Precedence::And, Infix::Logical),
+    (TokenType::BangEqual, Precedence::Equality, Infix::Binary),
+    (TokenType::EqualEqual, Precedence::Equality, Infix::Binary),
+    (TokenType::Greater, Precedence::Comparison, Infix::Binary),
+    (TokenType::GreaterEqual, Precedence::Comparison, Infix::Binary),
+    (TokenType::Less, Precedence::Comparison, Infix::Binary),
+    (TokenType::LessEqual, Precedence::Comparison, Infix::Binary),
+    (TokenType::Minus, Precedence::Term, Infix::Binary),
+    (TokenType::Plus, Precedence::Term, Infix::Binary),
+    (TokenType::Slash, Precedence::Factor, Infix::Binary),
+    (TokenType::Star, Precedence::Factor, Infix::Binary),
+    (TokenType::Percent, Precedence::Factor, Infix::Binary),
+];
+
+// 不是中缀运算符的话返回 None
+fn infix_rule(token_type: TokenType) -> Option<(Precedence, Infix)> {
+    INFIX_RULES.iter().find(|(operator, ..)| *operator == token_type).map(|&(_, precedence, infix)| (precedence, infix))
+}
+
+pub struct Parser<'a> {
+    tokens: &'a [Rc<Token>],
+    current: usize,
+    pub errors: Vec<LoxErr>,
+    last_error_position: Option<usize>,   // 同一个 token 位置只报告一次错误
+    pub max_expression_depth: usize,
+    expression_depth: usize,    // 当前 parse_precedence 递归了几层
+    pub unexpected_eof: bool,   // 第一个错误是在文件末尾报的，说明代码可能只是没写完，REPL 会接着读下一行
+}
+
+impl Parser<'_> {
+
+    pub fn new(tokens: &[Rc<Token>]) -> Parser<'_> {
+        Parser { tokens, current: 0, errors: Vec::new(), last_error_position: None, max_expression_depth: MAX_EXPRESSION_DEPTH, expression_depth: 0, unexpected_eof: false }
+    }
+
+    pub fn parse(&mut self) -> Vec<Stmt> {
+        let mut statements: Vec<Stmt> = Vec::new();
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+
+                // 原版是在 declaration 处理错误，这里先收集起来，由 Lox 统一报告
+                Err(lox_err) => {
+                    self.report_error(lox_err);
+                    self.synchronize();
+                }
+            }            
+        }
+        statements
+    }
+
+    // REPL 里输入的是一个不带分号的表达式时返回它，其他情况（语句、声明、语法错误）返回 None，交给 parse 照常处理
+    pub fn parse_bare_expression(&mut self) -> Option<Expr> {
+        let expression = self.expression().ok()?;
+        if self.is_at_end() && self.errors.is_empty() {
+            Some(expression)
+        } else {
+            None
+        }
+    }
+
+
+    fn declaration(&mut self) -> Result<Stmt, LoxErr> {
+        // 出错返回时没有恢复深度，表达式里不会出现声明，所以在这里清零就行
+        self.expression_depth = 0;
+        match self.get_match_type(&[TokenType::Var, TokenType::Const, TokenType::Fun, TokenType::Class]) {
+            Some(TokenType::Var) => self.var_declaration(),
+            Some(TokenType::Const) => self.const_declaration(),
+            Some(TokenType::Fun) => self.function_declaration("function"),
+            Some(TokenType::Class) => self.class_declaration(),
+            _ => self.statement(),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, LoxErr> {
+        self.comma_expression()
+    }
+
+    fn comma_expression(&mut self) -> Result<Expr, LoxErr> {
+        let mut exprs = Vec::new();
+        exprs.push(self.assignment()?);
+        while self.matches(&[TokenType::Comma]) {
+            exprs.push(self.assignment()?);
+        }
+        if exprs.len() == 1 {
+            return Ok(exprs.pop().unwrap());
+        } else {
+            return Ok(Expr::Comma(CommaExpr::new(exprs)));
+        }
+    }
+
+    // 不带逗号的表达式，函数调用的参数这些地方用
+    fn assignment(&mut self) -> Result<Expr, LoxErr> {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    /*
+    Pratt 解析：先解析前缀部分（一元运算、调用、字面量这些），然后只要下一个 token 是优先级不低于 min 的中缀运算符，
+    就把已经解析出来的表达式作为它的左操作数，接着往右解析
+    左结合的运算符，右操作数里只能有更高优先级的运算符；右结合的（赋值、三元）可以有同一级的
+    */
+    fn parse_precedence(&mut self, min: Precedence) -> Result<Expr, LoxErr> {
+        stack::guard(|| self.parse_precedence_inner(min))
+    }
+
+    fn parse_precedence_inner(&mut self, min: Precedence) -> Result<Expr, LoxErr> {
+        self.nest()?;
+        let mut expr = self.prefix()?;
+        while let Some((precedence, infix)) = infix_rule(self.peek().token_type) {
+            if precedence < min {
+                break;
+            }
+            let operator = self.advance().clone();
+            expr = self.infix(expr, operator, precedence, infix)?;
+        }
+        self.expression_depth -= 1;
+        Ok(expr)
+    }
+
+    fn infix(&mut self, left: Expr, operator: Rc<Token>, precedence: Precedence, infix: Infix) -> Result<Expr, LoxErr> {
+        match infix {
+            Infix::Binary => {
+                let right = self.parse_precedence(precedence.next())?;
+                Ok(Expr::Binary(BinaryExpr::new(left, operator, right)))
+            }
+            Infix::Logical => {
+                let right = self.parse_precedence(precedence.next())?;
+                Ok(Expr::Logical(LogicalExpr::new(left, operator, right)))
+            }
+            // 中间可以是任意表达式，: 后面是同一级的三元表达式
+            Infix::Conditional => {
+                let then_branch = self.expression()?;
+                self.consume(&TokenType::Colon, "Expect ':' after then branch of conditional expression.")?;
+                let else_branch = self.parse_precedence(Precedence::Conditional)?;
+                Ok(Expr::Conditional(ConditionalExpr::new(left, then_branch, else_branch)))
+            }
+            // 等号左边先当普通表达式解析出来，再看它是不是能赋值的目标
+            Infix::Assign => {
+                let value = self.parse_precedence(Precedence::Assignment)?;
+                match left {
+                    Expr::Variable(variable_expr) => Ok(Expr::Assign(AssignExpr::new(variable_expr.name, value))),
+                    Expr::Get(get_expr) => Ok(Expr::Set(SetExpr::new(*get_expr.object, get_expr.name, value))),
+                    // this 不是变量，不能重新绑定
+                    Expr::This(_) => Err(LoxErr::Parse { line: operator.line, column: operator.column, span: operator.span, lexeme: format!("'{}'", operator.lexeme), message: "Can't assign to 'this'.".to_string() }),
+                    _ => Err(LoxErr::Parse { line: operator.line, column: operator.column, span: operator.span, lexeme: format!("'{}'", operator.lexeme), message: "Invalid assignment target.".to_string() }),
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, LoxErr> {
+        match self.get_match_type(&[TokenType::Assert, TokenType::Break, TokenType::Continue, TokenType::Do, TokenType::If, TokenType::Import, TokenType::Switch, TokenType::Throw, TokenType::Try, TokenType::Print, TokenType::Return, TokenType::While, TokenType::For, TokenType::LeftBrace,]) {
+            Some(TokenType::Assert) => self.assert_statement(),
+            Some(TokenType::Break) => self.break_statement(),
+            Some(TokenType::Continue) => self.continue_statement(),
+            Some(TokenType::Do) => self.do_while_statement(),
+            Some(TokenType::If) => self.if_statement(),
+            Some(TokenType::Import) => self.import_statement(),
+            Some(TokenType::Switch) => self.switch_statement(),
+            Some(TokenType::Throw) => self.throw_statement(),
+            Some(TokenType::Try) => self.try_statement(),
+            Some(TokenType::Print) => self.print_statement(),
+            Some(TokenType::Return) => self.return_statement(),
+            Some(TokenType::While) => self.while_statement(),
+            Some(TokenType::For) => self.for_statement(),
+            Some(TokenType::LeftBrace) => {
+                let brace = Rc::clone(self.previous());
+                Ok(Stmt::Block { brace: brace, statements: self.block()? })
+            }
+            _ => self.expression_statement(),   // None
+        }
+    }
+
+    fn break_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = self.previous().clone();
+
+        self.consume(&TokenType::Semicolon, "Expect ';' after break.")?;
+        Ok(Stmt::Break { keyword: keyword })
+    }
+
+    fn continue_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = self.previous().clone();
+
+        self.consume(&TokenType::Semicolon, "Expect ';' after continue.")?;
+        Ok(Stmt::Continue { keyword: keyword })
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        let condition = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after if condition.")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.matches(&[TokenType::Else]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+        Ok(Stmt::If{
+            keyword: keyword,
+            condition: condition,
+            then_branch: then_branch,
+            else_branch: else_branch,
+        })
+    }
+
+    fn import_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = self.previous().clone();
+        // from 不是关键字，只在这里有特殊含义
+        let name = if self.matches(&[TokenType::Identifier]) {
+            let name = self.previous().clone();
+            if !(self.check(&TokenType::Identifier) && &*self.peek().lexeme == "from") {
+                return Err(self.error_at_peek("Expect 'from' after module name."));
+            }
+            self.advance();
+            Some(name)
+        } else {
+            None
+        };
+        let path = self.consume(&TokenType::String, "Expect module path after 'import'.")?.literal.to_string();
+        self.consume(&TokenType::Semicolon, "Expect ';' after module path.")?;
+        Ok(Stmt::Import { keyword: keyword, name: name, path: path })
+    }
+
+    fn switch_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'switch'.")?;
+        let subject = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after switch value.")?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' before switch cases.")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+        while self.check(&TokenType::Case) || self.check(&TokenType::Default) {
+            if default.is_some() {
+                // 报错后接着解析，不然后面的分支会被当成 switch 外面的语句，报出一串无关的错误
+                let lox_err = self.error_at_peek("Default must be the last case in a switch.");
+                self.report_error(lox_err);
+            }
+            // 分支开头出错时仍然解析分支体，让错误恢复停在下一个分支上
+            if self.matches(&[TokenType::Case]) {
+                let case = Rc::clone(self.previous());
+                let value = self.case_value();
+                let body = Stmt::Block { brace: case, statements: self.switch_arm()? };
+                if let Some(value) = value {
+                    cases.push(SwitchCase { value: value, body: body });
+                }
+            } else {
+                let case = Rc::clone(self.advance());
+                if let Err(lox_err) = self.consume(&TokenType::Colon, "Expect ':' after 'default'.") {
+                    self.report_error(lox_err);
+                }
+                default = Some(Box::new(Stmt::Block { brace: case, statements: self.switch_arm()? }));
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after switch cases.")?;
+        Ok(Stmt::Switch {
+            keyword: keyword,
+            subject: subject,
+            cases: cases,
+            default: default,
+        })
+    }
+
+    fn case_value(&mut self) -> Option<Expr> {
+        let value = self.expression().and_then(|value| {
+            self.consume(&TokenType::Colon, "Expect ':' after case value.")?;
+            Ok(value)
+        });
+        match value {
+            Ok(value) => Some(value),
+            Err(lox_err) => {
+                self.report_error(lox_err);
+                None
+            }
+        }
+    }
+
+    // 一个分支里的声明，一直到下一个 case、default 或者 switch 的 }
+    fn switch_arm(&mut self) -> Result<Vec<Stmt>, LoxErr> {
+        let mut statements = Vec::new();
+        while !self.check(&TokenType::Case) && !self.check(&TokenType::Default) && !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(lox_err) => {
+                    self.report_error(lox_err);
+                    if !self.check(&TokenType::RightBrace) && !self.check(&TokenType::Case) && !self.check(&TokenType::Default) {
+                        self.synchronize();
+                    }
+                }
+            }
+        }
+        Ok(statements)
+    }
+
+    fn assert_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = self.previous().clone();
+        // 条件和消息之间的逗号不是逗号表达式
+        let condition = self.assignment()?;
+        let message = if self.matches(&[TokenType::Comma]) {
+            Some(self.assignment()?)
+        } else {
+            None
+        };
+        self.consume(&TokenType::Semicolon, "Expect ';' after assertion.")?;
+        Ok(Stmt::Assert { keyword: keyword, condition: condition, message: message })
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = self.previous().clone();
+        let value = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::Throw { keyword: keyword, value: value })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        self.consume(&TokenType::LeftBrace, "Expect '{' after 'try'.")?;
+        let try_block = self.block()?;
+        self.consume(&TokenType::Catch, "Expect 'catch' after try block.")?;
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'catch'.")?;
+        let name = self.consume(&TokenType::Identifier, "Expect exception variable name.")?.clone();
+        self.consume(&TokenType::RightParen, "Expect ')' after exception variable.")?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' before catch body.")?;
+        let catch_block = self.block()?;
+        Ok(Stmt::Try { keyword: keyword, try_block: try_block, name: name, catch_block: catch_block })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.statement()?);
+        Ok(Stmt::While { keyword: keyword, condition: condition, body: body, increment: Vec::new() })
+    }
+
+    fn do_while_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        let body = Box::new(self.statement()?);
+        self.consume(&TokenType::While, "Expect 'while' after do-while body.")?;
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after condition.")?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after do-while condition.")?;
+        Ok(Stmt::DoWhile { keyword: keyword, body: body, condition: condition })
+    }
+
+    // 语法糖，变成 while
+    fn for_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        if self.is_for_in() {
+            return self.for_in_statement();
+        }
+        
+        let initializer = match self.get_match_type(&[TokenType::Semicolon, TokenType::Var,]) {
+            Some(TokenType::Semicolon) => None,
+            Some(TokenType::Var) => Some(self.var_declaration()?),
+            _ => Some(self.expression_statement()?),
+        };
+
+        let condition = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenType::Semicolon, "Expect ';' after loop condition.")?;
+        let increment = if self.check(&TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+        let body = self.statement()?;
+
+        Ok(Stmt::For {
+            keyword: keyword,
+            initializer: initializer.map(Box::new),
+            condition: condition,
+            increment: increment,
+            body: Box::new(body),
+        })
+    }
+
+    // ( 后面是 var IDENTIFIER in
+    fn is_for_in(&self) -> bool {
+        let lookahead = [TokenType::Var, TokenType::Identifier, TokenType::In];
+        lookahead.iter().enumerate().all(|(offset, tt)| {
+            self.tokens.get(self.current + offset).is_some_and(|token| token.token_type == *tt)
+        })
+    }
+
+    fn for_in_statement(&mut self) -> Result<Stmt, LoxErr> {
+        self.advance();
+        let name = self.advance().clone();
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(&TokenType::RightParen, "Expect ')' after for-in clause.")?;
+        let body = Box::new(self.statement()?);
+        Ok(Stmt::ForIn { name: name, iterable: iterable, body: body })
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = Rc::clone(self.previous());
+        let value = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Print{keyword: keyword, expression: value})
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let keyword = self.previous().clone();
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(&TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword: keyword, value: value })
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, LoxErr> {
+        let start = Rc::clone(self.peek());
+        let expr = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
+        Ok(Stmt::Expression{start: start, expression: expr})
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, LoxErr> {
+        let name = self.consume(&TokenType::Identifier, "Expect variable name.")?.clone();
+        
+        let initializer: Option<Expr> = if self.matches(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(&TokenType::Semicolon, "Expect ';' after variable declaration.")?;
+        Ok(Stmt::Var { name: name, initializer: initializer, is_const: false })
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, LoxErr> {
+        let name = self.consume(&TokenType::Identifier, "Expect constant name.")?.clone();
+        self.consume(&TokenType::Equal, "Expect '=' after constant name.")?;
+        let initializer = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after constant declaration.")?;
+        Ok(Stmt::Var { name: name, initializer: Some(initializer), is_const: true })
+    }
+
+    fn function_declaration(&mut self, kind: &str) -> Result<Stmt, LoxErr> {
+        let name = self.consume(&TokenType::Identifier, &format!("Expect {} name.", kind))?.clone();
+        self.consume(&TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let mut parameters = Vec::new();
+        let mut is_variadic = false;
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if parameters.len() >= 255 {
+                    return Err(self.error_at_peek("Can't have more than 255 parameters."));
+                }
+                is_variadic = self.matches(&[TokenType::DotDotDot]);
+                parameters.push(self.consume(&TokenType::Identifier, "Expect parameter name.")?.clone());
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+                if is_variadic {
+                    return Err(self.error_at_peek("Rest parameter must be the last parameter."));
+                }
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        self.consume(&TokenType::LeftBrace, &format!("Expect '{{' before {} body.", kind))?;    // format 里的大括号需要使用两个连续的大括号 {{ 或 }}
+        let body = self.block()?;
+        Ok(Stmt::FunctionDeclaration { function_declaration: Rc::new(FunctionDeclaration {
+            name: name,
+            params: parameters,
+            is_variadic: is_variadic,
+            body: body,
+            captures: Vec::new(),
+        }) })
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, LoxErr> {
+        let name = self.consume(&TokenType::Identifier, "Expect class name.")?.clone();
+        let superclass = if self.matches(&[TokenType::Less]) {
+            self.consume(&TokenType::Identifier, "Expect superclass name.")?;
+            Some(VariableExpr::new(self.previous().clone()))
+        } else {
+            None
+        };
+
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function_declaration("method")?.into_function_declaration().unwrap());
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::ClassDeclaration { class_declaration: ClassDeclaration {
+            name: name,
+            superclass: superclass,
+            methods: methods,
+        } })
+    }
+
+    // 调用 block 前要先消费掉开头的 `{` 
+    // 块内的语句出错时就地恢复，这样块后面的 `}` 不会被当成下一条语句再报一次错
+    fn block(&mut self) -> Result<Vec<Stmt>, LoxErr>{
+        let mut statements = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(lox_err) => {
+                    self.report_error(lox_err);
+                    if !self.check(&TokenType::RightBrace) {
+                        self.synchronize();
+                    }
+                }
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(statements)
+    }
+
+    // 前缀部分：一元运算符，或者后面可能跟着调用、属性访问、后缀自增自减的基本表达式
+    fn prefix(&mut self) -> Result<Expr, LoxErr> {
+        if self.matches(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.previous().clone();
+            let right = self.parse_precedence(Precedence::Unary)?;
+            return Ok(Expr::Unary(UnaryExpr::new(operator, right)));
+        }
+        if self.matches(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            let target = self.call()?;
+            return Parser::increment(target, &operator, false);
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, LoxErr> {
+        let expr = self.call()?;
+        if self.matches(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            return Parser::increment(expr, &operator, true);
+        }
+        Ok(expr)
+    }
+
+    /*
+    自增自减是赋值的语法糖，都用减法表示，这样操作数不是数字时会报错，而不是拼接字符串
+        ++x     =>  x = x - -1
+        --x     =>  x = x - 1
+        x++     =>  (x = x - -1) - 1     值是加之前的数
+        ++a.b   =>  a.b = a.b - -1       a 会求值两次
+    */
+    fn increment(target: Expr, operator: &Token, is_postfix: bool) -> Result<Expr, LoxErr> {
+        let step = if operator.token_type == TokenType::PlusPlus { -1 } else { 1 };
+        let subtract = |left: Expr, right: i64| {
+            let minus = Rc::new(Token::new(TokenType::Minus, "-".to_string(), Object::None, operator.line).with_position(operator.column, operator.span));
+            Expr::Binary(BinaryExpr::new(left, minus, Expr::Literal(LiteralExpr::new(Object::Int(right)))))
+        };
+
+        let assignment = match target {
+            Expr::Variable(variable_expr) => {
+                let value = subtract(Expr::Variable(variable_expr.clone()), step);
+                Expr::Assign(AssignExpr::new(variable_expr.name, value))
+            }
+            Expr::Get(get_expr) => {
+                let current = Expr::Get(GetExpr::new((*get_expr.object).clone(), Rc::clone(&get_expr.name)));
+                Expr::Set(SetExpr::new(*get_expr.object, get_expr.name, subtract(current, step)))
+            }
+            _ => return Err(LoxErr::Parse { line: operator.line, column: operator.column, span: operator.span, lexeme: format!("'{}'", operator.lexeme), message: "Invalid increment target.".to_string() }),
+        };
+        if is_postfix {
+            Ok(subtract(assignment, -step))
+        } else {
+            Ok(assignment)
+        }
+    }
+
+
+
+    // todo 把 call 和 get 分开
+    fn call(&mut self) -> Result<Expr, LoxErr> {
+        let mut expr = self.primary()?;
+
+        // 这里有个 loop，是因为一个 call 的结果可能也是 callee，比如f1(a1, a2) 的结果是 f2，可以 f1(a1, a2)(b1, b2) 这样调用。加了 . 后可能是 a.b.c(d)e(f,g).h
+        loop {
+            // if self.matches(&[TokenType::LeftParen]) {
+            //     expr = self.finish_call(expr)?;
+            // } else {
+            //     break;
+            // }
+
+            match self.get_match_type(&[TokenType::LeftParen, TokenType::Dot]) {
+                Some(TokenType::LeftParen) => {
+                    expr = self.finish_call(expr)?;
+                }
+                Some(TokenType::Dot) => {
+                    let name = self.consume(&TokenType::Identifier, "Expect property name after '.'.")?;
+                    expr = Expr::Get(GetExpr::new(expr, name.clone()));
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, LoxErr> {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    // 整条语句都丢掉，不会再做语义分析和执行
+                    return Err(self.error_at_peek("Can't have more than 255 arguments."));
+                }
+                arguments.push(self.assignment()?);     // 函数调用的参数列表里的 expression 不支持逗号，只能是 assignment
+                if !self.matches(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(&TokenType::RightParen, "Expect ')' after arguments.")?.clone();
+        Ok(Expr::Call(CallExpr::new(callee, paren, arguments)))
+    }
+
+    /*
+    插值字符串变成从左往右的一串加法，第一段总是字符串，后面的值都会拼接成字符串
+        "a ${b} c ${d}"  =>  "a " + b + " c " + d
+    */
+    fn interpolation(&mut self) -> Result<Expr, LoxErr> {
+        let mut expr = Expr::Literal(LiteralExpr::new(self.previous().literal.clone()));
+        loop {
+            let segment = self.previous();
+            let plus = Rc::new(Token::new(TokenType::Plus, "+".to_string(), Object::None, segment.line).with_position(segment.column, segment.span));
+            let embedded = self.expression()?;
+            expr = Expr::Binary(BinaryExpr::new(expr, Rc::clone(&plus), embedded));
+
+            let is_last = !self.matches(&[TokenType::Interpolation]);
+            if is_last {
+                self.consume(&TokenType::String, "Expect '}' after interpolated expression.")?;
+            }
+            // 空的片段不用拼
+            if !matches!(&self.previous().literal, Object::String(segment) if segment.is_empty()) {
+                expr = Expr::Binary(BinaryExpr::new(expr, plus, Expr::Literal(LiteralExpr::new(self.previous().literal.clone()))));
+            }
+            if is_last {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, LoxErr> {
+        // 原版用的是多个 if else 配合 self.matches，会自动 advance，所以这里记得要手动 advance。这里还是不要用 get_match_type 了，没必要多写一遍
+        match self.peek().token_type {
+
+            // 插值表达式 } 后面接着的字符串片段不是字面量，轮到它说明 ${} 里的表达式没写完，比如 "${1 + }"
+            TokenType::String | TokenType::Interpolation if self.peek().lexeme.starts_with('}') => Err(self.error_at_peek("Expect expression.")),
+
+            TokenType::False | TokenType::True | TokenType::Nil | TokenType::Number | TokenType::String => {
+                self.advance();
+                Ok(Expr::Literal(LiteralExpr::new(self.previous().literal.clone())))
+            }
+
+            TokenType::This => {
+                self.advance();
+                Ok(Expr::This(ThisExpr::new(self.previous().clone())))
+            }
+
+            TokenType::Interpolation => {
+                self.advance();
+                self.interpolation()
+            }
+
+            TokenType::Super => {
+                self.advance();
+                let keyword = self.previous().clone();
+                self.consume(&TokenType::Dot, "Expect '.' after 'super'.")?;
+                let method = self.consume(&TokenType::Identifier, "Expect superclass method name.")?.clone();
+
+                Ok(Expr::Super(SuperExpr::new(keyword, method)))
+            }
+
+            TokenType::Identifier => {
+                self.advance();
+                Ok(Expr::Variable(VariableExpr::new(self.previous().clone())))
+            }
+
+            TokenType::LeftParen => {
+                self.advance();
+                let expr = self.expression()?;
+                self.consume(&TokenType::RightParen, "Expect ')' after expression.")?;
+                Ok(Expr::Grouping(GroupingExpr::new(expr)))
+            }
+
+            /*
+            错误产生式：二元运算符前面少了左操作数，比如 `+ 3`、`== x`
+            先把右操作数解析掉再报错，错误恢复从右操作数后面开始。右操作数自己的错误不报，一处只报一个错误
+            */
+            token_type => match infix_rule(token_type) {
+                Some((precedence, Infix::Binary | Infix::Logical)) => {
+                    let lox_err = self.error_at_peek("Missing left-hand operand.");
+                    self.advance();
+                    let _ = self.parse_precedence(precedence.next());
+                    Err(lox_err)
+                }
+                _ => Err(self.error_at_peek("Expect expression.")),
+            },
+        }
+
+
+    }
+
+    // 进入更深一层表达式，超过上限就报错，不再往下递归。括号、一元运算符、调用的参数、右结合的运算符才会嵌套
+    // 左结合的运算符链是在 parse_precedence 的循环里接起来的，多长都不算深
+    fn nest(&mut self) -> Result<(), LoxErr> {
+        self.expression_depth += 1;
+        if self.expression_depth > self.max_expression_depth {
+            return Err(self.error_at_peek("Expression nesting is too deep."));
+        }
+        Ok(())
+    }
+
+    fn error_at_peek(&self, message: &str) -> LoxErr {
+        let peek = self.peek();
+        let lexeme = match peek.token_type {
+            TokenType::Eof => "end".to_string(),
+            _ => format!("'{}'", peek.lexeme),
+        };
+        LoxErr::Parse { line: peek.line, column: peek.column, span: peek.span, lexeme: lexeme, message: message.to_string() }
+    }
+
+    fn consume(&mut self, tt: &TokenType, message: &str) -> Result<&Rc<Token>, LoxErr> {
+        if self.check(tt) {
+            Ok(self.advance())
+        } else {
+            let peek = self.peek();
+            match peek.token_type {
+                TokenType::Eof => Err(LoxErr::Parse { line: peek.line, column: peek.column, span: peek.span, lexeme: "end".to_string(), message: message.to_string() }),
+                _ => Err(LoxErr::Parse { line: peek.line, column: peek.column, span: peek.span, lexeme: format!("'{}'", peek.lexeme.clone()), message: message.to_string() }),
+            }
+        }
+    }
+
+    fn matches(&mut self, types: &[TokenType]) -> bool{
+        for t in types {
+            if self.check(t) {
+                self.advance();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn get_match_type(&mut self, types: &[TokenType]) -> Option<TokenType> {
+        for t in types {
+            if self.check(t) {
+                self.advance();
+                return Some(*t);
+            }
+        }
+        None
+    }
+
+    fn check(&self, tt: &TokenType) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+        self.peek().token_type == *tt
+    }
+
+    fn advance(&mut self) -> &Rc<Token> {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type == TokenType::Eof
+    }
+
+    fn peek(&self) -> &Rc<Token> {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Rc<Token> {
+        &self.tokens[self.current - 1]
+    }
+
+    fn report_error(&mut self, lox_err: LoxErr) {
+        if self.last_error_position == Some(self.current) {
+            return;
+        }
+        self.last_error_position = Some(self.current);
+        // 要看错误报在哪，缺左操作数这种错误是解析完后面的代码才报的，报的时候可能已经到末尾了
+        if self.errors.is_empty() && matches!(&lox_err, LoxErr::Parse { lexeme, .. } if lexeme == "end") {
+            self.unexpected_eof = true;
+        }
+        self.errors.push(lox_err);
+    }
+
+    // 校准到下一条语句。跳过的 token 里如果有 `{`，要把整个块跳完，不然块里的语句和 `}` 会引出一连串错误
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+
+        while !self.is_at_end() {
+            match self.advance().token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                TokenType::Semicolon if depth == 0 => return,
+                _ => (),
+            }
+
+            if depth == 0 {
+                match self.peek().token_type {
+                    TokenType::Assert | TokenType::Class | TokenType::Fun | TokenType::Var | TokenType::Const | TokenType::Do | TokenType::For | TokenType::If | TokenType::Import | TokenType::While | TokenType::Print | TokenType::Return | TokenType::Break | TokenType::Continue | TokenType::Switch | TokenType::Throw | TokenType::Try
+                    | TokenType::Case | TokenType::Default  // 下一个 switch 分支
+                    | TokenType::RightBrace => return,     // `}` 留给外层的 block 消费
+                    _ => (),
+                }
+            }
+        }
+    }
+
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::Lox;
+    use crate::scanner::Scanner;
+
+    fn parse_errors(code: &str) -> Vec<LoxErr> {
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(&scanner.tokens);
+        parser.parse();
+        parser.errors
+    }
+
+    fn ast(code: &str) -> String {
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(&scanner.tokens).parse();
+        statements.iter().map(Stmt::to_string).collect::<Vec<String>>().join("\n")
+    }
+
+    #[test]
+    fn test_print_ast() {
+        let code = r#"
+fun f(a, ...rest) {
+    if (a) return g(a, "s"); else print a.b = nil;
+}
+var x = 1 < 2 ? -3 : 4;
+"#;
+        assert_eq!(ast(code), r#"(fun f (a ...rest)
+  (if a
+    (return (call g a "s"))
+    (print (= (. a b) nil))))
+(var x (?: (< 1 2) (- 3) 4))"#);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let cases = [
+            ("a = b = 1 + 2 * 3 - 4 / 5 % 6;", "(; (= a (= b (- (+ 1 (* 2 3)) (% (/ 4 5) 6)))))"),
+            ("a or b and !c == d < -e;", "(; (or a (and b (== (! c) (< d (- e))))))"),
+            ("a ? b : c ? d : e;", "(; (?: a b (?: c d e)))"),
+            ("x = a or b ? c, d : -f.g;", "(; (= x (?: (or a b) (comma c d) (- (. f g)))))"),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(ast(code), expected, "{}", code);
+        }
+        assert!(matches!(&parse_errors("a + b = c;")[..], [LoxErr::Parse { message, .. }] if message == "Invalid assignment target."));
+        assert!(matches!(&parse_errors("a ? b : c = d;")[..], [LoxErr::Parse { message, .. }] if message == "Invalid assignment target."));
+    }
+
+    #[test]
+    fn test_print_for() {
+        assert_eq!(ast("for (var i = 0; i < 3; i = i + 1) print i;"), "(for (var i 0) (< i 3) (= i (+ i 1))\n  (print i))");
+        assert_eq!(ast("for (;;) {}"), "(for _ _ _\n  (block))");
+        // 解析器不脱糖，交给语义分析
+        let mut scanner = Scanner::new();
+        scanner.load_code("for (var i = 0; i < 3;) {}");
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(&scanner.tokens).parse();
+        assert_eq!(statements[0].clone().desugar_for().to_string(), "(block\n  (var i 0)\n  (while (< i 3)\n    (block)\n    (fresh i)))");
+    }
+
+    #[test]
+    fn test_ast_json() {
+        let mut scanner = Scanner::new();
+        scanner.load_code("fun f(a) { return a + 1.5; }\nprint f(nil);");
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(&scanner.tokens).parse();
+        let json = serde_json::to_value(&statements).unwrap();
+
+        let function = &json[0]["function_declaration"];
+        assert_eq!(json[0]["type"], "FunctionDeclaration");
+        assert_eq!(function["params"][0]["lexeme"], "a");
+        // 语义分析填的槽位和捕获不输出
+        assert!(function.get("captures").is_none());
+        let sum = &function["body"][0]["value"];
+        assert_eq!(sum["type"], "Binary");
+        assert_eq!(sum["operator"]["token_type"], "Plus");
+        assert_eq!(sum["operator"]["span"], serde_json::json!({ "start": 20, "end": 21 }));
+        assert_eq!(sum["right"], serde_json::json!({ "type": "Literal", "literal": 1.5 }));
+        assert!(sum["left"].get("slot").is_none());
+
+        let call = &json[1]["expression"];
+        assert_eq!(call["type"], "Call");
+        assert_eq!(call["callee"]["name"]["line"], 2);
+        assert_eq!(call["arguments"][0]["literal"], serde_json::Value::Null);
+    }
+
+    fn nesting_errors(errors: &[LoxErr]) -> usize {
+        errors.iter().filter(|err| matches!(err, LoxErr::Parse { message, .. } if message == "Expression nesting is too deep.")).count()
+    }
+
+    #[test]
+    fn test_deep_grouping_is_an_error() {
+        let code = format!("print {}1{};", "(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(nesting_errors(&parse_errors(&code)), 1);
+        let code = format!("print {}1;", "- ".repeat(10_000));
+        assert_eq!(nesting_errors(&parse_errors(&code)), 1);
+    }
+
+    #[test]
+    fn test_nesting_within_limit() {
+        // 深度按每条语句算，不会累加
+        let statement = format!("var x = {}1{};\n", "(".repeat(MAX_EXPRESSION_DEPTH - 1), ")".repeat(MAX_EXPRESSION_DEPTH - 1));
+        assert!(parse_errors(&statement.repeat(10)).is_empty());
+        // 左结合的运算符链多长都不算嵌套
+        let code = format!("var s = \"\"{};\nprint s;", " + \"x\"".repeat(10_000));
+        assert!(parse_errors(&code).is_empty());
+        assert!(parse_errors(&format!("print f(){};", "(1)".repeat(10_000))).is_empty());
+    }
+
+    #[test]
+    fn test_configurable_nesting_limit() {
+        let mut scanner = Scanner::new();
+        scanner.load_code("print (1 + 2) * 3;");
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(&scanner.tokens);
+        parser.max_expression_depth = 1;
+        parser.parse();
+        assert_eq!(nesting_errors(&parser.errors), 1);
+    }
+
+    #[test]
+    fn test_ast_shares_scanned_tokens() {
+        let mut scanner = Scanner::new();
+        scanner.load_code("print a + b;");
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(&scanner.tokens).parse();
+        let Some(Stmt::Print { expression: Expr::Binary(binary_expr), .. }) = statements.first() else {
+            panic!("Expected print of binary expression, got {:?}", statements);
+        };
+        assert!(Rc::ptr_eq(&binary_expr.operator, &scanner.tokens[2]));
+        match &*binary_expr.left {
+            Expr::Variable(variable_expr) => assert!(Rc::ptr_eq(&variable_expr.name, &scanner.tokens[1])),
+            other => panic!("Expected variable, got {:?}", other),
+        }
+    }
+
+    fn parse_with_errors(code: &str) -> (Vec<Stmt>, Vec<LoxErr>) {
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(&scanner.tokens);
+        let statements = parser.parse();
+        (statements, parser.errors)
+    }
+
+    #[test]
+    fn test_too_many_arguments() {
+        let arguments: Vec<String> = (0..256).map(|i| format!("a{}", i)).collect();
+        let code = format!("f({});\nprint \"after\";", arguments.join(", "));
+        let (statements, errors) = parse_with_errors(&code);
+        // 出错的调用语句被丢掉，后面的语句照常解析
+        assert_eq!(statements.len(), 1);
+        assert!(matches!(&statements[0], Stmt::Print { .. }));
+        match &errors[..] {
+            [LoxErr::Parse { line, lexeme, message, .. }] => {
+                assert_eq!(*line, 1);
+                assert_eq!(lexeme, "'a255'");
+                assert_eq!(message, "Can't have more than 255 arguments.");
+            }
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+
+        // 和原书一样，有语法错误的话整段代码都不执行，后面的语句也不会打印
+        let (output, errors) = Lox::new().run_capture(&code);
+        assert_eq!(output, "");
+        assert_eq!(errors.len(), 1);
+
+        let code = format!("f({});", arguments[..255].join(", "));
+        assert!(parse_errors(&code).is_empty());
+    }
+
+    #[test]
+    fn test_too_many_parameters() {
+        let parameters: Vec<String> = (0..256).map(|i| format!("p{}", i)).collect();
+        let code = format!("fun f({}) {{\n    print p0;\n}}\nprint \"after\";", parameters.join(", "));
+        let (statements, errors) = parse_with_errors(&code);
+        assert_eq!(statements.len(), 1);
+        assert!(matches!(&statements[0], Stmt::Print { .. }));
+        match &errors[..] {
+            [LoxErr::Parse { line, lexeme, message, .. }] => {
+                assert_eq!(*line, 1);
+                assert_eq!(lexeme, "'p255'");
+                assert_eq!(message, "Can't have more than 255 parameters.");
+            }
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+
+        let code = format!("fun f({}) {{}}", parameters[..255].join(", "));
+        assert!(parse_errors(&code).is_empty());
+    }
+
+    // 解析一条表达式语句，返回其中的表达式
+    fn parse_expression(code: &str) -> Expr {
+        let (mut statements, errors) = parse_with_errors(code);
+        assert!(errors.is_empty(), "Unexpected parse errors: {:?}", errors);
+        match statements.pop() {
+            Some(Stmt::Expression { expression, .. }) => expression,
+            other => panic!("Expected expression statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_comma_expression() {
+        match parse_expression("a = 1, b = 2, c;") {
+            Expr::Comma(comma_expr) => {
+                assert_eq!(comma_expr.exprs.len(), 3);
+                assert!(matches!(comma_expr.exprs[0], Expr::Assign(_)));
+                assert!(matches!(comma_expr.exprs[1], Expr::Assign(_)));
+                assert!(matches!(comma_expr.exprs[2], Expr::Variable(_)));
+            }
+            other => panic!("Expected comma expression, got {:?}", other),
+        }
+        // 逗号的优先级比三元表达式低
+        match parse_expression("x ? 1 : 2, 3;") {
+            Expr::Comma(comma_expr) => assert!(matches!(comma_expr.exprs[0], Expr::Conditional(_))),
+            other => panic!("Expected comma expression, got {:?}", other),
+        }
+        assert!(matches!(parse_expression("a = 1;"), Expr::Assign(_)));
+    }
+
+    #[test]
+    fn test_comma_in_arguments() {
+        // 实参列表里的逗号是分隔符
+        match parse_expression("f(1, 2);") {
+            Expr::Call(call_expr) => assert_eq!(call_expr.arguments.len(), 2),
+            other => panic!("Expected call expression, got {:?}", other),
+        }
+        // 加了括号才是逗号表达式
+        match parse_expression("f((1, 2));") {
+            Expr::Call(call_expr) => match &call_expr.arguments[..] {
+                [Expr::Grouping(grouping_expr)] => assert!(matches!(*grouping_expr.expression, Expr::Comma(_))),
+                other => panic!("Expected one grouping argument, got {:?}", other),
+            },
+            other => panic!("Expected call expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_increment_target() {
+        for code in ["++1;", "(a)++;", "a() --;", "++this;"] {
+            match &parse_errors(code)[..] {
+                [LoxErr::Parse { message, .. }] => assert_eq!(message, "Invalid increment target."),
+                other => panic!("Expected one parse error for {}, got {:?}", code, other),
+            }
+        }
+        assert!(parse_errors("a.b.c++; --a.b; x = y++ + ++z;").is_empty());
+    }
+
+    #[test]
+    fn test_do_while_errors() {
+        let message = |code: &str| match &parse_errors(code)[..] {
+            [LoxErr::Parse { message, .. }] => message.clone(),
+            other => panic!("Expected one parse error for {}, got {:?}", code, other),
+        };
+        assert_eq!(message("do print 1; (true);"), "Expect 'while' after do-while body.");
+        assert_eq!(message("do print 1; while (true)"), "Expect ';' after do-while condition.");
+        assert!(parse_errors("do { print 1; } while (false); do print 2; while (nil);").is_empty());
+    }
+
+    #[test]
+    fn test_for_in() {
+        let (statements, errors) = parse_with_errors("for (var c in s) print c; for (var i = 0; i < 1; i = i + 1) {}");
+        assert!(errors.is_empty(), "Unexpected parse errors: {:?}", errors);
+        assert!(matches!(&statements[0], Stmt::ForIn { name, .. } if &*name.lexeme == "c"));
+        assert!(matches!(&statements[1], Stmt::For { .. }));
+        match &parse_errors("for (var c in s print c;")[..] {
+            [LoxErr::Parse { message, .. }] => assert_eq!(message, "Expect ')' after for-in clause."),
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_import() {
+        assert_eq!(ast("import \"a.lox\"; import util from \"lib/util.lox\";"), "(import \"a.lox\")\n(import util \"lib/util.lox\")");
+        match &parse_errors("import util \"lib/util.lox\";")[..] {
+            [LoxErr::Parse { message, .. }] => assert_eq!(message, "Expect 'from' after module name."),
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_try_errors() {
+        let message = |code: &str| match &parse_errors(code)[..] {
+            [LoxErr::Parse { message, .. }] => message.clone(),
+            other => panic!("Expected one parse error for {}, got {:?}", code, other),
+        };
+        assert_eq!(message("try { print 1; } print 2;"), "Expect 'catch' after try block.");
+        assert_eq!(message("try { print 1; } catch { print 2; }"), "Expect '(' after 'catch'.");
+        assert_eq!(message("throw;"), "Expect expression.");
+    }
+
+    #[test]
+    fn test_assert_errors() {
+        let message = |code: &str| match &parse_errors(code)[..] {
+            [LoxErr::Parse { message, .. }] => message.clone(),
+            other => panic!("Expected one parse error for {}, got {:?}", code, other),
+        };
+        assert_eq!(message("assert x"), "Expect ';' after assertion.");
+        assert_eq!(message("assert x, \"a\", \"b\";"), "Expect ';' after assertion.");
+        assert_eq!(message("assert;"), "Expect expression.");
+    }
+
+    #[test]
+    fn test_const_requires_initializer() {
+        match &parse_errors("const x;")[..] {
+            [LoxErr::Parse { message, .. }] => assert_eq!(message, "Expect '=' after constant name."),
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rest_parameter_must_be_last() {
+        match &parse_errors("fun f(...a, b) {}")[..] {
+            [LoxErr::Parse { lexeme, message, .. }] => {
+                assert_eq!(lexeme, "'b'");
+                assert_eq!(message, "Rest parameter must be the last parameter.");
+            }
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+        assert!(parse_errors("fun f(...a) {} fun g(a, ...b) {} print a...b;").len() == 1);
+    }
+
+    #[test]
+    fn test_switch_errors() {
+        let message = |code: &str| match &parse_errors(code)[..] {
+            [LoxErr::Parse { message, .. }] => message.clone(),
+            other => panic!("Expected one parse error for {}, got {:?}", code, other),
+        };
+        assert_eq!(message("switch (x) { default: print 1; case 2: print 2; }"), "Default must be the last case in a switch.");
+        assert_eq!(message("switch (x) { case 1 print 1; }"), "Expect ':' after case value.");
+        assert_eq!(message("switch (x) { case 1: print 1;"), "Expect '}' after switch cases.");
+        // 分支里的错误不会吞掉后面的分支
+        assert_eq!(message("switch (x) { case 1: print ; case 2: print 2; }"), "Expect expression.");
+        assert!(parse_errors("switch (x) {} switch (x) { case 1: case 2: default: }").is_empty());
+    }
+
+    #[test]
+    fn test_interpolation_missing_brace() {
+        match &parse_errors(r#"print "a ${b c}";"#)[..] {
+            [LoxErr::Parse { lexeme, message, .. }] => {
+                assert_eq!(lexeme, "'c'");
+                assert_eq!(message, "Expect '}' after interpolated expression.");
+            }
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+        // 插值里的表达式没写完，报在 } 上，而不是整个字符串后面
+        for (code, expected_lexeme) in [(r#"print "${1 + }";"#, "'}\"'"), (r#"print "${}";"#, "'}\"'"), (r#"print "${-} a ${2}";"#, "'} a ${'")] {
+            match &parse_errors(code)[..] {
+                [LoxErr::Parse { column, lexeme, message, .. }] => {
+                    assert_eq!(message, "Expect expression.", "{}", code);
+                    assert_eq!(lexeme, expected_lexeme);
+                    assert_eq!(&code[*column - 1..*column], "}");
+                }
+                other => panic!("Expected one parse error for {}, got {:?}", code, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_eof_error_line() {
+        let code = "var a = 1;\nprint a\n\n// trailing comment\n";
+        match &parse_errors(code)[..] {
+            [LoxErr::Parse { line, column, span, lexeme, message }] => {
+                assert_eq!(*line, 2);
+                assert_eq!(*column, 8);    // 紧跟在 print a 后面
+                assert_eq!(span.start, code.len());
+                assert_eq!(lexeme, "end");
+                assert_eq!(message, "Expect ';' after value.");
+            }
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_assign_to_this() {
+        let code = r#"
+class A {
+    method() {
+        this = 1;
+    }
+}
+        "#;
+        match &parse_errors(code)[..] {
+            [LoxErr::Parse { line, message, .. }] => {
+                assert_eq!(*line, 4);
+                assert_eq!(message, "Can't assign to 'this'.");
+            }
+            other => panic!("Expected one parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_semicolon_in_block_reports_once() {
+        let code = r#"
+{
+    var a = 1
+    print a;
+}
+print "after";
+        "#;
+        let errors = parse_errors(code);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], LoxErr::Parse { line: 4, .. }));
+    }
+
+    #[test]
+    fn test_missing_left_operand() {
+        for code in ["+ 3;", "print == x;", "var a = * 2 + 1;", "f(and b);"] {
+            match &parse_errors(code)[..] {
+                [LoxErr::Parse { message, .. }] => assert_eq!(message, "Missing left-hand operand.", "{}", code),
+                other => panic!("Expected one parse error for {}, got {:?}", code, other),
+            }
+        }
+        // 右操作数也缺了的话只报缺左操作数
+        assert!(matches!(&parse_errors("/;")[..], [LoxErr::Parse { lexeme, .. }] if lexeme == "'/'"));
+        // 出错的语句丢掉，从右操作数后面接着解析
+        let (statements, errors) = parse_with_errors("print < 1 + 2; print 3;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(statements.iter().map(Stmt::to_string).collect::<Vec<String>>(), vec!["(print 3)"]);
+        assert_eq!(ast("- 3;"), "(; (- 3))");
+    }
+
+    #[test]
+    fn test_missing_paren_skips_whole_block() {
+        let errors = parse_errors("if (true { print 1; }\nprint \"after\";");
+        assert_eq!(errors.len(), 1);
+    }
+}
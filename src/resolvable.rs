@@ -1,7 +1,14 @@
-use crate::token::Token;
-
-pub trait Resolvable {
-    fn name(&self) -> &Token;
-    fn set_distance(&mut self, distance: usize);
-    fn get_distance(&self) -> Option<usize>;
-}
\ No newline at end of file
+use crate::token::Token;
+
+// 局部变量的位置：往外数 distance 层环境，在那层环境里的第 index 个槽位
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Slot {
+    pub distance: usize,
+    pub index: usize,
+}
+
+pub trait Resolvable {
+    fn name(&self) -> &Token;
+    fn set_slot(&mut self, slot: Slot);
+    fn get_slot(&self) -> Option<Slot>;
+}
@@ -1,357 +1,970 @@
-use std::collections::HashMap;
-
-
-use crate::err::LoxErr;
-use crate::expr::{AssignExpr, BinaryExpr, CallExpr, CommaExpr, ConditionalExpr, Expr, GetExpr, GroupingExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr, UnaryExpr, VariableExpr};
-
-use crate::resolvable::Resolvable;
-use crate::stmt::{ClassDeclaration, FunctionDeclaration, Stmt};
-use crate::token::Token;
-
-pub struct Resolver {
-    pub had_resolve_error: bool,
-    scopes: Vec<HashMap<String, bool>>, // 作用域栈，scopes[i] 中值为 false 代表已经声明，true 代表已经定义
-    current_function: FunctionType,
-    current_class: ClassType,
-    is_in_loop: bool,
-}
-
-
-impl Resolver {
-
-    pub fn new() -> Resolver {
-        Resolver {
-            had_resolve_error: false,
-            scopes: Vec::new(),
-            current_function: FunctionType::None,
-            current_class: ClassType::None,
-            is_in_loop: false,
-        }
-    }
-
-    pub fn resolve(&mut self, statements: &mut Vec<Stmt>) {
-        for statement in statements {
-            if let Err(lox_err) = self.resolve_stmt(statement) {
-                eprintln!("{}", lox_err);
-                self.had_resolve_error = true;
-            }
-        }
-    }
-
-    fn resolve_stmt(&mut self, stmt: &mut Stmt) -> Result<(), LoxErr> {
-        match stmt {
-            Stmt::Block { statements } => self.visit_block_stmt(statements),
-            Stmt::Break { keyword } => self.visit_break_stmt(keyword),
-            Stmt::ClassDeclaration { class_declaration } => self.visit_class_declaration_stmt(class_declaration),
-            Stmt::Expression { expression } => self.visit_expression_stmt(expression),
-            Stmt::FunctionDeclaration { function_declaration } => self.visit_function_declaration_stmt(function_declaration),
-            Stmt::If { condition, then_branch, else_branch } => self.visit_if_stmt(condition, then_branch, else_branch),
-            Stmt::While { condition, body } => self.visit_while_stmt(condition, body),
-            Stmt::Print { expression } => self.visit_print_stmt(expression),
-            Stmt::Return { keyword, value } => self.visit_return_stmt(keyword, value),
-            Stmt::Var { name, initializer } => self.visit_var_stmt(name, initializer),
-        }
-    }
-
-    fn resolve_expr(&mut self, expr: &mut Expr) -> Result<(), LoxErr> {
-        match expr {
-            Expr::Assign(assign_expr) => self.visit_assign_expr(assign_expr),
-            Expr::Binary(binary_expr) => self.visit_binary_expr(binary_expr),
-            Expr::Call(call_expr) => self.visit_call_expr(call_expr),
-            Expr::Conditional(conditional_expr) => self.visit_conditional_expr(conditional_expr),
-            Expr::Comma(comma_expr) => self.visit_comma_expr(comma_expr),
-            Expr::Get(get_expr) => self.visit_get_expr(get_expr),
-            Expr::Grouping(grouping_expr) => self.visit_grouping_expr(grouping_expr),
-            Expr::Literal(_literal_expr) => self.visit_literal_expr(),
-            Expr::Logical(logical_expr) => self.visit_logical_expr(logical_expr),
-            Expr::Set(set_expr) => self.visit_set_expr(set_expr),
-            Expr::Super(super_expr) => self.visit_super_expr(super_expr),
-            Expr::This(this_expr) => self.visit_this_expr(this_expr),
-            Expr::Unary(unary_expr) => self.visit_unary_expr(unary_expr),
-            Expr::Variable(variable_expr) => self.visit_variable_expr(variable_expr),
-            
-            
-        }
-    }
-
-    fn resolve_function(&mut self, function_declaration: &mut FunctionDeclaration, function_type: FunctionType) -> Result<(), LoxErr> {
-        let enclosing_function = self.current_function;
-        self.current_function = function_type;
-
-        self.begin_scope();
-        for param in &function_declaration.params {
-            self.declare(param)?;
-            self.define(param);
-        }
-        self.resolve(&mut function_declaration.body);
-        self.end_scope();
-
-        self.current_function = enclosing_function;
-
-        Ok(())
-    }
-
-
-    fn resolve_local(&mut self, resolvable: &mut impl Resolvable) {
-        for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains_key(&resolvable.name().lexeme) {
-                resolvable.set_distance(self.scopes.len() - 1 - i);
-                return;
-            }
-        }
-    }
-
-    fn begin_scope(&mut self) {
-        self.scopes.push(HashMap::new());
-    }
-
-    fn end_scope(&mut self) {
-        self.scopes.pop();
-    }
-
-    fn declare(&mut self, name: &Token) -> Result<(), LoxErr> {
-        if let Some(scope) = self.scopes.last_mut() {
-            if scope.contains_key(&name.lexeme) {
-                return Err(LoxErr::Resolve { line: name.line, message: "Already variable with this name in this scope.".to_string() });
-            }
-            scope.insert(name.lexeme.clone(), false);
-            
-        }
-        Ok(())
-    }
-
-    fn define(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), true);
-        }
-    }
-
-    fn visit_block_stmt(&mut self, statements: &mut Vec<Stmt>) -> Result<(), LoxErr> {
-        self.begin_scope();
-        self.resolve(statements);
-        self.end_scope();
-        Ok(())
-    }
-
-    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<(), LoxErr> {
-        if !self.is_in_loop {
-            return Err(LoxErr::Resolve { line: keyword.line, message: "Can only use break inside a loop.".to_string() })
-        }
-        Ok(())
-    }
-
-    fn visit_class_declaration_stmt(&mut self, class_declaration: &mut ClassDeclaration) -> Result<(), LoxErr> {
-
-        let enclosing_class = self.current_class;
-        self.current_class = ClassType::Class;
-
-        self.declare(&class_declaration.name)?;
-        self.define(&class_declaration.name);
-
-        if let Some(exist_superclass) = &mut class_declaration.superclass {
-            if &class_declaration.name.lexeme == &exist_superclass.name.lexeme {
-                return Err(LoxErr::Resolve { line: exist_superclass.name.line, message: "A class can't inherit from itself.".to_string() });
-            }
-
-            self.current_class = ClassType::SubClass;
-            self.visit_variable_expr(exist_superclass)?;
-
-            self.begin_scope(); // 创建超类环境
-            self.scopes.last_mut().unwrap().insert("super".to_string(), true);
-
-        }
-
-        self.begin_scope();     // 这个 scope 里有 this，是 get 一个 method 时，创建的新环境
-        self.scopes.last_mut().unwrap().insert("this".to_string(), true);
-
-        for method in &mut class_declaration.methods {
-            let function_type = if &method.name.lexeme == "init" {
-                FunctionType::Initializer
-            } else {
-                FunctionType::Method
-            };
-            self.resolve_function(method, function_type)?;
-        }
-
-        self.end_scope();
-
-        if class_declaration.superclass.is_some() {
-            self.end_scope();
-            
-        }
-
-        self.current_class = enclosing_class;
-        Ok(())
-    }
-
-    fn visit_expression_stmt(&mut self, expression: &mut Expr) -> Result<(), LoxErr> {
-        self.resolve_expr(expression)?;
-        Ok(())
-    }
-
-    fn visit_var_stmt(&mut self, name: &Token, initializer: &mut Option<Expr>) -> Result<(), LoxErr> {
-        self.declare(name)?;
-        if let Some(expr) = initializer {
-            self.resolve_expr(expr)?;
-        }
-        self.define(name);
-
-        Ok(())
-    }
-
-    fn visit_function_declaration_stmt(&mut self, function_declaration: &mut FunctionDeclaration) -> Result<(), LoxErr> {
-        self.declare(&function_declaration.name)?;
-        self.define(&function_declaration.name);
-        self.resolve_function(function_declaration, FunctionType::Function)?;
-        Ok(())
-    }
-
-    fn visit_if_stmt(&mut self, condition: &mut Expr, then_branch: &mut Box<Stmt>, else_branch: &mut Option<Box<Stmt>>) -> Result<(), LoxErr> {
-        self.resolve_expr(condition)?;
-        self.resolve_stmt(then_branch)?;
-        if let Some(exist_else_branch) = else_branch {
-            self.resolve_stmt(exist_else_branch)?;
-        }
-        Ok(())
-    }
-
-    fn visit_print_stmt(&mut self, expression: &mut Expr) -> Result<(), LoxErr> {
-        self.resolve_expr(expression)?;
-        Ok(())
-    }
-
-    fn visit_return_stmt(&mut self, keyword: &Token, value: &mut Option<Expr>) -> Result<(), LoxErr> {
-
-        if self.current_function == FunctionType::None {
-            return Err(LoxErr::Resolve { line: keyword.line, message: "Can't return from top-level code.".to_string() });
-        }
-
-        if let Some(exist_ret_value) = value {
-            if self.current_function == FunctionType::Initializer {
-                return Err(LoxErr::Resolve { line: keyword.line, message: "Can't return a value from an initializer.".to_string() });
-            }
-            self.resolve_expr(exist_ret_value)?;
-        }
-        Ok(())
-    }
-
-    fn visit_while_stmt(&mut self, condition: &mut Expr, body: &mut Box<Stmt>) -> Result<(), LoxErr> {
-        self.resolve_expr(condition)?;
-
-        let is_already_in_loop = self.is_in_loop;
-        self.is_in_loop = true;
-        self.resolve_stmt(body)?;
-        self.is_in_loop = is_already_in_loop;
-        Ok(())
-    }
-
-    fn visit_variable_expr(&mut self, variable_expr: &mut VariableExpr) -> Result<(), LoxErr> {
-        if let Some(scope) = self.scopes.last() {
-            if scope.get(&variable_expr.name.lexeme) == Some(&false) {
-                // 在初始化式中引用一个变量是错误的。如果初始化式使用了要初始化的变量，则解释器在编译时或运行时都会失败。
-                return Err(LoxErr::Resolve { line: variable_expr.name.line, message: "Can't read local variable in its own initializer.".to_string() })
-            }
-        }
-        self.resolve_local(variable_expr);
-        Ok(())
-    }
-
-    fn visit_assign_expr(&mut self, assign_expr: &mut AssignExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*assign_expr).value)?;
-        self.resolve_local(assign_expr);
-        Ok(())
-    }
-
-    fn visit_binary_expr(&mut self, binary_expr: &mut BinaryExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*binary_expr).left)?;
-        self.resolve_expr(&mut *(*binary_expr).right)?;
-        Ok(())
-    }
-    
-    fn visit_call_expr(&mut self, call_expr: &mut CallExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*call_expr).callee)?;
-        for argument in &mut call_expr.arguments {
-            self.resolve_expr(argument)?;
-        }
-        Ok(())
-    }
-
-    fn visit_conditional_expr(&mut self, conditional_expr: &mut ConditionalExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*conditional_expr).condition)?;
-        self.resolve_expr(&mut *(*conditional_expr).then_branch)?;
-        self.resolve_expr(&mut *(*conditional_expr).else_branch)?;
-        Ok(())
-    }
-
-    fn visit_comma_expr(&mut self, comma_expr: &mut CommaExpr) -> Result<(), LoxErr> {
-        for expr in &mut comma_expr.exprs {
-            self.resolve_expr(expr)?;
-        }
-        Ok(())
-    }
-
-    fn visit_literal_expr(&self) -> Result<(), LoxErr> {
-        Ok(())
-    }
-
-    fn visit_get_expr(&mut self, get_expr: &mut GetExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*get_expr).object)?;
-        Ok(())
-    }
-
-    fn visit_grouping_expr(&mut self, grouping_expr: &mut GroupingExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*grouping_expr).expression)?;
-        Ok(())
-    }
-
-    fn visit_logical_expr(&mut self, logical_expr: &mut LogicalExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*logical_expr).left)?;
-        self.resolve_expr(&mut *(*logical_expr).right)?;
-        Ok(())
-    }
-
-    fn visit_set_expr(&mut self, set_expr: &mut SetExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*set_expr).value)?;
-        self.resolve_expr(&mut *(*set_expr).object)?;
-        Ok(())
-    }
-
-    fn visit_super_expr(&mut self, super_expr: &mut SuperExpr) -> Result<(), LoxErr> {
-        if self.current_class == ClassType::None {
-            return Err(LoxErr::Resolve { line: super_expr.keyword.line, message: "Can't use 'super' outside of a class.".to_string() });
-        } else if self.current_class != ClassType::SubClass {
-            return Err(LoxErr::Resolve { line: super_expr.keyword.line, message: "Can't use 'super' in a class with no superclass.".to_string() });
-        }
-        self.resolve_local(super_expr);
-        Ok(())
-    }
-
-    fn visit_this_expr(&mut self, this_expr: &mut ThisExpr) -> Result<(), LoxErr> {
-        if self.current_class == ClassType::None {
-            return Err(LoxErr::Resolve { line: this_expr.keyword.line, message: "Can't use 'this' outside of a class.".to_string() })
-        }
-        self.resolve_local(this_expr);
-        Ok(())
-    }
-
-    fn visit_unary_expr(&mut self, unary_expr: &mut UnaryExpr) -> Result<(), LoxErr> {
-        self.resolve_expr(&mut *(*unary_expr).right)?;
-        Ok(())
-    }
-
-}
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum FunctionType {
-    None,
-    Function,
-    Initializer,    // 构造函数
-    Method,
-}
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-enum ClassType {
-    None,
-    Class,
-    SubClass,
-}
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+
+use crate::diagnostic::{Diagnostic, Stage};
+use crate::err::LoxErr;
+use crate::expr::{AssignExpr, BinaryExpr, CallExpr, CommaExpr, ConditionalExpr, Expr, GetExpr, GroupingExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr, UnaryExpr, VariableExpr};
+
+use crate::resolvable::{Resolvable, Slot};
+use crate::stack;
+use crate::stmt::{ClassDeclaration, FunctionDeclaration, Stmt, SwitchCase};
+use crate::token::Token;
+
+pub struct Resolver {
+    pub had_resolve_error: bool,
+    pub errors: Vec<LoxErr>,
+    scopes: Vec<HashMap<Rc<str>, ScopeVariable>>, // 作用域栈
+    functions: Vec<FunctionScope>,  // 正在分析的函数栈，记录每个函数捕获了哪些外层局部变量
+    current_function: FunctionType,
+    current_class: ClassType,
+    is_in_loop: bool,
+    pub warn_on_shadowing: bool,    // 局部变量遮蔽外层作用域的同名变量时给出警告，`_` 开头的名字除外
+    pub warnings: Vec<Diagnostic>,
+    global_constants: HashSet<Rc<str>>, // 全局变量不在作用域栈里，常量单独记下来
+    pub track_references: bool,    // 记下每处变量引用指向哪个声明，语言服务器跳转到定义时用
+    pub references: Vec<Reference>,
+    global_declarations: HashMap<Rc<str>, Token>,   // 全局变量第一次声明的地方
+    global_usages: Vec<Token>,  // 全局变量可以先用后声明，整段分析完再找它们的声明
+}
+
+// 一处变量引用和它引用的声明
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub usage: Token,
+    pub declaration: Token,
+}
+
+
+impl Resolver {
+
+    pub fn new() -> Resolver {
+        Resolver {
+            had_resolve_error: false,
+            errors: Vec::new(),
+            scopes: Vec::new(),
+            functions: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            is_in_loop: false,
+            warn_on_shadowing: false,
+            warnings: Vec::new(),
+            global_constants: HashSet::new(),
+            track_references: false,
+            references: Vec::new(),
+            global_declarations: HashMap::new(),
+            global_usages: Vec::new(),
+        }
+    }
+
+    // 从快照恢复的全局变量，是常量的话之后的输入不能给它赋值，也不能重新声明
+    pub fn set_global_constant(&mut self, name: &str, constant: bool) {
+        if constant {
+            self.global_constants.insert(Rc::from(name));
+        } else {
+            self.global_constants.remove(name);
+        }
+    }
+
+    // 分析一段完整的输入。REPL 的每一行都用同一个 Resolver 分析：先清掉上一行的结果和出错时没退出的作用域，
+    // 前面声明过的全局常量留着。这段输入有错误的话不会执行，里面声明的常量也不算
+    pub fn resolve_input(&mut self, statements: &mut Vec<Stmt>) {
+        self.had_resolve_error = false;
+        self.errors.clear();
+        self.warnings.clear();
+        self.scopes.clear();
+        self.functions.clear();
+        self.current_function = FunctionType::None;
+        self.current_class = ClassType::None;
+        self.is_in_loop = false;
+        self.references.clear();
+        let global_constants = self.global_constants.clone();
+        self.resolve(statements);
+        if self.had_resolve_error {
+            self.global_constants = global_constants;
+        }
+        for usage in std::mem::take(&mut self.global_usages) {
+            if let Some(declaration) = self.global_declarations.get(&usage.lexeme) {
+                self.references.push(Reference { usage: usage, declaration: declaration.clone() });
+            }
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &mut Vec<Stmt>) {
+        for statement in &mut *statements {
+            if let Err(lox_err) = self.resolve_stmt(statement) {
+                self.errors.push(lox_err);
+                self.had_resolve_error = true;
+            }
+        }
+        self.check_unreachable(statements);
+    }
+
+    // 同一个块里跟在 return、break、continue、throw 后面的语句执行不到，只对第一条给出警告
+    fn check_unreachable(&mut self, statements: &[Stmt]) {
+        let Some(jump_index) = statements.iter().position(Stmt::is_unconditional_jump) else {
+            return;
+        };
+        let Some(dead) = statements.get(jump_index + 1) else {
+            return;
+        };
+        let jump = statements[jump_index].first_token();
+        self.warn(dead.first_token(), "unreachable-code", format!("Unreachable code after '{}'.", jump.lexeme));
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) -> Result<(), LoxErr> {
+        match stmt {
+            Stmt::Block { statements, .. } => self.visit_block_stmt(statements),
+            Stmt::Break { keyword } => self.visit_break_stmt(keyword),
+            Stmt::ClassDeclaration { class_declaration } => self.visit_class_declaration_stmt(class_declaration),
+            Stmt::Continue { keyword } => self.visit_continue_stmt(keyword),
+            Stmt::DoWhile { body, condition, .. } => self.visit_do_while_stmt(body, condition),
+            Stmt::Expression { expression, .. } => self.visit_expression_stmt(expression),
+            Stmt::For { .. } => {
+                let brace = Rc::clone(stmt.first_token());
+                let for_stmt = std::mem::replace(stmt, Stmt::Block { brace: brace, statements: Vec::new() });
+                *stmt = for_stmt.desugar_for();
+                self.resolve_stmt(stmt)
+            }
+            Stmt::ForIn { name, iterable, body } => self.visit_for_in_stmt(name, iterable, body),
+            Stmt::FunctionDeclaration { function_declaration } => self.visit_function_declaration_stmt(function_declaration),
+            Stmt::If { condition, then_branch, else_branch, .. } => self.visit_if_stmt(condition, then_branch, else_branch),
+            Stmt::While { condition, body, increment, .. } => self.visit_while_stmt(condition, body, increment),
+            Stmt::FreshLoopVariable { variable } => self.visit_fresh_loop_variable_stmt(variable),
+            Stmt::Import { keyword, name, .. } => self.visit_import_stmt(keyword, name),
+            Stmt::Print { expression, .. } => self.visit_print_stmt(expression),
+            Stmt::Return { keyword, value } => self.visit_return_stmt(keyword, value),
+            Stmt::Switch { subject, cases, default, .. } => self.visit_switch_stmt(subject, cases, default),
+            Stmt::Throw { value, .. } => self.visit_throw_stmt(value),
+            Stmt::Assert { condition, message, .. } => self.visit_assert_stmt(condition, message),
+            Stmt::Try { try_block, name, catch_block, .. } => self.visit_try_stmt(try_block, name, catch_block),
+            Stmt::Var { name, initializer, is_const } => self.visit_var_stmt(name, initializer, *is_const),
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) -> Result<(), LoxErr> {
+        stack::guard(|| match expr {
+            Expr::Assign(assign_expr) => self.visit_assign_expr(assign_expr),
+            Expr::Binary(binary_expr) => self.visit_binary_expr(binary_expr),
+            Expr::Call(call_expr) => self.visit_call_expr(call_expr),
+            Expr::Conditional(conditional_expr) => self.visit_conditional_expr(conditional_expr),
+            Expr::Comma(comma_expr) => self.visit_comma_expr(comma_expr),
+            Expr::Get(get_expr) => self.visit_get_expr(get_expr),
+            Expr::Grouping(grouping_expr) => self.visit_grouping_expr(grouping_expr),
+            Expr::Literal(_literal_expr) => self.visit_literal_expr(),
+            Expr::Logical(logical_expr) => self.visit_logical_expr(logical_expr),
+            Expr::Set(set_expr) => self.visit_set_expr(set_expr),
+            Expr::Super(super_expr) => self.visit_super_expr(super_expr),
+            Expr::This(this_expr) => self.visit_this_expr(this_expr),
+            Expr::Unary(unary_expr) => self.visit_unary_expr(unary_expr),
+            Expr::Variable(variable_expr) => self.visit_variable_expr(variable_expr),
+        })
+    }
+
+    fn resolve_function(&mut self, function_declaration: &mut Rc<FunctionDeclaration>, function_type: FunctionType) -> Result<(), LoxErr> {
+        // 语义分析在解释执行之前，这时还没有函数对象共享这份声明，make_mut 不会真的拷贝
+        let function_declaration = Rc::make_mut(function_declaration);
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+        // 函数体里的 break、continue 不能跳出声明函数时所在的循环
+        let enclosing_is_in_loop = self.is_in_loop;
+        self.is_in_loop = false;
+
+        // 方法的 this 作用域在运行时是 bind 出来的环境，不属于捕获的范围
+        let base = match function_type {
+            FunctionType::Method | FunctionType::Initializer => self.scopes.len() - 1,
+            _ => self.scopes.len(),
+        };
+        self.functions.push(FunctionScope { base: base, captures: Vec::new(), capture_indices: HashMap::new() });
+
+        self.begin_scope();
+        for param in &function_declaration.params {
+            self.declare(param)?;
+            self.define(param);
+        }
+        self.resolve(&mut function_declaration.body);
+        self.end_scope();
+
+        function_declaration.captures = self.functions.pop().unwrap().captures;
+        self.current_function = enclosing_function;
+        self.is_in_loop = enclosing_is_in_loop;
+
+        Ok(())
+    }
+
+
+    fn resolve_local(&mut self, resolvable: &mut impl Resolvable) {
+        if let Some(slot) = self.lookup_slot(&resolvable.name().lexeme) {
+            resolvable.set_slot(slot);
+        }
+    }
+
+    fn record_reference(&mut self, usage: &Token) {
+        if !self.track_references {
+            return;
+        }
+        match self.scopes.iter().rev().find_map(|scope| scope.get(&usage.lexeme)) {
+            Some(variable) => {
+                if let Some(declaration) = &variable.declaration {
+                    self.references.push(Reference { usage: usage.clone(), declaration: declaration.clone() });
+                }
+            }
+            None => self.global_usages.push(usage.clone()),
+        }
+    }
+
+    // 找不到说明是全局变量
+    fn lookup_slot(&mut self, name: &str) -> Option<Slot> {
+        for i in (0..self.scopes.len()).rev() {
+            if let Some(variable) = self.scopes[i].get(name) {
+                let index = variable.slot;
+                return Some(self.slot_from(self.scopes.len() - 1, self.functions.len(), i, index));
+            }
+        }
+        None
+    }
+
+    // 从 from_scope 这层作用域看，定义在 scope_index 层第 index 个的变量在哪。function_depth 是 from_scope 所在的函数层数
+    // 在当前函数里面的变量沿环境链找；在外面的变量变成当前函数的捕获，放在函数的闭包环境里，闭包环境紧挨在函数最外层环境的外面
+    fn slot_from(&mut self, from_scope: usize, function_depth: usize, scope_index: usize, index: usize) -> Slot {
+        if function_depth == 0 || scope_index >= self.functions[function_depth - 1].base {
+            return Slot { distance: from_scope - scope_index, index: index };
+        }
+        let function_index = function_depth - 1;
+        let base = self.functions[function_index].base;
+        let capture_index = self.capture(function_index, scope_index, index);
+        Slot { distance: from_scope - base + 1, index: capture_index }
+    }
+
+    fn capture(&mut self, function_index: usize, scope_index: usize, index: usize) -> usize {
+        if let Some(capture_index) = self.functions[function_index].capture_indices.get(&(scope_index, index)) {
+            return *capture_index;
+        }
+        // 捕获的来源要从声明这个函数的地方看，外层函数没有的话它也要先捕获
+        let declaring_scope = self.functions[function_index].base - 1;
+        let source = self.slot_from(declaring_scope, function_index, scope_index, index);
+
+        if let Some(variable) = self.scopes[scope_index].values_mut().find(|variable| variable.slot == index) {
+            variable.is_captured = true;
+        }
+
+        let function = &mut self.functions[function_index];
+        function.captures.push(source);
+        function.capture_indices.insert((scope_index, index), function.captures.len() - 1);
+        function.captures.len() - 1
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) -> Result<(), LoxErr> {
+        if self.warn_on_shadowing && !name.lexeme.starts_with('_') && self.is_declared_in_outer_scope(&name.lexeme) {
+            self.warn(name, "shadowed-variable", format!("Local variable '{}' shadows a variable in an enclosing scope.", name.lexeme));
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                return Err(LoxErr::Resolve { line: name.line, column: name.column, span: name.span, message: "Already variable with this name in this scope.".to_string() });
+            }
+            let slot = scope.len();
+            let declaration = self.track_references.then(|| name.clone());
+            scope.insert(name.lexeme.clone(), ScopeVariable { is_defined: false, slot: slot, is_captured: false, is_const: false, declaration: declaration });
+        } else if self.global_constants.contains(&name.lexeme) {
+            return Err(LoxErr::Resolve { line: name.line, column: name.column, span: name.span, message: format!("Can't redeclare constant '{}'.", name.lexeme) });
+
+        } else if self.track_references && !self.global_declarations.contains_key(&name.lexeme) {
+            self.global_declarations.insert(Rc::clone(&name.lexeme), name.clone());
+        }
+        Ok(())
+    }
+
+    fn is_declared_in_outer_scope(&self, name: &str) -> bool {
+        let Some((_, outer_scopes)) = self.scopes.split_last() else {
+            return false;
+        };
+        outer_scopes.iter().any(|scope| scope.contains_key(name))
+    }
+
+    fn warn(&mut self, token: &Token, code: &'static str, message: String) {
+        self.warnings.push(Diagnostic::warning(Stage::Resolve, code, token, message));
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(variable) = self.scopes.last_mut().and_then(|scope| scope.get_mut(&name.lexeme)) {
+            variable.is_defined = true;
+        }
+    }
+
+    // this 和 super 这种由解释器隐式定义的名字，直接视为已定义
+    fn define_implicit(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        let slot = scope.len();
+        scope.insert(Rc::from(name), ScopeVariable { is_defined: true, slot: slot, is_captured: false, is_const: false, declaration: None });
+    }
+
+    fn visit_block_stmt(&mut self, statements: &mut Vec<Stmt>) -> Result<(), LoxErr> {
+        self.begin_scope();
+        self.resolve(statements);
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<(), LoxErr> {
+        if !self.is_in_loop {
+            return Err(LoxErr::Resolve { line: keyword.line, column: keyword.column, span: keyword.span, message: "Can only use break inside a loop.".to_string() })
+        }
+        Ok(())
+    }
+
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> Result<(), LoxErr> {
+        if !self.is_in_loop {
+            return Err(LoxErr::Resolve { line: keyword.line, column: keyword.column, span: keyword.span, message: "Can only use continue inside a loop.".to_string() })
+        }
+        Ok(())
+    }
+
+    fn visit_class_declaration_stmt(&mut self, class_declaration: &mut ClassDeclaration) -> Result<(), LoxErr> {
+        // 同名方法在 HashMap 里后者会悄悄覆盖前者
+        let mut method_names = HashSet::new();
+        for method in &class_declaration.methods {
+            if !method_names.insert(&method.name.lexeme) {
+                return Err(LoxErr::Resolve { line: method.name.line, column: method.name.column, span: method.name.span, message: format!("Already a method named '{}' in this class.", method.name.lexeme) });
+            }
+        }
+
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(&class_declaration.name)?;
+        self.define(&class_declaration.name);
+
+        if let Some(exist_superclass) = &mut class_declaration.superclass {
+            if class_declaration.name.lexeme == exist_superclass.name.lexeme {
+                return Err(LoxErr::Resolve { line: exist_superclass.name.line, column: exist_superclass.name.column, span: exist_superclass.name.span, message: "A class can't inherit from itself.".to_string() });
+            }
+
+            self.current_class = ClassType::SubClass;
+            self.visit_variable_expr(exist_superclass)?;
+
+            self.begin_scope(); // 创建超类环境
+            self.define_implicit("super");
+
+        }
+
+        self.begin_scope();     // 这个 scope 里有 this，是 get 一个 method 时，创建的新环境
+        self.define_implicit("this");
+
+        for method in &mut class_declaration.methods {
+            let function_type = if &*method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, function_type)?;
+        }
+
+        self.end_scope();
+
+        if class_declaration.superclass.is_some() {
+            self.end_scope();
+            
+        }
+
+        self.current_class = enclosing_class;
+        Ok(())
+    }
+
+    fn visit_do_while_stmt(&mut self, body: &mut Box<Stmt>, condition: &mut Expr) -> Result<(), LoxErr> {
+        let is_already_in_loop = self.is_in_loop;
+        self.is_in_loop = true;
+        let body_result = self.resolve_stmt(body);
+        self.is_in_loop = is_already_in_loop;
+        body_result?;
+
+        // 循环体是单独的作用域，条件里看不到循环体里声明的变量
+        self.resolve_expr(condition)?;
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&mut self, expression: &mut Expr) -> Result<(), LoxErr> {
+        self.resolve_expr(expression)?;
+        Ok(())
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &mut Option<Expr>, is_const: bool) -> Result<(), LoxErr> {
+        self.declare(name)?;
+        if is_const {
+            match self.scopes.last_mut() {
+                Some(scope) => scope.get_mut(&name.lexeme).unwrap().is_const = true,
+                None => {
+                    self.global_constants.insert(Rc::clone(&name.lexeme));
+                }
+            }
+        }
+        if let Some(expr) = initializer {
+            self.resolve_expr(expr)?;
+        }
+        self.define(name);
+
+        Ok(())
+    }
+
+    // 循环变量在自己的作用域里，循环体是它里面的一层
+    fn visit_for_in_stmt(&mut self, name: &Token, iterable: &mut Expr, body: &mut Box<Stmt>) -> Result<(), LoxErr> {
+        self.resolve_expr(iterable)?;
+
+        self.begin_scope();
+        self.declare(name)?;
+        self.define(name);
+        let is_already_in_loop = self.is_in_loop;
+        self.is_in_loop = true;
+        let body_result = self.resolve_stmt(body);
+        self.is_in_loop = is_already_in_loop;
+        self.end_scope();
+        body_result
+    }
+
+    fn visit_function_declaration_stmt(&mut self, function_declaration: &mut Rc<FunctionDeclaration>) -> Result<(), LoxErr> {
+        self.declare(&function_declaration.name)?;
+        self.define(&function_declaration.name);
+        self.resolve_function(function_declaration, FunctionType::Function)?;
+        Ok(())
+    }
+
+    fn visit_if_stmt(&mut self, condition: &mut Expr, then_branch: &mut Box<Stmt>, else_branch: &mut Option<Box<Stmt>>) -> Result<(), LoxErr> {
+        self.resolve_expr(condition)?;
+        self.resolve_stmt(then_branch)?;
+        if let Some(exist_else_branch) = else_branch {
+            self.resolve_stmt(exist_else_branch)?;
+        }
+        Ok(())
+    }
+
+    // 循环体已经分析完了，这时就知道循环变量有没有被闭包捕获
+    // 没被捕获的话换不换 cell 都一样，不给它槽位，解释器就直接跳过
+    fn visit_fresh_loop_variable_stmt(&mut self, variable: &mut VariableExpr) -> Result<(), LoxErr> {
+        let is_captured = self.scopes.iter().rev()
+            .find_map(|scope| scope.get(&variable.name.lexeme))
+            .is_some_and(|scope_variable| scope_variable.is_captured);
+        if is_captured {
+            self.resolve_local(variable);
+        }
+        Ok(())
+    }
+
+    // 模块的声明都进全局环境，写在块或函数里会让人以为它们是局部的
+    fn visit_import_stmt(&mut self, keyword: &Token, name: &Option<Rc<Token>>) -> Result<(), LoxErr> {
+        if !self.scopes.is_empty() {
+            return Err(LoxErr::Resolve { line: keyword.line, column: keyword.column, span: keyword.span, message: "Can only import at top level.".to_string() });
+        }
+        if let Some(name) = name {
+            self.declare(name)?;
+            self.define(name);
+        }
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, expression: &mut Expr) -> Result<(), LoxErr> {
+        self.resolve_expr(expression)?;
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: &mut Option<Expr>) -> Result<(), LoxErr> {
+
+        if self.current_function == FunctionType::None {
+            return Err(LoxErr::Resolve { line: keyword.line, column: keyword.column, span: keyword.span, message: "Can't return from top-level code.".to_string() });
+        }
+
+        if let Some(exist_ret_value) = value {
+            if self.current_function == FunctionType::Initializer {
+                return Err(LoxErr::Resolve { line: keyword.line, column: keyword.column, span: keyword.span, message: "Can't return a value from an initializer.".to_string() });
+            }
+            self.resolve_expr(exist_ret_value)?;
+        }
+        Ok(())
+    }
+
+    fn visit_switch_stmt(&mut self, subject: &mut Expr, cases: &mut Vec<SwitchCase>, default: &mut Option<Box<Stmt>>) -> Result<(), LoxErr> {
+        self.resolve_expr(subject)?;
+        for case in cases {
+            self.resolve_expr(&mut case.value)?;
+            self.resolve_stmt(&mut case.body)?;
+        }
+        if let Some(exist_default) = default {
+            self.resolve_stmt(exist_default)?;
+        }
+        Ok(())
+    }
+
+    fn visit_assert_stmt(&mut self, condition: &mut Expr, message: &mut Option<Expr>) -> Result<(), LoxErr> {
+        self.resolve_expr(condition)?;
+        if let Some(exist_message) = message {
+            self.resolve_expr(exist_message)?;
+        }
+        Ok(())
+    }
+
+    fn visit_throw_stmt(&mut self, value: &mut Expr) -> Result<(), LoxErr> {
+        self.resolve_expr(value)?;
+        Ok(())
+    }
+
+    // 和函数的参数一样，异常变量和 catch 块里的声明在同一个作用域
+    fn visit_try_stmt(&mut self, try_block: &mut Vec<Stmt>, name: &Token, catch_block: &mut Vec<Stmt>) -> Result<(), LoxErr> {
+        self.visit_block_stmt(try_block)?;
+
+        self.begin_scope();
+        self.declare(name)?;
+        self.define(name);
+        self.resolve(catch_block);
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_while_stmt(&mut self, condition: &mut Expr, body: &mut Box<Stmt>, increment: &mut Vec<Stmt>) -> Result<(), LoxErr> {
+        self.resolve_expr(condition)?;
+
+        let is_already_in_loop = self.is_in_loop;
+        self.is_in_loop = true;
+        let body_result = self.resolve_stmt(body);
+        self.is_in_loop = is_already_in_loop;
+        body_result?;
+
+        // 递增语句在循环体之后分析，这时才知道循环变量有没有被捕获
+        for stmt in increment {
+            self.resolve_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn visit_variable_expr(&mut self, variable_expr: &mut VariableExpr) -> Result<(), LoxErr> {
+        if let Some(scope) = self.scopes.last() {
+            if scope.get(&variable_expr.name.lexeme).is_some_and(|variable| !variable.is_defined) {
+                // 在初始化式中引用一个变量是错误的。如果初始化式使用了要初始化的变量，则解释器在编译时或运行时都会失败。
+                return Err(LoxErr::Resolve { line: variable_expr.name.line, column: variable_expr.name.column, span: variable_expr.name.span, message: "Can't read local variable in its own initializer.".to_string() })
+            }
+        }
+        self.record_reference(&variable_expr.name);
+        self.resolve_local(variable_expr);
+        Ok(())
+    }
+
+    fn visit_assign_expr(&mut self, assign_expr: &mut AssignExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*assign_expr).value)?;
+        let name = &assign_expr.name.lexeme;
+        let is_const = match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(variable) => variable.is_const,
+            None => self.global_constants.contains(name),
+        };
+        if is_const {
+            return Err(LoxErr::Resolve { line: assign_expr.name.line, column: assign_expr.name.column, span: assign_expr.name.span, message: format!("Can't assign to constant '{}'.", name) });
+        }
+        self.record_reference(&assign_expr.name);
+        self.resolve_local(assign_expr);
+        Ok(())
+    }
+
+    fn visit_binary_expr(&mut self, binary_expr: &mut BinaryExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*binary_expr).left)?;
+        self.resolve_expr(&mut *(*binary_expr).right)?;
+        Ok(())
+    }
+    
+    fn visit_call_expr(&mut self, call_expr: &mut CallExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*call_expr).callee)?;
+        for argument in &mut call_expr.arguments {
+            self.resolve_expr(argument)?;
+        }
+        Ok(())
+    }
+
+    fn visit_conditional_expr(&mut self, conditional_expr: &mut ConditionalExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*conditional_expr).condition)?;
+        self.resolve_expr(&mut *(*conditional_expr).then_branch)?;
+        self.resolve_expr(&mut *(*conditional_expr).else_branch)?;
+        Ok(())
+    }
+
+    fn visit_comma_expr(&mut self, comma_expr: &mut CommaExpr) -> Result<(), LoxErr> {
+        for expr in &mut comma_expr.exprs {
+            self.resolve_expr(expr)?;
+        }
+        Ok(())
+    }
+
+    fn visit_literal_expr(&self) -> Result<(), LoxErr> {
+        Ok(())
+    }
+
+    fn visit_get_expr(&mut self, get_expr: &mut GetExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*get_expr).object)?;
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&mut self, grouping_expr: &mut GroupingExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*grouping_expr).expression)?;
+        Ok(())
+    }
+
+    fn visit_logical_expr(&mut self, logical_expr: &mut LogicalExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*logical_expr).left)?;
+        self.resolve_expr(&mut *(*logical_expr).right)?;
+        Ok(())
+    }
+
+    fn visit_set_expr(&mut self, set_expr: &mut SetExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*set_expr).value)?;
+        self.resolve_expr(&mut *(*set_expr).object)?;
+        Ok(())
+    }
+
+    fn visit_super_expr(&mut self, super_expr: &mut SuperExpr) -> Result<(), LoxErr> {
+        if self.current_class == ClassType::None {
+            return Err(LoxErr::Resolve { line: super_expr.keyword.line, column: super_expr.keyword.column, span: super_expr.keyword.span, message: "Can't use 'super' outside of a class.".to_string() });
+        } else if self.current_class != ClassType::SubClass {
+            return Err(LoxErr::Resolve { line: super_expr.keyword.line, column: super_expr.keyword.column, span: super_expr.keyword.span, message: "Can't use 'super' in a class with no superclass.".to_string() });
+        }
+        self.resolve_local(super_expr);
+        super_expr.this_slot = self.lookup_slot("this");
+        Ok(())
+    }
+
+    fn visit_this_expr(&mut self, this_expr: &mut ThisExpr) -> Result<(), LoxErr> {
+        if self.current_class == ClassType::None {
+            return Err(LoxErr::Resolve { line: this_expr.keyword.line, column: this_expr.keyword.column, span: this_expr.keyword.span, message: "Can't use 'this' outside of a class.".to_string() })
+        }
+        self.resolve_local(this_expr);
+        Ok(())
+    }
+
+    fn visit_unary_expr(&mut self, unary_expr: &mut UnaryExpr) -> Result<(), LoxErr> {
+        self.resolve_expr(&mut *(*unary_expr).right)?;
+        Ok(())
+    }
+
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct ScopeVariable {
+    is_defined: bool,   // false 代表已经声明，true 代表已经定义
+    slot: usize,        // 在运行时环境里的下标，也就是在这个作用域里的声明顺序
+    is_captured: bool,  // 有没有被内层函数捕获
+    is_const: bool,
+    declaration: Option<Token>, // 声明处的 token，只在 track_references 时记下
+}
+
+struct FunctionScope {
+    base: usize,    // 函数自己的最外层作用域在 scopes 里的下标，方法是 this 作用域
+    captures: Vec<Slot>,
+    capture_indices: HashMap<(usize, usize), usize>,    // (作用域下标, 槽位) -> 在 captures 里的下标
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,    // 构造函数
+    Method,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ClassType {
+    None,
+    Class,
+    SubClass,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::DiagnosticFormat;
+    use crate::module::parse;
+
+    // 整段代码做语义分析，返回所有错误
+    fn resolve_errors(code: &str) -> Vec<String> {
+        let mut resolver = Resolver::new();
+        resolver.resolve(&mut parse(code));
+        resolver.errors.iter().map(|err| err.to_string()).collect()
+    }
+
+    // 逐条语义分析，返回第一个错误
+    fn resolve_err(code: &str) -> Option<LoxErr> {
+        let mut statements = parse(code);
+        let mut resolver = Resolver::new();
+        statements.iter_mut().find_map(|stmt| resolver.resolve_stmt(stmt).err())
+    }
+
+    #[test]
+    fn test_continue_outside_loop() {
+        let code = r#"
+continue;
+while (true) {
+    continue;
+    fun f() {
+        continue;
+    }
+}
+        "#;
+        assert_eq!(resolve_errors(code), vec![
+            "Resolve Error: [line 2:1] Can only use continue inside a loop.".to_string(),
+            "Resolve Error: [line 6:9] Can only use continue inside a loop.".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_import_outside_top_level() {
+        let code = r#"
+import "a.lox";
+{ import "b.lox"; }
+fun f() { import "c.lox"; }
+        "#;
+        assert_eq!(resolve_errors(code), vec![
+            "Resolve Error: [line 3:3] Can only import at top level.".to_string(),
+            "Resolve Error: [line 4:11] Can only import at top level.".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_assign_to_constant() {
+        let code = r#"
+const answer = 42;
+answer = 1;
+fun f() {
+    const local = 1;
+    {
+        local++;
+        var local = 2;
+        local = 3;
+    }
+}
+var answer = 0;
+fun g() { answer = 2; }
+        "#;
+        assert_eq!(resolve_errors(code), vec![
+            "Resolve Error: [line 3:1] Can't assign to constant 'answer'.".to_string(),
+            "Resolve Error: [line 7:9] Can't assign to constant 'local'.".to_string(),
+            "Resolve Error: [line 12:5] Can't redeclare constant 'answer'.".to_string(),
+            "Resolve Error: [line 13:11] Can't assign to constant 'answer'.".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_duplicate_method() {
+        let code = r#"
+class A {
+    foo() {}
+    bar() {}
+    foo() {}
+}
+        "#;
+        match resolve_err(code) {
+            Some(LoxErr::Resolve { line, column, message, .. }) => {
+                assert_eq!(line, 5);
+                assert_eq!(column, 5);
+                assert_eq!(message, "Already a method named 'foo' in this class.");
+            }
+            other => panic!("Expected resolve error, got {:?}", other),
+        }
+    }
+
+    // 按出现顺序收集 print 语句里变量的槽位
+    fn print_slots(code: &str) -> Vec<Option<Slot>> {
+        fn collect_expr(expr: &Expr, slots: &mut Vec<Option<Slot>>) {
+            match expr {
+                Expr::Variable(variable_expr) => slots.push(variable_expr.get_slot()),
+                Expr::Comma(comma_expr) => comma_expr.exprs.iter().for_each(|expr| collect_expr(expr, slots)),
+                Expr::Conditional(conditional_expr) => {
+                    collect_expr(&conditional_expr.condition, slots);
+                    collect_expr(&conditional_expr.then_branch, slots);
+                    collect_expr(&conditional_expr.else_branch, slots);
+                }
+                Expr::Grouping(grouping_expr) => collect_expr(&grouping_expr.expression, slots),
+                _ => (),
+            }
+        }
+
+        fn collect(stmts: &[Stmt], slots: &mut Vec<Option<Slot>>) {
+            for stmt in stmts {
+                match stmt {
+                    Stmt::Block { statements, .. } => collect(statements, slots),
+                    Stmt::FunctionDeclaration { function_declaration } => collect(&function_declaration.body, slots),
+                    Stmt::Print { expression, .. } => collect_expr(expression, slots),
+                    _ => (),
+                }
+            }
+        }
+
+        let mut statements = parse(code);
+        let mut resolver = Resolver::new();
+        resolver.resolve(&mut statements);
+        assert!(!resolver.had_resolve_error);
+
+        let mut slots = Vec::new();
+        collect(&statements, &mut slots);
+        slots
+    }
+
+    #[test]
+    fn test_slots_in_blocks() {
+        let code = r#"
+var g = 0;
+{
+    var a = 1;
+    var b = 2;
+    {
+        var c = 3;
+        print a;
+        print b;
+        print c;
+        print g;
+    }
+}
+        "#;
+        assert_eq!(print_slots(code), vec![
+            Some(Slot { distance: 1, index: 0 }),
+            Some(Slot { distance: 1, index: 1 }),
+            Some(Slot { distance: 0, index: 0 }),
+            None,
+        ]);
+    }
+
+    #[test]
+    fn test_slots_with_shadowing() {
+        let code = r#"
+{
+    var a = 1;
+    var b = 2;
+    {
+        var b = 3;
+        var a = 4;
+        print a;
+        print b;
+    }
+    print a;
+    print b;
+}
+        "#;
+        assert_eq!(print_slots(code), vec![
+            Some(Slot { distance: 0, index: 1 }),
+            Some(Slot { distance: 0, index: 0 }),
+            Some(Slot { distance: 0, index: 0 }),
+            Some(Slot { distance: 0, index: 1 }),
+        ]);
+    }
+
+    #[test]
+    fn test_slots_of_params_and_captures() {
+        let code = r#"
+{
+    var skipped = 0;
+    var outer = 1;
+    fun f(p, q) {
+        var local = 2;
+        print q;
+        print local;
+        print outer;
+    }
+}
+        "#;
+        assert_eq!(print_slots(code), vec![
+            Some(Slot { distance: 0, index: 1 }),
+            Some(Slot { distance: 0, index: 2 }),
+            Some(Slot { distance: 1, index: 0 }),    // 闭包环境里的第一个捕获
+        ]);
+    }
+
+    #[test]
+    fn test_slots_in_comma_and_conditional() {
+        let code = r#"
+var g = 0;
+{
+    var a = 1;
+    {
+        var b = 2;
+        var a = 3;
+        print (g, a, b);
+        print g ? a : b;
+    }
+    print a ? (a, g) : a;
+}
+        "#;
+        assert_eq!(print_slots(code), vec![
+            None,
+            Some(Slot { distance: 0, index: 1 }),
+            Some(Slot { distance: 0, index: 0 }),
+            None,
+            Some(Slot { distance: 0, index: 1 }),
+            Some(Slot { distance: 0, index: 0 }),
+            Some(Slot { distance: 0, index: 0 }),
+            Some(Slot { distance: 0, index: 0 }),
+            None,
+            Some(Slot { distance: 0, index: 0 }),
+        ]);
+    }
+
+    fn resolve_warnings(code: &str) -> Vec<String> {
+        let mut resolver = Resolver::new();
+        resolver.warn_on_shadowing = true;
+        resolver.resolve(&mut parse(code));
+        assert!(!resolver.had_resolve_error);
+        resolver.warnings.iter().map(|warning| warning.render(DiagnosticFormat::Human)).collect()
+    }
+
+    #[test]
+    fn test_shadowing_warning() {
+        let code = r#"
+{
+    var x = 1;
+    var y = 2;
+    {
+        var x = 3;
+        var z = 4;
+        var _y = 5;
+    }
+}
+        "#;
+        let warnings = resolve_warnings(code);
+        assert_eq!(warnings, vec!["Resolve Warning: [line 6:13] Local variable 'x' shadows a variable in an enclosing scope.".to_string()]);
+    }
+
+    #[test]
+    fn test_shadowing_underscore_suppressed() {
+        let code = r#"
+{
+    var _x = 1;
+    {
+        var _x = 2;
+    }
+}
+        "#;
+        assert!(resolve_warnings(code).is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_code_warning() {
+        let code = r#"
+fun f(n) {
+    while (true) {
+        if (n > 1) {
+            break;
+            n = n - 1;
+        }
+        continue;
+    }
+    return n;
+    print 1;
+    print n;
+}
+        "#;
+        let warnings = resolve_warnings(code);
+        assert_eq!(warnings, vec![
+            "Resolve Warning: [line 6:13] Unreachable code after 'break'.".to_string(),
+            "Resolve Warning: [line 11:5] Unreachable code after 'return'.".to_string(),
+        ]);
+    }
+}
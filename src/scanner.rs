@@ -1,252 +1,782 @@
-use crate::token::Token;
-use crate::object::Object;
-
-use crate::token_type::TokenType;
-use crate::err::LoxErr;
-
-
-pub struct Scanner {
-    keywords: std::collections::HashMap<String, TokenType>,
-    source: Vec<char>,
-    pub tokens: Vec<Token>,
-    start: usize,
-    current: usize,
-    line: usize,
-}
-
-
-impl Scanner {
-    pub fn new() -> Scanner {
-        Scanner {
-            keywords: std::collections::HashMap::from([
-                ("and".to_string(), TokenType::And),
-                ("break".to_string(), TokenType::Break),
-                ("class".to_string(), TokenType::Class),
-                ("else".to_string(), TokenType::Else),
-                ("false".to_string(), TokenType::False),
-                ("for".to_string(), TokenType::For),
-                ("fun".to_string(), TokenType::Fun),
-                ("if".to_string(), TokenType::If),
-                ("nil".to_string(), TokenType::Nil),
-                ("or".to_string(), TokenType::Or),
-                ("print".to_string(), TokenType::Print),
-                ("return".to_string(), TokenType::Return),
-                ("super".to_string(), TokenType::Super),
-                ("this".to_string(), TokenType::This),
-                ("true".to_string(), TokenType::True),
-                ("var".to_string(), TokenType::Var),
-                ("while".to_string(), TokenType::While),
-            ]),
-            source: Vec::new(),
-            tokens: Vec::new(),
-            start: 0,
-            current: 0,
-            line: 1,
-        }
-    }
-
-    pub fn load_code(&mut self, code: &str) {
-        self.source = code.chars().collect();
-        self.tokens.clear();
-        self.start = 0;
-        self.current = 0;
-        self.line = 1;
-    }
-
-    pub fn scan_tokens(&mut self) -> Result<(), LoxErr> {
-        // let mut many_err = LoxErr::Many(Vec::new());
-        let mut err_vec = Vec::new();
-        while !self.is_at_end() {
-            self.start = self.current;
-            if let Err(err) = self.scan_one_token() {
-                err_vec.push(err);
-            }
-        }
-        
-        if err_vec.len() > 1 {
-            return Err(LoxErr::Many(err_vec));
-        } else if err_vec.len() == 1 {
-            return Err(err_vec.remove(0));
-        }
-
-        self.tokens.push(Token::new(TokenType::Eof, String::new(), Object::None, self.line));
-        
-        Ok(())
-    }
-
-    fn scan_one_token(&mut self) -> Result<(), LoxErr> {
-
-        let c = self.advance();
-        
-        match c {
-            '(' => self.push_token(TokenType::LeftParen, Object::None),
-            ')' => self.push_token(TokenType::RightParen, Object::None),
-            '{' => self.push_token(TokenType::LeftBrace, Object::None),
-            '}' => self.push_token(TokenType::RightBrace, Object::None),
-            ':' => self.push_token(TokenType::Colon, Object::None),
-            ',' => self.push_token(TokenType::Comma, Object::None),
-            '.' => self.push_token(TokenType::Dot, Object::None),
-            '-' => self.push_token(TokenType::Minus, Object::None),
-            '+' => self.push_token(TokenType::Plus, Object::None),
-            '?' => self.push_token(TokenType::Question, Object::None),
-            ';' => self.push_token(TokenType::Semicolon, Object::None),
-            '*' => self.push_token(TokenType::Star, Object::None),
-
-            '!' => {
-                let tt = if self.match_char('=') {TokenType::BangEqual} else {TokenType::Bang};
-                self.push_token(tt, Object::None);
-            }
-            '=' => {
-                let tt = if self.match_char('=') {TokenType::EqualEqual} else {TokenType::Equal};
-                self.push_token(tt, Object::None);
-            }
-            '<' => {
-                let tt = if self.match_char('=') {TokenType::LessEqual} else {TokenType::Less};
-                self.push_token(tt, Object::None);
-            }
-            '>' => {
-                let tt = if self.match_char('=') {TokenType::GreaterEqual} else {TokenType::Greater};
-                self.push_token(tt , Object::None);
-
-            }
-
-            '/' => {
-                if self.match_char('/') {
-                    while self.peek() != '\n' && !self.is_at_end() {
-                        self.advance();
-                    }
-                } else if self.match_char('*') {
-                    self.block_comment()?;
-                } else {
-                    self.push_token(TokenType::Slash, Object::None);
-                }
-            }
-
-            ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
-
-            '"' => self.string()?,
-
-            '0'..='9' => self.number(),
-
-            'a'..='z' | 'A'..='Z' | '_' => self.identifier(),
-
-            _ => return Err(LoxErr::Scan { line: self.line, message: "Unexpected character.".to_string() }),
-
-        }
-        Ok(())
-    }
-
-
-
-    fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
-        }
-        if self.source[self.current] != expected {
-            return false;
-        }
-
-        self.current += 1;
-        true
-    }
-
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.source[self.current]
-    }
-
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        self.source[self.current + 1]
-    }
-
-    fn is_at_end(&self) -> bool {
-        self.current >= self.source.len()
-    }
-
-    fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source[self.current - 1]
-    }
-
-    fn push_token(&mut self, token_type: TokenType, literal: Object) {
-        let text: String = self.source[self.start..self.current].iter().collect::<String>();
-        self.tokens.push(Token::new(token_type, text, literal, self.line));
-    }
-
-    /*
-        处理类似这样的块注释
-     */
-    fn block_comment(&mut self) -> Result<(), LoxErr>{
-        while self.peek() != '*' && self.peek_next() != '/' {
-            if self.is_at_end() {
-                return Err(LoxErr::Scan { line: self.line, message: "Unterminated block comment.".to_string() });
-            }
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
-            self.advance();
-        }
-        self.advance();
-        self.advance();
-        Ok(())
-    }
-    
-    fn string(&mut self) -> Result<(), LoxErr>{
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
-            }
-            self.advance();
-        }
-
-        if self.is_at_end() {
-            return Err(LoxErr::Scan { line: self.line, message: "Unterminated string.".to_string() });
-        }
-
-        self.advance();
-        let value: String = self.source[self.start + 1 .. self.current - 1].iter().collect::<String>();
-        self.push_token(TokenType::String, Object::String(value));
-        Ok(())
-    }
-
-    fn number(&mut self) {
-        while self.peek().is_ascii_digit() {
-            self.advance();
-        }
-        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
-            self.advance();
-            while self.peek().is_ascii_digit() {
-                self.advance();
-            }
-        }
-        let value_s = self.source[self.start .. self.current].iter().collect::<String>();
-        self.push_token(TokenType::Number, Object::Number(value_s.parse::<f64>().unwrap()));
-    }
-
-    fn identifier(&mut self) {
-        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
-            self.advance();
-        }
-        let text = self.source[self.start..self.current].iter().collect::<String>();
-        let tt = if let Some(word) = self.keywords.get(&text) {
-            *word
-        } else {
-            TokenType::Identifier
-        };
-
-        match tt {
-            // Bool 值特殊处理
-            TokenType::False => self.push_token(tt, Object::Bool(false)),
-            TokenType::True => self.push_token(tt, Object::Bool(true)),
-            _ => self.push_token(tt, Object::None),
-        }
-
-    }
-}
-
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::token::{Span, Token};
+use crate::object::Object;
+
+use crate::token_type::TokenType;
+use crate::err::LoxErr;
+
+
+pub struct Scanner {
+    keywords: std::collections::HashMap<String, TokenType>,
+    identifiers: HashSet<Rc<str>>,  // 标识符驻留表，跨多次 load_code 保留
+    source: Vec<char>,
+    byte_offsets: Vec<usize>,   // 第 i 个字符在源代码里的字节偏移，多一项是末尾
+    pub tokens: Vec<Rc<Token>>,    // 语法树直接共享这些 token，不用逐个深拷贝
+    start: usize,
+    current: usize,
+    line: usize,
+    line_start: usize,  // 当前行第一个字符的下标，用来算列号
+    start_line: usize,  // 正在扫描的 token 从哪一行开始。跨行的字符串的行号和列号都指向开头
+    start_column: usize,    // 正在扫描的 token 的列号
+    pub unterminated: bool, // 代码在字符串或块注释中间就结束了，REPL 据此判断输入还没写完
+    interpolations: Vec<usize>, // 每层还没结束的字符串插值里，有几个还没闭合的 {
+    pub keep_comments: bool,    // 注释也作为 Comment token 交出去，格式化代码时不能丢掉注释
+    next_token: usize,  // 当迭代器用时，tokens 里下一个要交出去的
+    finished: bool, // 当迭代器用时，已经扫描到末尾、补上了 Eof
+}
+
+
+impl Scanner {
+    pub fn new() -> Scanner {
+        Scanner {
+            keywords: std::collections::HashMap::from([
+                ("and".to_string(), TokenType::And),
+                ("assert".to_string(), TokenType::Assert),
+                ("break".to_string(), TokenType::Break),
+                ("case".to_string(), TokenType::Case),
+                ("catch".to_string(), TokenType::Catch),
+                ("class".to_string(), TokenType::Class),
+                ("const".to_string(), TokenType::Const),
+                ("continue".to_string(), TokenType::Continue),
+                ("default".to_string(), TokenType::Default),
+                ("do".to_string(), TokenType::Do),
+                ("else".to_string(), TokenType::Else),
+                ("false".to_string(), TokenType::False),
+                ("for".to_string(), TokenType::For),
+                ("fun".to_string(), TokenType::Fun),
+                ("if".to_string(), TokenType::If),
+                ("import".to_string(), TokenType::Import),
+                ("in".to_string(), TokenType::In),
+                ("nil".to_string(), TokenType::Nil),
+                ("or".to_string(), TokenType::Or),
+                ("print".to_string(), TokenType::Print),
+                ("return".to_string(), TokenType::Return),
+                ("super".to_string(), TokenType::Super),
+                ("switch".to_string(), TokenType::Switch),
+                ("this".to_string(), TokenType::This),
+                ("throw".to_string(), TokenType::Throw),
+                ("true".to_string(), TokenType::True),
+                ("try".to_string(), TokenType::Try),
+                ("var".to_string(), TokenType::Var),
+                ("while".to_string(), TokenType::While),
+            ]),
+            identifiers: HashSet::new(),
+            source: Vec::new(),
+            byte_offsets: Vec::new(),
+            tokens: Vec::new(),
+            start: 0,
+            current: 0,
+            line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            unterminated: false,
+            interpolations: Vec::new(),
+            keep_comments: false,
+            next_token: 0,
+            finished: false,
+        }
+    }
+
+    pub fn load_code(&mut self, code: &str) {
+        self.source = code.chars().collect();
+        self.byte_offsets = code.char_indices().map(|(offset, _)| offset).chain(std::iter::once(code.len())).collect();
+        self.tokens.clear();
+        self.start = 0;
+        self.current = 0;
+        self.line = 1;
+        self.line_start = 0;
+        self.start_line = 1;
+        self.start_column = 1;
+        self.unterminated = false;
+        self.interpolations.clear();
+        self.next_token = 0;
+        self.finished = false;
+    }
+
+    pub fn scan_tokens(&mut self) -> Result<(), LoxErr> {
+        // let mut many_err = LoxErr::Many(Vec::new());
+        let mut err_vec = Vec::new();
+        while !self.is_at_end() {
+            if let Err(err) = self.scan_next() {
+                err_vec.push(err);
+            }
+        }
+        if let Err(err) = self.finish() {
+            err_vec.push(err);
+        }
+
+        if err_vec.len() > 1 {
+            return Err(LoxErr::Many(err_vec));
+        } else if err_vec.len() == 1 {
+            return Err(err_vec.remove(0));
+        }
+
+        Ok(())
+    }
+
+    fn scan_next(&mut self) -> Result<(), LoxErr> {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.current_column();
+        self.scan_one_token()
+    }
+
+    // 扫描到末尾之后，检查插值有没有闭合，补上 Eof
+    fn finish(&mut self) -> Result<(), LoxErr> {
+        // 出错时也补上 Eof，解析器照常处理扫描出来的 token
+        // 文件末尾的换行和注释不算，Eof 的行号用最后一个 token 所在的行，"Expect ';'" 这种错误才会指向真正缺东西的地方
+        let (eof_line, eof_column) = self.tokens.last().map_or((self.line, self.current_column()), |token| (token.line, token.column + token.lexeme.chars().count()));
+        self.tokens.push(Rc::new(Token::new(TokenType::Eof, String::new(), Object::None, eof_line).with_position(eof_column, self.end_span())));
+
+        if !self.interpolations.is_empty() {
+            self.unterminated = true;
+            return Err(LoxErr::Scan { line: self.line, column: self.current_column(), span: self.end_span(), message: "Unterminated string interpolation.".to_string() });
+        }
+        Ok(())
+    }
+
+    fn scan_one_token(&mut self) -> Result<(), LoxErr> {
+
+        let c = self.advance();
+        
+        match c {
+            '(' => self.push_token(TokenType::LeftParen, Object::None),
+            ')' => self.push_token(TokenType::RightParen, Object::None),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.push_token(TokenType::LeftBrace, Object::None);
+            }
+            '}' => match self.interpolations.last_mut() {
+                // 插值表达式结束，接着扫描字符串剩下的部分
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()?;
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.push_token(TokenType::RightBrace, Object::None);
+                }
+                None => self.push_token(TokenType::RightBrace, Object::None),
+            },
+            ':' => self.push_token(TokenType::Colon, Object::None),
+            ',' => self.push_token(TokenType::Comma, Object::None),
+            '.' => {
+                if self.peek().is_ascii_digit() && !self.previous_ends_operand() {
+                    self.number()?;  // .5 就是 0.5
+                } else if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    self.push_token(TokenType::DotDotDot, Object::None);
+                } else {
+                    self.push_token(TokenType::Dot, Object::None);
+                }
+            }
+            '-' => {
+                let tt = if self.match_char('-') {TokenType::MinusMinus} else {TokenType::Minus};
+                self.push_token(tt, Object::None);
+            }
+            '+' => {
+                let tt = if self.match_char('+') {TokenType::PlusPlus} else {TokenType::Plus};
+                self.push_token(tt, Object::None);
+            }
+            '?' => self.push_token(TokenType::Question, Object::None),
+            ';' => self.push_token(TokenType::Semicolon, Object::None),
+            '*' => self.push_token(TokenType::Star, Object::None),
+            '%' => self.push_token(TokenType::Percent, Object::None),
+
+            '!' => {
+                let tt = if self.match_char('=') {TokenType::BangEqual} else {TokenType::Bang};
+                self.push_token(tt, Object::None);
+            }
+            '=' => {
+                let tt = if self.match_char('=') {TokenType::EqualEqual} else {TokenType::Equal};
+                self.push_token(tt, Object::None);
+            }
+            '<' => {
+                let tt = if self.match_char('=') {TokenType::LessEqual} else {TokenType::Less};
+                self.push_token(tt, Object::None);
+            }
+            '>' => {
+                let tt = if self.match_char('=') {TokenType::GreaterEqual} else {TokenType::Greater};
+                self.push_token(tt , Object::None);
+
+            }
+
+            '/' => {
+                if self.match_char('/') {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        self.advance();
+                    }
+                    if self.keep_comments {
+                        self.push_token(TokenType::Comment, Object::None);
+                    }
+                } else if self.match_char('*') {
+                    self.block_comment()?;
+                    if self.keep_comments {
+                        self.push_token(TokenType::Comment, Object::None);
+                    }
+                } else {
+                    self.push_token(TokenType::Slash, Object::None);
+                }
+            }
+
+            ' ' | '\r' | '\t' => (),
+            '\n' => self.new_line(),
+
+            '"' => self.string()?,
+
+            '0'..='9' => self.number()?,
+
+            c if is_identifier_start(c) => self.identifier(),
+
+            _ => return Err(LoxErr::Scan { line: self.line, column: self.start_column, span: self.token_span(), message: "Unexpected character.".to_string() }),
+
+        }
+        Ok(())
+    }
+
+
+
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+        if self.source[self.current] != expected {
+            return false;
+        }
+
+        self.current += 1;
+        true
+    }
+
+    fn peek(&self) -> char {
+        if self.is_at_end() {
+            return '\0';
+        }
+        self.source[self.current]
+    }
+
+    fn peek_next(&self) -> char {
+        if self.current + 1 >= self.source.len() {
+            return '\0';
+        }
+        self.source[self.current + 1]
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.source.len()
+    }
+
+    fn advance(&mut self) -> char {
+        self.current += 1;
+        self.source[self.current - 1]
+    }
+
+    // 刚消耗掉一个换行符
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    fn current_column(&self) -> usize {
+        self.current - self.line_start + 1
+    }
+
+    // 正在扫描的 token 的字节范围
+    fn token_span(&self) -> Span {
+        Span { start: self.byte_offsets[self.start], end: self.byte_offsets[self.current] }
+    }
+
+    // 当前位置，长度是 0。代码没写完时的错误指向这里
+    fn end_span(&self) -> Span {
+        Span { start: self.byte_offsets[self.current], end: self.byte_offsets[self.current] }
+    }
+
+    fn push_token(&mut self, token_type: TokenType, literal: Object) {
+        let text: String = self.source[self.start..self.current].iter().collect::<String>();
+        self.push_token_with_lexeme(token_type, text, literal);
+    }
+
+    fn push_token_with_lexeme(&mut self, token_type: TokenType, lexeme: impl Into<Rc<str>>, literal: Object) {
+        let token = Token::new(token_type, lexeme, literal, self.start_line).with_position(self.start_column, self.token_span());
+        self.tokens.push(Rc::new(token));
+    }
+
+    fn intern(&mut self, text: &str) -> Rc<str> {
+        if let Some(interned) = self.identifiers.get(text) {
+            return Rc::clone(interned);
+        }
+        let interned: Rc<str> = Rc::from(text);
+        self.identifiers.insert(Rc::clone(&interned));
+        interned
+    }
+
+    /*
+        处理类似这样的块注释
+     */
+    fn block_comment(&mut self) -> Result<(), LoxErr>{
+        while self.peek() != '*' && self.peek_next() != '/' {
+            if self.is_at_end() {
+                self.unterminated = true;
+                return Err(LoxErr::Scan { line: self.line, column: self.current_column(), span: self.end_span(), message: "Unterminated block comment.".to_string() });
+            }
+            if self.advance() == '\n' {
+                self.new_line();
+            }
+        }
+        self.advance();
+        self.advance();
+        Ok(())
+    }
+    
+    /*
+    扫描字符串，从开头的 " 或者插值结束的 } 后面开始。和 clox 一样，"a ${b} c" 会扫描成
+        Interpolation("a ")  Identifier(b)  String(" c")
+    遇到 ${ 时先把前面这段作为 Interpolation 交出去，回到普通的扫描，等匹配的 } 出现再回到这里
+    */
+    // 支持 \n \t \r \0 \" \\ \$ 和 \u{1F600} 这些转义，和 escape_string 的输出对得上。转义写错了的话报错，字符串照常扫描完
+    fn string(&mut self) -> Result<(), LoxErr>{
+        let mut value = String::new();
+        let mut escape_err = None;
+        while !self.is_at_end() && self.peek() != '"' && !(self.peek() == '$' && self.peek_next() == '{') {
+            match self.advance() {
+                '\n' => {
+                    value.push('\n');
+                    self.new_line();
+                }
+                '\\' => match self.escape() {
+                    Ok(c) => value.push(c),
+                    Err(err) => {
+                        escape_err.get_or_insert(err);
+                    }
+                },
+                c => value.push(c),
+            }
+        }
+
+        if self.is_at_end() {
+            self.unterminated = true;
+            return Err(LoxErr::Scan { line: self.line, column: self.current_column(), span: self.end_span(), message: "Unterminated string.".to_string() });
+        }
+
+        if self.advance() == '"' {
+            self.push_token(TokenType::String, Object::String(value));
+        } else {
+            self.advance();     // ${ 的 {
+            self.push_token(TokenType::Interpolation, Object::String(value));
+            self.interpolations.push(0);
+        }
+        match escape_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    // 反斜杠已经消耗掉了，返回转义出的字符
+    fn escape(&mut self) -> Result<char, LoxErr> {
+        let start = self.current - 1;
+        if self.is_at_end() {
+            return Err(self.escape_error(start, "Invalid escape sequence."));
+        }
+        let escaped = match self.peek() {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'r' => Some('\r'),
+            '0' => Some('\0'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            '$' => Some('$'),
+            'u' if self.peek_next() == '{' => {
+                self.advance();
+                self.advance();
+                let digits_start = self.current;
+                while self.peek().is_ascii_hexdigit() {
+                    self.advance();
+                }
+                let digits = self.source[digits_start .. self.current].iter().collect::<String>();
+                let c = if self.peek() == '}' {
+                    self.advance();
+                    u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                };
+                return c.ok_or_else(|| self.escape_error(start, "Invalid unicode escape sequence."));
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(c) => {
+                self.advance();
+                Ok(c)
+            }
+            None => {
+                // 换行留给 string 处理，记下行号
+                if self.peek() != '\n' {
+                    self.advance();
+                }
+                Err(self.escape_error(start, "Invalid escape sequence."))
+            }
+        }
+    }
+
+    fn escape_error(&self, start: usize, message: &str) -> LoxErr {
+        let span = Span { start: self.byte_offsets[start], end: self.byte_offsets[self.current] };
+        LoxErr::Scan { line: self.line, column: start - self.line_start + 1, span: span, message: message.to_string() }
+    }
+
+    // 前一个 token 能作为操作数的结尾时，后面的 . 是属性访问，比如 obj.5 还是 Dot
+    fn previous_ends_operand(&self) -> bool {
+        match self.tokens.last() {
+            Some(token) => matches!(token.token_type,
+                TokenType::Identifier | TokenType::Number | TokenType::String | TokenType::RightParen
+                | TokenType::This | TokenType::Super | TokenType::True | TokenType::False | TokenType::Nil),
+            None => false,
+        }
+    }
+
+    fn number(&mut self) -> Result<(), LoxErr> {
+        if self.source[self.start] == '0' {
+            let radix = match self.peek() {
+                'x' => Some((16, "hexadecimal")),
+                'b' => Some((2, "binary")),
+                'o' => Some((8, "octal")),
+                _ => None,
+            };
+            if let Some((radix, kind)) = radix {
+                self.advance();
+                return self.radix_number(radix, kind);
+            }
+        }
+        while self.peek().is_ascii_digit() {
+            self.advance();
+        }
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
+            self.advance();
+            while self.peek().is_ascii_digit() {
+                self.advance();
+            }
+        }
+        let value_s = self.source[self.start .. self.current].iter().collect::<String>();
+        // 没有小数点的是整数，超出 i64 范围的只能当浮点数
+        let value = match value_s.parse::<i64>() {
+            Ok(int) => Object::Int(int),
+            Err(_) => Object::Number(value_s.parse::<f64>().unwrap()),
+        };
+        self.push_token(TokenType::Number, value);
+        Ok(())
+    }
+
+    // 0xFF、0b1010、0o755，结果都是整数。紧跟着的字母和数字都算进字面量里，0b102 报一个错误而不是拆成 0b10 和 2
+    // 写错了的字面量照样交出一个值为 0 的 Number，解析器不会因为少了操作数再多报一个错误
+    fn radix_number(&mut self, radix: u32, kind: &str) -> Result<(), LoxErr> {
+        let prefix_end = self.current;
+        while self.peek().is_ascii_alphanumeric() {
+            self.advance();
+        }
+        let digits = self.source[prefix_end .. self.current].iter().collect::<String>();
+        let message = if digits.is_empty() {
+            format!("Expect digits after '{}'.", self.source[self.start .. prefix_end].iter().collect::<String>())
+        } else if let Some(digit) = digits.chars().find(|c| !c.is_digit(radix)) {
+            format!("Invalid digit '{}' in {} literal.", digit, kind)
+        } else if let Ok(int) = i64::from_str_radix(&digits, radix) {
+            self.push_token(TokenType::Number, Object::Int(int));
+            return Ok(());
+        } else {
+            format!("The {} literal is too large.", kind)
+        };
+        self.push_token(TokenType::Number, Object::Int(0));
+        Err(LoxErr::Scan { line: self.line, column: self.start_column, span: self.token_span(), message: message })
+    }
+
+    fn identifier(&mut self) {
+        while is_identifier_continue(self.peek()) {
+            self.advance();
+        }
+        let text = self.source[self.start..self.current].iter().collect::<String>();
+        let tt = if let Some(word) = self.keywords.get(&text) {
+            *word
+        } else {
+            TokenType::Identifier
+        };
+
+        match tt {
+            // Bool 值特殊处理
+            TokenType::False => self.push_token(tt, Object::Bool(false)),
+            TokenType::True => self.push_token(tt, Object::Bool(true)),
+            TokenType::Identifier | TokenType::This | TokenType::Super => {
+                let lexeme = self.intern(&text);
+                self.push_token_with_lexeme(tt, lexeme, Object::None);
+            }
+            _ => self.push_token(tt, Object::None),
+        }
+
+    }
+}
+
+
+// 边扫描边交出 token，不用等整个文件扫描完。和 scan_tokens 一样先 load_code，两个不要混着用
+// 遇到错误时交出错误，然后接着扫描，最后一个是 Eof。交出去的 token 只在 tokens 里留最后一个，扫描 .5 这种数字时要看它
+impl Iterator for Scanner {
+    type Item = Result<Token, LoxErr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.next_token < self.tokens.len() {
+                let token = (*self.tokens[self.next_token]).clone();
+                self.tokens.drain(..self.next_token);
+                self.next_token = 1;
+                return Some(Ok(token));
+            }
+            if self.finished {
+                return None;
+            }
+            let result = if self.is_at_end() {
+                self.finished = true;
+                self.finish()
+            } else {
+                self.scan_next()
+            };
+            if let Err(err) = result {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+// 标识符按 Unicode 的 XID 规则，变量、café 都可以：开头是字母一类的字符或者 _，后面还可以有数字、组合附加符号这些
+// 不做正规化，写法不同的同一个字符算不同的名字
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c)
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lox::Lox;
+    use crate::object::escape_string;
+
+    #[test]
+    fn test_identifiers_are_interned() {
+        let mut scanner = Scanner::new();
+        scanner.load_code("var a = a; var b;");
+        scanner.scan_tokens().unwrap();
+        let a1 = &scanner.tokens[1].lexeme;
+        let a2 = &scanner.tokens[3].lexeme;
+        assert_eq!(&**a1, "a");
+        assert!(Rc::ptr_eq(a1, a2));
+        assert!(!Rc::ptr_eq(a1, &scanner.tokens[6].lexeme));
+
+        // 驻留表在多次扫描之间保留
+        let first = Rc::clone(a1);
+        scanner.load_code("a;");
+        scanner.scan_tokens().unwrap();
+        assert!(Rc::ptr_eq(&first, &scanner.tokens[0].lexeme));
+    }
+
+    #[test]
+    fn test_positions() {
+        let code = "var 名 = \"a\nb\";\n  名 = 1; /* x\n*/ print";
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        scanner.scan_tokens().unwrap();
+        let positions: Vec<(&str, usize, usize)> = scanner.tokens.iter()
+            .map(|token| (&code[token.span.start..token.span.end], token.line, token.column))
+            .collect();
+        assert_eq!(positions, vec![
+            ("var", 1, 1), ("名", 1, 5), ("=", 1, 7), ("\"a\nb\"", 1, 9), (";", 2, 3),
+            ("名", 3, 3), ("=", 3, 5), ("1", 3, 7), (";", 3, 8), ("print", 4, 4), ("", 4, 9),
+        ]);
+
+        scanner.load_code("var a = 1;\n  @");
+        match scanner.scan_tokens() {
+            Err(LoxErr::Scan { line, column, .. }) => assert_eq!((line, column), (2, 3)),
+            other => panic!("Expected scan error, got {:?}", other),
+        }
+    }
+
+    fn token_types(code: &str) -> Vec<TokenType> {
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        scanner.scan_tokens().unwrap();
+        scanner.tokens.iter().map(|token| token.token_type).collect()
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let mut scanner = Scanner::new();
+        scanner.load_code("var café = 变量_1 + x\u{301} + _ü;");
+        scanner.scan_tokens().unwrap();
+        let identifiers: Vec<&str> = scanner.tokens.iter()
+            .filter(|token| token.token_type == TokenType::Identifier)
+            .map(|token| &*token.lexeme)
+            .collect();
+        assert_eq!(identifiers, vec!["café", "变量_1", "x\u{301}", "_ü"]);
+        // 组合附加符号和数字不能打头，表情符号不是标识符
+        for code in ["\u{301}x", "١x", "a😀"] {
+            scanner.load_code(code);
+            assert!(matches!(scanner.scan_tokens(), Err(LoxErr::Scan { .. })), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_iterator() {
+        let code = "var s = \"a${b}c\";\nprint .5 + x.y; @ // done\nprint \"${";
+        let mut scanner = Scanner::new();
+        scanner.load_code(code);
+        let batch_errors = match scanner.scan_tokens() {
+            Err(LoxErr::Many(errors)) => errors.len(),
+            other => panic!("Expected two scan errors, got {:?}", other),
+        };
+        let batch: Vec<Token> = scanner.tokens.iter().map(|token| (**token).clone()).collect();
+
+        scanner.load_code(code);
+        let mut streamed = Vec::new();
+        let mut errors = 0;
+        while let Some(result) = scanner.next() {
+            match result {
+                Ok(token) => streamed.push(token),
+                Err(_) => errors += 1,
+            }
+            // 交出去的 token 不会越攒越多
+            assert!(scanner.tokens.len() <= 2);
+        }
+        assert_eq!(streamed, batch);
+        assert_eq!((errors, batch_errors), (2, 2));
+        assert_eq!(streamed.last().unwrap().token_type, TokenType::Eof);
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn test_leading_dot_number() {
+        let mut scanner = Scanner::new();
+        scanner.load_code(".5 + -.25;");
+        scanner.scan_tokens().unwrap();
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[0].literal, Object::Number(0.5));
+        assert_eq!(&*scanner.tokens[0].lexeme, ".5");
+        assert_eq!(scanner.tokens[3].literal, Object::Number(0.25));
+
+        // 属性访问里的 . 不受影响
+        assert_eq!(token_types("x.y"), vec![TokenType::Identifier, TokenType::Dot, TokenType::Identifier, TokenType::Eof]);
+        assert_eq!(token_types("x.5"), vec![TokenType::Identifier, TokenType::Dot, TokenType::Number, TokenType::Eof]);
+        assert_eq!(token_types("f().5"), vec![TokenType::Identifier, TokenType::LeftParen, TokenType::RightParen, TokenType::Dot, TokenType::Number, TokenType::Eof]);
+        // 3. 还是数字 3 后面跟一个 Dot
+        assert_eq!(token_types("3."), vec![TokenType::Number, TokenType::Dot, TokenType::Eof]);
+        assert_eq!(token_types("3.5.5"), vec![TokenType::Number, TokenType::Dot, TokenType::Number, TokenType::Eof]);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let literal = |code: &str| {
+            let mut scanner = Scanner::new();
+            scanner.load_code(code);
+            scanner.scan_tokens().map(|_| scanner.tokens[0].literal.clone())
+        };
+        assert_eq!(literal(r#""a\tb\n\"c\"\\\0\r""#).unwrap(), Object::String("a\tb\n\"c\"\\\0\r".to_string()));
+        assert_eq!(literal(r#""\u{48}\u{1F600}\${x}""#).unwrap(), Object::String("H\u{1F600}${x}".to_string()));
+
+        // escape_string 的输出扫描回来还是同一个字符串
+        for s in ["", "plain", "quote \" and \\", "line\nbreak\r\t\0", "bell\u{7}\u{1b}[0m", "cost: $5, ${name}", "中文 \u{1F600}"] {
+            assert_eq!(literal(&escape_string(s)).unwrap(), Object::String(s.to_string()), "{}", escape_string(s));
+        }
+
+        let error = |code: &str| match literal(code) {
+            Err(LoxErr::Scan { line, column, message, .. }) => (line, column, message),
+            other => panic!("Expected scan error, got {:?}", other),
+        };
+        assert_eq!(error("\"a\\qb\""), (1, 3, "Invalid escape sequence.".to_string()));
+        assert_eq!(error("\"\\u{110000}\""), (1, 2, "Invalid unicode escape sequence.".to_string()));
+        assert_eq!(error("\"\\u{41\""), (1, 2, "Invalid unicode escape sequence.".to_string()));
+        // 转义写错了，字符串还是完整地扫描完，后面不会多出错误
+        let mut scanner = Scanner::new();
+        scanner.load_code("\"\\q\" + 1;");
+        assert!(matches!(scanner.scan_tokens(), Err(LoxErr::Scan { .. })));
+        let types: Vec<TokenType> = scanner.tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(types, vec![TokenType::String, TokenType::Plus, TokenType::Number, TokenType::Semicolon, TokenType::Eof]);
+    }
+
+    #[test]
+    fn test_radix_literals() {
+        let literal = |code: &str| {
+            let mut scanner = Scanner::new();
+            scanner.load_code(code);
+            scanner.scan_tokens().map(|_| scanner.tokens[0].literal.clone())
+        };
+        assert_eq!(literal("0xFF").unwrap(), Object::Int(255));
+        assert_eq!(literal("0xff").unwrap(), Object::Int(255));
+        assert_eq!(literal("0b1010").unwrap(), Object::Int(10));
+        assert_eq!(literal("0o755").unwrap(), Object::Int(493));
+        assert_eq!(literal("0").unwrap(), Object::Int(0));
+        assert_eq!(token_types("0x10.y"), vec![TokenType::Number, TokenType::Dot, TokenType::Identifier, TokenType::Eof]);
+
+        let message = |code: &str| match literal(code) {
+            Err(LoxErr::Scan { message, .. }) => message,
+            other => panic!("Expected scan error, got {:?}", other),
+        };
+        assert_eq!(message("0x;"), "Expect digits after '0x'.");
+        assert_eq!(message("0b102;"), "Invalid digit '2' in binary literal.");
+        assert_eq!(message("0o8"), "Invalid digit '8' in octal literal.");
+        assert_eq!(message("0xFG"), "Invalid digit 'G' in hexadecimal literal.");
+        assert_eq!(message("0x8000000000000000"), "The hexadecimal literal is too large.");
+
+        // 写错的字面量后面还是有一个 Number，整段代码只有一个错误
+        for code in ["print 0x;", "print 0b102 + 1;", "print 0o9;"] {
+            let (_, errors) = Lox::new().run_capture(code);
+            assert!(matches!(&errors[..], [LoxErr::Scan { .. }]), "{}: {:?}", code, errors);
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation_tokens() {
+        let mut scanner = Scanner::new();
+        scanner.load_code(r#""a ${b} c ${"d ${e}"}""#);
+        scanner.scan_tokens().unwrap();
+        let tokens: Vec<(TokenType, Object)> = scanner.tokens.iter().map(|token| (token.token_type, token.literal.clone())).collect();
+        let string = |s: &str| Object::String(s.to_string());
+        assert_eq!(tokens, vec![
+            (TokenType::Interpolation, string("a ")),
+            (TokenType::Identifier, Object::None),
+            (TokenType::Interpolation, string(" c ")),
+            (TokenType::Interpolation, string("d ")),
+            (TokenType::Identifier, Object::None),
+            (TokenType::String, string("")),
+            (TokenType::String, string("")),
+            (TokenType::Eof, Object::None),
+        ]);
+
+        // 只有 $ 或者只有 { 都是普通字符
+        assert_eq!(token_types(r#""$5 {x}""#), vec![TokenType::String, TokenType::Eof]);
+
+        scanner.load_code(r#""open ${x"#);
+        assert!(scanner.scan_tokens().is_err());
+        assert!(scanner.unterminated);
+    }
+
+    #[test]
+    fn test_token_display() {
+        let mut scanner = Scanner::new();
+        scanner.load_code("var s = \"a\nb\";\nprint 2;");
+        scanner.scan_tokens().unwrap();
+        let lines: Vec<String> = scanner.tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(lines, vec![
+            "1:1     Var           var",
+            "1:5     Identifier    s",
+            "1:7     Equal         =",
+            "1:9     String        \"a\\nb\"  \"a\\nb\"",
+            "2:3     Semicolon     ;",
+            "3:1     Print         print",
+            "3:7     Number        2  2",
+            "3:8     Semicolon     ;",
+            "3:9     Eof",
+        ]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::environment::Environment;
+use crate::object::Object;
+
+/*
+全局环境的快照，REPL 会话或者嵌入的脚本可以存到文件里，下次恢复了接着用
+只保存纯数据：nil、布尔、数字、字符串，和只含这些的列表
+函数、类、实例带着代码和环境，没法单独存下来，跳过并记下名字。原生函数每个解释器都有，不用存
+存成 JSON，整数和浮点数分开记：
+    {"version": 1, "globals": [{"name": "x", "value": {"type": "Int", "value": 1}, "constant": false}]}
+*/
+
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", content = "value")]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    String(String),
+    List(Vec<Value>),
+}
+
+impl Value {
+    // 不是纯数据的话返回 None。JSON 里存不了 NaN 和无穷大，也算
+    fn from_object(object: &Object) -> Option<Value> {
+        match object {
+            Object::None => Some(Value::Nil),
+            Object::Bool(b) => Some(Value::Bool(*b)),
+            Object::Int(int) => Some(Value::Int(*int)),
+            Object::Number(n) if n.is_finite() => Some(Value::Number(*n)),
+            Object::String(s) => Some(Value::String(s.clone())),
+            Object::List(list) => list.iter().map(Value::from_object).collect::<Option<Vec<Value>>>().map(Value::List),
+            _ => None,
+        }
+    }
+
+    fn to_object(&self) -> Object {
+        match self {
+            Value::Nil => Object::None,
+            Value::Bool(b) => Object::Bool(*b),
+            Value::Int(int) => Object::Int(*int),
+            Value::Number(n) => Object::Number(*n),
+            Value::String(s) => Object::String(s.clone()),
+            Value::List(list) => Object::List(Rc::new(list.iter().map(Value::to_object).collect())),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Global {
+    pub name: String,
+    pub value: Value,
+    pub constant: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Snapshot {
+    version: u32,
+    pub globals: Vec<Global>,
+    #[serde(skip)]
+    pub skipped: Vec<String>,   // 没存下来的全局变量，按名字排序
+}
+
+impl Snapshot {
+    // 按名字排序，同样的状态存出来的文件也一样
+    pub fn capture(globals: &Environment) -> Snapshot {
+        let mut snapshot = Snapshot { version: VERSION, globals: Vec::new(), skipped: Vec::new() };
+        for (name, object) in &globals.values {
+            match Value::from_object(object) {
+                Some(value) => snapshot.globals.push(Global {
+                    name: name.to_string(),
+                    value: value,
+                    constant: globals.constants.contains(name),
+                }),
+                None if matches!(object, Object::NativeFunction(_)) => (),
+                None => snapshot.skipped.push(name.to_string()),
+            }
+        }
+        snapshot.globals.sort_by(|a, b| a.name.cmp(&b.name));
+        snapshot.skipped.sort();
+        snapshot
+    }
+
+    // 同名的全局变量会被覆盖
+    pub fn restore(&self, globals: &mut Environment) {
+        for global in &self.globals {
+            let name: Rc<str> = Rc::from(global.name.as_str());
+            if global.constant {
+                globals.constants.insert(Rc::clone(&name));
+            } else {
+                globals.constants.remove(&name);
+            }
+            globals.values.insert(name, global.value.to_object());
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Snapshots always serialize to JSON.")
+    }
+
+    pub fn from_json(json: &str) -> Result<Snapshot, std::io::Error> {
+        let snapshot: Snapshot = serde_json::from_str(json)?;
+        if snapshot.version != VERSION {
+            return Err(std::io::Error::other(format!("Unsupported snapshot version {}.", snapshot.version)));
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::err::LoxErr;
+    use crate::lox::Lox;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let globals = Environment::new();
+        {
+            let mut globals = globals.borrow_mut();
+            globals.define(&Rc::from("n"), Object::Int(1));
+            globals.define(&Rc::from("x"), Object::Number(2.0));
+            globals.define(&Rc::from("name"), Object::String("rilox".to_string()));
+            globals.constants.insert(Rc::from("name"));
+            globals.define(&Rc::from("list"), Object::List(Rc::new(vec![Object::None, Object::Bool(true)])));
+            globals.define(&Rc::from("inf"), Object::Number(f64::INFINITY));
+        }
+        let snapshot = Snapshot::from_json(&Snapshot::capture(&globals.borrow()).to_json()).unwrap();
+        let names: Vec<&str> = snapshot.globals.iter().map(|global| global.name.as_str()).collect();
+        assert_eq!(names, vec!["list", "n", "name", "x"]);
+        assert_eq!(snapshot.globals[2], Global { name: "name".to_string(), value: Value::String("rilox".to_string()), constant: true });
+        // 2.0 恢复出来还是浮点数
+        assert_eq!(snapshot.globals[3].value, Value::Number(2.0));
+
+        let restored = Environment::new();
+        snapshot.restore(&mut restored.borrow_mut());
+        let restored = restored.borrow();
+        assert_eq!(restored.values.get("x"), Some(&Object::Number(2.0)));
+        assert_eq!(restored.values.get("list"), globals.borrow().values.get("list"));
+        assert!(restored.constants.contains("name"));
+        assert!(!restored.values.contains_key("inf"));
+        assert!(Snapshot::from_json("{\"version\": 2, \"globals\": []}").is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut lox = Lox::new();
+        lox.eval("var count = 3; const limit = 10; fun f() {} class C {} var c = C();").unwrap();
+        let path = std::env::temp_dir().join(format!("rilox_snapshot_{}.json", std::process::id()));
+        // 原生函数不算
+        assert_eq!(lox.save_snapshot(&path).unwrap(), vec!["C", "c", "f"]);
+
+        let mut restored = Lox::new();
+        restored.load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.eval("count + limit;").unwrap(), Object::Int(13));
+        assert_eq!(restored.get_global("f"), None);
+        // 常量恢复后还是常量
+        assert!(matches!(restored.eval("limit = 1;").unwrap_err()[..], [LoxErr::Resolve { .. }]));
+        assert!(restored.load_snapshot(&path).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+// 解析、语义分析、编译和求值都是递归的。表达式嵌套很深（一长串 + 也是一棵往左偏的很深的树），或者 Lox 函数递归很多层时，
+// 线程的栈可能不够用，特别是库、LSP 的调用方不一定在大栈的线程里跑。剩下的栈不多了就在堆上分配一段新的栈接着跑
+
+// debug 构建下一层 Lox 调用要占十几 KB 的栈，留出几层的余量，报错和展开也要用栈
+#[cfg(not(target_arch = "wasm32"))]
+const RED_ZONE: usize = 256 * 1024;
+#[cfg(not(target_arch = "wasm32"))]
+const SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn guard<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, SEGMENT_SIZE, f)
+}
+
+// WebAssembly 里没法换栈，只能直接调用
+#[cfg(target_arch = "wasm32")]
+pub fn guard<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
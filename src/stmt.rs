@@ -1,76 +1,363 @@
-use crate::expr::{Expr, VariableExpr};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::expr::{Expr, LiteralExpr, VariableExpr};
+use crate::object::Object;
+use crate::resolvable::Slot;
 use crate::token::Token;
 
-#[derive(Debug, PartialEq, Clone)]
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "type")]
 pub enum Stmt {
+    // 条件为假时报运行时错误，message 只在失败时求值
+    Assert {
+        keyword: Rc<Token>,
+        condition: Expr,
+        message: Option<Expr>,
+    },
     Block {
+        brace: Rc<Token>,   // 左花括号。for 循环、switch 分支这些改写出来的块用原来语句的关键字
         statements: Vec<Stmt>,
     },
     Break {
-        keyword: Token,
+        keyword: Rc<Token>,
     },
     ClassDeclaration {
         class_declaration: ClassDeclaration,
     },
+    Continue {
+        keyword: Rc<Token>,
+    },
+
+    // 先执行循环体再判断条件，continue 跳到条件判断
+    DoWhile {
+        keyword: Rc<Token>,
+        body: Box<Stmt>,
+        condition: Expr,
+    },
 
     Expression {
+        start: Rc<Token>,   // 语句的第一个 token，表达式只是一个字面量时也能定位
         expression: Expr,
     },
 
-    // 函数定义
+    // for 循环脱糖时插在循环体和递增语句之间：给循环变量换一个新的 cell，值不变
+    // 这样每次迭代里创建的闭包捕获的是这一次迭代的循环变量，不会被后面的递增改掉
+    FreshLoopVariable {
+        variable: VariableExpr,
+    },
+
+    // for (initializer; condition; increment) body。解析出来的语法树保留用户写的样子，给 --ast 和别的工具看
+    // 语义分析时改写成 While，后面的优化、解释和编译都只看到 While
+    For {
+        keyword: Rc<Token>,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+
+    // for (var name in iterable) body，每次迭代都有一个新的循环变量
+    ForIn {
+        name: Rc<Token>,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+
+    // 函数定义。用 Rc 共享，创建函数对象（包括每次 bind 方法）时不用深拷贝整个函数体
     FunctionDeclaration {
-        function_declaration: FunctionDeclaration,
+        function_declaration: Rc<FunctionDeclaration>,
     },
     If {
+        keyword: Rc<Token>,
         condition: Expr,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
     },
     While {
+        keyword: Rc<Token>,     // 从 for 循环改写来的是 for 关键字
         condition: Expr,
         body: Box<Stmt>,
+        increment: Vec<Stmt>,   // for 循环脱糖后每次迭代末尾执行的语句（换新的循环变量、递增），continue 之后也要执行
+    },
+    // 执行另一个文件，有 name 的话把模块对象绑定到这个全局变量上
+    Import {
+        keyword: Rc<Token>,
+        name: Option<Rc<Token>>,
+        path: String,
     },
     Print {
+        keyword: Rc<Token>,
         expression: Expr,
     },
     Return {
-        keyword: Token,
+        keyword: Rc<Token>,
         value: Option<Expr>,
     },
+
+    // 依次用 == 比较 subject 和各个 case 的值，只执行第一个相等的分支，不会贯穿到下一个分支
+    // 分支体都是 Block，有自己的作用域。break 和 continue 仍然作用于外层的循环
+    Switch {
+        keyword: Rc<Token>,
+        subject: Expr,
+        cases: Vec<SwitchCase>,
+        default: Option<Box<Stmt>>,
+    },
+    Throw {
+        keyword: Rc<Token>,
+        value: Expr,
+    },
+
+    // catch 接住 throw 抛出的值。打开了 catch_runtime_errors 的话也接住运行时错误，这时 name 绑定的是错误信息字符串
+    Try {
+        keyword: Rc<Token>,
+        try_block: Vec<Stmt>,
+        name: Rc<Token>,
+        catch_block: Vec<Stmt>,
+    },
     Var {
-        name: Token,
+        name: Rc<Token>,
         initializer: Option<Expr>, // 初始化表达式
+        is_const: bool, // const 声明的变量不能再赋值，一定有初始化表达式
     },
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct FunctionDeclaration {
-    pub name: Token,
-    pub params: Vec<Token>,
+    pub name: Rc<Token>,
+    pub params: Vec<Rc<Token>>,
+    pub is_variadic: bool,  // 最后一个参数是 ...rest，多出来的实参收进一个列表传给它
     pub body: Vec<Stmt>,
+    #[serde(skip)]
+    pub captures: Vec<Slot>,    // 函数体用到的外层局部变量，相对于声明函数时的环境。由 Resolver 填
 
 }
 
 
 impl Stmt {
     pub fn is_function_declaration(&self) -> bool {
-        match self {
-            Stmt::FunctionDeclaration { .. } => true,
-            _ => false,
-        }
+        matches!(self, Stmt::FunctionDeclaration { .. })
     }
 
-    pub fn into_function_declaration(self) -> Option<FunctionDeclaration> {
+    pub fn into_function_declaration(self) -> Option<Rc<FunctionDeclaration>> {
         match self {
             Stmt::FunctionDeclaration { function_declaration } => Some(function_declaration),
             _ => None
         }
     }
+
+    // 把 for 循环改写成 while。没写条件时视为 true
+    // 递增语句不放进循环体，continue 跳过循环体剩下的部分后还要执行它们。用 var 声明的循环变量每次迭代前换一个新的，闭包捕获的是当次迭代的值
+    // 有初始化语句的话，外面再包一个块，循环变量只在循环里可见。其他语句原样返回
+    pub fn desugar_for(self) -> Stmt {
+        let Stmt::For { keyword, initializer, condition, increment, body } = self else {
+            return self;
+        };
+        let mut increment_statements = vec![];
+        if let Some(Stmt::Var { name, .. }) = initializer.as_deref() {
+            increment_statements.push(Stmt::FreshLoopVariable { variable: VariableExpr::new(Rc::clone(name)) });
+        }
+        if let Some(increment) = increment {
+            increment_statements.push(Stmt::Expression { start: Rc::clone(&keyword), expression: increment });
+        }
+        let while_stmt = Stmt::While {
+            keyword: keyword,
+            condition: condition.unwrap_or(Expr::Literal(LiteralExpr::new(Object::Bool(true)))),
+            body: body,
+            increment: increment_statements,
+        };
+        match initializer {
+            Some(initializer) => Stmt::Block { brace: Rc::clone(while_stmt.first_token()), statements: vec![*initializer, while_stmt] },
+            None => while_stmt,
+        }
+    }
+
+    // return、break、continue、throw 之后的语句执行不到
+    pub fn is_unconditional_jump(&self) -> bool {
+        matches!(self, Stmt::Return { .. } | Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Throw { .. })
+    }
+
+    // 语句开头的 token，用来给警告和中止定位。声明指向名字
+    pub fn first_token(&self) -> &Rc<Token> {
+        match self {
+            Stmt::Assert { keyword, .. } | Stmt::Break { keyword } | Stmt::Continue { keyword } | Stmt::DoWhile { keyword, .. } | Stmt::For { keyword, .. }
+                | Stmt::If { keyword, .. } | Stmt::Import { keyword, .. } | Stmt::Print { keyword, .. } | Stmt::Return { keyword, .. }
+                | Stmt::Switch { keyword, .. } | Stmt::Throw { keyword, .. } | Stmt::Try { keyword, .. } | Stmt::While { keyword, .. } => keyword,
+            Stmt::Block { brace, .. } => brace,
+            Stmt::ClassDeclaration { class_declaration } => &class_declaration.name,
+            Stmt::Expression { start, .. } => start,
+            Stmt::FreshLoopVariable { variable } => &variable.name,
+            Stmt::ForIn { name, .. } | Stmt::Var { name, .. } => name,
+            Stmt::FunctionDeclaration { function_declaration } => &function_declaration.name,
+        }
+    }
+}
+
+// 和 Expr 一样打印成 S 表达式。含有语句的语句把子语句换行缩进，方便看清嵌套
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_at(f, 0)
+    }
+}
+
+impl Stmt {
+    fn fmt_at(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        match self {
+            Stmt::Assert { condition, message, .. } => match message {
+                Some(message) => write!(f, "(assert {} {})", condition, message),
+                None => write!(f, "(assert {})", condition),
+            },
+            Stmt::Block { statements, .. } => {
+                write!(f, "(block")?;
+                write_children(f, statements, depth)?;
+                write!(f, ")")
+            }
+            Stmt::Break { .. } => write!(f, "(break)"),
+            Stmt::ClassDeclaration { class_declaration } => {
+                write!(f, "(class {}", class_declaration.name.lexeme)?;
+                if let Some(superclass) = &class_declaration.superclass {
+                    write!(f, " < {}", superclass.name.lexeme)?;
+                }
+                for method in &class_declaration.methods {
+                    new_line(f, depth + 1)?;
+                    method.fmt_at(f, depth + 1)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Continue { .. } => write!(f, "(continue)"),
+            Stmt::DoWhile { body, condition, .. } => {
+                write!(f, "(do-while {}", condition)?;
+                write_children(f, std::slice::from_ref(&**body), depth)?;
+                write!(f, ")")
+            }
+            Stmt::Expression { expression, .. } => write!(f, "(; {})", expression),
+            Stmt::FreshLoopVariable { variable } => write!(f, "(fresh {})", variable.name.lexeme),
+            // 省略的部分写成 _
+            Stmt::For { initializer, condition, increment, body, .. } => {
+                write!(f, "(for ")?;
+                match initializer {
+                    Some(initializer) => initializer.fmt_at(f, depth)?,
+                    None => write!(f, "_")?,
+                }
+                match condition {
+                    Some(condition) => write!(f, " {}", condition)?,
+                    None => write!(f, " _")?,
+                }
+                match increment {
+                    Some(increment) => write!(f, " {}", increment)?,
+                    None => write!(f, " _")?,
+                }
+                write_children(f, std::slice::from_ref(&**body), depth)?;
+                write!(f, ")")
+            }
+            Stmt::ForIn { name, iterable, body } => {
+                write!(f, "(for-in {} {}", name.lexeme, iterable)?;
+                write_children(f, std::slice::from_ref(&**body), depth)?;
+                write!(f, ")")
+            }
+            Stmt::FunctionDeclaration { function_declaration } => function_declaration.fmt_at(f, depth),
+            Stmt::If { condition, then_branch, else_branch, .. } => {
+                write!(f, "(if {}", condition)?;
+                new_line(f, depth + 1)?;
+                then_branch.fmt_at(f, depth + 1)?;
+                if let Some(else_branch) = else_branch {
+                    new_line(f, depth + 1)?;
+                    else_branch.fmt_at(f, depth + 1)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::While { condition, body, increment, .. } => {
+                write!(f, "(while {}", condition)?;
+                write_children(f, std::slice::from_ref(&**body), depth)?;
+                write_children(f, increment, depth)?;
+                write!(f, ")")
+            }
+            Stmt::Import { name, path, .. } => match name {
+                Some(name) => write!(f, "(import {} {:?})", name.lexeme, path),
+                None => write!(f, "(import {:?})", path),
+            },
+            Stmt::Print { expression, .. } => write!(f, "(print {})", expression),
+            Stmt::Return { value, .. } => match value {
+                Some(value) => write!(f, "(return {})", value),
+                None => write!(f, "(return)"),
+            },
+            Stmt::Switch { subject, cases, default, .. } => {
+                write!(f, "(switch {}", subject)?;
+                for case in cases {
+                    new_line(f, depth + 1)?;
+                    write!(f, "(case {}", case.value)?;
+                    write_children(f, std::slice::from_ref(&case.body), depth + 1)?;
+                    write!(f, ")")?;
+                }
+                if let Some(default) = default {
+                    new_line(f, depth + 1)?;
+                    write!(f, "(default")?;
+                    write_children(f, std::slice::from_ref(&**default), depth + 1)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            }
+            Stmt::Throw { value, .. } => write!(f, "(throw {})", value),
+            Stmt::Try { try_block, name, catch_block, .. } => {
+                write!(f, "(try")?;
+                write_children(f, try_block, depth)?;
+                new_line(f, depth + 1)?;
+                write!(f, "(catch {}", name.lexeme)?;
+                write_children(f, catch_block, depth + 1)?;
+                write!(f, "))")
+            }
+            Stmt::Var { name, initializer, is_const } => {
+                write!(f, "({} {}", if *is_const { "const" } else { "var" }, name.lexeme)?;
+                if let Some(initializer) = initializer {
+                    write!(f, " {}", initializer)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl FunctionDeclaration {
+    fn fmt_at(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "(fun {} (", self.name.lexeme)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            if self.is_variadic && i == self.params.len() - 1 {
+                write!(f, "...")?;
+            }
+            write!(f, "{}", param.lexeme)?;
+        }
+        write!(f, ")")?;
+        write_children(f, &self.body, depth)?;
+        write!(f, ")")
+    }
+}
+
+fn new_line(f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+    write!(f, "\n{}", "  ".repeat(depth))
+}
+
+fn write_children(f: &mut fmt::Formatter<'_>, statements: &[Stmt], depth: usize) -> fmt::Result {
+    for statement in statements {
+        new_line(f, depth + 1)?;
+        statement.fmt_at(f, depth + 1)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SwitchCase {
+    pub value: Expr,
+    pub body: Stmt,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct ClassDeclaration {
-    pub name: Token,
+    pub name: Rc<Token>,
     pub superclass: Option<VariableExpr>,
-    pub methods: Vec<FunctionDeclaration>,
+    pub methods: Vec<Rc<FunctionDeclaration>>,
 }
\ No newline at end of file
@@ -1,22 +1,56 @@
-use std::fmt::Debug;
-use crate::token_type::TokenType;
-use crate::object::Object;
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct Token {
-    pub token_type: TokenType,
-    pub lexeme: String, // token 在代码中的字符串
-    pub literal: Object,   // 实际的值
-    pub line: usize,
-}
-
-impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Object, line: usize) -> Token {
-        Token {
-            token_type,
-            lexeme,
-            literal,
-            line,
-        }
-    }
-}
\ No newline at end of file
+use std::fmt::{self, Debug};
+use std::rc::Rc;
+use crate::token_type::TokenType;
+use crate::object::Object;
+
+use serde::Serialize;
+
+// token 在源代码里的字节范围 [start, end)，给编辑器之类需要精确定位的地方用
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub lexeme: Rc<str>, // token 在代码中的字符串，标识符由 Scanner 驻留，同名的共享一份
+    pub literal: Object,   // 实际的值
+    pub line: usize,
+    pub column: usize,  // token 第一个字符在这一行的第几个字符，从 1 开始。语法糖里合成的 token 没有位置时是 0
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(token_type: TokenType, lexeme: impl Into<Rc<str>>, literal: Object, line: usize) -> Token {
+        Token {
+            token_type,
+            lexeme: lexeme.into(),
+            literal,
+            line,
+            column: 0,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_position(mut self, column: usize, span: Span) -> Token {
+        self.column = column;
+        self.span = span;
+        self
+    }
+}
+
+// 位置、类型、lexeme，有字面量的话再加上字面量。--tokens 一行打印一个
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let position = format!("{}:{}", self.line, self.column);
+        let token_type = format!("{:?}", self.token_type);
+        // 跨行的字符串里的换行写成 \n，保证一个 token 占一行
+        let mut text = format!("{:<8}{:<14}{}", position, token_type, self.lexeme.replace('\n', "\\n"));
+        if self.literal != Object::None {
+            text = format!("{}  {}", text, self.literal.nested_string());
+        }
+        f.write_str(text.trim_end())
+    }
+}
@@ -1,22 +1,30 @@
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum TokenType {
-    // Single-character tokens.
-    LeftParen, RightParen, // 小括号 parentheses
-    LeftBrace, RightBrace,  // 大括号 curly braces
-    Colon, Comma, Dot, Minus, Plus, Question, Semicolon, Slash, Star,
-
-    // One or two character tokens.
-    Bang, BangEqual,    // !, !=
-    Equal, EqualEqual,
-    Greater, GreaterEqual,
-    Less, LessEqual,
-
-    // Literals.
-    Identifier, String, Number,
-
-    // Keywords.
-    And, Break, Class, Else, False, Fun, For, If, Nil, Or,
-    Print, Return, Super, This, True, Var, While,
-
-    Eof,
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum TokenType {
+    // Single-character tokens.
+    LeftParen, RightParen, // 小括号 parentheses
+    LeftBrace, RightBrace,  // 大括号 curly braces
+    Colon, Comma, Dot, Percent, Question, Semicolon, Slash, Star,
+    DotDotDot,  // ... 剩余参数
+
+    // One or two character tokens.
+    Bang, BangEqual,    // !, !=
+    Equal, EqualEqual,
+    Greater, GreaterEqual,
+    Less, LessEqual,
+    Minus, MinusMinus,  // -, --
+    Plus, PlusPlus, // +, ++
+
+    // Literals.
+    Identifier, String, Number,
+    Interpolation,  // 字符串里 ${ 前面的一段，后面跟着插值表达式的 token
+
+    // Keywords.
+    And, Assert, Break, Case, Catch, Class, Const, Continue, Default, Do, Else, False, Fun, For, If, Import, In, Nil, Or,
+    Print, Return, Super, Switch, This, Throw, True, Try, Var, While,
+
+    Comment,    // 只有 Scanner 打开 keep_comments 时才有，给格式化用，解析器不认识
+
+    Eof,
 }
\ No newline at end of file
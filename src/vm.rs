@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::compiler::{BytecodeFunction, OpCode};
+use crate::err::LoxErr;
+use crate::interpreter::Interpreter;
+use crate::native::NativeRegistry;
+use crate::object::Object;
+
+const FRAMES_MAX: usize = 1024;
+
+struct CallFrame {
+    function: Rc<BytecodeFunction>,
+    ip: usize,
+    base: usize,    // 这个调用帧在值栈上的起点，第 0 个槽位是被调用的函数
+}
+
+impl CallFrame {
+    fn line(&self) -> usize {
+        self.function.chunk.lines[self.ip - 1]
+    }
+}
+
+// 执行 compiler 生成的字节码。全局变量在多次 interpret 之间保留，REPL 里可以接着用
+pub struct Vm {
+    stack: Vec<Object>,
+    frames: Vec<CallFrame>, // 调用者的帧，当前帧单独放在 run 的局部变量里
+    pub globals: HashMap<Rc<str>, Object>,
+    interrupted: Arc<AtomicBool>,   // 和树遍历解释器一样，跳转和调用时检查
+    output: Box<dyn Write>, // print 输出到哪里，默认是标准输出
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let mut globals = HashMap::new();
+        for native_function in NativeRegistry::standard().natives() {
+            globals.insert(Rc::clone(&native_function.name), Object::NativeFunction(native_function.clone()));
+        }
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: globals,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    pub fn interpret(&mut self, function: BytecodeFunction) -> Result<(), LoxErr> {
+        let function = Rc::new(function);
+        self.stack.push(Object::BytecodeFunction(Rc::clone(&function)));
+        let result = self.run(CallFrame { function: function, ip: 0, base: 0 });
+        // 出错时栈上和调用帧里还留着东西
+        self.stack.clear();
+        self.frames.clear();
+        result
+    }
+
+    // 返回原来的，方便用完后换回去
+    pub fn set_output(&mut self, output: Box<dyn Write>) -> Box<dyn Write> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupted)
+    }
+
+    // 字节码里只记了行号
+    fn check_interrupt(&self, frame: &CallFrame) -> Result<(), LoxErr> {
+        if self.interrupted.load(Ordering::Relaxed) {
+            self.interrupted.store(false, Ordering::Relaxed);
+            return Err(LoxErr::Aborted { line: Some(frame.line()), column: 0, span: None, message: "Interrupted.".to_string() });
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, mut frame: CallFrame) -> Result<(), LoxErr> {
+        loop {
+            let op = frame.function.chunk.code[frame.ip];
+            frame.ip += 1;
+            match op {
+                OpCode::Constant(index) => self.stack.push(frame.function.chunk.constants[index].clone()),
+                OpCode::Nil => self.stack.push(Object::None),
+                OpCode::True => self.stack.push(Object::Bool(true)),
+                OpCode::False => self.stack.push(Object::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal(index) => self.stack.push(self.stack[frame.base + index].clone()),
+                OpCode::SetLocal(index) => self.stack[frame.base + index] = self.peek(0).clone(),
+                OpCode::DefineGlobal(index) => {
+                    let value = self.pop();
+                    self.globals.insert(Rc::clone(&frame.function.chunk.names[index]), value);
+                }
+                OpCode::GetGlobal(index) => {
+                    let name = &frame.function.chunk.names[index];
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(Vm::undefined_variable_err(&frame, name)),
+                    }
+                }
+                OpCode::SetGlobal(index) => {
+                    let name = &frame.function.chunk.names[index];
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(name) {
+                        Some(existing) => *existing = value,
+                        None => return Err(Vm::undefined_variable_err(&frame, name)),
+                    }
+                }
+                OpCode::Binary(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Interpreter::binary_operation(operator, frame.line(), &left, &right)?);
+                }
+                OpCode::Unary(operator) => {
+                    let right = self.pop();
+                    self.stack.push(Interpreter::unary_operation(operator, frame.line(), &right)?);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    writeln!(self.output, "{}", value)?;
+                }
+                OpCode::Jump(target) => {
+                    self.check_interrupt(&frame)?;
+                    frame.ip = target;
+                }
+                OpCode::JumpIfFalse(target) => {
+                    if !Interpreter::is_truthy(self.peek(0)) {
+                        frame.ip = target;
+                    }
+                }
+                OpCode::Call(argument_count) => {
+                    self.check_interrupt(&frame)?;
+                    let callee_index = self.stack.len() - 1 - argument_count;
+                    match &self.stack[callee_index] {
+                        Object::BytecodeFunction(function) => {
+                            Vm::check_arity(&frame, function.arity(), argument_count)?;
+                            if self.frames.len() >= FRAMES_MAX {
+                                return Err(LoxErr::Runtime { line: frame.line(), column: 0, span: None, message: "Stack overflow.".to_string() });
+                            }
+                            let callee_frame = CallFrame { function: Rc::clone(function), ip: 0, base: callee_index };
+                            self.frames.push(std::mem::replace(&mut frame, callee_frame));
+                        }
+                        Object::NativeFunction(native_function) => {
+                            Vm::check_arity(&frame, native_function.arity(), argument_count)?;
+                            let native_function = native_function.clone();
+                            let arguments = self.stack.split_off(callee_index + 1);
+                            let result = native_function.call_native(frame.line(), arguments)?;
+                            self.stack.pop();
+                            self.stack.push(result);
+                        }
+                        _ => return Err(LoxErr::Runtime { line: frame.line(), column: 0, span: None, message: "Can only call functions and classes.".to_string() }),
+                    }
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.stack.truncate(frame.base);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            self.stack.push(result);
+                        }
+                        None => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("Value stack underflow.")
+    }
+
+    fn peek(&self, distance: usize) -> &Object {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn check_arity(frame: &CallFrame, arity: usize, argument_count: usize) -> Result<(), LoxErr> {
+        if argument_count != arity {
+            return Err(LoxErr::Runtime { line: frame.line(), column: 0, span: None, message: format!("Expected {} arguments but got {}.", arity, argument_count) });
+        }
+        Ok(())
+    }
+
+    fn undefined_variable_err(frame: &CallFrame, name: &str) -> LoxErr {
+        LoxErr::Runtime { line: frame.line(), column: 0, span: None, message: format!("Undefined variable '{}'.", name) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::module::resolved;
+    use crate::token::Span;
+
+    fn run_vm(code: &str) -> Result<Vm, LoxErr> {
+        let function = Compiler::compile(&resolved(code))?;
+        let mut vm = Vm::new();
+        vm.interpret(function)?;
+        Ok(vm)
+    }
+
+    // 同一段程序分别用树遍历解释器和虚拟机跑，比较全局变量的值
+    fn assert_same_as_tree_walker(code: &str, names: &[&str]) {
+        let statements = resolved(code);
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(&statements);
+        assert!(!interpreter.had_runtime_error);
+
+        let mut vm = Vm::new();
+        vm.interpret(Compiler::compile(&statements).unwrap()).unwrap();
+
+        for name in names {
+            let expected = interpreter.globals.borrow().values.get(*name).map(|value| value.to_string());
+            let actual = vm.globals.get(*name).map(|value| value.to_string());
+            assert!(expected.is_some(), "Global {} is not defined", name);
+            assert_eq!(actual, expected, "Global {} differs", name);
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_same_as_tree_walker(r#"
+var a = 1 + 2 * 3 - 4 / 2;
+var b = -a;
+var c = !nil == true;
+var d = "foo" + "bar" + 1;
+var e = a > 3 ? "big" : "small";
+var f = nil or "default";
+var g = 0 and "unreachable";
+var h = (1, 2, 3);
+var i = a;
+i = i + 10;
+var j = 1 <= 2 and 2 >= 3;
+var k = "a" != "b";
+var l = -7 % 3;
+var m = mod(-7, 3);
+var n = 1;
+var o = n++ + ++n;
+        "#, &["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o"]);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_same_as_tree_walker(r#"
+var sum = 0;
+for (var i = 0; i < 10; i = i + 1) {
+    if (i == 7) break;
+    if (i / 2 == 1) sum = sum + 100; else sum = sum + i;
+}
+var count = 0;
+while (true) {
+    var local = count;
+    {
+        var inner = local + 1;
+        count = inner;
+    }
+    if (count >= 5) break;
+}
+var shadow = "global";
+{
+    var shadow = "block";
+    shadow = shadow + "!";
+    var seen = shadow;
+}
+var odd = 0;
+for (var j = 0; j < 10; j = j + 1) {
+    var half = j / 2;
+    if (j % 2 == 0) continue;
+    odd = odd + j;
+}
+var skipped = 0;
+var n = 0;
+while (n < 5) {
+    n = n + 1;
+    if (n == 2) continue;
+    skipped = skipped + n;
+}
+var kind = "";
+for (var k = 0; k < 5; k = k + 1) {
+    switch (k) {
+        case 0: kind = kind + "zero";
+        case 1:
+            var one = "one";
+            kind = kind + one;
+        case 3: continue;
+        case 4: break;
+        default: kind = kind + "?";
+    }
+    kind = kind + ",";
+}
+var steps = 0;
+do {
+    var step = 1;
+    steps = steps + step;
+    if (steps == 2) continue;
+    if (steps == 6) break;
+} while (steps < 10);
+do steps = steps * 10; while (false);
+        "#, &["sum", "count", "shadow", "odd", "skipped", "kind", "steps"]);
+    }
+
+    #[test]
+    fn test_functions() {
+        assert_same_as_tree_walker(r#"
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+fun noReturn() {}
+fun add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var f = fib(15);
+var nothing = noReturn();
+var sum = add(1, add(2, 3));
+var name = fib;
+var native = clock;
+{
+    fun local(x) { return x * 2; }
+    var twice = local(21);
+    sum = sum + twice;
+}
+        "#, &["f", "nothing", "sum", "name", "native"]);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let message = |code: &str| match run_vm(code) {
+            Err(LoxErr::Runtime { message, .. }) => message,
+            other => panic!("Expected runtime error, got {:?}", other.err()),
+        };
+        assert_eq!(message("-\"a\";"), "Operand must be a number, got string.");
+        assert_eq!(message("print undefined;"), "Undefined variable 'undefined'.");
+        assert_eq!(message("fun f(a) {} f();"), "Expected 1 arguments but got 0.");
+        assert_eq!(message("\"f\"();"), "Can only call functions and classes.");
+        assert_eq!(message("fun f() { f(); } f();"), "Stack overflow.");
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut vm = Vm::new();
+        let handle = vm.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.store(true, Ordering::Relaxed);
+        });
+        match vm.interpret(Compiler::compile(&resolved("while (true) {}")).unwrap()) {
+            Err(LoxErr::Aborted { line, message, .. }) => {
+                assert_eq!(message, "Interrupted.");
+                assert_eq!(line, Some(1));
+            }
+            other => panic!("Expected an interrupt, got {:?}", other),
+        }
+        interrupter.join().unwrap();
+        vm.interpret(Compiler::compile(&resolved("var after = 1;")).unwrap()).unwrap();
+        assert_eq!(vm.globals.get("after"), Some(&Object::Int(1)));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let message = |code: &str| match run_vm(code) {
+            Err(LoxErr::Compile { message, .. }) => message,
+            other => panic!("Expected compile error, got {:?}", other.err()),
+        };
+        assert_eq!(message("class A {}"), "Classes are not supported by the bytecode backend yet.");
+        assert_eq!(message("fun f(...rest) {}"), "Rest parameters are not supported by the bytecode backend yet.");
+        assert_eq!(message("import \"a.lox\";"), "Imports are not supported by the bytecode backend yet.");
+        assert_eq!(message("assert true;"), "Assertions are not supported by the bytecode backend yet.");
+        assert_eq!(message("throw 1;"), "Exceptions are not supported by the bytecode backend yet.");
+        assert_eq!(message("for (var c in \"ab\") print c;"), "For-in loops are not supported by the bytecode backend yet.");
+        let code = r#"
+fun outer() {
+    var x = 1;
+    fun inner() { return x; }
+    return inner;
+}
+        "#;
+        assert_eq!(message(code), "Closures are not supported by the bytecode backend yet.");
+        // 指向不支持的语法
+        match run_vm("var a = 1;\nprint a.b;") {
+            Err(lox_err @ LoxErr::Compile { span, .. }) => {
+                assert_eq!(span, Span { start: 19, end: 20 });
+                assert_eq!(lox_err.to_string(), "Compile Error: [line 2:9] Classes are not supported by the bytecode backend yet.");
+            }
+            other => panic!("Expected compile error, got {:?}", other.err()),
+        }
+    }
+}
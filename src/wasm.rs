@@ -0,0 +1,36 @@
+use wasm_bindgen::prelude::*;
+
+use crate::diagnostic::Diagnostic;
+use crate::err::LoxErr;
+use crate::lox::Lox;
+
+/*
+给网页上的 Lox playground 用的 JavaScript 接口。编译和生成胶水代码：
+    cargo build --lib --release --target wasm32-unknown-unknown
+    wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/rilox.wasm
+JavaScript 里：
+    import init, { run } from "./pkg/rilox.js";
+    await init();
+    const { output, errors } = run("print 1 + 2;");
+每次 run 都用一个新的解释器，上一次定义的变量不保留
+WebAssembly 里读不了文件，import 会报错；sleep 也用不了
+*/
+
+// print 输出的内容，和出的所有错误，每个错误是一条文字
+#[wasm_bindgen(getter_with_clone)]
+pub struct RunResult {
+    pub output: String,
+    pub errors: Vec<String>,
+}
+
+#[wasm_bindgen]
+pub fn run(source: &str) -> RunResult {
+    let mut lox = Lox::new();
+    // 警告没地方显示，丢掉
+    lox.set_reporter(Box::new(|_: &Diagnostic| {}));
+    let (output, errors) = lox.run_capture(source);
+    RunResult {
+        output: output,
+        errors: errors.iter().map(LoxErr::to_string).collect(),
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rilox::diagnostic::Diagnostic;
+use rilox::err::LoxErr;
+use rilox::lox::Lox;
+
+/*
+兼容性测试：跑按 Crafting Interpreters 官方测试格式写的 .lox 文件，期望的结果写在注释里
+    // expect: 输出                  print 打印的一行
+    // expect runtime error: 信息    这一行报运行时错误
+    // Error at 'x': 信息            这一行有扫描、解析或语义分析错误，也可以写成 // [line N] Error at 'x': 信息
+官方测试里只给 clox 的期望（// [c line N]）跳过，只给 jlox 的（// [java line N]）照常检查
+tests/lox 下是跟着仓库走的用例，rilox 和原书行为不同的地方按 rilox 的写
+要跑官方的测试，把 RILOX_COMPAT_DIR 设成 craftinginterpreters 仓库的 test 目录：
+    RILOX_COMPAT_DIR=../craftinginterpreters/test cargo test --test compat -- --ignored --nocapture
+*/
+
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    output: Vec<String>,
+    errors: Vec<String>,    // 扫描、解析、语义分析错误，格式是 [line N] Error at 'x': 信息
+    runtime_error: Option<String>,  // 格式是 [line N] 信息
+}
+
+fn expected(code: &str) -> Outcome {
+    let mut outcome = Outcome::default();
+    for (index, line) in code.lines().enumerate() {
+        let Some((_, comment)) = line.split_once("// ") else {
+            continue;
+        };
+        // 期望空行时冒号后面没有空格
+        if let Some(output) = comment.strip_prefix("expect:") {
+            outcome.output.push(output.strip_prefix(' ').unwrap_or(output).to_string());
+        } else if let Some(message) = comment.strip_prefix("expect runtime error: ") {
+            outcome.runtime_error = Some(format!("[line {}] {}", index + 1, message));
+        } else if comment.starts_with("Error") {
+            outcome.errors.push(format!("[line {}] {}", index + 1, comment));
+        } else if let Some(error) = comment.strip_prefix("[java line ").or_else(|| comment.strip_prefix("[line ")) {
+            outcome.errors.push(format!("[line {}", error));
+        }
+    }
+    outcome
+}
+
+fn actual(code: &str) -> Outcome {
+    let mut lox = Lox::new();
+    // 警告不算在结果里，也别打印出来
+    lox.set_reporter(Box::new(|_: &Diagnostic| {}));
+    let (output, errors) = lox.run_capture(code);
+    let mut outcome = Outcome { output: output.lines().map(str::to_string).collect(), ..Outcome::default() };
+    for lox_err in errors {
+        describe(code, lox_err, &mut outcome);
+    }
+    outcome
+}
+
+fn describe(code: &str, lox_err: LoxErr, outcome: &mut Outcome) {
+    match lox_err {
+        LoxErr::Many(errs) => errs.into_iter().for_each(|err| describe(code, err, outcome)),
+        LoxErr::Scan { line, message, .. } => outcome.errors.push(format!("[line {}] Error: {}", line, message)),
+        LoxErr::Parse { line, lexeme, message, .. } => outcome.errors.push(format!("[line {}] Error at {}: {}", line, lexeme, message)),
+        // 语义分析的错误不带 lexeme，从源代码里取
+        LoxErr::Resolve { line, span, message, .. } => {
+            outcome.errors.push(format!("[line {}] Error at '{}': {}", line, &code[span.start..span.end], message));
+        }
+        LoxErr::Runtime { line, message, .. } => outcome.runtime_error = Some(format!("[line {}] {}", line, message)),
+        LoxErr::RuntimeThrow { line, value, .. } => outcome.runtime_error = Some(format!("[line {}] Uncaught exception: {}.", line, value)),
+        other => outcome.runtime_error = Some(other.to_string()),
+    }
+}
+
+// 目录下所有 .lox 文件，按路径排序
+fn lox_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            lox_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+}
+
+// 返回测试文件的个数，和没通过的文件及差异
+fn run_suite(dir: &Path) -> (usize, Vec<String>) {
+    let mut files = Vec::new();
+    lox_files(dir, &mut files);
+    let mut failures = Vec::new();
+    for path in &files {
+        let code = fs::read_to_string(path).unwrap();
+        let (expected, actual) = (expected(&code), actual(&code));
+        if expected != actual {
+            failures.push(format!("{}\n  expected: {:?}\n  actual:   {:?}", path.display(), expected, actual));
+        }
+    }
+    (files.len(), failures)
+}
+
+#[test]
+fn test_bundled_suite() {
+    let (count, failures) = run_suite(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"));
+    assert!(count > 0);
+    assert!(failures.is_empty(), "{} of {} tests failed:\n{}", failures.len(), count, failures.join("\n"));
+}
+
+// rilox 扩展和改动了不少语法，官方测试不会全部通过，这里只打印差异
+#[test]
+#[ignore]
+fn test_official_suite() {
+    let Ok(dir) = std::env::var("RILOX_COMPAT_DIR") else {
+        panic!("Set RILOX_COMPAT_DIR to the test directory of the craftinginterpreters repository.");
+    };
+    let (count, failures) = run_suite(Path::new(&dir));
+    for failure in &failures {
+        println!("{}", failure);
+    }
+    println!("{} of {} tests passed.", count - failures.len(), count);
+}
+
+#[test]
+fn test_expectations() {
+    let code = "print 1; // expect: 1\nvar a = ; // Error at ';': Expect expression.\n// [line 5] Error at end: Expect '}' after block.\n// [c line 2] Error: C only.\nprint nil - 1; // expect runtime error: Operand must be a number.";
+    assert_eq!(expected(code), Outcome {
+        output: vec!["1".to_string()],
+        errors: vec!["[line 2] Error at ';': Expect expression.".to_string(), "[line 5] Error at end: Expect '}' after block.".to_string()],
+        runtime_error: Some("[line 5] Operand must be a number.".to_string()),
+    });
+}